@@ -144,9 +144,14 @@ async fn get_ad4m_client(args: &ClapApp) -> Result<Ad4mClient> {
 }
 
 #[tokio::main(flavor = "multi_thread")]
-async fn main() -> Result<()> {
-    let args = ClapApp::parse();
+async fn main() {
+    if let Err(error) = run(ClapApp::parse()).await {
+        eprintln!("Error: {:?}", error);
+        std::process::exit(util::exit_code(&error));
+    }
+}
 
+async fn run(args: ClapApp) -> Result<()> {
     let ad4m_client = get_ad4m_client(&args).await?;
 
     match args.domain {
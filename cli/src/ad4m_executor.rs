@@ -28,7 +28,7 @@ use ad4m_client::*;
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use dev::DevFunctions;
-use rust_executor::{config::TlsConfig, Ad4mConfig};
+use rust_executor::{config::TlsConfig, Ad4mConfig, KeystoreKdfParams};
 
 /// AD4M command line interface.
 /// https://ad4m.dev
@@ -127,8 +127,90 @@ enum Domain {
         tls_key_file: Option<String>,
         #[arg(long, action)]
         log_holochain_metrics: Option<bool>,
+        /// Number of Prolog engines that answer queries for each perspective
+        /// (default 1, more engines allow concurrent queries but hold all facts each)
+        #[arg(long, action)]
+        prolog_engine_pool_size: Option<usize>,
+        /// Time limit of Prolog queries that don't set one, like those of subject
+        /// classes and SDNA (default 30000, 0 for no limit)
+        #[arg(long, action)]
+        prolog_query_timeout_ms: Option<u64>,
+        /// Inference limit of Prolog queries that don't set one (default 50000000, 0 for no limit)
+        #[arg(long, action)]
+        prolog_inference_limit: Option<u64>,
+        /// Argon2id memory cost in KiB for writing the agent keystore
+        #[arg(long, action)]
+        keystore_kdf_memory_kib: Option<u32>,
+        /// Argon2id iterations for writing the agent keystore
+        #[arg(long, action)]
+        keystore_kdf_iterations: Option<u32>,
+        /// Argon2id parallelism for writing the agent keystore
+        #[arg(long, action)]
+        keystore_kdf_parallelism: Option<u32>,
+        /// Days that applied diffs are kept in the perspective history (default: forever)
+        #[arg(long, action)]
+        perspective_history_retention_days: Option<u32>,
     },
     RunLocalHcServices {},
+    /// Apply pending database schema migrations (a backup is written first)
+    MigrateDb {
+        #[arg(short, long, action)]
+        data_path: Option<String>,
+        /// Only list pending migrations without applying them
+        #[arg(long, action)]
+        dry_run: bool,
+    },
+    /// Write the agent (keystore, profile, app tokens and database) to an encrypted backup file.
+    /// Stop the executor first.
+    Backup {
+        #[arg(short, long, action)]
+        data_path: Option<String>,
+        /// File to write the backup to
+        #[arg(short, long, action)]
+        output: String,
+        /// Passphrase the backup is encrypted with
+        #[arg(short, long, action)]
+        passphrase: Option<String>,
+    },
+    /// Restore an agent from a backup file into the data directory.
+    /// Stop the executor first.
+    Restore {
+        #[arg(short, long, action)]
+        data_path: Option<String>,
+        /// Backup file to restore
+        #[arg(short, long, action)]
+        input: String,
+        /// Passphrase the backup is encrypted with
+        #[arg(short, long, action)]
+        passphrase: Option<String>,
+        /// Overwrite an agent that already exists in the data directory
+        #[arg(long, action)]
+        force: bool,
+    },
+}
+
+fn data_path_or_default(data_path: Option<String>) -> std::path::PathBuf {
+    match data_path {
+        Some(data_path) => std::path::PathBuf::from(data_path),
+        None => dirs::home_dir().expect("home directory").join(".ad4m"),
+    }
+}
+
+fn print_backup_summary(summary: &rust_executor::backup::BackupSummary) {
+    println!(
+        "Agent: {}",
+        summary.agent_did.as_deref().unwrap_or("<unknown>")
+    );
+    println!(
+        "Created at {} by AD4M {}",
+        summary.created_at, summary.ad4m_version
+    );
+    for file in &summary.files {
+        println!("  {}", file);
+    }
+    for (table, count) in &summary.tables {
+        println!("  {}: {}", table, count);
+    }
 }
 
 #[tokio::main(flavor = "multi_thread")]
@@ -155,6 +237,79 @@ async fn main() -> Result<()> {
         return Ok(());
     };
 
+    if let Domain::MigrateDb { data_path, dry_run } = args.domain {
+        if let Err(e) = rust_executor::init::migrate_db(data_path, dry_run) {
+            println!("Failed to migrate AD4M database: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    };
+
+    if let Domain::Backup {
+        data_path,
+        output,
+        passphrase,
+    } = args.domain
+    {
+        let passphrase = match passphrase {
+            Some(passphrase) => passphrase,
+            None => {
+                let passphrase1 = util::readline_masked("Backup passphrase: ")?;
+                let passphrase2 = util::readline_masked("Repeat backup passphrase: ")?;
+                if passphrase1 != passphrase2 {
+                    println!("Passphrases do not match");
+                    std::process::exit(1);
+                }
+                passphrase1
+            }
+        };
+        match rust_executor::backup::backup_data_dir(
+            &data_path_or_default(data_path),
+            std::path::Path::new(&output),
+            &passphrase,
+        ) {
+            Ok(summary) => {
+                println!("Wrote backup to {}", output);
+                print_backup_summary(&summary);
+            }
+            Err(e) => {
+                println!("Failed to back up agent: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    };
+
+    if let Domain::Restore {
+        data_path,
+        input,
+        passphrase,
+        force,
+    } = args.domain
+    {
+        let passphrase = match passphrase {
+            Some(passphrase) => passphrase,
+            None => util::readline_masked("Backup passphrase: ")?,
+        };
+        let archive = std::fs::read_to_string(&input)?;
+        match rust_executor::backup::restore_data_dir(
+            &data_path_or_default(data_path),
+            &archive,
+            &passphrase,
+            force,
+        ) {
+            Ok(summary) => {
+                println!("Restored agent from {}", input);
+                print_backup_summary(&summary);
+            }
+            Err(e) => {
+                println!("Failed to restore agent: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    };
+
     if let Domain::Run {
         app_data_path,
         network_bootstrap_seed,
@@ -175,8 +330,28 @@ async fn main() -> Result<()> {
         tls_cert_file,
         tls_key_file,
         log_holochain_metrics,
+        prolog_engine_pool_size,
+        prolog_query_timeout_ms,
+        prolog_inference_limit,
+        keystore_kdf_memory_kib,
+        keystore_kdf_iterations,
+        keystore_kdf_parallelism,
+        perspective_history_retention_days,
     } = args.domain
     {
+        let keystore_kdf_params = if keystore_kdf_memory_kib.is_some()
+            || keystore_kdf_iterations.is_some()
+            || keystore_kdf_parallelism.is_some()
+        {
+            let defaults = KeystoreKdfParams::default();
+            Some(KeystoreKdfParams {
+                memory_kib: keystore_kdf_memory_kib.unwrap_or(defaults.memory_kib),
+                iterations: keystore_kdf_iterations.unwrap_or(defaults.iterations),
+                parallelism: keystore_kdf_parallelism.unwrap_or(defaults.parallelism),
+            })
+        } else {
+            None
+        };
         let tls = if tls_cert_file.is_some() && tls_cert_file.is_some() {
             Some(TlsConfig {
                 cert_file_path: tls_cert_file.unwrap(),
@@ -209,6 +384,11 @@ async fn main() -> Result<()> {
                 auto_permit_cap_requests: Some(true),
                 tls,
                 log_holochain_metrics,
+                prolog_engine_pool_size,
+                prolog_query_timeout_ms,
+                prolog_inference_limit,
+                keystore_kdf_params,
+                perspective_history_retention_days,
             })
             .await;
         })
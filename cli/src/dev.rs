@@ -55,6 +55,11 @@ pub async fn run(command: DevFunctions) -> Result<()> {
                     auto_permit_cap_requests: Some(true),
                     tls: None,
                     log_holochain_metrics: None,
+                    prolog_engine_pool_size: None,
+                    prolog_query_timeout_ms: None,
+                    prolog_inference_limit: None,
+                    keystore_kdf_params: None,
+                    perspective_history_retention_days: None,
                 })
                 .await
                 .join()
@@ -69,7 +74,7 @@ pub async fn run(command: DevFunctions) -> Result<()> {
                 );
                 let me = client.agent.me().await;
                 println!("Me: {:?}", me);
-                let agent_generate = client.agent.generate(String::from("test")).await;
+                let agent_generate = client.agent.generate(String::from("test-passphrase")).await;
                 println!("Agent generate: {:?}", agent_generate);
                 let publish_language = client
                     .languages
@@ -206,6 +211,11 @@ pub async fn run(command: DevFunctions) -> Result<()> {
                     auto_permit_cap_requests: Some(true),
                     tls: None,
                     log_holochain_metrics: None,
+                    prolog_engine_pool_size: None,
+                    prolog_query_timeout_ms: None,
+                    prolog_inference_limit: None,
+                    keystore_kdf_params: None,
+                    perspective_history_retention_days: None,
                 })
                 .await
                 .join()
@@ -1,5 +1,5 @@
 use crate::{formatting::*, repl::repl_loop, util::maybe_parse_datetime};
-use ad4m_client::Ad4mClient;
+use ad4m_client::{perspectives::LinkPaging, Ad4mClient};
 use anyhow::{anyhow, Context, Result};
 use clap::{Args, Subcommand};
 
@@ -28,6 +28,18 @@ pub struct QueryLinksArgs {
     /// Get only the first n links
     #[arg(short, long)]
     limit: Option<f64>,
+
+    /// Continue after the cursor printed with a previous page
+    #[arg(long)]
+    after: Option<String>,
+
+    /// Get the page before the cursor printed with a previous page
+    #[arg(long)]
+    before: Option<String>,
+
+    /// Sort by timestamp: "asc" or "desc"
+    #[arg(long)]
+    direction: Option<String>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -50,9 +62,36 @@ pub enum PerspectiveFunctions {
     /// Query links from perspective with given uuid
     QueryLinks(QueryLinksArgs),
 
+    /// Search links of perspective with given uuid by the text of their literal targets
+    Search {
+        id: String,
+        text: String,
+        /// "fulltext" (default) or "semantic" (needs a default embedding model)
+        #[arg(short, long)]
+        mode: Option<String>,
+        /// Maximum number of results
+        #[arg(short, long)]
+        limit: Option<i64>,
+    },
+
     /// Retrieve snapshot of perspective with given uuid
     Snapshot { id: String },
 
+    /// Export perspective with given uuid (links, SDNA and metadata) as a signed archive file
+    Export {
+        id: String,
+        /// File to write the archive to
+        file: String,
+    },
+
+    /// Restore a perspective from an archive file created with `export`
+    Import {
+        file: String,
+        /// Import the archive even if its signature is invalid
+        #[arg(long)]
+        allow_invalid_signature: bool,
+    },
+
     /// Run Prolog / SDNA query on perspective with given uuid
     Infer { id: String, query: String },
 
@@ -162,7 +201,9 @@ pub async fn run(ad4m_client: Ad4mClient, command: Option<PerspectiveFunctions>)
         PerspectiveFunctions::QueryLinks(args) => {
             let from_date = maybe_parse_datetime(args.from_date)?;
             let until_date = maybe_parse_datetime(args.until_date)?;
-            let result = ad4m_client
+            let paging_back = args.before.is_some();
+            // One more link than asked for tells whether there is another page
+            let mut links = ad4m_client
                 .perspectives
                 .query_links(
                     args.id,
@@ -171,12 +212,60 @@ pub async fn run(ad4m_client: Ad4mClient, command: Option<PerspectiveFunctions>)
                     args.predicate,
                     from_date,
                     until_date,
-                    args.limit,
+                    args.limit.map(|limit| limit + 1.0),
+                    LinkPaging {
+                        after: args.after,
+                        before: args.before,
+                        direction: args.direction,
+                    },
                 )
                 .await?;
-            for link in result {
+            let has_more = match args.limit {
+                Some(limit) if links.len() as f64 > limit => {
+                    // Paging backwards, the extra link is the one before the page
+                    if paging_back {
+                        links.remove(0);
+                    } else {
+                        links.pop();
+                    }
+                    true
+                }
+                _ => false,
+            };
+            let next_page = if !has_more {
+                None
+            } else if paging_back {
+                links
+                    .first()
+                    .and_then(|link| link.cursor.clone())
+                    .map(|cursor| format!("--before {}", cursor))
+            } else {
+                links
+                    .last()
+                    .and_then(|link| link.cursor.clone())
+                    .map(|cursor| format!("--after {}", cursor))
+            };
+            for link in links {
                 print_link(link.into());
             }
+            if let Some(next_page) = next_page {
+                println!("\nMore links available. Next page: {}", next_page);
+            }
+        }
+        PerspectiveFunctions::Search {
+            id,
+            text,
+            mode,
+            limit,
+        } => {
+            let results = ad4m_client
+                .perspectives
+                .search(id, text, mode, limit)
+                .await?;
+            for result in results {
+                println!("\x1b[36m{:.3} \x1b[97m{}", result.score, result.text);
+                print_link(result.link.into());
+            }
         }
         PerspectiveFunctions::Infer { id, query } => {
             let results = ad4m_client.perspectives.infer(id, query).await?;
@@ -197,6 +286,43 @@ pub async fn run(ad4m_client: Ad4mClient, command: Option<PerspectiveFunctions>)
             let result = ad4m_client.perspectives.snapshot(id).await?;
             println!("{:#?}", result);
         }
+        PerspectiveFunctions::Export { id, file } => {
+            let archive = ad4m_client.perspectives.export(id).await?;
+            std::fs::write(&file, archive)
+                .with_context(|| anyhow!("Could not write archive to {}", file))?;
+            println!("Perspective exported to {}", file);
+        }
+        PerspectiveFunctions::Import {
+            file,
+            allow_invalid_signature,
+        } => {
+            let archive = std::fs::read_to_string(&file)
+                .with_context(|| anyhow!("Could not read archive file {}", file))?;
+            let result = ad4m_client
+                .perspectives
+                .import(archive, Some(allow_invalid_signature))
+                .await?;
+            println!("\x1b[36mID: \x1b[97m{}", result.perspective.uuid);
+            println!("\x1b[36mImported links: \x1b[97m{}", result.imported_links);
+            if !result.archive_signature_valid {
+                println!("\x1b[91mWarning: the archive's signature is invalid");
+            }
+            if !result.invalid_links.is_empty() {
+                println!(
+                    "\x1b[91mSkipped {} links with invalid signatures:",
+                    result.invalid_links.len()
+                );
+                for link in result.invalid_links {
+                    println!(
+                        "\x1b[90m{} \x1b[97m{} -{}-> {}",
+                        link.author,
+                        link.data.source,
+                        link.data.predicate.unwrap_or_default(),
+                        link.data.target
+                    );
+                }
+            }
+        }
         PerspectiveFunctions::Repl { id } => {
             //let _ = perspectives::run_watch(cap_token, id);
             repl_loop(ad4m_client.perspectives.get(id).await?).await?;
@@ -1,4 +1,5 @@
 use crate::types::Perspective;
+use ad4m_client::errors::{ErrorCode, ExecutorError};
 use ad4m_client::Ad4mClient;
 use anyhow::{anyhow, Result};
 
@@ -25,6 +26,23 @@ pub fn maybe_parse_datetime(
     )
 }
 
+/// Exit code for a failed command, following sysexits.h where the
+/// executor told us what went wrong
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    let executor_error = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<ExecutorError>());
+    match executor_error.map(|e| &e.code) {
+        Some(ErrorCode::InvalidInput | ErrorCode::PrologError) => 65,
+        Some(ErrorCode::PerspectiveNotFound | ErrorCode::NotFound) => 66,
+        Some(ErrorCode::LinkLanguageMissing | ErrorCode::AiModelNotLoaded) => 69,
+        Some(ErrorCode::Internal) => 70,
+        Some(ErrorCode::PrologQueryTimeout | ErrorCode::AgentNotInitialized) => 75,
+        Some(ErrorCode::CapabilityDenied | ErrorCode::AgentLocked) => 77,
+        Some(ErrorCode::Unknown(_)) | None => 1,
+    }
+}
+
 pub fn readline_masked(prompt: &str) -> Result<String> {
     use rustyline::completion::Completer;
     use rustyline::config::Configurer;
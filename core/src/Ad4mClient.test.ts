@@ -285,10 +285,114 @@ describe('Ad4mClient', () => {
             expect(jwt).toBe("test-jwt")
         })
 
+        it('agentGenerateCapabilityTokens() smoke tests', async () => {
+            const tokens = await ad4mClient.agent.generateCapabilityTokens("test-request-id", "123")
+            expect(tokens.accessToken).toBe("test-jwt")
+            expect(tokens.refreshToken).toBe("test-refresh-jwt")
+            expect(tokens.expiresAt).toBe("2024-01-08T00:00:00Z")
+        })
+
+        it('agentRefreshCapability() smoke tests', async () => {
+            const tokens = await ad4mClient.agent.refreshCapability("test-refresh-jwt")
+            expect(tokens.accessToken).toBe("test-jwt-2")
+            expect(tokens.refreshToken).toBe("test-refresh-jwt-2")
+        })
+
+        it('agentKeys() smoke tests', async () => {
+            const keys = await ad4mClient.agent.keys()
+            expect(keys.length).toBe(2)
+            expect(keys[0].isMain).toBe(true)
+            expect(keys[1].retiredAt).toBe("2024-01-02T00:00:00Z")
+        })
+
+        it('agentKeyRotations() smoke tests', async () => {
+            const rotations = await ad4mClient.agent.keyRotations("did:ad4m:test")
+            expect(rotations.length).toBe(1)
+            expect(rotations[0].agentDid).toBe("did:ad4m:test")
+            expect(rotations[0].newKey).toBe("did:key:z6Mknew")
+        })
+
+        it('agentCreateKey() smoke tests', async () => {
+            const key = await ad4mClient.agent.createKey("device", "passphrase")
+            expect(key.name).toBe("device")
+            expect(key.isMain).toBe(false)
+            expect(key.retiredAt).toBeNull()
+        })
+
+        it('agentRetireKey() smoke tests', async () => {
+            const key = await ad4mClient.agent.retireKey("device", "passphrase")
+            expect(key.name).toBe("device")
+            expect(key.retiredAt).toBe("2024-01-04T00:00:00Z")
+        })
+
+        it('agentRotateMainKey() smoke tests', async () => {
+            const rotation = await ad4mClient.agent.rotateMainKey("passphrase")
+            expect(rotation.oldKey).toBe("did:key:z6Mkold")
+            expect(rotation.newKey).toBe("did:key:z6Mknew")
+            expect(rotation.signature).toBe("test-signature")
+        })
+
+        it('agentDevices() smoke tests', async () => {
+            const devices = await ad4mClient.agent.devices()
+            expect(devices.length).toBe(2)
+            expect(devices[0].deviceKey).toBe("did:key:z6Mkdevice")
+            expect(devices[0].revokedAt).toBeNull()
+            expect(devices[1].revokedAt).toBe("2024-01-05T00:00:00Z")
+        })
+
+        it('device linking smoke tests', async () => {
+            const request = await ad4mClient.agent.requestDeviceLink("did:ad4m:test")
+            expect(request.deviceKeyType).toBe("ad4m-device")
+            expect(request.didSignedByDeviceKey).toBe("device-signature")
+
+            const authorization = await ad4mClient.agent.authorizeDevice({
+                did: request.did,
+                didSigningKeyId: request.didSigningKeyId,
+                deviceKeyType: request.deviceKeyType,
+                deviceKey: request.deviceKey,
+                deviceKeySignedByDid: request.deviceKeySignedByDid,
+                didSignedByDeviceKey: request.didSignedByDeviceKey!,
+            })
+            expect(authorization.deviceKeySignedByDid).toBe("agent-signature")
+
+            const status = await ad4mClient.agent.linkDevice({
+                did: authorization.did,
+                didSigningKeyId: authorization.didSigningKeyId,
+                deviceKeyType: authorization.deviceKeyType,
+                deviceKey: authorization.deviceKey,
+                deviceKeySignedByDid: authorization.deviceKeySignedByDid,
+                didSignedByDeviceKey: authorization.didSignedByDeviceKey!,
+            }, "passphrase")
+            expect(status.did).toBe("did:ad4m:test")
+        })
+
+        it('agentRevokeDevice() smoke tests', async () => {
+            const revocation = await ad4mClient.agent.revokeDevice("did:key:z6Mkdevice")
+            expect(revocation.deviceKey).toBe("did:key:z6Mkdevice")
+            expect(revocation.revokedAt).toBe("2024-01-05T00:00:00Z")
+        })
+
+        it('agentCreateBackup() smoke tests', async () => {
+            const backup = await ad4mClient.agent.createBackup("backup-passphrase", "/tmp/agent.ad4mbackup")
+            expect(backup.path).toBe("/tmp/agent.ad4mbackup")
+            expect(backup.agentDid).toBe("did:ad4m:test")
+            expect(backup.files).toContain("ad4m_db.sqlite")
+            expect(backup.tables[0].name).toBe("perspectives")
+            expect(backup.tables[0].count).toBe(2)
+        })
+
+        it('agentRestoreBackup() smoke tests', async () => {
+            const backup = await ad4mClient.agent.restoreBackup("backup-passphrase", "/tmp/agent.ad4mbackup", true)
+            expect(backup.agentDid).toBe("did:ad4m:test")
+            expect(backup.files).toContain("ad4m/agent.json")
+        })
+
         it('agentRevokeToken() smoke tests', async () => {
             const newApps = await ad4mClient.agent.revokeToken('test-request-id')
             expect(newApps.length).toBe(1)
             expect(newApps[0].revoked).toBe(true)
+            expect(newApps[0].rotationCount).toBe(1)
+            expect(newApps[0].tokenExpiresAt).toBe("2024-01-08T00:00:00Z")
         })
 
         it('agentRemoveToken() smoke tests', async () => {
@@ -589,6 +693,27 @@ describe('Ad4mClient', () => {
             expect(links[0].data.target).toBe('neighbourhood://Qm12345')
         })
 
+        it('queryLinks() returns link cursors', async () => {
+            const links = await ad4mClient.perspective.queryLinks('000001', {source: 'root', limit: 1, direction: 'desc'})
+            expect(links.length).toBe(1)
+            expect(links[0].cursor).toBe('cursor-1')
+        })
+
+        it('search() smoke test', async () => {
+            const results = await ad4mClient.perspective.search('000001', 'milk', 'fulltext', 5)
+            expect(results.length).toBe(1)
+            expect(results[0].text).toBe('milk')
+            expect(results[0].link.data.source).toBe('root')
+            expect(results[0].score).toBe(1)
+        })
+
+        it('linkEventSequences() smoke test', async () => {
+            const sequences = await ad4mClient.perspective.linkEventSequences()
+            expect(sequences.added).toBe(1)
+            expect(sequences.removed).toBe(2)
+            expect(sequences.updated).toBe(3)
+        })
+
         it('queryProlog() smoke test', async () => {
             let result = await ad4mClient.perspective.queryProlog('000001', "link(X, 2).")
             expect(result.length).toBe(1)
@@ -617,6 +742,69 @@ describe('Ad4mClient', () => {
             expect(r).toBeTruthy()
         })
 
+        it('queryProlog() with options and cancelPrologQuery() smoke test', async () => {
+            const result = await ad4mClient.perspective.queryProlog('000001', "link(X, 2).", { timeoutMs: 1000, inferenceLimit: 10000, queryId: 'q1' })
+            expect(result.length).toBe(1)
+            expect(result[0].X).toBe(1)
+
+            const cancelled = await ad4mClient.perspective.cancelPrologQuery('000001', 'q1')
+            expect(cancelled).toBe(true)
+        })
+
+        it('prologEngineMetrics() smoke test', async () => {
+            const metrics = await ad4mClient.perspective.prologEngineMetrics('000001')
+            expect(metrics.queueDepths).toStrictEqual([0, 1])
+            expect(metrics.queriesTotal).toBe(42)
+        })
+
+        it('syncStatus() smoke test', async () => {
+            const status = await ad4mClient.perspective.syncStatus('000001')
+            expect(status.uuid).toBe('000001')
+            expect(status.pendingDiffs).toBe(2)
+            expect(status.committedDiffs).toBe(40)
+            expect(status.failedDiffs[0].attempts).toBe(3)
+            expect(status.failedDiffs[0].lastError).toBe('Link language not reachable')
+
+            const statuses = await ad4mClient.perspective.syncStatuses()
+            expect(statuses.length).toBe(1)
+        })
+
+        it('retrySync() smoke test', async () => {
+            const status = await ad4mClient.perspective.retrySync('000001')
+            expect(status.pendingDiffs).toBe(0)
+            expect(status.failedDiffs).toStrictEqual([])
+        })
+
+        it('linksAt() smoke test', async () => {
+            const links = await ad4mClient.perspective.linksAt('000001', new Date())
+            expect(links.length).toBe(1)
+            expect(links[0].author).toBe('did:ad4m:test')
+        })
+
+        it('history() and undo() smoke test', async () => {
+            const history = await ad4mClient.perspective.history('000001', undefined, 10)
+            expect(history.length).toBe(1)
+            expect(history[0].id).toBe('2')
+            expect(history[0].local).toBe(true)
+            expect(history[0].authors).toStrictEqual(['did:ad4m:test'])
+            expect(history[0].additions.length).toBe(1)
+
+            const inverse = await ad4mClient.perspective.undo('000001', history[0].id)
+            expect(inverse.additions.length).toBe(0)
+            expect(inverse.removals.length).toBe(1)
+        })
+
+        it('export() and import() smoke test', async () => {
+            const archive = await ad4mClient.perspective.export('000001')
+            expect(JSON.parse(archive).handle.uuid).toBe('000001')
+
+            const result = await ad4mClient.perspective.import(archive)
+            expect(result.perspective.uuid).toBe('00007')
+            expect(result.importedLinks).toBe(1)
+            expect(result.invalidLinks.length).toBe(0)
+            expect(result.archiveSignatureValid).toBe(true)
+        })
+
         it('addLink() smoke test', async () => {
             const link = await ad4mClient.perspective.addLink('00001', {source: 'root', target: 'lang://Qm123', predicate: 'p'})
             expect(link.author).toBe('did:ad4m:test')
@@ -635,6 +823,13 @@ describe('Ad4mClient', () => {
             expect(link.status).toBe('local')
         })
 
+        it('addEncryptedLink() smoke test', async () => {
+            const link = await ad4mClient.perspective.addEncryptedLink('00001', {source: 'root', target: 'literal://string:secret', predicate: 'p'}, ['did:ad4m:friend'])
+            expect(link.author).toBe('did:ad4m:test')
+            expect(link.data.target).toBe('literal://string:secret')
+            expect(link.status).toBe('shared')
+        })
+
         it('addLinks() smoke test', async () => {
             const links = await ad4mClient.perspective.addLinks('00001', [
                 {source: 'root', target: 'lang://Qm123', predicate: 'p'},
@@ -973,6 +1168,17 @@ describe('Ad4mClient', () => {
             expect(notifications.length).toBe(1);
         })
 
+        it('notificationDeliveries smoke test', async () => {
+            const deliveries = await ad4mClient.runtime.notificationDeliveries("test-id", 10);
+            expect(deliveries.length).toBe(1);
+            expect(deliveries[0].notificationId).toBe("test-id");
+            expect(deliveries[0].status).toBe("DELIVERED");
+            expect(deliveries[0].attempts).toBe(2);
+
+            const secret = await ad4mClient.runtime.notificationWebhookSecret("test-id");
+            expect(secret).toBe("webhook-secret");
+        })
+
         it('updateNotification smoke test', async () => {
             await ad4mClient.runtime.updateNotification("test-notification", {
                 description: "Test description",
@@ -1167,6 +1373,7 @@ describe('Ad4mClient', () => {
                     expect(model.api).toHaveProperty('apiKey');
                     expect(model.api).toHaveProperty('model');
                     expect(model.api).toHaveProperty('apiType');
+                    expect(model.api.headers[0].name).toBe('X-Test');
                 }
                 if (model.local) {
                     expect(model.local).toHaveProperty('fileName');
@@ -1206,6 +1413,21 @@ describe('Ad4mClient', () => {
             expect(result).toBe("new-model-id");
         })
 
+        it('addModel with Ollama API and custom headers smoke test', async () => {
+            const result = await ad4mClient.ai.addModel({
+                name: "Local Ollama",
+                api: {
+                    baseUrl: "http://localhost:11434",
+                    apiKey: "",
+                    apiType: "OLLAMA",
+                    model: "llama3.2",
+                    headers: [{ name: "X-Team", value: "research" }]
+                },
+                modelType: "EMBEDDING"
+            });
+            expect(result).toBe("new-model-id");
+        })
+
         it('updateModel smoke test', async () => {
             const modelId = "test-model-id";
             const updatedModel = {
@@ -1291,6 +1513,20 @@ describe('Ad4mClient', () => {
             expect(task.systemPrompt).toBe("system prompt")
         });
 
+        it('addTask() with output constraint', async () => {
+            const jsonSchema = JSON.stringify({ type: "object", properties: { ok: { type: "boolean" } } })
+            const task = await ad4mClient.ai.addTask("task_name", "model_id", "system prompt", [], undefined, { jsonSchema });
+            expect(task.outputConstraint.jsonSchema).toBe(jsonSchema)
+            expect(task.outputConstraint.regex).toBeNull()
+
+            const updated = await ad4mClient.ai.updateTask(task.taskId, {
+                ...task,
+                outputConstraint: { regex: "yes|no" }
+            });
+            expect(updated.outputConstraint.regex).toBe("yes|no")
+            expect(updated.outputConstraint.jsonSchema).toBeNull()
+        });
+
         it('removeTask()', async () => {
             const task = await ad4mClient.ai.removeTask("task_id", "system prompt", []);
             expect(task.taskId).toBe("task_id")
@@ -1322,6 +1558,42 @@ describe('Ad4mClient', () => {
             expect(prompt).toBe("output")
         })
 
+        it('promptStream(), cancelPromptStream() & aiPromptTokens subscription', async () => {
+            const tokensCallback = jest.fn()
+            const streamId = await ad4mClient.ai.promptStream("task_id", "Do something", tokensCallback);
+            expect(streamId).toBe("promptStreamId")
+
+            await new Promise<void>(resolve => setTimeout(resolve, 100))
+
+            expect(await ad4mClient.ai.cancelPromptStream(streamId)).toBe(true)
+
+            await new Promise<void>(resolve => setTimeout(resolve, 100))
+
+            expect(tokensCallback).toBeCalledTimes(1)
+            expect(tokensCallback.mock.calls[0][0].text).toBe("output")
+            expect(tokensCallback.mock.calls[0][0].done).toBe(true)
+        })
+
+        it('createSession(), sessionPrompt(), sessions(), sessionMessages() & deleteSession()', async () => {
+            const session = await ad4mClient.ai.createSession({ taskId: "task_id", name: "chat", truncation: "LAST_MESSAGES", maxMessages: 10 })
+            expect(session.sessionId).toBe("session_id")
+            expect(session.taskId).toBe("task_id")
+            expect(session.truncation).toBe("LAST_MESSAGES")
+            expect(session.maxMessages).toBe(10)
+
+            expect(await ad4mClient.ai.sessionPrompt(session.sessionId, "Do something")).toBe("output")
+
+            const sessions = await ad4mClient.ai.sessions("task_id")
+            expect(sessions.length).toBe(1)
+            expect(sessions[0].taskId).toBe("task_id")
+
+            const messages = await ad4mClient.ai.sessionMessages(session.sessionId)
+            expect(messages.map(m => m.role)).toEqual(["USER", "ASSISTANT"])
+            expect(messages[1].content).toBe("output")
+
+            expect(await ad4mClient.ai.deleteSession(session.sessionId)).toBe(true)
+        })
+
         it('openTranscriptionStream(), closeTranscriptionStream(), feedTranscriptionStream() & aiTranscriptionText subscription', async () => {
             const streamCallback = jest.fn()
             const streamId = await ad4mClient.ai.openTranscriptionStream("model_id", streamCallback);
@@ -1340,5 +1612,37 @@ describe('Ad4mClient', () => {
             const stream = await ad4mClient.ai.closeTranscriptionStream(streamId)
             expect(stream).toBeTruthy()
         })
+
+        it('openTranscriptionStream() with aiTranscriptionSegments subscription', async () => {
+            const streamCallback = jest.fn()
+            const segmentCallback = jest.fn()
+            const streamId = await ad4mClient.ai.openTranscriptionStream("model_id", streamCallback, segmentCallback);
+
+            await new Promise<void>(resolve => setTimeout(resolve, 100))
+
+            await ad4mClient.ai.feedTranscriptionStream(streamId, [0, 10, 20, 30]);
+
+            await new Promise<void>(resolve => setTimeout(resolve, 100))
+
+            expect(streamCallback).toBeCalledWith("transcription")
+            expect(segmentCallback).toBeCalledTimes(1)
+            const segment = segmentCallback.mock.calls[0][0]
+            expect(segment.start).toBe(0)
+            expect(segment.end).toBe(1.5)
+            expect(segment.confidence).toBe(0.9)
+
+            await ad4mClient.ai.closeTranscriptionStream(streamId)
+        })
+
+        it('transcribeAudio() smoke test', async () => {
+            const segments = await ad4mClient.ai.transcribeAudio("Whisper", { samples: [0, 0.1, 0.2] })
+            expect(segments.length).toBe(2)
+            expect(segments[0].text).toBe("Hello")
+            expect(segments[1].start).toBe(0.5)
+            expect(segments[1].end).toBe(1.2)
+
+            const fromWav = await ad4mClient.ai.transcribeAudio("Whisper", { wavBase64: "UklGRg==" })
+            expect(fromWav[0].confidence).toBe(0.9)
+        })
     })
 })
\ No newline at end of file
@@ -214,16 +214,214 @@ export class Apps {
   @Field()
   auth: AuthInfo;
 
+  @Field({ nullable: true })
+  tokenExpiresAt?: string;
+
+  @Field({ nullable: true })
+  rotatedAt?: string;
+
+  @Field()
+  rotationCount: number;
+
   constructor(
     requestId: string,
     auth: AuthInfo,
     token: string,
-    revoked?: boolean
+    revoked?: boolean,
+    tokenExpiresAt?: string,
+    rotatedAt?: string,
+    rotationCount?: number
   ) {
     this.requestId = requestId;
     this.auth = auth;
     this.token = token;
     this.revoked = revoked;
+    this.tokenExpiresAt = tokenExpiresAt;
+    this.rotatedAt = rotatedAt;
+    this.rotationCount = rotationCount ?? 0;
+  }
+}
+
+@ObjectType()
+export class AgentKey {
+  @Field()
+  name: string;
+
+  @Field()
+  did: string;
+
+  @Field({ nullable: true })
+  createdAt?: string;
+
+  @Field({ nullable: true })
+  retiredAt?: string;
+
+  @Field()
+  isMain: boolean;
+
+  constructor(
+    name: string,
+    did: string,
+    isMain: boolean,
+    createdAt?: string,
+    retiredAt?: string
+  ) {
+    this.name = name;
+    this.did = did;
+    this.isMain = isMain;
+    this.createdAt = createdAt;
+    this.retiredAt = retiredAt;
+  }
+}
+
+@ObjectType()
+export class KeyRotation {
+  @Field()
+  agentDid: string;
+
+  @Field()
+  oldKey: string;
+
+  @Field()
+  newKey: string;
+
+  @Field()
+  timestamp: string;
+
+  @Field()
+  signature: string;
+
+  constructor(
+    agentDid: string,
+    oldKey: string,
+    newKey: string,
+    timestamp: string,
+    signature: string
+  ) {
+    this.agentDid = agentDid;
+    this.oldKey = oldKey;
+    this.newKey = newKey;
+    this.timestamp = timestamp;
+    this.signature = signature;
+  }
+}
+
+@ObjectType()
+export class AgentDevice {
+  @Field()
+  deviceKey: string;
+
+  @Field()
+  authorized: boolean;
+
+  @Field({ nullable: true })
+  revokedAt?: string;
+
+  constructor(deviceKey: string, authorized: boolean, revokedAt?: string) {
+    this.deviceKey = deviceKey;
+    this.authorized = authorized;
+    this.revokedAt = revokedAt;
+  }
+}
+
+@ObjectType()
+export class DeviceRevocation {
+  @Field()
+  did: string;
+
+  @Field()
+  deviceKey: string;
+
+  @Field()
+  revokedAt: string;
+
+  @Field()
+  signingKey: string;
+
+  @Field()
+  signature: string;
+
+  constructor(
+    did: string,
+    deviceKey: string,
+    revokedAt: string,
+    signingKey: string,
+    signature: string
+  ) {
+    this.did = did;
+    this.deviceKey = deviceKey;
+    this.revokedAt = revokedAt;
+    this.signingKey = signingKey;
+    this.signature = signature;
+  }
+}
+
+@ObjectType()
+export class BackupTableCount {
+  @Field()
+  name: string;
+
+  @Field()
+  count: number;
+
+  constructor(name: string, count: number) {
+    this.name = name;
+    this.count = count;
+  }
+}
+
+@ObjectType()
+export class AgentBackup {
+  @Field()
+  path: string;
+
+  @Field({ nullable: true })
+  agentDid?: string;
+
+  @Field()
+  createdAt: string;
+
+  @Field()
+  ad4mVersion: string;
+
+  @Field((type) => [String])
+  files: string[];
+
+  @Field((type) => [BackupTableCount])
+  tables: BackupTableCount[];
+
+  constructor(
+    path: string,
+    createdAt: string,
+    ad4mVersion: string,
+    files: string[],
+    tables: BackupTableCount[],
+    agentDid?: string
+  ) {
+    this.path = path;
+    this.createdAt = createdAt;
+    this.ad4mVersion = ad4mVersion;
+    this.files = files;
+    this.tables = tables;
+    this.agentDid = agentDid;
+  }
+}
+
+@ObjectType()
+export class CapabilityTokens {
+  @Field()
+  accessToken: string;
+
+  @Field()
+  refreshToken: string;
+
+  @Field()
+  expiresAt: string;
+
+  constructor(accessToken: string, refreshToken: string, expiresAt: string) {
+    this.accessToken = accessToken;
+    this.refreshToken = refreshToken;
+    this.expiresAt = expiresAt;
   }
 }
 
@@ -3,11 +3,17 @@ import { PerspectiveInput } from "../perspectives/Perspective";
 import unwrapApolloResult from "../unwrapApolloResult";
 import {
   Agent,
+  AgentBackup,
+  AgentDevice,
+  AgentKey,
   Apps,
   AuthInfo,
+  CapabilityTokens,
   AuthInfoInput,
+  DeviceRevocation,
   EntanglementProof,
   EntanglementProofInput,
+  KeyRotation,
 } from "./Agent";
 import { AgentStatus } from "./AgentStatus";
 import { LinkMutations } from "../links/Links";
@@ -29,9 +35,54 @@ const AGENT_SUBITEMS = `
     }
 `;
 
+const AGENT_KEY_FIELDS = `
+    name
+    did
+    createdAt
+    retiredAt
+    isMain
+`;
+
+const KEY_ROTATION_FIELDS = `
+    agentDid
+    oldKey
+    newKey
+    timestamp
+    signature
+`;
+
+const AGENT_DEVICE_FIELDS = `
+    deviceKey
+    authorized
+    revokedAt
+`;
+
+const DEVICE_REVOCATION_FIELDS = `
+    did
+    deviceKey
+    revokedAt
+    signingKey
+    signature
+`;
+
+const AGENT_BACKUP_FIELDS = `
+    path
+    agentDid
+    createdAt
+    ad4mVersion
+    files
+    tables {
+        name
+        count
+    }
+`;
+
 const Apps_FIELDS = `
     requestId
     revoked
+    tokenExpiresAt
+    rotatedAt
+    rotationCount
     auth {
         appName
         appDesc
@@ -461,6 +512,275 @@ export class AgentClient {
     return agentGenerateJwt;
   }
 
+  /**
+   * Like generateJwt() but returns a short-lived access token together with
+   * a refresh token that can be exchanged for new tokens with
+   * refreshCapability(), also after the access token expired.
+   */
+  async generateCapabilityTokens(
+    requestId: string,
+    rand: string
+  ): Promise<CapabilityTokens> {
+    const { agentGenerateCapabilityTokens } = unwrapApolloResult(
+      await this.#apolloClient.mutate({
+        mutation: gql`
+          mutation agentGenerateCapabilityTokens($requestId: String!, $rand: String!) {
+            agentGenerateCapabilityTokens(requestId: $requestId, rand: $rand) {
+              accessToken
+              refreshToken
+              expiresAt
+            }
+          }
+        `,
+        variables: { requestId, rand },
+      })
+    );
+    return agentGenerateCapabilityTokens;
+  }
+
+  /**
+   * Exchanges a refresh token for a new token pair. The old access and
+   * refresh tokens stop working. The refresh token is the only credential
+   * needed, so this works with an expired access token. Tokens from before
+   * the executor signed them with the agent's key can be passed instead of a
+   * refresh token to exchange them for a token pair.
+   */
+  async refreshCapability(refreshToken: string): Promise<CapabilityTokens> {
+    const { agentRefreshCapability } = unwrapApolloResult(
+      await this.#apolloClient.mutate({
+        mutation: gql`
+          mutation agentRefreshCapability($refreshToken: String!) {
+            agentRefreshCapability(refreshToken: $refreshToken) {
+              accessToken
+              refreshToken
+              expiresAt
+            }
+          }
+        `,
+        variables: { refreshToken },
+      })
+    );
+    return agentRefreshCapability;
+  }
+
+  /**
+   * All keys in the agent's wallet, including retired ones.
+   */
+  async keys(): Promise<AgentKey[]> {
+    const { agentKeys } = unwrapApolloResult(
+      await this.#apolloClient.query({
+        query: gql`query agentKeys {
+                agentKeys {
+                    ${AGENT_KEY_FIELDS}
+                }
+            }`,
+      })
+    );
+    return agentKeys;
+  }
+
+  /**
+   * Key rotations known for the given agent, ordered by time.
+   */
+  async keyRotations(did: string): Promise<KeyRotation[]> {
+    const { agentKeyRotations } = unwrapApolloResult(
+      await this.#apolloClient.query({
+        query: gql`query agentKeyRotations($did: String!) {
+                agentKeyRotations(did: $did) {
+                    ${KEY_ROTATION_FIELDS}
+                }
+            }`,
+        variables: { did },
+      })
+    );
+    return agentKeyRotations;
+  }
+
+  async createKey(name: string, passphrase: string): Promise<AgentKey> {
+    const { agentCreateKey } = unwrapApolloResult(
+      await this.#apolloClient.mutate({
+        mutation: gql`mutation agentCreateKey($name: String!, $passphrase: String!) {
+                agentCreateKey(name: $name, passphrase: $passphrase) {
+                    ${AGENT_KEY_FIELDS}
+                }
+            }`,
+        variables: { name, passphrase },
+      })
+    );
+    return agentCreateKey;
+  }
+
+  /**
+   * Retired keys can't sign anymore, signatures they made before stay valid.
+   */
+  async retireKey(name: string, passphrase: string): Promise<AgentKey> {
+    const { agentRetireKey } = unwrapApolloResult(
+      await this.#apolloClient.mutate({
+        mutation: gql`mutation agentRetireKey($name: String!, $passphrase: String!) {
+                agentRetireKey(name: $name, passphrase: $passphrase) {
+                    ${AGENT_KEY_FIELDS}
+                }
+            }`,
+        variables: { name, passphrase },
+      })
+    );
+    return agentRetireKey;
+  }
+
+  /**
+   * Replaces the main key while keeping the agent's DID. The returned
+   * rotation statement is signed by the old key and published in the
+   * agent's public perspective.
+   */
+  async rotateMainKey(passphrase: string): Promise<KeyRotation> {
+    const { agentRotateMainKey } = unwrapApolloResult(
+      await this.#apolloClient.mutate({
+        mutation: gql`mutation agentRotateMainKey($passphrase: String!) {
+                agentRotateMainKey(passphrase: $passphrase) {
+                    ${KEY_ROTATION_FIELDS}
+                }
+            }`,
+        variables: { passphrase },
+      })
+    );
+    return agentRotateMainKey;
+  }
+
+  /**
+   * Devices linked to this agent, including revoked ones.
+   */
+  async devices(): Promise<AgentDevice[]> {
+    const { agentDevices } = unwrapApolloResult(
+      await this.#apolloClient.query({
+        query: gql`query agentDevices {
+                agentDevices {
+                    ${AGENT_DEVICE_FIELDS}
+                }
+            }`,
+      })
+    );
+    return agentDevices;
+  }
+
+  /**
+   * Called on the new device: creates a link request for the agent `did`,
+   * signed by this executor's key. Pass it to `authorizeDevice()` on an
+   * executor of that agent.
+   */
+  async requestDeviceLink(did: string): Promise<EntanglementProof> {
+    const { agentRequestDeviceLink } = unwrapApolloResult(
+      await this.#apolloClient.mutate({
+        mutation: gql`mutation agentRequestDeviceLink($did: String!) {
+                agentRequestDeviceLink(did: $did) {
+                    ${ENTANGLEMENT_PROOF_FIELDS}
+                }
+            }`,
+        variables: { did },
+      })
+    );
+    return agentRequestDeviceLink;
+  }
+
+  /**
+   * Signs the device key of a link request and publishes the authorization
+   * in the agent's public perspective. Pass the result to `linkDevice()` on
+   * the new device.
+   */
+  async authorizeDevice(
+    request: EntanglementProofInput
+  ): Promise<EntanglementProof> {
+    const { agentAuthorizeDevice } = unwrapApolloResult(
+      await this.#apolloClient.mutate({
+        mutation: gql`mutation agentAuthorizeDevice($request: EntanglementProofInput!) {
+                agentAuthorizeDevice(request: $request) {
+                    ${ENTANGLEMENT_PROOF_FIELDS}
+                }
+            }`,
+        variables: { request },
+      })
+    );
+    return agentAuthorizeDevice;
+  }
+
+  /**
+   * Called on the new device with the authorization: from then on this
+   * executor acts as the authorizing agent.
+   */
+  async linkDevice(
+    authorization: EntanglementProofInput,
+    passphrase: string
+  ): Promise<AgentStatus> {
+    const { agentLinkDevice } = unwrapApolloResult(
+      await this.#apolloClient.mutate({
+        mutation: gql`mutation agentLinkDevice($authorization: EntanglementProofInput!, $passphrase: String!) {
+                agentLinkDevice(authorization: $authorization, passphrase: $passphrase) {
+                    ${AGENT_STATUS_FIELDS}
+                }
+            }`,
+        variables: { authorization, passphrase },
+      })
+    );
+    return new AgentStatus(agentLinkDevice);
+  }
+
+  /**
+   * Signatures the device makes after the revocation don't verify anymore.
+   */
+  async revokeDevice(deviceKey: string): Promise<DeviceRevocation> {
+    const { agentRevokeDevice } = unwrapApolloResult(
+      await this.#apolloClient.mutate({
+        mutation: gql`mutation agentRevokeDevice($deviceKey: String!) {
+                agentRevokeDevice(deviceKey: $deviceKey) {
+                    ${DEVICE_REVOCATION_FIELDS}
+                }
+            }`,
+        variables: { deviceKey },
+      })
+    );
+    return agentRevokeDevice;
+  }
+
+  /**
+   * Writes the agent's keystore, profile, app tokens and database to an
+   * encrypted backup file at `path` on the executor's machine.
+   */
+  async createBackup(passphrase: string, path: string): Promise<AgentBackup> {
+    const { agentCreateBackup } = unwrapApolloResult(
+      await this.#apolloClient.mutate({
+        mutation: gql`mutation agentCreateBackup($passphrase: String!, $path: String!) {
+                agentCreateBackup(passphrase: $passphrase, path: $path) {
+                    ${AGENT_BACKUP_FIELDS}
+                }
+            }`,
+        variables: { passphrase, path },
+      })
+    );
+    return agentCreateBackup;
+  }
+
+  /**
+   * Restores an agent from a backup file. Refuses to overwrite an existing
+   * agent unless `force` is set and while perspectives are running. The
+   * restored agent has to be unlocked with its own passphrase afterwards.
+   */
+  async restoreBackup(
+    passphrase: string,
+    path: string,
+    force?: boolean
+  ): Promise<AgentBackup> {
+    const { agentRestoreBackup } = unwrapApolloResult(
+      await this.#apolloClient.mutate({
+        mutation: gql`mutation agentRestoreBackup($passphrase: String!, $path: String!, $force: Boolean) {
+                agentRestoreBackup(passphrase: $passphrase, path: $path, force: $force) {
+                    ${AGENT_BACKUP_FIELDS}
+                }
+            }`,
+        variables: { passphrase, path, force },
+      })
+    );
+    return agentRestoreBackup;
+  }
+
   async getApps(): Promise<Apps[]> {
     const { agentGetApps } = unwrapApolloResult(
       await this.#apolloClient.mutate({
@@ -9,11 +9,18 @@ import {
 import { Perspective, PerspectiveInput } from "../perspectives/Perspective";
 import {
   Agent,
+  AgentBackup,
+  AgentDevice,
+  AgentKey,
   AgentSignature,
   Apps,
   AuthInfoInput,
+  BackupTableCount,
+  CapabilityTokens,
+  DeviceRevocation,
   EntanglementProof,
   EntanglementProofInput,
+  KeyRotation,
 } from "./Agent";
 import { AgentStatus } from "./AgentStatus";
 import { AGENT_STATUS_CHANGED, AGENT_UPDATED, APPS_CHANGED } from "../PubSub";
@@ -207,6 +214,177 @@ export default class AgentResolver {
     return "test-jwt";
   }
 
+  @Mutation((returns) => CapabilityTokens)
+  agentGenerateCapabilityTokens(
+    @Arg("requestId") requestId: string,
+    @Arg("rand") rand: string
+  ): CapabilityTokens {
+    return new CapabilityTokens(
+      "test-jwt",
+      "test-refresh-jwt",
+      "2024-01-08T00:00:00Z"
+    );
+  }
+
+  @Mutation((returns) => CapabilityTokens)
+  agentRefreshCapability(
+    @Arg("refreshToken") refreshToken: string
+  ): CapabilityTokens {
+    return new CapabilityTokens(
+      "test-jwt-2",
+      "test-refresh-jwt-2",
+      "2024-01-15T00:00:00Z"
+    );
+  }
+
+  @Query((returns) => [AgentKey])
+  agentKeys(): AgentKey[] {
+    return [
+      new AgentKey("main", "did:key:z6Mknew", true, "2024-01-02T00:00:00Z"),
+      new AgentKey(
+        "main-retired-1704153600000",
+        "did:key:z6Mkold",
+        false,
+        "2024-01-01T00:00:00Z",
+        "2024-01-02T00:00:00Z"
+      ),
+    ];
+  }
+
+  @Query((returns) => [KeyRotation])
+  agentKeyRotations(@Arg("did") did: string): KeyRotation[] {
+    return [
+      new KeyRotation(
+        did,
+        "did:key:z6Mkold",
+        "did:key:z6Mknew",
+        "2024-01-02T00:00:00Z",
+        "test-signature"
+      ),
+    ];
+  }
+
+  @Mutation((returns) => AgentKey)
+  agentCreateKey(
+    @Arg("name") name: string,
+    @Arg("passphrase") passphrase: string
+  ): AgentKey {
+    return new AgentKey(name, "did:key:z6Mkdevice", false, "2024-01-03T00:00:00Z");
+  }
+
+  @Mutation((returns) => AgentKey)
+  agentRetireKey(
+    @Arg("name") name: string,
+    @Arg("passphrase") passphrase: string
+  ): AgentKey {
+    return new AgentKey(
+      name,
+      "did:key:z6Mkdevice",
+      false,
+      "2024-01-03T00:00:00Z",
+      "2024-01-04T00:00:00Z"
+    );
+  }
+
+  @Mutation((returns) => KeyRotation)
+  agentRotateMainKey(@Arg("passphrase") passphrase: string): KeyRotation {
+    return new KeyRotation(
+      "did:ad4m:test",
+      "did:key:z6Mkold",
+      "did:key:z6Mknew",
+      "2024-01-02T00:00:00Z",
+      "test-signature"
+    );
+  }
+
+  @Query((returns) => [AgentDevice])
+  agentDevices(): AgentDevice[] {
+    return [
+      new AgentDevice("did:key:z6Mkdevice", true),
+      new AgentDevice("did:key:z6Mklost", true, "2024-01-05T00:00:00Z"),
+    ];
+  }
+
+  @Mutation((returns) => EntanglementProof)
+  agentRequestDeviceLink(@Arg("did") did: string): EntanglementProof {
+    return new EntanglementProof(
+      did,
+      "",
+      "ad4m-device",
+      "did:key:z6Mkdevice",
+      "",
+      "device-signature"
+    );
+  }
+
+  @Mutation((returns) => EntanglementProof)
+  agentAuthorizeDevice(
+    @Arg("request") request: EntanglementProofInput
+  ): EntanglementProof {
+    return new EntanglementProof(
+      request.did,
+      "did:key:z6Mknew#z6Mknew",
+      request.deviceKeyType,
+      request.deviceKey,
+      "agent-signature",
+      request.didSignedByDeviceKey
+    );
+  }
+
+  @Mutation((returns) => AgentStatus)
+  agentLinkDevice(
+    @Arg("authorization") authorization: EntanglementProofInput,
+    @Arg("passphrase") passphrase: string
+  ): AgentStatus {
+    return new AgentStatus({
+      isInitialized: true,
+      isUnlocked: true,
+      did: authorization.did,
+    });
+  }
+
+  @Mutation((returns) => DeviceRevocation)
+  agentRevokeDevice(@Arg("deviceKey") deviceKey: string): DeviceRevocation {
+    return new DeviceRevocation(
+      TEST_AGENT_DID,
+      deviceKey,
+      "2024-01-05T00:00:00Z",
+      "did:key:z6Mknew#z6Mknew",
+      "test-signature"
+    );
+  }
+
+  @Mutation((returns) => AgentBackup)
+  agentCreateBackup(
+    @Arg("passphrase") passphrase: string,
+    @Arg("path") path: string
+  ): AgentBackup {
+    return new AgentBackup(
+      path,
+      "2024-01-01T00:00:00Z",
+      "0.10.1",
+      ["ad4m/agent.json", "apps_data.json", "ad4m_db.sqlite"],
+      [new BackupTableCount("perspectives", 2)],
+      "did:ad4m:test"
+    );
+  }
+
+  @Mutation((returns) => AgentBackup)
+  agentRestoreBackup(
+    @Arg("passphrase") passphrase: string,
+    @Arg("path") path: string,
+    @Arg("force", { nullable: true }) force?: boolean
+  ): AgentBackup {
+    return new AgentBackup(
+      path,
+      "2024-01-01T00:00:00Z",
+      "0.10.1",
+      ["ad4m/agent.json", "apps_data.json", "ad4m_db.sqlite"],
+      [new BackupTableCount("perspectives", 2)],
+      "did:ad4m:test"
+    );
+  }
+
   @Query((returns) => Boolean)
   agentIsLocked(): Boolean {
     return false;
@@ -252,6 +430,9 @@ export default class AgentResolver {
           ],
         },
         token: "test-token",
+        tokenExpiresAt: "2024-01-08T00:00:00Z",
+        rotatedAt: "2024-01-01T00:00:00Z",
+        rotationCount: 1,
       },
     ];
   }
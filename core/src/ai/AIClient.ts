@@ -2,7 +2,7 @@ import { ApolloClient, gql } from "@apollo/client";
 import unwrapApolloResult from "../unwrapApolloResult";
 import base64js from 'base64-js';
 import pako from 'pako'
-import { AIModelLoadingStatus, AITask, AITaskInput } from "./Tasks";
+import { AIMessage, AIModelLoadingStatus, AIOutputConstraintInput, AIPromptTokens, AISession, AISessionInput, AITask, AITaskInput, TranscriptionSegment } from "./Tasks";
 import { ModelInput, Model, ModelType } from "./AIResolver"
 
 export class AIClient {
@@ -25,6 +25,10 @@ export class AIClient {
                             apiKey
                             model
                             apiType
+                            headers {
+                                name
+                                value
+                            }
                         }
                         local {
                             fileName
@@ -35,6 +39,7 @@ export class AIClient {
                             }
                             huggingfaceRepo
                             revision
+                            language
                         }
                         modelType
                     }
@@ -104,6 +109,10 @@ export class AIClient {
                             apiKey
                             model
                             apiType
+                            headers {
+                                name
+                                value
+                            }
                         }
                         local {
                             fileName
@@ -114,6 +123,7 @@ export class AIClient {
                             }
                             huggingfaceRepo
                             revision
+                            language
                         }
                         modelType
                     }
@@ -138,6 +148,10 @@ export class AIClient {
                             output
                         }
                         metaData
+                        outputConstraint {
+                            jsonSchema
+                            regex
+                        }
                         createdAt
                         updatedAt
                     }
@@ -148,8 +162,12 @@ export class AIClient {
         return aiTasks;
     }
 
-    async addTask(name: string, modelId: string, systemPrompt: string, promptExamples: { input: string, output: string }[], metaData?: string): Promise<AITask> {
-        const task = new AITaskInput(name, modelId, systemPrompt, promptExamples, metaData);
+    /**
+     * `outputConstraint` restricts responses to `prompt()` to JSON valid against
+     * a JSON Schema (given as string) or to text matching a regex
+     */
+    async addTask(name: string, modelId: string, systemPrompt: string, promptExamples: { input: string, output: string }[], metaData?: string, outputConstraint?: AIOutputConstraintInput): Promise<AITask> {
+        const task = new AITaskInput(name, modelId, systemPrompt, promptExamples, metaData, outputConstraint);
         const { aiAddTask } = unwrapApolloResult(await this.#apolloClient.mutate({
             mutation: gql`
                 mutation AiAddTask($task: AITaskInput!) {
@@ -163,6 +181,10 @@ export class AIClient {
                             output
                         }
                         metaData
+                        outputConstraint {
+                            jsonSchema
+                            regex
+                        }
                         createdAt
                         updatedAt
                     }
@@ -190,6 +212,10 @@ export class AIClient {
                             output
                         }
                         metaData
+                        outputConstraint {
+                            jsonSchema
+                            regex
+                        }
                         createdAt
                         updatedAt
                     }
@@ -217,6 +243,10 @@ export class AIClient {
                             output
                         }
                         metaData
+                        outputConstraint {
+                            jsonSchema
+                            regex
+                        }
                         createdAt
                         updatedAt
                     }
@@ -228,7 +258,11 @@ export class AIClient {
                     name: task.name,
                     modelId: task.modelId,
                     systemPrompt: task.systemPrompt,
-                    promptExamples: task.promptExamples
+                    promptExamples: task.promptExamples,
+                    outputConstraint: task.outputConstraint ? {
+                        jsonSchema: task.outputConstraint.jsonSchema,
+                        regex: task.outputConstraint.regex
+                    } : undefined
                 }
             }
         }));
@@ -273,6 +307,158 @@ export class AIClient {
         return aiPrompt;
     }
 
+    /**
+     * Like prompt() but calls tokensCallback with the text as it gets generated.
+     * The last call has `done` set, and `error` if generation failed.
+     * Returns the stream id, to be used with cancelPromptStream().
+     */
+    async promptStream(taskId: string, prompt: string, tokensCallback: (tokens: AIPromptTokens) => void): Promise<string> {
+        const { aiPromptStream } = unwrapApolloResult(await this.#apolloClient.mutate({
+            mutation: gql`
+                mutation AiPromptStream($taskId: String!, $prompt: String!) {
+                    aiPromptStream(taskId: $taskId, prompt: $prompt)
+                }
+            `,
+            variables: {
+                taskId,
+                prompt
+            }
+        }));
+
+        const subscription = this.#apolloClient.subscribe({
+            query: gql`
+                subscription AiPromptTokens($streamId: String!) {
+                    aiPromptTokens(streamId: $streamId) {
+                        streamId
+                        text
+                        done
+                        error
+                    }
+                }
+            `,
+            variables: { streamId: aiPromptStream }
+        }).subscribe({
+            next(result) {
+                const tokens = result.data.aiPromptTokens;
+                tokensCallback(tokens);
+                if (tokens.done) {
+                    subscription.unsubscribe();
+                }
+            },
+            error(err) {
+                console.error(err);
+            }
+        });
+
+        return aiPromptStream;
+    }
+
+    async cancelPromptStream(streamId: string): Promise<boolean> {
+        const { aiCancelPromptStream } = unwrapApolloResult(await this.#apolloClient.mutate({
+            mutation: gql`
+                mutation AiCancelPromptStream($streamId: String!) {
+                    aiCancelPromptStream(streamId: $streamId)
+                }
+            `,
+            variables: { streamId }
+        }));
+
+        return aiCancelPromptStream;
+    }
+
+    /**
+     * Starts a conversation with the model of a task. Prompts sent with
+     * sessionPrompt() get the earlier messages of the session as context.
+     */
+    async createSession(session: AISessionInput): Promise<AISession> {
+        const { aiCreateSession } = unwrapApolloResult(await this.#apolloClient.mutate({
+            mutation: gql`
+                mutation AiCreateSession($session: AISessionInput!) {
+                    aiCreateSession(session: $session) {
+                        sessionId
+                        taskId
+                        name
+                        truncation
+                        maxMessages
+                        maxTokens
+                        perspectiveUuid
+                        createdAt
+                        updatedAt
+                    }
+                }
+            `,
+            variables: { session }
+        }));
+
+        return aiCreateSession;
+    }
+
+    async sessionPrompt(sessionId: string, prompt: string): Promise<string> {
+        const { aiSessionPrompt } = unwrapApolloResult(await this.#apolloClient.mutate({
+            mutation: gql`
+                mutation AiSessionPrompt($sessionId: String!, $prompt: String!) {
+                    aiSessionPrompt(sessionId: $sessionId, prompt: $prompt)
+                }
+            `,
+            variables: { sessionId, prompt }
+        }));
+
+        return aiSessionPrompt;
+    }
+
+    async sessions(taskId?: string): Promise<AISession[]> {
+        const { aiSessions } = unwrapApolloResult(await this.#apolloClient.query({
+            query: gql`
+                query AiSessions($taskId: String) {
+                    aiSessions(taskId: $taskId) {
+                        sessionId
+                        taskId
+                        name
+                        truncation
+                        maxMessages
+                        maxTokens
+                        perspectiveUuid
+                        createdAt
+                        updatedAt
+                    }
+                }
+            `,
+            variables: { taskId }
+        }));
+
+        return aiSessions;
+    }
+
+    async sessionMessages(sessionId: string): Promise<AIMessage[]> {
+        const { aiSessionMessages } = unwrapApolloResult(await this.#apolloClient.query({
+            query: gql`
+                query AiSessionMessages($sessionId: String!) {
+                    aiSessionMessages(sessionId: $sessionId) {
+                        role
+                        content
+                        createdAt
+                    }
+                }
+            `,
+            variables: { sessionId }
+        }));
+
+        return aiSessionMessages;
+    }
+
+    async deleteSession(sessionId: string): Promise<boolean> {
+        const { aiDeleteSession } = unwrapApolloResult(await this.#apolloClient.mutate({
+            mutation: gql`
+                mutation AiDeleteSession($sessionId: String!) {
+                    aiDeleteSession(sessionId: $sessionId)
+                }
+            `,
+            variables: { sessionId }
+        }));
+
+        return aiDeleteSession;
+    }
+
     async embed(modelId: string, text: string): Promise<Array<number>> {
         const { aiEmbed } = unwrapApolloResult(await this.#apolloClient.mutate({
             mutation: gql`
@@ -293,7 +479,18 @@ export class AIClient {
         return decompressed;
     }
 
-    async openTranscriptionStream(modelId: string, streamCallback: (text: string) => void): Promise<string> {
+    /**
+     * Opens a stream to feed audio into with `feedTranscriptionStream()`.
+     * `streamCallback` gets the text of every transcribed segment and
+     * `segmentCallback`, if given, the segment with its timing and confidence.
+     * `modelId` is the id or name of a transcription model, `default`
+     * or the name of Whisper weights like `whisper-tiny`.
+     */
+    async openTranscriptionStream(
+        modelId: string,
+        streamCallback: (text: string) => void,
+        segmentCallback?: (segment: TranscriptionSegment) => void
+    ): Promise<string> {
         const { aiOpenTranscriptionStream } = unwrapApolloResult(await this.#apolloClient.mutate({
             mutation: gql`
                 mutation AiOpenTranscriptionStream($modelId: String!) {
@@ -305,12 +502,28 @@ export class AIClient {
             }
         }));
 
-        const subscription = this.#apolloClient.subscribe({
-            query: gql` subscription {
+        const query = segmentCallback
+            ? gql` subscription {
+                aiTranscriptionSegments(streamId: "${aiOpenTranscriptionStream}") {
+                    text
+                    start
+                    end
+                    confidence
+                }
+            }`
+            : gql` subscription {
                 aiTranscriptionText(streamId: "${aiOpenTranscriptionStream}")
             }`
-        }).subscribe({
+
+        const subscription = this.#apolloClient.subscribe({ query }).subscribe({
             next(data) {
+                if (segmentCallback) {
+                    const segment = data.data.aiTranscriptionSegments;
+                    streamCallback(segment.text);
+                    segmentCallback(segment);
+                    return segment.text;
+                }
+
                 streamCallback(data.data.aiTranscriptionText);
 
                 return data.data.aiTranscriptionText;
@@ -361,4 +574,34 @@ export class AIClient {
 
         return feedTranscriptionStream;
     }
+
+    /**
+     * Transcribes a whole recording, given either as 16 kHz mono `samples`
+     * or as the contents of a WAV file in `wavBase64`, with a model
+     * chosen like in `openTranscriptionStream()`
+     */
+    async transcribeAudio(
+        modelId: string,
+        audio: { samples?: number[] | Float32Array, wavBase64?: string }
+    ): Promise<TranscriptionSegment[]> {
+        const { aiTranscribeAudio } = unwrapApolloResult(await this.#apolloClient.mutate({
+            mutation: gql`
+                mutation AiTranscribeAudio($modelId: String!, $samples: [Float!], $wavBase64: String) {
+                    aiTranscribeAudio(modelId: $modelId, samples: $samples, wavBase64: $wavBase64) {
+                        text
+                        start
+                        end
+                        confidence
+                    }
+                }
+            `,
+            variables: {
+                modelId,
+                samples: audio.samples ? Array.from(audio.samples) : undefined,
+                wavBase64: audio.wavBase64
+            }
+        }));
+
+        return aiTranscribeAudio;
+    }
 }
\ No newline at end of file
@@ -1,13 +1,22 @@
 import { Query, Resolver, Mutation, Arg, InputType, Field, Subscription, Float, PubSub, ObjectType} from "type-graphql";
-import { AIModelLoadingStatus, AITask, AITaskInput } from "./Tasks";
+import { AIMessage, AIModelLoadingStatus, AIPromptTokens, AISession, AISessionInput, AITask, AITaskInput, TranscriptionSegment } from "./Tasks";
 import pako from "pako";
 import base64js from 'base64-js';
-import { AI_TRANSCRIPTION_TEXT_TOPIC } from "../PubSub";
+import { AI_PROMPT_TOKENS_TOPIC, AI_TRANSCRIPTION_TEXT_TOPIC } from "../PubSub";
 
 let createdAt = Date.now().toString();
 let updatedAt = Date.now().toString();
 
 
+@ObjectType()
+export class ModelApiHeader {
+    @Field()
+    name: string;
+
+    @Field()
+    value: string;
+}
+
 @ObjectType()
 export class ModelApi {
     @Field()
@@ -19,8 +28,13 @@ export class ModelApi {
     @Field()
     model: string;
 
+    // "OPEN_AI" for OpenAI-compatible APIs or "OLLAMA"
     @Field()
     apiType: String;
+
+    // Sent along with every request to the API
+    @Field(type => [ModelApiHeader])
+    headers: ModelApiHeader[];
 }
 
 @ObjectType()
@@ -48,6 +62,10 @@ export class LocalModel {
 
     @Field({ nullable: true })
     revision?: string;
+
+    // Spoken language for transcription models (e.g. "en"), detected if not set
+    @Field({ nullable: true })
+    language?: string;
 }
 
 export type ModelType = "LLM" | "EMBEDDING" | "TRANSCRIPTION";
@@ -70,6 +88,15 @@ export class Model {
     modelType: ModelType;
 }
 
+@InputType()
+export class ModelApiHeaderInput {
+    @Field()
+    name: string;
+
+    @Field()
+    value: string;
+}
+
 @InputType()
 export class ModelApiInput {
     @Field()
@@ -83,6 +110,9 @@ export class ModelApiInput {
 
     @Field()
     apiType: string;
+
+    @Field(type => [ModelApiHeaderInput], { nullable: true })
+    headers?: ModelApiHeaderInput[];
 }
 
 @InputType()
@@ -110,6 +140,10 @@ export class LocalModelInput {
 
     @Field({ nullable: true })
     revision?: string;
+
+    // Spoken language for transcription models (e.g. "en"), detected if not set
+    @Field({ nullable: true })
+    language?: string;
 }
 
 @InputType()
@@ -139,7 +173,8 @@ export default class AIResolver {
                     baseUrl: "https://api.example.com",
                     apiKey: "test-api-key",
                     model: "gpt4o",
-                    apiType: "OpenAi"
+                    apiType: "OpenAi",
+                    headers: [{ name: "X-Test", value: "test" }]
                 },
                 local: {
                     fileName: "test-model.bin",
@@ -196,7 +231,8 @@ export default class AIResolver {
                 baseUrl: "https://api.example.com", 
                 apiKey: "test-api-key",
                 model: "gpt4o",
-                apiType: "OpenAi"
+                apiType: "OpenAi",
+                headers: []
             },
             local: {
                 fileName: "test-model.bin",
@@ -248,7 +284,8 @@ export default class AIResolver {
             task.promptExamples,
             undefined,
             createdAt,
-            updatedAt
+            updatedAt,
+            task.outputConstraint
         )
     }
 
@@ -281,7 +318,8 @@ export default class AIResolver {
             task.promptExamples,
             undefined,
             createdAt,
-            updatedAt
+            updatedAt,
+            task.outputConstraint
         )
     }
 
@@ -304,6 +342,79 @@ export default class AIResolver {
         return "output"
     }
 
+    @Mutation(() => String)
+    aiPromptStream(
+        @Arg("taskId") taskId: string,
+        @Arg("prompt") input: string
+    ): string {
+        return "promptStreamId"
+    }
+
+    @Mutation(() => Boolean)
+    aiCancelPromptStream(
+        @Arg("streamId") streamId: string,
+        @PubSub() pubSub: any
+    ): boolean {
+        pubSub.publish(AI_PROMPT_TOKENS_TOPIC, { streamId });
+        return true
+    }
+
+    @Subscription({ topics: AI_PROMPT_TOKENS_TOPIC, nullable: false })
+    aiPromptTokens(
+        @Arg("streamId") streamId: string
+    ): AIPromptTokens {
+        return new AIPromptTokens(streamId, "output", true)
+    }
+
+    @Mutation(() => AISession)
+    aiCreateSession(
+        @Arg("session") session: AISessionInput
+    ): AISession {
+        return new AISession(
+            "session_id",
+            session.taskId,
+            session.truncation ?? "KEEP_ALL",
+            createdAt,
+            updatedAt,
+            session.name,
+            session.maxMessages,
+            session.maxTokens,
+            session.perspectiveUuid
+        )
+    }
+
+    @Mutation(() => String)
+    aiSessionPrompt(
+        @Arg("sessionId") sessionId: string,
+        @Arg("prompt") prompt: string
+    ): string {
+        return "output"
+    }
+
+    @Mutation(() => Boolean)
+    aiDeleteSession(
+        @Arg("sessionId") sessionId: string
+    ): boolean {
+        return true
+    }
+
+    @Query(() => [AISession])
+    aiSessions(
+        @Arg("taskId", type => String, { nullable: true }) taskId?: string
+    ): AISession[] {
+        return [new AISession("session_id", taskId ?? "task_id", "LAST_MESSAGES", createdAt, updatedAt, "session", 10)]
+    }
+
+    @Query(() => [AIMessage])
+    aiSessionMessages(
+        @Arg("sessionId") sessionId: string
+    ): AIMessage[] {
+        return [
+            new AIMessage("USER", "input", createdAt),
+            new AIMessage("ASSISTANT", "output", updatedAt)
+        ]
+    }
+
     @Mutation(() => String)
     aiEmbed(
         @Arg("modelId") modelId: string,
@@ -324,6 +435,18 @@ export default class AIResolver {
         return "streamId"
     }
 
+    @Mutation(() => [TranscriptionSegment])
+    aiTranscribeAudio(
+        @Arg("modelId") modelId: string,
+        @Arg("samples", () => [Float], { nullable: true }) samples?: number[],
+        @Arg("wavBase64", { nullable: true }) wavBase64?: string
+    ): TranscriptionSegment[] {
+        return [
+            new TranscriptionSegment("Hello", 0, 0.5, 0.9),
+            new TranscriptionSegment("world", 0.5, 1.2, 0.8)
+        ]
+    }
+
     @Mutation(() => String)
     aiCloseTranscriptionStream(
         @Arg("streamId") streamId: string
@@ -347,4 +470,11 @@ export default class AIResolver {
     ): string {
         return "transcription"
     }
+
+    @Subscription({ topics: AI_TRANSCRIPTION_TEXT_TOPIC, nullable: false })
+    aiTranscriptionSegments(
+        @Arg("streamId") streamId: string
+    ): TranscriptionSegment {
+        return new TranscriptionSegment("transcription", 0, 1.5, 0.9)
+    }
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-import { Field, InputType, ObjectType } from "type-graphql";
+import { Field, Float, InputType, Int, ObjectType } from "type-graphql";
 import { string } from "yargs";
 
 @InputType()
@@ -30,6 +30,29 @@ export class AIPromptExamples {
     }
 }
 
+// Format that responses to a task's prompts have to follow. Local models are
+// constrained while sampling, responses of remote models are validated.
+// Only one of the two can be set.
+@ObjectType()
+export class AIOutputConstraint {
+    // JSON Schema that responses are JSON instances of
+    @Field(type => String, { nullable: true })
+    jsonSchema?: string;
+
+    // Regular expression that whole responses match
+    @Field(type => String, { nullable: true })
+    regex?: string;
+}
+
+@InputType()
+export class AIOutputConstraintInput {
+    @Field(type => String, { nullable: true })
+    jsonSchema?: string;
+
+    @Field(type => String, { nullable: true })
+    regex?: string;
+}
+
 @InputType()
 export class AITaskInput {
     @Field()
@@ -47,12 +70,16 @@ export class AITaskInput {
     @Field(type => String, { nullable: true })
     metaData: string;
 
-    constructor(name: string, model_id: string, system_prompt: string, prompt_examples: AIPromptExamplesInput[], metaData?: string) {
+    @Field(type => AIOutputConstraintInput, { nullable: true })
+    outputConstraint?: AIOutputConstraintInput;
+
+    constructor(name: string, model_id: string, system_prompt: string, prompt_examples: AIPromptExamplesInput[], metaData?: string, outputConstraint?: AIOutputConstraintInput) {
         this.name = name;
         this.modelId = model_id;
         this.systemPrompt = system_prompt;
         this.promptExamples = prompt_examples;
         this.metaData = metaData;
+        this.outputConstraint = outputConstraint;
     }
 }
 
@@ -76,13 +103,16 @@ export class AITask {
     @Field(type => String, { nullable: true })
     metaData?: string;
 
+    @Field(type => AIOutputConstraint, { nullable: true })
+    outputConstraint?: AIOutputConstraint;
+
     @Field()
     createdAt: string;
 
     @Field()
     updatedAt: string;
 
-    constructor(name: string, model_id: string, task_id: string, system_prompt: string, prompt_examples: AIPromptExamples[], metaData?: string, created_at?: string, updated_at?: string) {
+    constructor(name: string, model_id: string, task_id: string, system_prompt: string, prompt_examples: AIPromptExamples[], metaData?: string, created_at?: string, updated_at?: string, outputConstraint?: AIOutputConstraint) {
         this.name = name;
         this.modelId = model_id;
         this.taskId = task_id;
@@ -91,6 +121,7 @@ export class AITask {
         this.metaData = metaData;
         this.createdAt = created_at;
         this.updatedAt = updated_at;
+        this.outputConstraint = outputConstraint;
     }
 }
 
@@ -118,4 +149,145 @@ export class AIModelLoadingStatus {
         this.downloaded = downloaded;
         this.loaded = loaded;
     }
-}
\ No newline at end of file
+}
+
+@ObjectType()
+export class AIPromptTokens {
+    @Field()
+    streamId: string;
+
+    @Field()
+    text: string;
+
+    @Field()
+    done: boolean;
+
+    @Field(type => String, { nullable: true })
+    error?: string;
+
+    constructor(streamId: string, text: string, done: boolean, error?: string) {
+        this.streamId = streamId;
+        this.text = text;
+        this.done = done;
+        this.error = error;
+    }
+}
+
+// Times are in seconds from the start of the audio
+@ObjectType()
+export class TranscriptionSegment {
+    @Field()
+    text: string;
+
+    @Field(type => Float)
+    start: number;
+
+    @Field(type => Float)
+    end: number;
+
+    // Probability that the segment is speech rather than noise
+    @Field(type => Float)
+    confidence: number;
+
+    constructor(text: string, start: number, end: number, confidence: number) {
+        this.text = text;
+        this.start = start;
+        this.end = end;
+        this.confidence = confidence;
+    }
+}
+
+export type AIContextTruncation = "KEEP_ALL" | "LAST_MESSAGES" | "TOKEN_BUDGET";
+
+export type AIMessageRole = "USER" | "ASSISTANT";
+
+@InputType()
+export class AISessionInput {
+    @Field()
+    taskId: string;
+
+    @Field(type => String, { nullable: true })
+    name?: string;
+
+    @Field(type => String, { nullable: true })
+    truncation?: AIContextTruncation;
+
+    @Field(type => Int, { nullable: true })
+    maxMessages?: number;
+
+    @Field(type => Int, { nullable: true })
+    maxTokens?: number;
+
+    /** Perspective to store the transcript of the session in as links */
+    @Field(type => String, { nullable: true })
+    perspectiveUuid?: string;
+
+    constructor(taskId: string, name?: string, truncation?: AIContextTruncation, maxMessages?: number, maxTokens?: number, perspectiveUuid?: string) {
+        this.taskId = taskId;
+        this.name = name;
+        this.truncation = truncation;
+        this.maxMessages = maxMessages;
+        this.maxTokens = maxTokens;
+        this.perspectiveUuid = perspectiveUuid;
+    }
+}
+
+@ObjectType()
+export class AISession {
+    @Field()
+    sessionId: string;
+
+    @Field()
+    taskId: string;
+
+    @Field(type => String, { nullable: true })
+    name?: string;
+
+    @Field(type => String)
+    truncation: AIContextTruncation;
+
+    @Field(type => Int, { nullable: true })
+    maxMessages?: number;
+
+    @Field(type => Int, { nullable: true })
+    maxTokens?: number;
+
+    @Field(type => String, { nullable: true })
+    perspectiveUuid?: string;
+
+    @Field()
+    createdAt: string;
+
+    @Field()
+    updatedAt: string;
+
+    constructor(sessionId: string, taskId: string, truncation: AIContextTruncation, createdAt: string, updatedAt: string, name?: string, maxMessages?: number, maxTokens?: number, perspectiveUuid?: string) {
+        this.sessionId = sessionId;
+        this.taskId = taskId;
+        this.truncation = truncation;
+        this.createdAt = createdAt;
+        this.updatedAt = updatedAt;
+        this.name = name;
+        this.maxMessages = maxMessages;
+        this.maxTokens = maxTokens;
+        this.perspectiveUuid = perspectiveUuid;
+    }
+}
+
+@ObjectType()
+export class AIMessage {
+    @Field(type => String)
+    role: AIMessageRole;
+
+    @Field()
+    content: string;
+
+    @Field()
+    createdAt: string;
+
+    constructor(role: AIMessageRole, content: string, createdAt: string) {
+        this.role = role;
+        this.content = content;
+        this.createdAt = createdAt;
+    }
+}
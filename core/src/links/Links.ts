@@ -70,6 +70,10 @@ export class LinkExpression extends ExpressionGeneric(Link) {
 
     @Field({ nullable: true, defaultValue: 'shared' })
     status?: LinkStatus;
+
+    // Set on results of perspectiveQueryLinks: pass as LinkQuery.after/before to page from this link
+    @Field({ nullable: true })
+    cursor?: string;
 };
 
 @InputType()
@@ -1,5 +1,5 @@
 import { Field, InputType, ObjectType } from "type-graphql";
-import { Link } from "../links/Links"
+import { Link, LinkExpression, LinkExpressionUpdated } from "../links/Links"
 
 @ObjectType()
 @InputType()
@@ -7,6 +7,7 @@ export class LinkQuery {
     @Field({nullable: true})
     source?: string;
 
+    // Matched against the stored target, so it never matches encrypted links
     @Field({nullable: true})
     target?: string;
 
@@ -22,6 +23,18 @@ export class LinkQuery {
     @Field({nullable: true})
     limit?: number;
 
+    // Cursor of a link from a previous query: only links after this position
+    @Field({nullable: true})
+    after?: string;
+
+    // Cursor of a link from a previous query: only links before this position
+    @Field({nullable: true})
+    before?: string;
+
+    // "asc" or "desc" by timestamp
+    @Field({nullable: true})
+    direction?: string;
+
     constructor(obj: object) {
         if(obj) {
             // @ts-ignore
@@ -45,6 +58,12 @@ export class LinkQuery {
                 // @ts-ignore
                 this.limit = obj.limit;
             }
+            // @ts-ignore
+            this.after = obj.after
+            // @ts-ignore
+            this.before = obj.before
+            // @ts-ignore
+            this.direction = obj.direction
         }
     }
 
@@ -63,4 +82,51 @@ export class LinkQuery {
 
         return true
     }
-}
\ No newline at end of file
+}
+
+@ObjectType()
+export class LinkEventSequences {
+    // Sequences of the last link events at the time of the query, a starting point
+    // for the first subscription. The sequenced subscriptions deliver the sequence
+    // of every event to pass as `fromSequence` when resuming after a reconnect.
+    @Field()
+    added: number;
+
+    @Field()
+    removed: number;
+
+    @Field()
+    updated: number;
+}
+
+@ObjectType()
+export class SequencedLinkExpression {
+    @Field()
+    sequence: number;
+
+    @Field(type => LinkExpression)
+    link: LinkExpression;
+}
+
+@ObjectType()
+export class SequencedLinkUpdated {
+    @Field()
+    sequence: number;
+
+    @Field(type => LinkExpressionUpdated)
+    update: LinkExpressionUpdated;
+}
+
+@ObjectType()
+export class LinkSearchResult {
+    @Field(type => LinkExpression)
+    link: LinkExpression;
+
+    // Decoded literal target text that matched
+    @Field()
+    text: string;
+
+    // Higher is better: BM25 for full-text, cosine similarity for semantic search
+    @Field()
+    score: number;
+}
@@ -5,9 +5,9 @@ import { Link, LinkExpressionInput, LinkExpression, LinkInput, LinkMutations, Li
 import { NeighbourhoodClient } from "../neighbourhood/NeighbourhoodClient";
 import { NeighbourhoodProxy } from "../neighbourhood/NeighbourhoodProxy";
 import unwrapApolloResult from "../unwrapApolloResult";
-import { LinkQuery } from "./LinkQuery";
+import { LinkEventSequences, LinkQuery, LinkSearchResult } from "./LinkQuery";
 import { Perspective } from "./Perspective";
-import { PerspectiveHandle, PerspectiveState } from "./PerspectiveHandle";
+import { PerspectiveHandle, PerspectiveHistoryEntry, PerspectiveImportResult, PerspectiveState, PerspectiveSyncStatus, PrologEngineMetrics } from "./PerspectiveHandle";
 import { LinkStatus, PerspectiveProxy } from './PerspectiveProxy';
 import { AIClient } from "../ai/AIClient";
 
@@ -41,9 +41,30 @@ neighbourhood {
 }
 `
 
+const SYNC_STATUS_FIELDS = `
+uuid
+sharedUrl
+pendingDiffs
+committedDiffs
+lastCommitAt
+lastError
+lastErrorAt
+failedDiffs { id, additions, removals, createdAt, attempts, lastAttemptAt, lastError }
+`
+
+export interface PrologQueryOptions {
+    /** Time after which the query fails with a PROLOG_QUERY_TIMEOUT error, 0 for no limit */
+    timeoutMs?: number,
+    /** Maximum number of Prolog inferences, 0 for no limit */
+    inferenceLimit?: number,
+    /** Id that can be passed to cancelPrologQuery() */
+    queryId?: string,
+}
+
 export type PerspectiveHandleCallback = (perspective: PerspectiveHandle) => null
 export type UuidCallback = (uuid: string) => null
 export type LinkCallback = (link: LinkExpression) => null
+export type SequenceCallback = (sequence: number) => void
 export type SyncStateChangeCallback = (state: PerspectiveState) => null
 
 export class PerspectiveClient {
@@ -136,6 +157,7 @@ export class PerspectiveClient {
             query: gql`query perspectiveQueryLinks($uuid: String!, $query: LinkQuery!) {
                 perspectiveQueryLinks(query: $query, uuid: $uuid) {
                     ${LINK_EXPRESSION_FIELDS}
+                    cursor
                 }
             }`,
             variables: { uuid, query }
@@ -143,17 +165,145 @@ export class PerspectiveClient {
         return perspectiveQueryLinks
     }
 
-    async queryProlog(uuid: string, query: string): Promise<any> {
+    async search(uuid: string, text: string, mode?: 'fulltext' | 'semantic', limit?: number): Promise<LinkSearchResult[]> {
+        const { perspectiveSearch } = unwrapApolloResult(await this.#apolloClient.query({
+            query: gql`query perspectiveSearch($uuid: String!, $text: String!, $mode: String, $limit: Int) {
+                perspectiveSearch(uuid: $uuid, text: $text, mode: $mode, limit: $limit) {
+                    link { ${LINK_EXPRESSION_FIELDS} }
+                    text
+                    score
+                }
+            }`,
+            variables: { uuid, text, mode, limit }
+        }))
+        return perspectiveSearch
+    }
+
+    async linkEventSequences(): Promise<LinkEventSequences> {
+        const { perspectiveLinkEventSequences } = unwrapApolloResult(await this.#apolloClient.query({
+            query: gql`query perspectiveLinkEventSequences {
+                perspectiveLinkEventSequences {
+                    added
+                    removed
+                    updated
+                }
+            }`
+        }))
+        return perspectiveLinkEventSequences
+    }
+
+    async queryProlog(uuid: string, query: string, options?: PrologQueryOptions): Promise<any> {
         const { perspectiveQueryProlog } = unwrapApolloResult(await this.#apolloClient.query({
-            query: gql`query perspectiveQueryProlog($uuid: String!, $query: String!) {
-                perspectiveQueryProlog(uuid: $uuid, query: $query)
+            query: gql`query perspectiveQueryProlog($uuid: String!, $query: String!, $timeoutMs: Int, $inferenceLimit: Int, $queryId: String) {
+                perspectiveQueryProlog(uuid: $uuid, query: $query, timeoutMs: $timeoutMs, inferenceLimit: $inferenceLimit, queryId: $queryId)
             }`,
-            variables: { uuid, query }
+            variables: { uuid, query, ...options }
         }))
 
         return JSON.parse(perspectiveQueryProlog)
     }
 
+    async prologEngineMetrics(uuid: string): Promise<PrologEngineMetrics> {
+        const { perspectivePrologEngineMetrics } = unwrapApolloResult(await this.#apolloClient.query({
+            query: gql`query perspectivePrologEngineMetrics($uuid: String!) {
+                perspectivePrologEngineMetrics(uuid: $uuid) {
+                    queueDepths
+                    queriesTotal
+                }
+            }`,
+            variables: { uuid }
+        }))
+        return perspectivePrologEngineMetrics
+    }
+
+    async syncStatus(uuid: string): Promise<PerspectiveSyncStatus> {
+        const { perspectiveSyncStatus } = unwrapApolloResult(await this.#apolloClient.query({
+            query: gql`query perspectiveSyncStatus($uuid: String!) {
+                perspectiveSyncStatus(uuid: $uuid) { ${SYNC_STATUS_FIELDS} }
+            }`,
+            variables: { uuid }
+        }))
+        return perspectiveSyncStatus
+    }
+
+    // Sync status of all perspectives that are shared as neighbourhoods
+    async syncStatuses(): Promise<PerspectiveSyncStatus[]> {
+        const { perspectiveSyncStatuses } = unwrapApolloResult(await this.#apolloClient.query({
+            query: gql`query perspectiveSyncStatuses {
+                perspectiveSyncStatuses { ${SYNC_STATUS_FIELDS} }
+            }`
+        }))
+        return perspectiveSyncStatuses
+    }
+
+    // Commits pending diffs now. Failed commits show up in `lastError` of the returned status.
+    async retrySync(uuid: string): Promise<PerspectiveSyncStatus> {
+        const { perspectiveRetrySync } = unwrapApolloResult(await this.#apolloClient.mutate({
+            mutation: gql`mutation perspectiveRetrySync($uuid: String!) {
+                perspectiveRetrySync(uuid: $uuid) { ${SYNC_STATUS_FIELDS} }
+            }`,
+            variables: { uuid }
+        }))
+        return perspectiveRetrySync
+    }
+
+    // The links of the perspective as they were at the given time
+    async linksAt(uuid: string, timestamp: Date): Promise<LinkExpression[]> {
+        const { perspectiveLinksAt } = unwrapApolloResult(await this.#apolloClient.query({
+            query: gql`query perspectiveLinksAt($uuid: String!, $timestamp: DateTime!) {
+                perspectiveLinksAt(uuid: $uuid, timestamp: $timestamp) {
+                    ${LINK_EXPRESSION_FIELDS}
+                }
+            }`,
+            variables: { uuid, timestamp: timestamp.toISOString() }
+        }))
+        return perspectiveLinksAt
+    }
+
+    // Diffs applied to the perspective, newest first
+    async history(uuid: string, since?: Date, limit?: number): Promise<PerspectiveHistoryEntry[]> {
+        const { perspectiveHistory } = unwrapApolloResult(await this.#apolloClient.query({
+            query: gql`query perspectiveHistory($uuid: String!, $since: DateTime, $limit: Int) {
+                perspectiveHistory(uuid: $uuid, since: $since, limit: $limit) {
+                    id
+                    additions { ${LINK_EXPRESSION_FIELDS} }
+                    removals { ${LINK_EXPRESSION_FIELDS} }
+                    authors
+                    local
+                    appliedAt
+                    undoes
+                    undoneBy
+                }
+            }`,
+            variables: { uuid, since: since?.toISOString(), limit }
+        }))
+        return perspectiveHistory
+    }
+
+    // Reverts a diff this agent made and returns the inverse diff that got applied
+    async undo(uuid: string, diffId: string): Promise<LinkExpressionMutations> {
+        const { perspectiveUndo } = unwrapApolloResult(await this.#apolloClient.mutate({
+            mutation: gql`mutation perspectiveUndo($uuid: String!, $diffId: String!) {
+                perspectiveUndo(uuid: $uuid, diffId: $diffId) {
+                    additions { ${LINK_EXPRESSION_FIELDS} }
+                    removals { ${LINK_EXPRESSION_FIELDS} }
+                }
+            }`,
+            variables: { uuid, diffId }
+        }))
+        return perspectiveUndo
+    }
+
+    async cancelPrologQuery(uuid: string, queryId: string): Promise<boolean> {
+        const { perspectiveCancelPrologQuery } = unwrapApolloResult(await this.#apolloClient.mutate({
+            mutation: gql`mutation perspectiveCancelPrologQuery($uuid: String!, $queryId: String!) {
+                perspectiveCancelPrologQuery(uuid: $uuid, queryId: $queryId)
+            }`,
+            variables: { uuid, queryId }
+        }))
+        return perspectiveCancelPrologQuery
+    }
+
     async add(name: string): Promise<PerspectiveProxy> {
         const { perspectiveAdd } = unwrapApolloResult(await this.#apolloClient.mutate({
             mutation: gql`mutation perspectiveAdd($name: String!) {
@@ -187,6 +337,31 @@ export class PerspectiveClient {
         }))
     }
 
+    async export(uuid: string): Promise<string> {
+        const { perspectiveExport } = unwrapApolloResult(await this.#apolloClient.mutate({
+            mutation: gql`mutation perspectiveExport($uuid: String!) {
+                perspectiveExport(uuid: $uuid)
+            }`,
+            variables: { uuid }
+        }))
+        return perspectiveExport
+    }
+
+    async import(archive: string, allowInvalidSignature?: boolean): Promise<PerspectiveImportResult> {
+        const { perspectiveImport } = unwrapApolloResult(await this.#apolloClient.mutate({
+            mutation: gql`mutation perspectiveImport($archive: String!, $allowInvalidSignature: Boolean) {
+                perspectiveImport(archive: $archive, allowInvalidSignature: $allowInvalidSignature) {
+                    perspective { ${PERSPECTIVE_HANDLE_FIELDS} }
+                    importedLinks
+                    invalidLinks { ${LINK_EXPRESSION_FIELDS} }
+                    archiveSignatureValid
+                }
+            }`,
+            variables: { archive, allowInvalidSignature }
+        }))
+        return perspectiveImport
+    }
+
     async addLink(uuid: string, link: Link, status?: LinkStatus): Promise<LinkExpression> {
         const { perspectiveAddLink } = unwrapApolloResult(await this.#apolloClient.mutate({
             mutation: gql`mutation perspectiveAddLink($uuid: String!, $link: LinkInput!, $status: String){
@@ -199,6 +374,19 @@ export class PerspectiveClient {
         return perspectiveAddLink
     }
 
+    /** Adds a shared link whose target only the given recipient agents (and we) can read */
+    async addEncryptedLink(uuid: string, link: Link, recipients: string[]): Promise<LinkExpression> {
+        const { perspectiveAddEncryptedLink } = unwrapApolloResult(await this.#apolloClient.mutate({
+            mutation: gql`mutation perspectiveAddEncryptedLink($uuid: String!, $link: LinkInput!, $recipients: [String!]!){
+                perspectiveAddEncryptedLink(link: $link, uuid: $uuid, recipients: $recipients) {
+                    ${LINK_EXPRESSION_FIELDS}
+                }
+            }`,
+            variables: { uuid, link, recipients }
+        }))
+        return perspectiveAddEncryptedLink
+    }
+
     async addLinks(uuid: string, links: Link[], status?: LinkStatus): Promise<LinkExpression[]> {
         const { perspectiveAddLinks } = unwrapApolloResult(await this.#apolloClient.mutate({
             mutation: gql`mutation perspectiveAddLinks($uuid: String!, $links: [LinkInput!]!, $status: String){
@@ -417,15 +605,26 @@ export class PerspectiveClient {
         })
     }
 
-    async addPerspectiveLinkAddedListener(uuid: String, cb: LinkCallback[]): Promise<void> {
+    // Pass `sequenceCb` to receive the sequence of every event, which can be given as
+    // `fromSequence` when adding the listener again after a reconnect.
+    async addPerspectiveLinkAddedListener(uuid: String, cb: LinkCallback[], fromSequence?: number, sequenceCb?: SequenceCallback): Promise<void> {
+        const args = `uuid: "${uuid}"${fromSequence !== undefined ? `, fromSequence: ${fromSequence}` : ''}`
         this.#apolloClient.subscribe({
-            query: gql` subscription {
-                perspectiveLinkAdded(uuid: "${uuid}") { ${LINK_EXPRESSION_FIELDS} }
+            query: sequenceCb ? gql` subscription {
+                perspectiveLinkAddedSequenced(${args}) { sequence link { ${LINK_EXPRESSION_FIELDS} } }
+            }
+        ` : gql` subscription {
+                perspectiveLinkAdded(${args}) { ${LINK_EXPRESSION_FIELDS} }
             }
         `}).subscribe({
             next: result => {
+                let link = result.data.perspectiveLinkAdded
+                if (sequenceCb) {
+                    link = result.data.perspectiveLinkAddedSequenced.link
+                    sequenceCb(result.data.perspectiveLinkAddedSequenced.sequence)
+                }
                 cb.forEach(c => {
-                    c(result.data.perspectiveLinkAdded)
+                    c(link)
                 })
             },
             error: (e) => console.error(e)
@@ -434,18 +633,27 @@ export class PerspectiveClient {
         await new Promise<void>(resolve => setTimeout(resolve, 500))
     }
 
-    async addPerspectiveLinkRemovedListener(uuid: String, cb: LinkCallback[]): Promise<void> {
+    async addPerspectiveLinkRemovedListener(uuid: String, cb: LinkCallback[], fromSequence?: number, sequenceCb?: SequenceCallback): Promise<void> {
+        const args = `uuid: "${uuid}"${fromSequence !== undefined ? `, fromSequence: ${fromSequence}` : ''}`
         this.#apolloClient.subscribe({
-            query: gql` subscription {
-                perspectiveLinkRemoved(uuid: "${uuid}") { ${LINK_EXPRESSION_FIELDS} }
+            query: sequenceCb ? gql` subscription {
+                perspectiveLinkRemovedSequenced(${args}) { sequence link { ${LINK_EXPRESSION_FIELDS} } }
+            }
+        ` : gql` subscription {
+                perspectiveLinkRemoved(${args}) { ${LINK_EXPRESSION_FIELDS} }
             }
         `}).subscribe({
             next: result => {
+                let link = result.data.perspectiveLinkRemoved
+                if (sequenceCb) {
+                    link = result.data.perspectiveLinkRemovedSequenced.link
+                    sequenceCb(result.data.perspectiveLinkRemovedSequenced.sequence)
+                }
                 cb.forEach(c => {
-                    if (!result.data.perspectiveLinkRemoved.status) {
-                        delete result.data.perspectiveLinkRemoved.status
+                    if (!link.status) {
+                        delete link.status
                     }
-                    c(result.data.perspectiveLinkRemoved)
+                    c(link)
                 })
             },
             error: (e) => console.error(e)
@@ -454,28 +662,37 @@ export class PerspectiveClient {
         await new Promise<void>(resolve => setTimeout(resolve, 500))
     }
 
-    async addPerspectiveLinkUpdatedListener(uuid: String, cb: LinkCallback[]): Promise<void> {
-        this.#apolloClient.subscribe({
-            query: gql` subscription {
-                perspectiveLinkUpdated(uuid: "${uuid}") {
+    async addPerspectiveLinkUpdatedListener(uuid: String, cb: LinkCallback[], fromSequence?: number, sequenceCb?: SequenceCallback): Promise<void> {
+        const args = `uuid: "${uuid}"${fromSequence !== undefined ? `, fromSequence: ${fromSequence}` : ''}`
+        const fields = `
                     oldLink {
                         ${LINK_EXPRESSION_FIELDS}
                     }
                     newLink {
                         ${LINK_EXPRESSION_FIELDS}
-                    }
-                }
+                    }`
+        this.#apolloClient.subscribe({
+            query: sequenceCb ? gql` subscription {
+                perspectiveLinkUpdatedSequenced(${args}) { sequence update { ${fields} } }
+            }
+        ` : gql` subscription {
+                perspectiveLinkUpdated(${args}) { ${fields} }
             }
         `}).subscribe({
             next: result => {
+                let update = result.data.perspectiveLinkUpdated
+                if (sequenceCb) {
+                    update = result.data.perspectiveLinkUpdatedSequenced.update
+                    sequenceCb(result.data.perspectiveLinkUpdatedSequenced.sequence)
+                }
                 cb.forEach(c => {
-                    if (!result.data.perspectiveLinkUpdated.newLink.status) {
-                        delete result.data.perspectiveLinkUpdated.newLink.status
+                    if (!update.newLink.status) {
+                        delete update.newLink.status
                     }
-                    if (!result.data.perspectiveLinkUpdated.oldLink.status) {
-                        delete result.data.perspectiveLinkUpdated.oldLink.status
+                    if (!update.oldLink.status) {
+                        delete update.oldLink.status
                     }
-                    c(result.data.perspectiveLinkUpdated)
+                    c(update)
                 })
             },
             error: (e) => console.error(e)
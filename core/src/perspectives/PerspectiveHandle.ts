@@ -1,4 +1,5 @@
-import { Field, ObjectType } from "type-graphql";
+import { Field, Int, ObjectType } from "type-graphql";
+import { LinkExpression } from "../links/Links";
 import { NeighbourhoodExpression } from "../neighbourhood/Neighbourhood";
 
 export enum PerspectiveState {
@@ -37,3 +38,111 @@ export class PerspectiveHandle {
         }
     }
 }
+
+@ObjectType()
+export class PerspectiveImportResult {
+    @Field(type => PerspectiveHandle)
+    perspective: PerspectiveHandle
+
+    @Field()
+    importedLinks: number
+
+    // Links that were not imported because their signature did not verify
+    @Field(type => [LinkExpression])
+    invalidLinks: LinkExpression[]
+
+    @Field()
+    archiveSignatureValid: boolean
+}
+
+@ObjectType()
+export class PendingDiff {
+    @Field()
+    id: string
+
+    @Field(type => Int)
+    additions: number
+
+    @Field(type => Int)
+    removals: number
+
+    @Field({nullable: true})
+    createdAt?: string
+
+    // Failed commit attempts, diffs waiting for the link language to sync have none
+    @Field(type => Int)
+    attempts: number
+
+    @Field({nullable: true})
+    lastAttemptAt?: string
+
+    @Field({nullable: true})
+    lastError?: string
+}
+
+@ObjectType()
+export class PerspectiveSyncStatus {
+    @Field()
+    uuid: string
+
+    @Field({nullable: true})
+    sharedUrl?: string
+
+    @Field(type => Int)
+    pendingDiffs: number
+
+    @Field(type => Int)
+    committedDiffs: number
+
+    @Field({nullable: true})
+    lastCommitAt?: string
+
+    @Field({nullable: true})
+    lastError?: string
+
+    @Field({nullable: true})
+    lastErrorAt?: string
+
+    @Field(type => [PendingDiff])
+    failedDiffs: PendingDiff[]
+}
+
+@ObjectType()
+export class PerspectiveHistoryEntry {
+    @Field()
+    id: string
+
+    @Field(type => [LinkExpression])
+    additions: LinkExpression[]
+
+    @Field(type => [LinkExpression])
+    removals: LinkExpression[]
+
+    // Authors of the added and removed links
+    @Field(type => [String])
+    authors: string[]
+
+    // Made by this agent, as opposed to received from the neighbourhood
+    @Field()
+    local: boolean
+
+    @Field()
+    appliedAt: string
+
+    // Id of the diff this one undid
+    @Field({nullable: true})
+    undoes?: string
+
+    @Field({nullable: true})
+    undoneBy?: string
+}
+
+@ObjectType()
+export class PrologEngineMetrics {
+    // Number of queries waiting for or running on each engine of the perspective
+    @Field(type => [Int])
+    queueDepths: number[]
+
+    @Field()
+    queriesTotal: number
+}
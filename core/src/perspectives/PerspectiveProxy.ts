@@ -1,7 +1,7 @@
 import { LinkCallback, PerspectiveClient, SyncStateChangeCallback } from "./PerspectiveClient";
 import { Link, LinkExpression, LinkExpressionInput, LinkExpressionMutations, LinkMutations } from "../links/Links";
 import { LinkQuery } from "./LinkQuery";
-import { PerspectiveHandle, PerspectiveState } from './PerspectiveHandle'
+import { PerspectiveHandle, PerspectiveHistoryEntry, PerspectiveState } from './PerspectiveHandle'
 import { Perspective } from "./Perspective";
 import { Literal } from "../Literal";
 import { Subject } from "../subject/Subject";
@@ -75,6 +75,21 @@ export class PerspectiveProxy {
         return await this.#client.queryLinks(this.#handle.uuid, query)
     }
 
+    /** Returns the links of this perspective as they were at the given time */
+    async linksAt(timestamp: Date): Promise<LinkExpression[]> {
+        return await this.#client.linksAt(this.#handle.uuid, timestamp)
+    }
+
+    /** Returns the diffs applied to this perspective, newest first */
+    async history(since?: Date, limit?: number): Promise<PerspectiveHistoryEntry[]> {
+        return await this.#client.history(this.#handle.uuid, since, limit)
+    }
+
+    /** Reverts a diff from `history()` that this agent made */
+    async undo(diffId: string): Promise<LinkExpressionMutations> {
+        return await this.#client.undo(this.#handle.uuid, diffId)
+    }
+
     /** Runs a Prolog query on the perspective's Prolog engine */
     async infer(query: string): Promise<any> {
         return await this.#client.queryProlog(this.#handle.uuid, query)
@@ -85,6 +100,11 @@ export class PerspectiveProxy {
         return await this.#client.addLink(this.#handle.uuid, link, status)
     }
 
+    /** Adds a shared link whose target only the given recipient agents can read */
+    async addEncrypted(link: Link, recipients: string[]): Promise<LinkExpression> {
+        return await this.#client.addEncryptedLink(this.#handle.uuid, link, recipients)
+    }
+
     /** Adds multiple links to this perspective **/
     async addLinks(links: Link[], status: LinkStatus = 'shared'): Promise<LinkExpression[]> {
         return await this.#client.addLinks(this.#handle.uuid, links, status)
@@ -1,10 +1,10 @@
-import { Arg, Mutation, PubSub, Query, Resolver, Subscription } from "type-graphql";
+import { Arg, Int, Mutation, PubSub, Query, Resolver, Subscription } from "type-graphql";
 import { LinkExpression, LinkExpressionInput, LinkExpressionMutations, LinkExpressionUpdated, LinkInput, LinkMutations } from "../links/Links";
 import { Neighbourhood, NeighbourhoodExpression } from "../neighbourhood/Neighbourhood";
-import { LinkQuery } from "./LinkQuery";
+import { LinkEventSequences, LinkQuery, LinkSearchResult, SequencedLinkExpression, SequencedLinkUpdated } from "./LinkQuery";
 import { Perspective } from "./Perspective";
 import { LinkStatus } from "./PerspectiveProxy";
-import { PerspectiveHandle, PerspectiveState } from "./PerspectiveHandle";
+import { PendingDiff, PerspectiveHandle, PerspectiveHistoryEntry, PerspectiveImportResult, PerspectiveState, PerspectiveSyncStatus, PrologEngineMetrics } from "./PerspectiveHandle";
 import { LINK_ADDED_TOPIC, LINK_REMOVED_TOPIC, LINK_UDATED_TOPIC, PERSPECTIVE_ADDED_TOPIC, PERSPECTIVE_REMOVED_TOPIC, PERSPECTIVE_UPDATED_TOPIC, PERSPECTIVE_SYNC_STATE_CHANGE } from '../PubSub'
 
 export const testLink = new LinkExpression()
@@ -20,6 +20,26 @@ testLink.proof = {
     valid: true
 }
 
+function testSyncStatus(uuid: string): PerspectiveSyncStatus {
+    const failed = new PendingDiff()
+    failed.id = '7'
+    failed.additions = 2
+    failed.removals = 0
+    failed.attempts = 3
+    failed.lastError = 'Link language not reachable'
+
+    const status = new PerspectiveSyncStatus()
+    status.uuid = uuid
+    status.sharedUrl = 'neighbourhood://Qm12345'
+    status.pendingDiffs = 2
+    status.committedDiffs = 40
+    status.lastCommitAt = '2024-01-01T00:00:00.000Z'
+    status.lastError = failed.lastError
+    status.lastErrorAt = '2024-01-01T00:01:00.000Z'
+    status.failedDiffs = [failed]
+    return status
+}
+
 /**
  * Resolver classes are used here to define the GraphQL schema
  * (through the type-graphql annotations)
@@ -68,11 +88,90 @@ export default class PerspectiveResolver {
 
     @Query(returns => [LinkExpression], {nullable: true})
     perspectiveQueryLinks(@Arg('uuid') uuid: string, @Arg('query') query: LinkQuery): LinkExpression[] {
+        const link = Object.assign(new LinkExpression(), testLink)
+        link.cursor = 'cursor-1'
+        return [link]
+    }
+
+    @Query(returns => [LinkSearchResult])
+    perspectiveSearch(
+        @Arg('uuid') uuid: string,
+        @Arg('text') text: string,
+        @Arg('mode', {nullable: true}) mode?: string,
+        @Arg('limit', type => Int, {nullable: true}) limit?: number
+    ): LinkSearchResult[] {
+        const result = new LinkSearchResult()
+        result.link = testLink
+        result.text = text
+        result.score = 1
+        return [result]
+    }
+
+    @Query(returns => LinkEventSequences)
+    perspectiveLinkEventSequences(): LinkEventSequences {
+        const sequences = new LinkEventSequences()
+        sequences.added = 1
+        sequences.removed = 2
+        sequences.updated = 3
+        return sequences
+    }
+
+    @Query(returns => PrologEngineMetrics)
+    perspectivePrologEngineMetrics(@Arg('uuid') uuid: string): PrologEngineMetrics {
+        const metrics = new PrologEngineMetrics()
+        metrics.queueDepths = [0, 1]
+        metrics.queriesTotal = 42
+        return metrics
+    }
+
+    @Query(returns => PerspectiveSyncStatus)
+    perspectiveSyncStatus(@Arg('uuid') uuid: string): PerspectiveSyncStatus {
+        return testSyncStatus(uuid)
+    }
+
+    @Query(returns => [PerspectiveSyncStatus])
+    perspectiveSyncStatuses(): PerspectiveSyncStatus[] {
+        return [testSyncStatus('00001')]
+    }
+
+    @Query(returns => [LinkExpression])
+    perspectiveLinksAt(@Arg('uuid') uuid: string, @Arg('timestamp') timestamp: Date): LinkExpression[] {
         return [testLink]
     }
 
+    @Query(returns => [PerspectiveHistoryEntry])
+    perspectiveHistory(@Arg('uuid') uuid: string, @Arg('since', {nullable: true}) since?: Date, @Arg('limit', type => Int, {nullable: true}) limit?: number): PerspectiveHistoryEntry[] {
+        const entry = new PerspectiveHistoryEntry()
+        entry.id = '2'
+        entry.additions = [testLink]
+        entry.removals = []
+        entry.authors = [testLink.author]
+        entry.local = true
+        entry.appliedAt = new Date().toISOString()
+        return [entry]
+    }
+
+    @Mutation(returns => LinkExpressionMutations)
+    perspectiveUndo(@Arg('uuid') uuid: string, @Arg('diffId') diffId: string): LinkExpressionMutations {
+        return new LinkExpressionMutations([], [testLink])
+    }
+
+    @Mutation(returns => PerspectiveSyncStatus)
+    perspectiveRetrySync(@Arg('uuid') uuid: string): PerspectiveSyncStatus {
+        const status = testSyncStatus(uuid)
+        status.pendingDiffs = 0
+        status.failedDiffs = []
+        return status
+    }
+
     @Query(returns => String)
-    perspectiveQueryProlog(@Arg('uuid') uuid: string, @Arg('query') query: String): string {
+    perspectiveQueryProlog(
+        @Arg('uuid') uuid: string,
+        @Arg('query') query: String,
+        @Arg('timeoutMs', type => Int, {nullable: true}) timeoutMs?: number,
+        @Arg('inferenceLimit', type => Int, {nullable: true}) inferenceLimit?: number,
+        @Arg('queryId', {nullable: true}) queryId?: string
+    ): string {
         return `[{"X": 1}]`
     }
 
@@ -97,6 +196,26 @@ export default class PerspectiveResolver {
         return true
     }
 
+    @Mutation(returns => Boolean)
+    perspectiveCancelPrologQuery(@Arg('uuid') uuid: string, @Arg('queryId') queryId: string): boolean {
+        return true
+    }
+
+    @Mutation(returns => String)
+    perspectiveExport(@Arg('uuid') uuid: string): string {
+        return JSON.stringify({ handle: new PerspectiveHandle(uuid, 'exported'), links: [testLink] })
+    }
+
+    @Mutation(returns => PerspectiveImportResult)
+    perspectiveImport(@Arg('archive') archive: string, @Arg('allowInvalidSignature', { nullable: true }) allowInvalidSignature?: boolean): PerspectiveImportResult {
+        const result = new PerspectiveImportResult()
+        result.perspective = new PerspectiveHandle('00007', 'imported')
+        result.importedLinks = 1
+        result.invalidLinks = []
+        result.archiveSignatureValid = true
+        return result
+    }
+
     @Mutation(returns => LinkExpression)
     perspectiveAddLink(@Arg('uuid') uuid: string, @Arg('link') link: LinkInput, @Arg('status', { nullable: true, defaultValue: 'shared'}) status: LinkStatus, @PubSub() pubSub: any): LinkExpression {
         const l = new LinkExpression()
@@ -111,6 +230,19 @@ export default class PerspectiveResolver {
         return l
     }
 
+    @Mutation(returns => LinkExpression)
+    perspectiveAddEncryptedLink(@Arg('uuid') uuid: string, @Arg('link') link: LinkInput, @Arg('recipients', type => [String]) recipients: string[], @PubSub() pubSub: any): LinkExpression {
+        const l = new LinkExpression()
+        l.author = 'did:ad4m:test'
+        l.timestamp = Date.now()
+        l.proof = testLink.proof
+        l.data = link
+        l.status = 'shared'
+
+        pubSub.publish(LINK_ADDED_TOPIC, { link: l })
+        return l
+    }
+
     @Mutation(returns => [LinkExpression])
     perspectiveAddLinks(@Arg('uuid') uuid: string, @Arg('links', type => [LinkInput]) links: LinkInput[], @Arg('status', { nullable: true}) status: string, @PubSub() pubSub: any): LinkExpression[] {
         const l = new LinkExpression()
@@ -246,6 +378,21 @@ export default class PerspectiveResolver {
         return {oldLink: testLink, newLink: testLink}
     }
 
+    @Subscription({topics: LINK_ADDED_TOPIC, nullable: true})
+    perspectiveLinkAddedSequenced(@Arg('uuid') uuid: string, @Arg('fromSequence', {nullable: true}) fromSequence?: number): SequencedLinkExpression {
+        return {sequence: 1, link: testLink}
+    }
+
+    @Subscription({topics: LINK_REMOVED_TOPIC, nullable: true})
+    perspectiveLinkRemovedSequenced(@Arg('uuid') uuid: string, @Arg('fromSequence', {nullable: true}) fromSequence?: number): SequencedLinkExpression {
+        return {sequence: 2, link: testLink}
+    }
+
+    @Subscription({topics: LINK_UDATED_TOPIC, nullable: true})
+    perspectiveLinkUpdatedSequenced(@Arg('uuid') uuid: string, @Arg('fromSequence', {nullable: true}) fromSequence?: number): SequencedLinkUpdated {
+        return {sequence: 3, update: {oldLink: testLink, newLink: testLink}}
+    }
+
     @Subscription({topics: PERSPECTIVE_SYNC_STATE_CHANGE, nullable: false})
     perspectiveSyncStateChange(@Arg('uuid') uuid: string): PerspectiveState {
         return PerspectiveState.Synced
@@ -1,7 +1,7 @@
 import { ApolloClient, gql } from "@apollo/client/core"
 import { Perspective, PerspectiveExpression } from "../perspectives/Perspective"
 import unwrapApolloResult from "../unwrapApolloResult"
-import { RuntimeInfo, ExceptionInfo, SentMessage, NotificationInput, Notification, TriggeredNotification } from "./RuntimeResolver"
+import { RuntimeInfo, ExceptionInfo, SentMessage, NotificationInput, Notification, TriggeredNotification, NotificationDelivery } from "./RuntimeResolver"
 
 const PERSPECTIVE_EXPRESSION_FIELDS = `
 author
@@ -296,6 +296,39 @@ export class RuntimeClient {
         return runtimeNotifications
     }
 
+    async notificationDeliveries(notificationId: string, limit?: number): Promise<NotificationDelivery[]> {
+        const { runtimeNotificationDeliveries } = unwrapApolloResult(await this.#apolloClient.query({
+            query: gql`query runtimeNotificationDeliveries($notificationId: String!, $limit: Int) {
+                runtimeNotificationDeliveries(notificationId: $notificationId, limit: $limit) {
+                    id
+                    notificationId
+                    webhookUrl
+                    payload
+                    status
+                    attempts
+                    createdAt
+                    nextAttemptAt
+                    lastAttemptAt
+                    lastResponseStatus
+                    lastError
+                    deliveredAt
+                }
+            }`,
+            variables: { notificationId, limit }
+        }))
+        return runtimeNotificationDeliveries
+    }
+
+    async notificationWebhookSecret(notificationId: string): Promise<string> {
+        const { runtimeNotificationWebhookSecret } = unwrapApolloResult(await this.#apolloClient.query({
+            query: gql`query runtimeNotificationWebhookSecret($notificationId: String!) {
+                runtimeNotificationWebhookSecret(notificationId: $notificationId)
+            }`,
+            variables: { notificationId }
+        }))
+        return runtimeNotificationWebhookSecret
+    }
+
     async updateNotification(id: string, notification: NotificationInput): Promise<boolean> {
         const { runtimeUpdateNotification } = unwrapApolloResult(await this.#apolloClient.mutate({
             mutation: gql`mutation runtimeUpdateNotification($id: String!, $notification: NotificationInput!) {
@@ -138,6 +138,36 @@ export class TriggeredNotification {
     triggerMatch: string;
 }
 
+@ObjectType()
+export class NotificationDelivery {
+    @Field()
+    id: string;
+    @Field()
+    notificationId: string;
+    @Field()
+    webhookUrl: string;
+    // JSON encoded TriggeredNotification that gets posted to the webhook
+    @Field()
+    payload: string;
+    // "PENDING", "DELIVERED" or "FAILED"
+    @Field()
+    status: string;
+    @Field(type => Int)
+    attempts: number;
+    @Field()
+    createdAt: string;
+    @Field({ nullable: true })
+    nextAttemptAt?: string;
+    @Field({ nullable: true })
+    lastAttemptAt?: string;
+    @Field(type => Int, { nullable: true })
+    lastResponseStatus?: number;
+    @Field({ nullable: true })
+    lastError?: string;
+    @Field({ nullable: true })
+    deliveredAt?: string;
+}
+
 /**
  * Resolver classes are used here to define the GraphQL schema 
  * (through the type-graphql annotations)
@@ -299,6 +329,30 @@ export default class RuntimeResolver {
         }]
     }
 
+    @Query(returns => [NotificationDelivery])
+    runtimeNotificationDeliveries(
+        @Arg("notificationId", type => String) notificationId: string,
+        @Arg("limit", type => Int, { nullable: true }) limit?: number
+    ): NotificationDelivery[] {
+        return [{
+            id: "1",
+            notificationId,
+            webhookUrl: "https://example.com/webhook",
+            payload: "{}",
+            status: "DELIVERED",
+            attempts: 2,
+            createdAt: "2024-01-01T00:00:00.000Z",
+            lastAttemptAt: "2024-01-01T00:00:05.000Z",
+            lastResponseStatus: 200,
+            deliveredAt: "2024-01-01T00:00:05.000Z",
+        }]
+    }
+
+    @Query(returns => String)
+    runtimeNotificationWebhookSecret(@Arg("notificationId", type => String) notificationId: string): string {
+        return "webhook-secret"
+    }
+
     @Mutation()
     runtimeUpdateNotification(
         @Arg("id", type => String) id: string, 
@@ -0,0 +1,126 @@
+use std::fmt;
+
+/// Code an executor error carries in the GraphQL `extensions.code`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    CapabilityDenied,
+    PerspectiveNotFound,
+    LinkLanguageMissing,
+    PrologError,
+    PrologQueryTimeout,
+    AgentNotInitialized,
+    AgentLocked,
+    AiModelNotLoaded,
+    NotFound,
+    InvalidInput,
+    Internal,
+    /// A code this client doesn't know (yet)
+    Unknown(String),
+}
+
+impl ErrorCode {
+    pub fn from_code(code: &str) -> Self {
+        match code {
+            "CAPABILITY_DENIED" => ErrorCode::CapabilityDenied,
+            "PERSPECTIVE_NOT_FOUND" => ErrorCode::PerspectiveNotFound,
+            "LINK_LANGUAGE_MISSING" => ErrorCode::LinkLanguageMissing,
+            "PROLOG_ERROR" => ErrorCode::PrologError,
+            "PROLOG_QUERY_TIMEOUT" => ErrorCode::PrologQueryTimeout,
+            "AGENT_NOT_INITIALIZED" => ErrorCode::AgentNotInitialized,
+            "AGENT_LOCKED" => ErrorCode::AgentLocked,
+            "AI_MODEL_NOT_LOADED" => ErrorCode::AiModelNotLoaded,
+            "NOT_FOUND" => ErrorCode::NotFound,
+            "INVALID_INPUT" => ErrorCode::InvalidInput,
+            "INTERNAL_ERROR" => ErrorCode::Internal,
+            other => ErrorCode::Unknown(other.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            ErrorCode::CapabilityDenied => "CAPABILITY_DENIED",
+            ErrorCode::PerspectiveNotFound => "PERSPECTIVE_NOT_FOUND",
+            ErrorCode::LinkLanguageMissing => "LINK_LANGUAGE_MISSING",
+            ErrorCode::PrologError => "PROLOG_ERROR",
+            ErrorCode::PrologQueryTimeout => "PROLOG_QUERY_TIMEOUT",
+            ErrorCode::AgentNotInitialized => "AGENT_NOT_INITIALIZED",
+            ErrorCode::AgentLocked => "AGENT_LOCKED",
+            ErrorCode::AiModelNotLoaded => "AI_MODEL_NOT_LOADED",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::InvalidInput => "INVALID_INPUT",
+            ErrorCode::Internal => "INTERNAL_ERROR",
+            ErrorCode::Unknown(code) => code,
+        }
+    }
+}
+
+/// Error the executor reported for a request. Errors without a code
+/// (e.g. from older executors) are `ErrorCode::Unknown("")`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExecutorError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ExecutorError {
+    pub fn from_graphql_error(error: &graphql_client::Error) -> Self {
+        let code = error
+            .extensions
+            .as_ref()
+            .and_then(|extensions| extensions.get("code"))
+            .and_then(|code| code.as_str())
+            .unwrap_or_default();
+        Self {
+            code: ErrorCode::from_code(code),
+            message: error.message.clone(),
+        }
+    }
+
+    /// The first of the errors of a response without data
+    pub fn from_graphql_errors(errors: Option<&Vec<graphql_client::Error>>) -> Self {
+        match errors.and_then(|errors| errors.first()) {
+            Some(error) => Self::from_graphql_error(error),
+            None => Self {
+                code: ErrorCode::Unknown(String::new()),
+                message: "No data in response".to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ExecutorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ExecutorError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_code_from_extensions() {
+        let error: graphql_client::Error = serde_json::from_value(json!({
+            "message": "No perspective found with uuid 1234",
+            "extensions": { "code": "PERSPECTIVE_NOT_FOUND", "uuid": "1234" }
+        }))
+        .unwrap();
+        let error = ExecutorError::from_graphql_error(&error);
+        assert_eq!(error.code, ErrorCode::PerspectiveNotFound);
+        assert_eq!(error.to_string(), "No perspective found with uuid 1234");
+
+        let error: graphql_client::Error =
+            serde_json::from_value(json!({ "message": "Something else" })).unwrap();
+        assert_eq!(
+            ExecutorError::from_graphql_error(&error).code,
+            ErrorCode::Unknown(String::new())
+        );
+        assert_eq!(
+            ErrorCode::from_code("AGENT_LOCKED").as_str(),
+            "AGENT_LOCKED"
+        );
+    }
+}
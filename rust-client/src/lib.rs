@@ -21,6 +21,7 @@ extern crate rustyline;
 extern crate tokio;
 
 pub mod agent;
+pub mod errors;
 pub mod expressions;
 pub mod languages;
 pub mod literal;
@@ -4,7 +4,7 @@ use crate::{
     literal::{Literal, LiteralValue},
     perspectives::{
         add_link::AddLinkPerspectiveAddLink, query_links::QueryLinksPerspectiveQueryLinks,
-        PerspectivesClient,
+        LinkPaging, PerspectivesClient,
     },
     subject_proxy::SubjectProxy,
     types::LinkExpression,
@@ -64,6 +64,7 @@ impl PerspectiveProxy {
                 from_date,
                 until_date,
                 limit,
+                LinkPaging::default(),
             )
             .await
     }
@@ -142,6 +143,7 @@ impl PerspectiveProxy {
                 None,
                 None,
                 None,
+                LinkPaging::default(),
             )
             .await?;
         if links.is_empty() {
@@ -169,6 +171,7 @@ impl PerspectiveProxy {
                 None,
                 None,
                 None,
+                LinkPaging::default(),
             )
             .await?
             .into_iter()
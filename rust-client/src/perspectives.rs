@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use crate::errors::{ErrorCode, ExecutorError};
 use crate::perspective_proxy::PerspectiveProxy;
 use crate::types::{LinkExpression, Perspective};
 use crate::util::{create_websocket_client, query, query_raw};
@@ -74,6 +75,52 @@ pub async fn remove(executor_url: String, cap_token: String, uuid: String) -> Re
     }
 }
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "schema.gql",
+    query_path = "src/perspectives.gql",
+    response_derives = "Debug"
+)]
+pub struct Export;
+
+pub async fn export(executor_url: String, cap_token: String, uuid: String) -> Result<String> {
+    let response_data: export::ResponseData = query(
+        executor_url,
+        cap_token,
+        Export::build_query(export::Variables { uuid }),
+    )
+    .await
+    .with_context(|| "Failed to run perspectives->export query")?;
+    Ok(response_data.perspective_export)
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "schema.gql",
+    query_path = "src/perspectives.gql",
+    response_derives = "Debug"
+)]
+pub struct Import;
+
+pub async fn import(
+    executor_url: String,
+    cap_token: String,
+    archive: String,
+    allow_invalid_signature: Option<bool>,
+) -> Result<import::ImportPerspectiveImport> {
+    let response_data: import::ResponseData = query(
+        executor_url,
+        cap_token,
+        Import::build_query(import::Variables {
+            archive,
+            allow_invalid_signature,
+        }),
+    )
+    .await
+    .with_context(|| "Failed to run perspectives->import query")?;
+    Ok(response_data.perspective_import)
+}
+
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "schema.gql",
@@ -176,6 +223,7 @@ pub async fn query_links(
     from_date: Option<DateTime>,
     until_date: Option<DateTime>,
     limit: Option<f64>,
+    paging: LinkPaging,
 ) -> Result<Vec<query_links::QueryLinksPerspectiveQueryLinks>> {
     let response_data: query_links::ResponseData = query(
         executor_url,
@@ -189,6 +237,9 @@ pub async fn query_links(
                 from_date,
                 until_date,
                 limit,
+                after: paging.after,
+                before: paging.before,
+                direction: paging.direction,
             },
         }),
     )
@@ -198,6 +249,48 @@ pub async fn query_links(
     Ok(response_data.perspective_query_links.unwrap_or_default())
 }
 
+/// Cursor based paging on top of the `query_links` filters.
+/// `after` and `before` take the `cursor` of a link returned by a previous query,
+/// `direction` is either "asc" or "desc".
+#[derive(Debug, Default, Clone)]
+pub struct LinkPaging {
+    pub after: Option<String>,
+    pub before: Option<String>,
+    pub direction: Option<String>,
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "schema.gql",
+    query_path = "src/perspectives.gql",
+    response_derives = "Debug"
+)]
+pub struct Search;
+
+pub async fn search(
+    executor_url: String,
+    cap_token: String,
+    uuid: String,
+    text: String,
+    mode: Option<String>,
+    limit: Option<i64>,
+) -> Result<Vec<search::SearchPerspectiveSearch>> {
+    let response_data: search::ResponseData = query(
+        executor_url,
+        cap_token,
+        Search::build_query(search::Variables {
+            uuid,
+            text,
+            mode,
+            limit,
+        }),
+    )
+    .await
+    .with_context(|| "Failed to run perspectives->search query")?;
+
+    Ok(response_data.perspective_search)
+}
+
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "schema.gql",
@@ -237,17 +330,7 @@ pub async fn infer(
             _ => v,
         })
     } else {
-        if let Some(errors) = response.errors.clone() {
-            if let Some(error) = errors.first() {
-                if error.message.starts_with("error(") {
-                    return Err(anyhow!(error.message.clone()));
-                }
-            }
-        }
-        Err(anyhow!(
-            "Failed to run perspective->infer query: {:?}",
-            response.errors
-        ))
+        Err(ExecutorError::from_graphql_errors(response.errors.as_ref()).into())
     }
 }
 
@@ -358,6 +441,29 @@ impl PerspectivesClient {
         .await
     }
 
+    pub async fn export(&self, uuid: String) -> Result<String> {
+        export(
+            self.info.executor_url.clone(),
+            self.info.cap_token.clone(),
+            uuid,
+        )
+        .await
+    }
+
+    pub async fn import(
+        &self,
+        archive: String,
+        allow_invalid_signature: Option<bool>,
+    ) -> Result<import::ImportPerspectiveImport> {
+        import(
+            self.info.executor_url.clone(),
+            self.info.cap_token.clone(),
+            archive,
+            allow_invalid_signature,
+        )
+        .await
+    }
+
     pub async fn add_link(
         &self,
         uid: String,
@@ -398,6 +504,7 @@ impl PerspectivesClient {
         from_date: Option<DateTime>,
         until_date: Option<DateTime>,
         limit: Option<f64>,
+        paging: LinkPaging,
     ) -> Result<Vec<query_links::QueryLinksPerspectiveQueryLinks>> {
         query_links(
             self.info.executor_url.clone(),
@@ -409,6 +516,25 @@ impl PerspectivesClient {
             from_date,
             until_date,
             limit,
+            paging,
+        )
+        .await
+    }
+
+    pub async fn search(
+        &self,
+        uuid: String,
+        text: String,
+        mode: Option<String>,
+        limit: Option<i64>,
+    ) -> Result<Vec<search::SearchPerspectiveSearch>> {
+        search(
+            self.info.executor_url.clone(),
+            self.info.cap_token.clone(),
+            uuid,
+            text,
+            mode,
+            limit,
         )
         .await
     }
@@ -451,7 +577,10 @@ impl PerspectivesClient {
             .await?
             .iter()
             .find(|p| p.uuid == uuid)
-            .ok_or_else(|| anyhow!("Perspective with ID {} not found!", uuid))?;
+            .ok_or_else(|| ExecutorError {
+                code: ErrorCode::PerspectiveNotFound,
+                message: format!("Perspective with ID {} not found!", uuid),
+            })?;
 
         Ok(PerspectiveProxy::new(self.clone(), uuid.clone()))
     }
@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 use crate::agent::by_did::{ByDidAgentByDid, ByDidAgentByDidPerspectiveLinks};
 use crate::agent::me::{MeAgent, MeAgentPerspectiveLinks};
 use crate::perspectives::query_links::QueryLinksPerspectiveQueryLinks;
+use crate::perspectives::search::SearchPerspectiveSearchLink;
 use crate::perspectives::subscription_link_added::SubscriptionLinkAddedPerspectiveLinkAdded;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -91,6 +92,27 @@ impl From<QueryLinksPerspectiveQueryLinks> for LinkExpression {
     }
 }
 
+impl From<SearchPerspectiveSearchLink> for LinkExpression {
+    fn from(link: SearchPerspectiveSearchLink) -> Self {
+        Self {
+            author: link.author,
+            timestamp: link.timestamp,
+            data: Link {
+                predicate: link.data.predicate,
+                source: link.data.source,
+                target: link.data.target,
+            },
+            proof: ExpressionProof {
+                invalid: link.proof.invalid,
+                key: link.proof.key,
+                signature: link.proof.signature,
+                valid: link.proof.valid,
+            },
+            status: link.status,
+        }
+    }
+}
+
 impl From<SubscriptionLinkAddedPerspectiveLinkAdded> for LinkExpression {
     fn from(link: SubscriptionLinkAddedPerspectiveLinkAdded) -> Self {
         Self {
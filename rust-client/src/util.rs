@@ -1,4 +1,5 @@
-use anyhow::{anyhow, Result};
+use crate::errors::ExecutorError;
+use anyhow::Result;
 use async_tungstenite::tungstenite::{client::IntoClientRequest, http::HeaderValue, Message};
 use futures::StreamExt;
 use graphql_client::{QueryBody, Response};
@@ -20,7 +21,7 @@ where
         .await?;
     let response_data = response_body
         .data
-        .ok_or_else(|| anyhow!("No data in response! Errors: {:?}", response_body.errors))?;
+        .ok_or_else(|| ExecutorError::from_graphql_errors(response_body.errors.as_ref()))?;
     Ok(response_data)
 }
 
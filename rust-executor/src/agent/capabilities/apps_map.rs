@@ -1,4 +1,5 @@
-use super::types::AuthInfoExtended;
+use super::types::{AuthInfoExtended, TokenUse};
+use crate::graphql::graphql_types::CapabilityTokens;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::{self, File};
@@ -11,18 +12,41 @@ pub struct App {
     auth_info_extended: AuthInfoExtended,
     revoked: bool,
     token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    /// Unix timestamps in seconds
+    #[serde(default)]
+    token_expires_at: Option<u64>,
+    #[serde(default)]
+    rotated_at: Option<u64>,
+    #[serde(default)]
+    rotation_count: u32,
 }
 
 impl App {
-    pub fn new(auth_info_extended: AuthInfoExtended, revoked: bool, token: String) -> Self {
+    pub fn new(
+        auth_info_extended: AuthInfoExtended,
+        revoked: bool,
+        tokens: &CapabilityTokens,
+        expires_at: u64,
+    ) -> Self {
         App {
             auth_info_extended,
             revoked,
-            token,
+            token: tokens.access_token.clone(),
+            refresh_token: Some(tokens.refresh_token.clone()),
+            token_expires_at: Some(expires_at),
+            rotated_at: None,
+            rotation_count: 0,
         }
     }
 }
 
+pub(crate) fn seconds_to_timestamp(seconds: u64) -> Option<String> {
+    chrono::DateTime::<chrono::Utc>::from_timestamp(seconds as i64, 0)
+        .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+}
+
 use std::env;
 
 lazy_static! {
@@ -66,13 +90,23 @@ lazy_static! {
     };
 }
 
+/// Replaces the apps in memory with the ones in the data file, e.g. after restoring a backup
+pub fn reload_from_file() {
+    let apps = load_apps_from_file().unwrap_or_default();
+    *APPS.lock().unwrap() = apps;
+}
+
 pub fn insert_app(
     request_key: String,
     auth_info_extended: AuthInfoExtended,
-    token: String,
+    tokens: &CapabilityTokens,
+    expires_at: u64,
 ) -> Result<(), String> {
     let mut apps = APPS.lock().map_err(|e| e.to_string())?;
-    apps.insert(request_key, App::new(auth_info_extended, false, token));
+    apps.insert(
+        request_key,
+        App::new(auth_info_extended, false, tokens, expires_at),
+    );
     persist_apps_to_file(&apps).map_err(|e| e.to_string())?;
     Ok(())
 }
@@ -86,6 +120,53 @@ pub fn revoke_app(request_key: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// Replaces the tokens of an app after a refresh, which invalidates the old ones
+pub fn rotate_app_tokens(
+    request_key: &str,
+    tokens: &CapabilityTokens,
+    expires_at: u64,
+    rotated_at: u64,
+) -> Result<(), String> {
+    let mut apps = APPS.lock().map_err(|e| e.to_string())?;
+    let app = apps
+        .get_mut(request_key)
+        .ok_or(format!("App with request_key '{}' not found.", request_key))?;
+    app.token = tokens.access_token.clone();
+    app.refresh_token = Some(tokens.refresh_token.clone());
+    app.token_expires_at = Some(expires_at);
+    app.rotated_at = Some(rotated_at);
+    app.rotation_count += 1;
+    persist_apps_to_file(&apps).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Checks that `token` is the current, non-revoked token of the app
+pub fn check_app_token(request_key: &str, token: &str, token_use: TokenUse) -> Result<(), String> {
+    let apps = APPS.lock().map_err(|e| e.to_string())?;
+    let app = apps
+        .get(request_key)
+        .ok_or("Unauthorized access".to_string())?;
+    if app.revoked {
+        return Err("Unauthorized access".to_string());
+    }
+    let current = match token_use {
+        TokenUse::Access => Some(&app.token),
+        TokenUse::Refresh => app.refresh_token.as_ref(),
+    };
+    if current.map(|t| t.as_str()) != Some(token) {
+        return Err("Token has been rotated, use the latest token of this app".to_string());
+    }
+    Ok(())
+}
+
+/// The request key of the app whose current access token is `token`
+pub fn request_key_of_token(token: &str) -> Option<String> {
+    let apps = APPS.lock().expect("apps lock");
+    apps.iter()
+        .find(|(_, app)| app.token == token)
+        .map(|(request_key, _)| request_key.clone())
+}
+
 pub fn remove_app(request_key: &str) -> Result<(), String> {
     let mut apps = APPS.lock().map_err(|e| e.to_string())?;
     if apps.remove(request_key).is_some() {
@@ -109,6 +190,9 @@ pub fn get_apps() -> Vec<crate::graphql::graphql_types::Apps> {
             request_id: request_id.clone(),
             revoked: Some(app.revoked),
             token: app.token.clone(),
+            token_expires_at: app.token_expires_at.and_then(seconds_to_timestamp),
+            rotated_at: app.rotated_at.and_then(seconds_to_timestamp),
+            rotation_count: app.rotation_count as i32,
         })
         .collect()
 }
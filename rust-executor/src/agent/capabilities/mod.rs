@@ -9,18 +9,44 @@ use requests_map::{get_request, insert_request, remove_request};
 pub use token::*;
 pub use types::*;
 
+use crate::errors::ExecutorError;
 use crate::graphql::graphql_types::*;
 use crate::pubsub::{get_global_pubsub, APPS_CHANGED, EXCEPTION_OCCURRED_TOPIC};
 
 pub const DEFAULT_TOKEN_VALID_PERIOD: u64 = 180 * 24 * 60 * 60; // 180 days in seconds
+pub const ACCESS_TOKEN_VALID_PERIOD: u64 = 7 * 24 * 60 * 60; // 7 days in seconds
+pub const REFRESH_TOKEN_VALID_PERIOD: u64 = DEFAULT_TOKEN_VALID_PERIOD;
+
+fn covers_domain_and_action(cap: &Capability, expected: &Capability) -> bool {
+    if cap.with.domain != WILD_CARD && cap.with.domain != expected.with.domain {
+        return false;
+    }
+
+    if !cap.can.contains(&WILD_CARD.to_string())
+        && expected.can.iter().any(|c| !cap.can.contains(c))
+    {
+        return false;
+    }
+
+    true
+}
+
+fn capability_not_matched(capabilities: &[Capability], expected: &Capability) -> ExecutorError {
+    ExecutorError::CapabilityDenied(format!(
+        "Capability is not matched, you have capabilities: {:?}, expected: {:?}",
+        capabilities, expected
+    ))
+}
 
 pub fn check_capability(
     capabilities: &Result<Vec<Capability>, String>,
     expected: &Capability,
-) -> Result<(), String> {
-    let capabilities = capabilities.clone()?;
+) -> Result<(), ExecutorError> {
+    let capabilities = capabilities
+        .clone()
+        .map_err(ExecutorError::CapabilityDenied)?;
     let custom_cap_match = |cap: &Capability, expected: &Capability| -> bool {
-        if cap.with.domain != WILD_CARD && cap.with.domain != expected.with.domain {
+        if !covers_domain_and_action(cap, expected) {
             return false;
         }
 
@@ -34,12 +60,6 @@ pub fn check_capability(
             return false;
         }
 
-        if !cap.can.contains(&WILD_CARD.to_string())
-            && expected.can.iter().any(|c| !cap.can.contains(c))
-        {
-            return false;
-        }
-
         true
     };
 
@@ -47,23 +67,65 @@ pub fn check_capability(
         .iter()
         .any(|cap| custom_cap_match(cap, expected))
     {
-        return Err(format!(
-            "Capability is not matched, you have capabilities: {:?}, expected: {:?}",
-            capabilities, expected
-        ));
+        return Err(capability_not_matched(&capabilities, expected));
     }
 
     Ok(())
 }
 
-pub fn check_token_revoked(token: &String) -> Result<(), String> {
-    if let Some(app) = apps_map::get_apps().iter().find(|app| app.token == *token) {
-        if app.revoked.unwrap_or(false) {
-            return Err("Unauthorized access".to_string());
+/// The pointers (e.g. perspective UUIDs) that a set of capabilities grants
+/// the domain and action of a capability for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PointerScope {
+    All,
+    Only(Vec<String>),
+}
+
+impl PointerScope {
+    pub fn contains(&self, pointer: &str) -> bool {
+        match self {
+            PointerScope::All => true,
+            PointerScope::Only(pointers) => pointers.iter().any(|p| p == pointer),
         }
-    };
+    }
+}
 
-    Ok(())
+/// Like `check_capability()` but ignores the pointers of `expected` and returns
+/// the union of pointers granted for its domain and action instead.
+/// Used for subscriptions that span all pointers of a domain and have to be
+/// filtered down to what the caller is allowed to see.
+pub fn capability_pointer_scope(
+    capabilities: &Result<Vec<Capability>, String>,
+    expected: &Capability,
+) -> Result<PointerScope, ExecutorError> {
+    let capabilities = capabilities
+        .clone()
+        .map_err(ExecutorError::CapabilityDenied)?;
+    let matching = capabilities
+        .iter()
+        .filter(|cap| covers_domain_and_action(cap, expected))
+        .collect::<Vec<&Capability>>();
+
+    if matching.is_empty() {
+        return Err(capability_not_matched(&capabilities, expected));
+    }
+
+    if matching
+        .iter()
+        .any(|cap| cap.with.pointers.contains(&WILD_CARD.to_string()))
+    {
+        return Ok(PointerScope::All);
+    }
+
+    let mut pointers = Vec::new();
+    for cap in matching {
+        for pointer in &cap.with.pointers {
+            if !pointers.contains(pointer) {
+                pointers.push(pointer.clone());
+            }
+        }
+    }
+    Ok(PointerScope::Only(pointers))
 }
 
 pub fn capabilities_from_token(
@@ -87,9 +149,19 @@ pub fn capabilities_from_token(
         return Ok(vec![AGENT_AUTH_CAPABILITY.clone()]);
     }
 
-    check_token_revoked(&token)?;
+    let claims = decode_jwt(token.clone()).map_err(|e| e.to_string())?;
 
-    let claims = decode_jwt(token).map_err(|e| e.to_string())?;
+    if claims.token_use == TokenUse::Refresh {
+        return Err("Refresh tokens can only be used with agentRefreshCapability".to_string());
+    }
+
+    // Only tokens of apps are accepted, legacy ones carry no `sub` and are
+    // looked up by the token itself
+    let request_id = match &claims.sub {
+        Some(request_id) => request_id.clone(),
+        None => apps_map::request_key_of_token(&token).ok_or("Unauthorized access".to_string())?,
+    };
+    apps_map::check_app_token(&request_id, &token, TokenUse::Access)?;
 
     if claims.capabilities.capabilities.is_none() {
         Ok(vec![AGENT_AUTH_CAPABILITY.clone()])
@@ -98,6 +170,39 @@ pub fn capabilities_from_token(
     }
 }
 
+/// Issues an access token valid for `access_valid_period` seconds and a
+/// refresh token for an app
+fn issue_app_tokens(
+    request_id: &str,
+    auth: &AuthInfo,
+    access_valid_period: u64,
+) -> Result<(CapabilityTokens, u64), String> {
+    let issuer = main_did().map_err(|e| e.to_string())?;
+    let access = Claims::new(
+        issuer.clone(),
+        auth.app_name.clone(),
+        access_valid_period,
+        auth.clone(),
+    )
+    .for_app(request_id.to_string());
+    let refresh = Claims::new(
+        issuer,
+        auth.app_name.clone(),
+        REFRESH_TOKEN_VALID_PERIOD,
+        auth.clone(),
+    )
+    .for_app(request_id.to_string())
+    .with_token_use(TokenUse::Refresh);
+
+    let expires_at = access.exp;
+    let tokens = CapabilityTokens {
+        access_token: sign_claims(&access).map_err(|e| e.to_string())?,
+        refresh_token: sign_claims(&refresh).map_err(|e| e.to_string())?,
+        expires_at: apps_map::seconds_to_timestamp(expires_at).unwrap_or_default(),
+    };
+    Ok((tokens, expires_at))
+}
+
 pub async fn request_capability(auth_info: AuthInfo) -> String {
     let request_id = uuid::Uuid::new_v4().to_string();
     let app_name = auth_info.app_name.clone();
@@ -135,17 +240,19 @@ pub fn permit_capability(auth_info_extended: AuthInfoExtended) -> Result<String,
     Ok(rand)
 }
 
-pub async fn generate_capability_token(request_id: String, rand: String) -> Result<String, String> {
+/// Issues the tokens of a permitted request. `access_valid_period` is
+/// `ACCESS_TOKEN_VALID_PERIOD` unless the app can't refresh tokens, like
+/// callers of the legacy `agentGenerateJwt` which only get the access token.
+pub async fn generate_capability_token(
+    request_id: String,
+    rand: String,
+    access_valid_period: u64,
+) -> Result<CapabilityTokens, String> {
     let auth_key = gen_request_key(&request_id, &rand);
 
     let auth = get_request(&auth_key)?.ok_or("Can't find permitted request")?;
 
-    let cap_token = token::generate_jwt(
-        auth.app_name.clone(),
-        DEFAULT_TOKEN_VALID_PERIOD,
-        auth.clone(),
-    )
-    .map_err(|e| e.to_string())?;
+    let (tokens, expires_at) = issue_app_tokens(&request_id, &auth, access_valid_period)?;
 
     remove_request(&auth_key)?;
 
@@ -155,7 +262,8 @@ pub async fn generate_capability_token(request_id: String, rand: String) -> Resu
             request_id: request_id.clone(),
             auth,
         },
-        cap_token.clone(),
+        &tokens,
+        expires_at,
     )?;
 
     get_global_pubsub()
@@ -163,7 +271,49 @@ pub async fn generate_capability_token(request_id: String, rand: String) -> Resu
         .publish(&APPS_CHANGED, &String::from(""))
         .await;
 
-    Ok(cap_token)
+    Ok(tokens)
+}
+
+/// Exchanges the current refresh token of an app for a new token pair.
+/// Both old tokens stop working. The refresh token is the only credential
+/// needed, so apps can refresh after their access token expired.
+/// Legacy (HS256) access tokens, which came without a refresh token, can be
+/// exchanged the same way while they are valid.
+pub async fn refresh_capability(refresh_token: String) -> Result<CapabilityTokens, String> {
+    let claims = decode_jwt(refresh_token.clone()).map_err(|e| e.to_string())?;
+    let request_id = if is_legacy_jwt(&refresh_token) {
+        let request_id = apps_map::request_key_of_token(&refresh_token)
+            .ok_or("Unauthorized access".to_string())?;
+        apps_map::check_app_token(&request_id, &refresh_token, TokenUse::Access)?;
+        request_id
+    } else {
+        if claims.token_use != TokenUse::Refresh {
+            return Err("Not a refresh token".to_string());
+        }
+        let request_id = claims
+            .sub
+            .ok_or("Refresh token was not issued to an app".to_string())?;
+        apps_map::check_app_token(&request_id, &refresh_token, TokenUse::Refresh)?;
+        request_id
+    };
+
+    let (tokens, expires_at) =
+        issue_app_tokens(&request_id, &claims.capabilities, ACCESS_TOKEN_VALID_PERIOD)?;
+    apps_map::rotate_app_tokens(&request_id, &tokens, expires_at, unix_now())?;
+
+    get_global_pubsub()
+        .await
+        .publish(&APPS_CHANGED, &String::from(""))
+        .await;
+
+    Ok(tokens)
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
 }
 
 pub fn gen_random_digits() -> String {
@@ -295,4 +445,156 @@ mod tests {
         let key = gen_request_key("my-request-id", "123456");
         assert_eq!(key, "my-request-id-123456");
     }
+
+    #[test]
+    fn pointer_scope_of_wildcard_capability_covers_all_perspectives() {
+        assert_eq!(
+            capability_pointer_scope(
+                &Ok(vec![ALL_CAPABILITY.clone()]),
+                &PERSPECTIVE_SUBSCRIBE_CAPABILITY
+            ),
+            Ok(PointerScope::All)
+        );
+    }
+
+    #[test]
+    fn pointer_scope_is_limited_to_granted_perspectives() {
+        let capabilities = vec![
+            perspective_subscribe_capability(vec!["123".to_string()]),
+            perspective_query_capability(vec!["456".to_string()]),
+        ];
+        let scope =
+            capability_pointer_scope(&Ok(capabilities.clone()), &PERSPECTIVE_SUBSCRIBE_CAPABILITY)
+                .unwrap();
+        assert_eq!(scope, PointerScope::Only(vec!["123".to_string()]));
+        assert!(scope.contains("123"));
+        assert!(!scope.contains("456"));
+
+        assert!(capability_pointer_scope(
+            &Ok(vec![AGENT_AUTH_CAPABILITY.clone()]),
+            &PERSPECTIVE_SUBSCRIBE_CAPABILITY
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn refreshing_rotates_the_tokens_of_an_app() {
+        crate::test_utils::setup_wallet();
+        apps_map::set_data_file_path(
+            std::env::temp_dir()
+                .join(format!("ad4m-apps-{}.json", uuid::Uuid::new_v4()))
+                .to_string_lossy()
+                .to_string(),
+        );
+        let admin = Some("admin-credential".to_string());
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let rand = permit_capability(AuthInfoExtended {
+            request_id: request_id.clone(),
+            auth: AuthInfo {
+                app_name: "test-app".to_string(),
+                app_desc: "test".to_string(),
+                capabilities: Some(vec![AGENT_READ_CAPABILITY.clone()]),
+                ..Default::default()
+            },
+        })
+        .unwrap();
+
+        let tokens = generate_capability_token(request_id.clone(), rand, ACCESS_TOKEN_VALID_PERIOD)
+            .await
+            .unwrap();
+        assert!(capabilities_from_token(tokens.access_token.clone(), admin.clone()).is_ok());
+        assert!(capabilities_from_token(tokens.refresh_token.clone(), admin.clone()).is_err());
+
+        let refreshed = refresh_capability(tokens.refresh_token.clone())
+            .await
+            .unwrap();
+        assert!(capabilities_from_token(refreshed.access_token.clone(), admin.clone()).is_ok());
+        assert!(capabilities_from_token(tokens.access_token.clone(), admin.clone()).is_err());
+        assert!(refresh_capability(tokens.refresh_token).await.is_err());
+        assert!(refresh_capability(refreshed.access_token.clone())
+            .await
+            .is_err());
+
+        let app = apps_map::get_apps()
+            .into_iter()
+            .find(|app| app.request_id == request_id)
+            .unwrap();
+        assert_eq!(app.token, refreshed.access_token);
+        assert_eq!(app.rotation_count, 1);
+        assert!(app.rotated_at.is_some());
+        assert_eq!(app.token_expires_at, Some(refreshed.expires_at));
+
+        apps_map::revoke_app(&request_id).unwrap();
+        assert!(capabilities_from_token(refreshed.access_token, admin).is_err());
+        assert!(refresh_capability(refreshed.refresh_token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn only_tokens_of_apps_are_accepted() {
+        crate::test_utils::setup_wallet();
+        apps_map::set_data_file_path(
+            std::env::temp_dir()
+                .join(format!("ad4m-apps-{}.json", uuid::Uuid::new_v4()))
+                .to_string_lossy()
+                .to_string(),
+        );
+        let admin = Some("admin-credential".to_string());
+        let auth = AuthInfo {
+            app_name: "legacy-app".to_string(),
+            app_desc: "test".to_string(),
+            capabilities: Some(vec![AGENT_READ_CAPABILITY.clone()]),
+            ..Default::default()
+        };
+
+        let unregistered = generate_jwt(
+            "legacy-app".to_string(),
+            DEFAULT_TOKEN_VALID_PERIOD,
+            auth.clone(),
+        )
+        .unwrap();
+        assert!(capabilities_from_token(unregistered, admin.clone()).is_err());
+
+        let legacy_token = {
+            let wallet = crate::wallet::Wallet::instance();
+            let wallet_lock = wallet.lock().expect("wallet lock");
+            let wallet_ref = wallet_lock.as_ref().expect("wallet instance");
+            let secret_key = wallet_ref
+                .get_secret_key(&crate::wallet::MAIN_KEY.to_string())
+                .unwrap();
+            jsonwebtoken::encode(
+                &jsonwebtoken::Header::default(),
+                &Claims::new(
+                    main_did().unwrap(),
+                    "legacy-app".to_string(),
+                    DEFAULT_TOKEN_VALID_PERIOD,
+                    auth.clone(),
+                ),
+                &jsonwebtoken::EncodingKey::from_secret(secret_key.as_slice()),
+            )
+            .unwrap()
+        };
+        assert!(capabilities_from_token(legacy_token.clone(), admin.clone()).is_err());
+
+        let request_id = uuid::Uuid::new_v4().to_string();
+        apps_map::insert_app(
+            request_id.clone(),
+            AuthInfoExtended {
+                request_id: request_id.clone(),
+                auth,
+            },
+            &CapabilityTokens {
+                access_token: legacy_token.clone(),
+                ..Default::default()
+            },
+            0,
+        )
+        .unwrap();
+        assert!(capabilities_from_token(legacy_token.clone(), admin.clone()).is_ok());
+
+        // Legacy tokens get exchanged for a token pair once
+        let tokens = refresh_capability(legacy_token.clone()).await.unwrap();
+        assert!(capabilities_from_token(tokens.access_token, admin.clone()).is_ok());
+        assert!(capabilities_from_token(legacy_token.clone(), admin).is_err());
+        assert!(refresh_capability(legacy_token).await.is_err());
+    }
 }
@@ -1,54 +1,253 @@
 use super::types::*;
-use crate::wallet::Wallet;
+use crate::agent::AgentService;
+use crate::wallet::{Wallet, MAIN_KEY};
 use deno_core::{anyhow::anyhow, error::AnyError};
 use jsonwebtoken::{encode, Algorithm, DecodingKey, EncodingKey, Header};
 
-pub fn generate_jwt(
-    audience: String,
-    expiration_time: u64,
-    capabilities: AuthInfo,
-) -> Result<String, AnyError> {
-    // Get the private key
+/// DER prefix of a PKCS#8 (v1) document holding a raw 32 byte Ed25519 seed
+const ED25519_PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+fn ed25519_pkcs8(secret_key: &[u8]) -> Result<Vec<u8>, AnyError> {
+    if secret_key.len() < 32 {
+        return Err(anyhow!("main key is not an Ed25519 key"));
+    }
+    let mut der = ED25519_PKCS8_PREFIX.to_vec();
+    der.extend_from_slice(&secret_key[..32]);
+    Ok(der)
+}
+
+/// The verification method of a did:key DID, which third parties resolve
+/// to get the public key that tokens are signed with.
+pub fn did_key_id(did: &str) -> String {
+    format!("{}#{}", did, did.trim_start_matches("did:key:"))
+}
+
+/// Signs claims with the agent's main (Ed25519 DID) key.
+/// `kid` names that key, which differs from the issuer after a key rotation.
+pub fn sign_claims(claims: &Claims) -> Result<String, AnyError> {
     let wallet = Wallet::instance();
     let wallet_lock = wallet.lock().expect("wallet lock");
     let wallet_ref = wallet_lock.as_ref().expect("wallet instance");
-    let name = "main".to_string();
+    let name = MAIN_KEY.to_string();
 
     let secret_key = wallet_ref
         .get_secret_key(&name)
         .ok_or(anyhow!("main key not found. call createMainKey() first"))?;
-
-    let did_document = wallet_ref
+    let key_did = wallet_ref
         .get_did_document(&name)
-        .ok_or(anyhow!("main did not found. call createMainKey() first"))?;
+        .ok_or(anyhow!("main key not found. call createMainKey() first"))?
+        .id;
 
-    let payload = Claims::new(did_document.id, audience, expiration_time, capabilities);
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(did_key_id(&key_did));
 
     let token = encode(
-        &Header::default(),
-        &payload,
-        &EncodingKey::from_secret(secret_key.as_slice()),
+        &header,
+        claims,
+        &EncodingKey::from_ed_der(&ed25519_pkcs8(&secret_key)?),
     )?;
 
     Ok(token)
 }
 
+pub fn generate_jwt(
+    audience: String,
+    expiration_time: u64,
+    capabilities: AuthInfo,
+) -> Result<String, AnyError> {
+    sign_claims(&Claims::new(
+        main_did()?,
+        audience,
+        expiration_time,
+        capabilities,
+    ))
+}
+
+/// The agent's DID, which tokens are issued by. It stays the same when the
+/// main key gets rotated.
+pub fn main_did() -> Result<String, AnyError> {
+    let agent_did = AgentService::global_instance()
+        .lock()
+        .expect("agent lock")
+        .as_ref()
+        .and_then(|agent| agent.did.clone());
+    if let Some(did) = agent_did {
+        return Ok(did);
+    }
+
+    let wallet = Wallet::instance();
+    let wallet_lock = wallet.lock().expect("wallet lock");
+    let wallet_ref = wallet_lock.as_ref().expect("wallet instance");
+    let name = MAIN_KEY.to_string();
+
+    let did_document = wallet_ref
+        .get_did_document(&name)
+        .ok_or(anyhow!("main did not found. call createMainKey() first"))?;
+
+    Ok(did_document.id)
+}
+
+/// Verifies the EdDSA signature and enforces `exp` and `nbf`.
+/// The signing key is picked by the `kid` header so tokens issued before a
+/// main key rotation stay valid; tokens issued after a key was retired are not.
 pub fn decode_jwt(token: String) -> Result<Claims, AnyError> {
-    //Get the private key
+    let header = jsonwebtoken::decode_header(&token)?;
+    if header.alg == Algorithm::HS256 {
+        return decode_legacy_jwt(&token);
+    }
     let wallet = Wallet::instance();
     let wallet_lock = wallet.lock().expect("wallet lock");
     let wallet_ref = wallet_lock.as_ref().expect("wallet instance");
-    let name = "main".to_string();
 
-    let secret_key = wallet_ref
-        .get_secret_key(&name)
-        .ok_or(anyhow!("main key not found. call createMainKey() first"))?;
+    let (key, public_key) = match header.kid.as_deref() {
+        Some(kid) => wallet_ref
+            .find_key_by_did(kid.split('#').next().unwrap_or(kid))
+            .ok_or(anyhow!("token signed by an unknown key"))?,
+        None => wallet_ref
+            .get_key_info(MAIN_KEY)
+            .zip(wallet_ref.get_public_key(&MAIN_KEY.to_string()))
+            .ok_or(anyhow!("main key not found. call createMainKey() first"))?,
+    };
+
+    let mut validation = jsonwebtoken::Validation::new(Algorithm::EdDSA);
+    validation.validate_nbf = true;
+    validation.set_required_spec_claims(&["exp", "nbf"]);
 
     let result = jsonwebtoken::decode::<Claims>(
         &token,
-        &DecodingKey::from_secret(secret_key.as_slice()),
-        &jsonwebtoken::Validation::new(Algorithm::HS256),
+        &DecodingKey::from_ed_der(public_key.as_slice()),
+        &validation,
     )?;
 
+    if let Some(retired_at) = key.retired_at {
+        if result.claims.iat as i64 > retired_at.timestamp() {
+            return Err(anyhow!("token signed by retired key {}", key.name));
+        }
+    }
+
     Ok(result.claims)
 }
+
+/// True for tokens issued before capability tokens were signed with the DID
+/// key. Those are only accepted while they are the current token of an app
+/// (see `capabilities_from_token()`) and can be exchanged for a token pair
+/// with `refresh_capability()`.
+pub fn is_legacy_jwt(token: &str) -> bool {
+    jsonwebtoken::decode_header(token)
+        .map(|header| header.alg == Algorithm::HS256)
+        .unwrap_or(false)
+}
+
+/// Legacy tokens are HS256 signed with the secret of the main key at the time,
+/// which might have been rotated since, and carry no `nbf`.
+fn decode_legacy_jwt(token: &str) -> Result<Claims, AnyError> {
+    let wallet = Wallet::instance();
+    let wallet_lock = wallet.lock().expect("wallet lock");
+    let wallet_ref = wallet_lock.as_ref().expect("wallet instance");
+
+    let mut validation = jsonwebtoken::Validation::new(Algorithm::HS256);
+    validation.set_required_spec_claims(&["exp"]);
+
+    for key in wallet_ref.list_keys() {
+        let secret_key = match wallet_ref.get_secret_key(&key.name) {
+            Some(secret_key) => secret_key,
+            None => continue,
+        };
+        if let Ok(result) = jsonwebtoken::decode::<Claims>(
+            token,
+            &DecodingKey::from_secret(secret_key.as_slice()),
+            &validation,
+        ) {
+            return Ok(result.claims);
+        }
+    }
+    Err(anyhow!("invalid legacy token"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::setup_wallet;
+    use base64::Engine;
+    use did_key::CoreSign;
+
+    fn auth_info() -> AuthInfo {
+        AuthInfo {
+            app_name: "test-app".to_string(),
+            app_desc: "test".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tokens_can_be_verified_against_the_did_key() {
+        setup_wallet();
+        let token = generate_jwt("test-app".to_string(), 60, auth_info()).unwrap();
+
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+        assert_eq!(header.alg, Algorithm::EdDSA);
+        let claims = decode_jwt(token.clone()).unwrap();
+        assert_eq!(claims.iss, main_did().unwrap());
+        let kid = header.kid.unwrap();
+        let key_did = kid.split('#').next().unwrap();
+        assert_eq!(kid, did_key_id(key_did));
+
+        // Verify like a third party that resolves the key named in `kid`
+        let key = did_key::resolve(key_did).unwrap();
+        let (message, signature) = token.rsplit_once('.').unwrap();
+        let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(signature)
+            .unwrap();
+        assert!(key.verify(message.as_bytes(), &signature).is_ok());
+    }
+
+    #[test]
+    fn expired_and_not_yet_valid_tokens_are_rejected() {
+        setup_wallet();
+        let mut claims = Claims::new(main_did().unwrap(), "test-app".to_string(), 60, auth_info());
+        claims.exp = claims.iat - 3600;
+        assert!(decode_jwt(sign_claims(&claims).unwrap()).is_err());
+
+        let mut claims = Claims::new(main_did().unwrap(), "test-app".to_string(), 60, auth_info());
+        claims.nbf = claims.iat + 3600;
+        assert!(decode_jwt(sign_claims(&claims).unwrap()).is_err());
+    }
+
+    #[test]
+    fn legacy_tokens_decode_until_they_expire() {
+        setup_wallet();
+        let secret_key = {
+            let wallet = Wallet::instance();
+            let wallet_lock = wallet.lock().expect("wallet lock");
+            let wallet_ref = wallet_lock.as_ref().expect("wallet instance");
+            wallet_ref.get_secret_key(&MAIN_KEY.to_string()).unwrap()
+        };
+        let legacy = |claims: &Claims| {
+            encode(
+                &Header::default(),
+                claims,
+                &EncodingKey::from_secret(secret_key.as_slice()),
+            )
+            .unwrap()
+        };
+
+        let claims = Claims::new(main_did().unwrap(), "test-app".to_string(), 60, auth_info());
+        let token = legacy(&claims);
+        assert!(is_legacy_jwt(&token));
+        assert_eq!(decode_jwt(token).unwrap().nonce, claims.nonce);
+
+        let mut expired = claims.clone();
+        expired.exp = expired.iat - 3600;
+        assert!(decode_jwt(legacy(&expired)).is_err());
+
+        let forged = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"not the main key"),
+        )
+        .unwrap();
+        assert!(decode_jwt(forged).is_err());
+    }
+}
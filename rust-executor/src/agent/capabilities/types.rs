@@ -67,13 +67,31 @@ impl From<crate::graphql::graphql_types::ResourceInput> for Resource {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenUse {
+    /// Grants the capabilities in the claims
+    #[default]
+    Access,
+    /// Can only be exchanged for a new token pair via `agentRefreshCapability`
+    Refresh,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
-    iss: String,
-    aud: String,
-    exp: u64,
-    iat: u64,
-    nonce: String,
+    pub iss: String,
+    pub aud: String,
+    pub exp: u64,
+    /// Missing in legacy (HS256) tokens
+    #[serde(default)]
+    pub nbf: u64,
+    pub iat: u64,
+    pub nonce: String,
+    /// Request id of the app the token was issued to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(default)]
+    pub token_use: TokenUse,
     pub capabilities: AuthInfo,
 }
 
@@ -96,9 +114,22 @@ impl Claims {
             iss: issuer,
             aud: audience,
             exp: unix_timestamp + expiration_time,
+            nbf: unix_timestamp,
             iat: unix_timestamp,
             nonce,
+            sub: None,
+            token_use: TokenUse::Access,
             capabilities,
         }
     }
+
+    pub fn for_app(mut self, request_id: String) -> Self {
+        self.sub = Some(request_id);
+        self
+    }
+
+    pub fn with_token_use(mut self, token_use: TokenUse) -> Self {
+        self.token_use = token_use;
+        self
+    }
 }
@@ -7,8 +7,10 @@ use serde::{Deserialize, Serialize};
 
 use crate::graphql::graphql_types::{Agent, AgentStatus, Perspective};
 
-use crate::types::{Expression, ExpressionProof};
-use crate::wallet::Wallet;
+use crate::db::Ad4mDb;
+use crate::errors::ExecutorError;
+use crate::types::{Expression, ExpressionProof, KeyRotation};
+use crate::wallet::{KeyInfo, Wallet, MAIN_KEY};
 
 pub mod capabilities;
 pub mod signatures;
@@ -22,21 +24,46 @@ pub struct AgentStore {
     signing_key_id: String,
     keystore: String,
     agent: Option<Agent>,
+    #[serde(rename = "deviceDid", default)]
+    device_did: Option<String>,
 }
 
-pub fn did_document() -> did_key::Document {
+/// DID document of the key this executor signs with. After a main key
+/// rotation, or on a linked device, its id is not the agent's DID
+/// (see `did_document()`).
+fn key_did_document() -> did_key::Document {
     let wallet_instance = Wallet::instance();
     let wallet = wallet_instance.lock().expect("wallet lock");
     let wallet_ref = wallet.as_ref().expect("wallet instance");
-    let name = "main".to_string();
+    let name = MAIN_KEY.to_string();
     wallet_ref
         .get_did_document(&name)
         .ok_or(anyhow!("main key not found. call createMainKey() first"))
         .unwrap()
 }
 
+/// DID document of the agent `did` with our current signing key as its
+/// verification method
+pub fn did_document_for(did: &str) -> did_key::Document {
+    let mut document = key_did_document();
+    document.id = did.to_string();
+    for method in document.verification_method.iter_mut() {
+        method.controller = did.to_string();
+    }
+    document
+}
+
+pub fn did_document() -> did_key::Document {
+    did_document_for(&did())
+}
+
 pub fn signing_key_id() -> String {
-    did_document().verification_method[0].id.clone()
+    key_did_document().verification_method[0].id.clone()
+}
+
+/// The did:key of the key this executor signs with
+pub fn signing_key_did() -> String {
+    key_did_document().id
 }
 
 pub fn did() -> String {
@@ -48,11 +75,12 @@ pub fn check_keys_and_create(did: String) -> did_key::Document {
     let wallet_instance = Wallet::instance();
     let mut wallet = wallet_instance.lock().expect("wallet lock");
     let wallet_ref = wallet.as_mut().expect("wallet instance");
-    let name = "main".to_string();
+    let name = MAIN_KEY.to_string();
     if wallet_ref.get_did_document(&name).is_none() {
         wallet_ref.initialize_keys(name, did).unwrap()
     } else {
-        did_document()
+        drop(wallet);
+        did_document_for(&did)
     }
 }
 
@@ -77,7 +105,7 @@ pub fn sign(payload: &[u8]) -> Result<Vec<u8>, AnyError> {
     let wallet_instance = Wallet::instance();
     let wallet = wallet_instance.lock().expect("wallet lock");
     let wallet_ref = wallet.as_ref().expect("wallet instance");
-    let name = "main".to_string();
+    let name = MAIN_KEY.to_string();
     let signature = wallet_ref
         .sign(&name, payload)
         .ok_or(anyhow!("main key not found. call createMainKey() first"))?;
@@ -120,8 +148,11 @@ pub struct AgentService {
     pub did: Option<String>,
     pub did_document: Option<String>,
     pub signing_key_id: Option<String>,
+    /// DID this executor had before it got linked as a device of `did`
+    pub device_did: Option<String>,
     file: String,
     file_profile: String,
+    app_path: String,
     pub agent: Option<Agent>,
 }
 
@@ -144,8 +175,10 @@ impl AgentService {
             did_document: None,
             file: agent_path,
             file_profile: agent_profile_path,
+            app_path,
             agent: None,
             signing_key_id: None,
+            device_did: None,
         }
     }
 
@@ -179,6 +212,11 @@ impl AgentService {
         func(agent_service_mut)
     }
 
+    /// The app data directory the agent is stored in
+    pub fn app_path(&self) -> &str {
+        &self.app_path
+    }
+
     pub fn is_initialized(&self) -> bool {
         let is_initialized = path::Path::new(self.file.as_str()).exists();
         is_initialized
@@ -193,10 +231,10 @@ impl AgentService {
 
     fn signing_checks(&self) -> Result<(), AnyError> {
         if !self.is_initialized() {
-            return Err(anyhow!("Agent not initialized"));
+            return Err(ExecutorError::AgentNotInitialized.into());
         }
         if !self.is_unlocked() {
-            return Err(anyhow!("Agent not unlocked"));
+            return Err(ExecutorError::AgentLocked.into());
         }
         if self.signing_key_id.is_none() {
             return Err(anyhow!("Agent signing key not found"));
@@ -240,14 +278,14 @@ impl AgentService {
         let did = {
             let mut wallet = wallet_instance.lock().expect("wallet lock");
             let wallet_ref: &mut Wallet = wallet.as_mut().expect("wallet instance");
-            wallet_ref.generate_keypair("main".to_string());
+            wallet_ref.generate_keypair(MAIN_KEY.to_string());
             wallet_ref
-                .get_did_document(&"main".to_string())
+                .get_did_document(&MAIN_KEY.to_string())
                 .expect("couldn't get DID document for keys that were just generated above")
                 .id
         };
 
-        self.did_document = Some(serde_json::to_string(&did_document()).unwrap());
+        self.did_document = Some(serde_json::to_string(&did_document_for(&did)).unwrap());
         self.did = Some(did.clone());
         self.agent = Some(Agent {
             did,
@@ -258,10 +296,24 @@ impl AgentService {
     }
 
     pub fn unlock(&self, password: String) -> Result<(), AnyError> {
-        let wallet_instance = Wallet::instance();
-        let mut wallet = wallet_instance.lock().expect("wallet lock");
-        let wallet_ref: &mut Wallet = wallet.as_mut().expect("wallet instance");
-        wallet_ref.unlock(password)
+        let needs_migration = {
+            let wallet_instance = Wallet::instance();
+            let mut wallet = wallet_instance.lock().expect("wallet lock");
+            let wallet_ref: &mut Wallet = wallet.as_mut().expect("wallet instance");
+            let needs_migration = wallet_ref.keystore_needs_migration();
+            wallet_ref.unlock(password.clone())?;
+            needs_migration
+        };
+
+        if needs_migration && self.is_initialized() {
+            log::info!(
+                "Migrating keystore to format version {}",
+                crate::wallet::KEYSTORE_VERSION
+            );
+            self.save(password);
+        }
+
+        Ok(())
     }
 
     pub fn lock(&self, password: String) {
@@ -286,6 +338,7 @@ impl AgentService {
             signing_key_id: self.signing_key_id.clone().unwrap(),
             keystore,
             agent: self.agent.clone(),
+            device_did: self.device_did.clone(),
         };
 
         std::fs::write(self.file.as_str(), serde_json::to_string(&store).unwrap())
@@ -303,6 +356,7 @@ impl AgentService {
         self.did = Some(dump.did.clone());
         self.did_document = Some(dump.did_document);
         self.signing_key_id = Some(dump.signing_key_id);
+        self.device_did = dump.device_did;
 
         {
             let wallet_instance = Wallet::instance();
@@ -340,6 +394,99 @@ impl AgentService {
             error: None,
         }
     }
+
+    /// Wallet operations that change the keystore re-encrypt it with the
+    /// given passphrase, so make sure it is the one the keystore uses.
+    fn check_passphrase(&self, passphrase: &str) -> Result<(), AnyError> {
+        self.signing_checks()?;
+        let wallet_instance = Wallet::instance();
+        let wallet = wallet_instance.lock().expect("wallet lock");
+        let wallet_ref = wallet.as_ref().expect("wallet instance");
+        if !wallet_ref.verify_passphrase(passphrase.to_string()) {
+            return Err(anyhow!("Wrong passphrase"));
+        }
+        Ok(())
+    }
+
+    pub fn create_key(&self, name: String, passphrase: String) -> Result<KeyInfo, AnyError> {
+        self.check_passphrase(&passphrase)?;
+        let key = {
+            let wallet_instance = Wallet::instance();
+            let mut wallet = wallet_instance.lock().expect("wallet lock");
+            let wallet_ref = wallet.as_mut().expect("wallet instance");
+            wallet_ref.create_key(name)?
+        };
+        self.save(passphrase);
+        Ok(key)
+    }
+
+    pub fn retire_key(&self, name: String, passphrase: String) -> Result<KeyInfo, AnyError> {
+        self.check_passphrase(&passphrase)?;
+        let key = {
+            let wallet_instance = Wallet::instance();
+            let mut wallet = wallet_instance.lock().expect("wallet lock");
+            let wallet_ref = wallet.as_mut().expect("wallet instance");
+            wallet_ref.retire_key(&name)?
+        };
+        self.save(passphrase);
+        Ok(key)
+    }
+
+    /// Replaces the main key. The agent's DID stays the same, the returned
+    /// rotation statement is signed by the old key and links other agents
+    /// hold from us keep verifying.
+    pub fn rotate_main_key(&mut self, passphrase: String) -> Result<KeyRotation, AnyError> {
+        self.check_passphrase(&passphrase)?;
+        let mut rotation = KeyRotation {
+            agent_did: self.did.clone().ok_or(anyhow!("Agent DID not set"))?,
+            ..Default::default()
+        };
+
+        let signature = {
+            let wallet_instance = Wallet::instance();
+            let mut wallet = wallet_instance.lock().expect("wallet lock");
+            let wallet_ref = wallet.as_mut().expect("wallet instance");
+            let (_, signature) = wallet_ref.rotate_main_key(|retired, new_key| {
+                rotation.old_key = retired.did.clone();
+                rotation.new_key = new_key.to_string();
+                rotation.timestamp = retired
+                    .retired_at
+                    .unwrap_or_else(chrono::Utc::now)
+                    .to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+                signatures::key_rotation_message(&rotation)
+            })?;
+            signature
+        };
+        rotation.signature = hex::encode(signature);
+
+        signatures::add_key_rotation(rotation.clone())?;
+        Ad4mDb::with_global_instance(|db| db.add_key_rotation(&rotation))?;
+        self.signing_key_id = Some(signing_key_id());
+        self.did_document =
+            Some(serde_json::to_string(&did_document_for(&rotation.agent_did)).unwrap());
+        self.save(passphrase);
+        Ok(rotation)
+    }
+
+    /// Makes this executor act as the agent `did` once that agent authorized
+    /// our key (see `entanglement_service::authorize_device()`). Expressions
+    /// are then authored by `did` and signed with this device's own key.
+    pub fn become_device_of(&mut self, did: String, passphrase: String) -> Result<(), AnyError> {
+        self.check_passphrase(&passphrase)?;
+        if self.device_did.is_none() {
+            self.device_did = self.did.clone();
+        }
+        self.did = Some(did.clone());
+        self.did_document = Some(serde_json::to_string(&did_document_for(&did)).unwrap());
+        self.agent = Some(Agent {
+            did,
+            perspective: Some(Perspective { links: vec![] }),
+            direct_message_language: None,
+        });
+        self.save(passphrase);
+        self.store_agent_profile();
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -457,4 +604,174 @@ mod tests {
             "Signature verification for create_signed_expression with string data should succeed"
         );
     }
+
+    fn signed_by(
+        key: &did_key::PatchedKeyPair,
+        author: &str,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    ) -> Expression<serde_json::Value> {
+        use did_key::{CoreSign, DIDCore};
+        let data = json!({"test": "data"});
+        let signature = key.sign(&signatures::hash_data_and_timestamp(&data, &timestamp));
+        Expression {
+            author: author.to_string(),
+            timestamp: timestamp.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            data,
+            proof: ExpressionProof {
+                signature: hex::encode(signature),
+                key: key
+                    .get_did_document(did_key::Config::default())
+                    .verification_method[0]
+                    .id
+                    .clone(),
+            },
+        }
+    }
+
+    #[test]
+    fn signatures_of_retired_keys_verify_until_the_rotation() {
+        use did_key::{CoreSign, DIDCore, Ed25519KeyPair};
+        let did_of =
+            |key: &did_key::PatchedKeyPair| key.get_did_document(did_key::Config::default()).id;
+        let old_key = did_key::generate::<Ed25519KeyPair>(None);
+        let new_key = did_key::generate::<Ed25519KeyPair>(None);
+        let agent_did = did_of(&old_key);
+        let rotated_at = chrono::Utc::now();
+
+        let mut rotation = KeyRotation {
+            agent_did: agent_did.clone(),
+            old_key: agent_did.clone(),
+            new_key: did_of(&new_key),
+            timestamp: rotated_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            signature: String::new(),
+        };
+        // Must be signed by the old key
+        rotation.signature =
+            hex::encode(new_key.sign(&signatures::key_rotation_message(&rotation)));
+        assert!(signatures::add_key_rotation(rotation.clone()).is_err());
+        rotation.signature =
+            hex::encode(old_key.sign(&signatures::key_rotation_message(&rotation)));
+        signatures::add_key_rotation(rotation).unwrap();
+        assert_eq!(signatures::current_key(&agent_did), did_of(&new_key));
+
+        let before = rotated_at - chrono::Duration::hours(1);
+        let after = rotated_at + chrono::Duration::hours(1);
+        assert!(signatures::verify(&signed_by(&old_key, &agent_did, before)).unwrap());
+        assert!(!signatures::verify(&signed_by(&old_key, &agent_did, after)).unwrap());
+        assert!(signatures::verify(&signed_by(&new_key, &agent_did, after)).unwrap());
+        assert!(!signatures::verify(&signed_by(&new_key, &agent_did, before)).unwrap());
+
+        let unknown_key = did_key::generate::<Ed25519KeyPair>(None);
+        assert!(signatures::is_known_key(&agent_did, &did_of(&new_key)));
+        assert!(!signatures::is_known_key(&agent_did, &did_of(&unknown_key)));
+
+        // A retired (maybe compromised) key can't sign a backdated rotation
+        let attacker_key = did_key::generate::<Ed25519KeyPair>(None);
+        let mut backdated = KeyRotation {
+            agent_did: agent_did.clone(),
+            old_key: did_of(&old_key),
+            new_key: did_of(&attacker_key),
+            timestamp: before.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            signature: String::new(),
+        };
+        backdated.signature =
+            hex::encode(old_key.sign(&signatures::key_rotation_message(&backdated)));
+        assert!(signatures::add_key_rotation(backdated).is_err());
+
+        // Neither can the current key rotate to before its own introduction
+        let mut out_of_order = KeyRotation {
+            agent_did: agent_did.clone(),
+            old_key: did_of(&new_key),
+            new_key: did_of(&attacker_key),
+            timestamp: before.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            signature: String::new(),
+        };
+        out_of_order.signature =
+            hex::encode(new_key.sign(&signatures::key_rotation_message(&out_of_order)));
+        assert!(signatures::add_key_rotation(out_of_order).is_err());
+        assert_eq!(signatures::current_key(&agent_did), did_of(&new_key));
+
+        // Nor can a retired key authorize devices
+        let sign_hex = |key: &did_key::PatchedKeyPair, message: &str| {
+            hex::encode(key.sign(&signatures::hash_message(&message.to_string())))
+        };
+        let device_key = did_key::generate::<Ed25519KeyPair>(None);
+        let device_did = did_of(&device_key);
+        let mut proof = crate::graphql::graphql_types::EntanglementProof {
+            did: agent_did.clone(),
+            did_signing_key_id: did_of(&old_key),
+            device_key_type: signatures::DEVICE_KEY_TYPE.to_string(),
+            device_key: device_did.clone(),
+            device_key_signed_by_did: sign_hex(&old_key, &device_did),
+            did_signed_by_device_key: Some(sign_hex(&device_key, &agent_did)),
+        };
+        assert!(signatures::add_device_authorization(&proof).is_err());
+        proof.did_signing_key_id = did_of(&new_key);
+        proof.device_key_signed_by_did = sign_hex(&new_key, &device_did);
+        signatures::add_device_authorization(&proof).unwrap();
+        assert!(signatures::verify(&signed_by(&device_key, &agent_did, after)).unwrap());
+    }
+
+    #[test]
+    fn did_document_names_the_agent_did() {
+        ensure_setup();
+        let document = did_document();
+        assert_eq!(document.id, did());
+        assert_eq!(document.verification_method[0].id, signing_key_id());
+        assert_eq!(document.verification_method[0].controller, did());
+    }
+
+    #[test]
+    fn linked_devices_sign_for_the_agent_until_revoked() {
+        use crate::graphql::graphql_types::EntanglementProof;
+        use crate::types::DeviceRevocation;
+        use did_key::{CoreSign, DIDCore, Ed25519KeyPair};
+        let did_of =
+            |key: &did_key::PatchedKeyPair| key.get_did_document(did_key::Config::default()).id;
+        let sign_hex = |key: &did_key::PatchedKeyPair, message: &str| {
+            hex::encode(key.sign(&signatures::hash_message(&message.to_string())))
+        };
+        let agent_key = did_key::generate::<Ed25519KeyPair>(None);
+        let device_key = did_key::generate::<Ed25519KeyPair>(None);
+        let agent_did = did_of(&agent_key);
+        let device_did = did_of(&device_key);
+        let now = chrono::Utc::now();
+
+        let mut proof = EntanglementProof {
+            did: agent_did.clone(),
+            did_signing_key_id: agent_did.clone(),
+            device_key_type: signatures::DEVICE_KEY_TYPE.to_string(),
+            device_key: device_did.clone(),
+            device_key_signed_by_did: sign_hex(&device_key, &device_did),
+            did_signed_by_device_key: Some(sign_hex(&device_key, &agent_did)),
+        };
+        // The device can't authorize itself
+        assert!(signatures::add_device_authorization(&proof).is_err());
+        assert!(!signatures::verify(&signed_by(&device_key, &agent_did, now)).unwrap());
+
+        proof.device_key_signed_by_did = sign_hex(&agent_key, &device_did);
+        signatures::add_device_authorization(&proof).unwrap();
+        assert!(signatures::verify(&signed_by(&device_key, &agent_did, now)).unwrap());
+        assert!(signatures::verify(&signed_by(&agent_key, &agent_did, now)).unwrap());
+
+        let mut revocation = DeviceRevocation {
+            did: agent_did.clone(),
+            device_key: device_did.clone(),
+            revoked_at: now.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            signing_key: agent_did.clone(),
+            signature: String::new(),
+        };
+        revocation.signature =
+            hex::encode(device_key.sign(&signatures::device_revocation_message(&revocation)));
+        assert!(signatures::add_device_revocation(&revocation).is_err());
+        revocation.signature =
+            hex::encode(agent_key.sign(&signatures::device_revocation_message(&revocation)));
+        signatures::add_device_revocation(&revocation).unwrap();
+
+        let before = now - chrono::Duration::hours(1);
+        let after = now + chrono::Duration::hours(1);
+        assert!(signatures::verify(&signed_by(&device_key, &agent_did, before)).unwrap());
+        assert!(!signatures::verify(&signed_by(&device_key, &agent_did, after)).unwrap());
+        assert!(signatures::verify(&signed_by(&agent_key, &agent_did, after)).unwrap());
+    }
 }
@@ -1,14 +1,59 @@
-use crate::types::Expression;
+use crate::db::Ad4mDb;
+use crate::graphql::graphql_types::{EntanglementProof, PerspectiveExpression};
+use crate::types::{DecoratedLinkExpression, DeviceRevocation, Expression, KeyRotation};
+use ad4m_client::literal::{Literal, LiteralValue};
 use chrono::SecondsFormat;
 use chrono::{DateTime, Utc};
 use deno_core::anyhow::anyhow;
 use deno_core::error::AnyError;
 use did_key::{CoreSign, PatchedKeyPair};
-use log::error;
-use serde::Serialize;
+use log::{debug, error};
+use serde::{de::DeserializeOwned, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
 
+lazy_static! {
+    /// Known key rotations by agent DID, ordered by timestamp.
+    /// Kept in memory because `verify()` runs for every link.
+    static ref KEY_ROTATIONS: RwLock<HashMap<String, Vec<KeyRotation>>> =
+        RwLock::new(HashMap::new());
+    /// Keys of other devices entangled with an agent DID
+    static ref DEVICE_KEYS: RwLock<HashMap<String, Vec<DeviceKey>>> =
+        RwLock::new(HashMap::new());
+    /// When we last fetched the public statements of an agent
+    static ref STATEMENTS_FETCHED_AT: Mutex<HashMap<String, Instant>> =
+        Mutex::new(HashMap::new());
+}
+
+/// How often the public perspective of an agent gets fetched at most when
+/// its links are signed by keys we don't know
+const STATEMENTS_REFETCH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Predicates of the links that publish key statements in an agent's public perspective
+pub const KEY_ROTATION_PREDICATE: &str = "ad4m://key_rotation";
+pub const DEVICE_AUTHORIZATION_PREDICATE: &str = "ad4m://device_authorization";
+pub const DEVICE_REVOCATION_PREDICATE: &str = "ad4m://device_revocation";
+
+/// `device_key_type` of entanglement proofs that let another device's key sign as the agent
+pub const DEVICE_KEY_TYPE: &str = "ad4m-device";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceKey {
+    pub key: String,
+    pub authorized: bool,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl DeviceKey {
+    fn valid_at(&self, timestamp: &DateTime<Utc>) -> bool {
+        self.authorized && self.revoked_at.map(|t| *timestamp < t).unwrap_or(true)
+    }
+}
+
+/// Verifies a signature made by the current key of `did`
 pub fn verify_string_signed_by_did(
     did: &str,
     data: &str,
@@ -16,9 +61,28 @@ pub fn verify_string_signed_by_did(
 ) -> Result<bool, AnyError> {
     let sig_bytes = hex::decode(signed_data)?;
     let message = hash_message(&data.to_string());
-    Ok(inner_verify(did, &message, &sig_bytes))
+    if inner_verify(&current_key(did), &message, &sig_bytes) {
+        return Ok(true);
+    }
+    let now = Utc::now();
+    Ok(device_keys(did)
+        .iter()
+        .filter(|device| device.valid_at(&now))
+        .any(|device| inner_verify(&device.key, &message, &sig_bytes)))
+}
+
+/// The key an agent currently signs with, following its key rotations
+pub fn current_key(did: &str) -> String {
+    key_rotations(did)
+        .last()
+        .map(|rotation| rotation.new_key.clone())
+        .unwrap_or(did.to_string())
 }
 
+/// Verifies the signature of an expression.
+/// If the author rotated keys, the signature has to come from the key that
+/// was current at the expression's timestamp (see `add_key_rotation()`).
+/// Keys of linked devices are accepted until they got revoked.
 pub fn verify<T: Serialize>(expr: &Expression<T>) -> Result<bool, AnyError> {
     let sig_bytes = hex::decode(&expr.proof.signature)?;
     let timestamp = DateTime::<Utc>::from_str(&expr.timestamp).map_err(|e| {
@@ -28,8 +92,442 @@ pub fn verify<T: Serialize>(expr: &Expression<T>) -> Result<bool, AnyError> {
         )
     })?;
     let message = hash_data_and_timestamp(&expr.data, &timestamp);
-    let result = inner_verify(&expr.author, &message, &sig_bytes);
-    Ok(result)
+
+    let proof_key = did_of_key_id(&expr.proof.key);
+    if let Some(device) = device_keys(&expr.author)
+        .iter()
+        .find(|device| device.key == proof_key)
+    {
+        return Ok(device.valid_at(&timestamp) && inner_verify(proof_key, &message, &sig_bytes));
+    }
+
+    let rotations = key_rotations(&expr.author);
+    if rotations.is_empty() {
+        return Ok(inner_verify(&expr.author, &message, &sig_bytes));
+    }
+
+    let signing_key = if rotations.iter().any(|r| r.new_key == proof_key) {
+        proof_key
+    } else {
+        expr.author.as_str()
+    };
+    Ok(
+        key_valid_at(&expr.author, signing_key, &rotations, &timestamp)
+            && inner_verify(signing_key, &message, &sig_bytes),
+    )
+}
+
+/// `did:key:z6Mk...#z6Mk...` -> `did:key:z6Mk...`
+fn did_of_key_id(key_id: &str) -> &str {
+    key_id.split('#').next().unwrap_or(key_id)
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::<Utc>::from_str(timestamp).ok()
+}
+
+/// A key is valid from the rotation that introduced it (or always, for the
+/// author DID's own key) until the rotation that replaced it.
+fn key_valid_at(
+    author: &str,
+    key: &str,
+    rotations: &[KeyRotation],
+    timestamp: &DateTime<Utc>,
+) -> bool {
+    let introduced = key == author
+        || rotations
+            .iter()
+            .filter(|r| r.new_key == key)
+            .filter_map(|r| parse_timestamp(&r.timestamp))
+            .any(|t| t <= *timestamp);
+    let retired = rotations
+        .iter()
+        .filter(|r| r.old_key == key)
+        .filter_map(|r| parse_timestamp(&r.timestamp))
+        .any(|t| t <= *timestamp);
+    introduced && !retired
+}
+
+pub(super) fn key_rotation_message(rotation: &KeyRotation) -> Vec<u8> {
+    hash_message(&format!(
+        "ad4m-key-rotation:{}:{}:{}:{}",
+        rotation.agent_did, rotation.old_key, rotation.new_key, rotation.timestamp
+    ))
+}
+
+pub fn key_rotations(agent_did: &str) -> Vec<KeyRotation> {
+    KEY_ROTATIONS
+        .read()
+        .expect("key rotations lock")
+        .get(agent_did)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Forgets the cached key rotations and device keys, e.g. before loading
+/// those of a restored database
+pub fn clear_caches() {
+    KEY_ROTATIONS.write().expect("key rotations lock").clear();
+    DEVICE_KEYS.write().expect("device keys lock").clear();
+}
+
+/// Loads persisted key rotations, device links and revocations into the
+/// caches used by `verify()`
+pub fn load_from_db() {
+    match Ad4mDb::with_global_instance(|db| db.get_key_rotations()) {
+        Ok(rotations) => {
+            for rotation in rotations {
+                if let Err(e) = add_key_rotation(rotation) {
+                    error!("Ignoring stored key rotation: {}", e);
+                }
+            }
+        }
+        Err(e) => error!("Couldn't load key rotations: {}", e),
+    }
+    match Ad4mDb::with_global_instance(|db| db.get_all_entanglement_proofs()) {
+        Ok(proofs) => {
+            for proof in proofs
+                .iter()
+                .filter(|proof| proof.device_key_type == DEVICE_KEY_TYPE)
+            {
+                if let Err(e) = add_device_authorization(proof) {
+                    error!("Ignoring stored device authorization: {}", e);
+                }
+            }
+        }
+        Err(e) => error!("Couldn't load device authorizations: {}", e),
+    }
+    match Ad4mDb::with_global_instance(|db| db.get_device_revocations()) {
+        Ok(revocations) => {
+            for revocation in revocations {
+                if let Err(e) = add_device_revocation(&revocation) {
+                    error!("Ignoring stored device revocation: {}", e);
+                }
+            }
+        }
+        Err(e) => error!("Couldn't load device revocations: {}", e),
+    }
+}
+
+/// Checks that a rotation is signed by the agent's current key, is not older
+/// than its latest rotation, and adds it to the known rotations.
+/// Persisting it is up to the caller.
+pub fn add_key_rotation(rotation: KeyRotation) -> Result<(), AnyError> {
+    let timestamp = parse_timestamp(&rotation.timestamp).ok_or(anyhow!(
+        "Invalid key rotation timestamp: {}",
+        rotation.timestamp
+    ))?;
+    PatchedKeyPair::try_from(rotation.new_key.as_str())
+        .map_err(|_| anyhow!("New key is not a did:key: {}", rotation.new_key))?;
+
+    let mut all_rotations = KEY_ROTATIONS.write().expect("key rotations lock");
+    let rotations = all_rotations.entry(rotation.agent_did.clone()).or_default();
+    if rotations.contains(&rotation) {
+        return Ok(());
+    }
+    // A retired key must not be able to sign another, backdated rotation
+    if rotations.iter().any(|r| r.old_key == rotation.old_key) {
+        return Err(anyhow!("Key {} was already rotated", rotation.old_key));
+    }
+    if let Some(latest) = rotations.last().and_then(|r| parse_timestamp(&r.timestamp)) {
+        if timestamp < latest {
+            return Err(anyhow!(
+                "Key rotation at {} is older than the latest rotation of {}",
+                rotation.timestamp,
+                rotation.agent_did
+            ));
+        }
+    }
+    if !key_valid_at(
+        &rotation.agent_did,
+        &rotation.old_key,
+        rotations,
+        &timestamp,
+    ) {
+        return Err(anyhow!(
+            "Key {} was not valid for {} at {}",
+            rotation.old_key,
+            rotation.agent_did,
+            rotation.timestamp
+        ));
+    }
+    let signature = hex::decode(&rotation.signature)?;
+    if !inner_verify(
+        &rotation.old_key,
+        &key_rotation_message(&rotation),
+        &signature,
+    ) {
+        return Err(anyhow!("Invalid key rotation signature"));
+    }
+
+    rotations.push(rotation);
+    rotations.sort_by_key(|r| parse_timestamp(&r.timestamp));
+    Ok(())
+}
+
+pub fn device_keys(did: &str) -> Vec<DeviceKey> {
+    DEVICE_KEYS
+        .read()
+        .expect("device keys lock")
+        .get(did)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// True if `key` is the agent's own key or was introduced by one of its rotations
+fn is_agent_key(did: &str, key: &str) -> bool {
+    key == did || key_rotations(did).iter().any(|r| r.new_key == key)
+}
+
+/// True if `key_id` belongs to one of the agent's own keys or to a device
+/// linked to it, whether or not it is valid at a given time
+pub fn is_known_key(did: &str, key_id: &str) -> bool {
+    let key = did_of_key_id(key_id);
+    is_agent_key(did, key) || device_keys(did).iter().any(|device| device.key == key)
+}
+
+/// Fetches the public perspective of agents that signed with a key we don't
+/// know for them, or with the key of a linked device that might have been
+/// revoked since, and picks up the statements published there
+/// (see `ingest_public_statements()`).
+/// Each agent gets looked up at most once per `STATEMENTS_REFETCH_INTERVAL`.
+pub async fn resolve_unknown_keys(signers: impl IntoIterator<Item = (String, String)>) {
+    let dids: HashSet<String> = {
+        let now = Instant::now();
+        let mut fetched_at = STATEMENTS_FETCHED_AT.lock().expect("statements lock");
+        signers
+            .into_iter()
+            .filter(|(did, key_id)| !is_agent_key(did, did_of_key_id(key_id)))
+            .map(|(did, _)| did)
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .filter(|did| {
+                let due = fetched_at
+                    .get(did)
+                    .map(|t| now.duration_since(*t) >= STATEMENTS_REFETCH_INTERVAL)
+                    .unwrap_or(true);
+                if due {
+                    fetched_at.insert(did.clone(), now);
+                }
+                due
+            })
+            .collect()
+    };
+
+    for did in dids {
+        match crate::languages::LanguageController::get_agent(&did).await {
+            Ok(Some(agent)) => {
+                if let Some(perspective) = agent.perspective {
+                    ingest_public_statements(&did, &perspective.links);
+                }
+            }
+            Ok(None) => debug!("No public perspective found for {}", did),
+            Err(e) => debug!("Couldn't fetch public perspective of {}: {}", did, e),
+        }
+    }
+}
+
+/// Like `resolve_unknown_keys()` for the signers of a signed perspective
+/// (e.g. a telepresence signal) and its links
+pub async fn resolve_unknown_keys_of_perspective(expression: &PerspectiveExpression) {
+    resolve_unknown_keys(
+        std::iter::once((expression.author.clone(), expression.proof.key.clone())).chain(
+            expression
+                .data
+                .links
+                .iter()
+                .map(|link| (link.author.clone(), link.proof.key.clone())),
+        ),
+    )
+    .await;
+}
+
+fn verify_hex_signature(key: &str, message: &str, signature: &str) -> bool {
+    hex::decode(signature)
+        .map(|signature| inner_verify(key, &hash_message(&message.to_string()), &signature))
+        .unwrap_or(false)
+}
+
+/// Checks both signatures of a device link: the agent's current key over the
+/// device key and the device's over the agent DID. Valid links are added to
+/// the device keys `verify()` accepts for the agent. Retired keys can't
+/// authorize devices, so devices get re-authorized when the main key rotates
+/// (see `entanglement_service::reauthorize_devices()`).
+pub fn add_device_authorization(proof: &EntanglementProof) -> Result<(), AnyError> {
+    if proof.device_key_type != DEVICE_KEY_TYPE {
+        return Err(anyhow!(
+            "Not a device authorization: {}",
+            proof.device_key_type
+        ));
+    }
+    let agent_key = did_of_key_id(&proof.did_signing_key_id);
+    if agent_key != current_key(&proof.did) {
+        return Err(anyhow!(
+            "{} is not the current key of {}",
+            agent_key,
+            proof.did
+        ));
+    }
+    if !verify_hex_signature(
+        agent_key,
+        &proof.device_key,
+        &proof.device_key_signed_by_did,
+    ) {
+        return Err(anyhow!("Device key is not signed by {}", proof.did));
+    }
+    let did_signed_by_device_key = proof
+        .did_signed_by_device_key
+        .as_ref()
+        .ok_or(anyhow!("DID is not signed by the device key"))?;
+    if !verify_hex_signature(&proof.device_key, &proof.did, did_signed_by_device_key) {
+        return Err(anyhow!("DID is not signed by the device key"));
+    }
+
+    let mut all_devices = DEVICE_KEYS.write().expect("device keys lock");
+    let devices = all_devices.entry(proof.did.clone()).or_default();
+    match devices.iter_mut().find(|d| d.key == proof.device_key) {
+        Some(device) => device.authorized = true,
+        None => devices.push(DeviceKey {
+            key: proof.device_key.clone(),
+            authorized: true,
+            revoked_at: None,
+        }),
+    }
+    Ok(())
+}
+
+pub(crate) fn device_revocation_message(revocation: &DeviceRevocation) -> Vec<u8> {
+    hash_message(&format!(
+        "ad4m-device-revocation:{}:{}:{}",
+        revocation.did, revocation.device_key, revocation.revoked_at
+    ))
+}
+
+/// Checks that a revocation is signed by a key the agent had at that time.
+/// Signatures of the device made at or after `revoked_at` are rejected from then on.
+pub fn add_device_revocation(revocation: &DeviceRevocation) -> Result<(), AnyError> {
+    let revoked_at = parse_timestamp(&revocation.revoked_at).ok_or(anyhow!(
+        "Invalid revocation timestamp: {}",
+        revocation.revoked_at
+    ))?;
+    let signing_key = did_of_key_id(&revocation.signing_key);
+    if !key_valid_at(
+        &revocation.did,
+        signing_key,
+        &key_rotations(&revocation.did),
+        &revoked_at,
+    ) {
+        return Err(anyhow!(
+            "{} was not a key of {} at {}",
+            signing_key,
+            revocation.did,
+            revocation.revoked_at
+        ));
+    }
+    let signature = hex::decode(&revocation.signature)?;
+    if !inner_verify(
+        signing_key,
+        &device_revocation_message(revocation),
+        &signature,
+    ) {
+        return Err(anyhow!("Invalid device revocation signature"));
+    }
+
+    let mut all_devices = DEVICE_KEYS.write().expect("device keys lock");
+    let devices = all_devices.entry(revocation.did.clone()).or_default();
+    match devices.iter_mut().find(|d| d.key == revocation.device_key) {
+        Some(device) => {
+            device.revoked_at = Some(device.revoked_at.map_or(revoked_at, |t| t.min(revoked_at)))
+        }
+        None => devices.push(DeviceKey {
+            key: revocation.device_key.clone(),
+            authorized: false,
+            revoked_at: Some(revoked_at),
+        }),
+    }
+    Ok(())
+}
+
+/// The JSON literals that `did` published with `predicate`
+fn statements<'a, T: DeserializeOwned>(
+    did: &'a str,
+    links: &'a [DecoratedLinkExpression],
+    predicate: &'a str,
+) -> impl Iterator<Item = T> + 'a {
+    links
+        .iter()
+        .filter(move |link| {
+            link.data.source == did && link.data.predicate.as_deref() == Some(predicate)
+        })
+        .filter_map(|link| {
+            match Literal::from_url(link.data.target.clone())
+                .ok()?
+                .get()
+                .ok()?
+            {
+                LiteralValue::Json(json) => serde_json::from_value(json).ok(),
+                _ => None,
+            }
+        })
+}
+
+/// Picks up key rotations, device authorizations and revocations that another
+/// agent published in its public perspective, so its links keep verifying.
+/// The statements are signed, so it doesn't matter where they come from.
+pub fn ingest_public_statements(did: &str, links: &[DecoratedLinkExpression]) {
+    let mut rotations: Vec<KeyRotation> = statements(did, links, KEY_ROTATION_PREDICATE)
+        .filter(|r: &KeyRotation| r.agent_did == did)
+        .collect();
+    rotations.sort_by_key(|r| parse_timestamp(&r.timestamp));
+    for rotation in rotations {
+        if key_rotations(did).contains(&rotation) {
+            continue;
+        }
+        match add_key_rotation(rotation.clone()) {
+            Ok(()) => {
+                if let Err(e) = Ad4mDb::with_global_instance(|db| db.add_key_rotation(&rotation)) {
+                    error!("Couldn't store key rotation of {}: {}", did, e);
+                }
+            }
+            Err(e) => debug!("Ignoring key rotation published by {}: {}", did, e),
+        }
+    }
+
+    for proof in statements(did, links, DEVICE_AUTHORIZATION_PREDICATE)
+        .filter(|p: &EntanglementProof| p.did == did)
+    {
+        let known = device_keys(did)
+            .iter()
+            .any(|device| device.key == proof.device_key && device.authorized);
+        if known {
+            continue;
+        }
+        match add_device_authorization(&proof) {
+            Ok(()) => {
+                if let Err(e) =
+                    Ad4mDb::with_global_instance(|db| db.add_entanglement_proofs(vec![proof]))
+                {
+                    error!("Couldn't store device authorization of {}: {}", did, e);
+                }
+            }
+            Err(e) => debug!("Ignoring device authorization published by {}: {}", did, e),
+        }
+    }
+
+    for revocation in statements(did, links, DEVICE_REVOCATION_PREDICATE)
+        .filter(|r: &DeviceRevocation| r.did == did)
+    {
+        match add_device_revocation(&revocation) {
+            Ok(()) => {
+                if let Err(e) =
+                    Ad4mDb::with_global_instance(|db| db.add_device_revocation(&revocation))
+                {
+                    error!("Couldn't store device revocation of {}: {}", did, e);
+                }
+            }
+            Err(e) => debug!("Ignoring device revocation published by {}: {}", did, e),
+        }
+    }
 }
 
 pub(super) fn hash_data_and_timestamp<T: Serialize>(
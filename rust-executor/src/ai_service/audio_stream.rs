@@ -1,4 +1,6 @@
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use futures::StreamExt;
 use kalosm::sound::AsyncSource;
@@ -6,7 +8,8 @@ use tokio_stream::Stream;
 
 pub struct AudioStream {
     //pub drop_tx: std::sync::mpsc::Sender<()>,
-    pub read_data: Vec<f32>,
+    /// Number of samples streamed so far, to place transcriptions in time
+    pub samples_read: Arc<AtomicUsize>,
     pub receiver: Pin<Box<dyn futures_core::Stream<Item = f32> + Send + Sync>>,
 }
 
@@ -26,7 +29,7 @@ impl Stream for AudioStream {
         match self.receiver.as_mut().poll_next_unpin(cx) {
             std::task::Poll::Ready(Some(data_chunk)) => {
                 // println!("AudioStream produced item: {}", data_chunk); // Logging
-                self.read_data.push(data_chunk);
+                self.samples_read.fetch_add(1, Ordering::Relaxed);
                 std::task::Poll::Ready(Some(data_chunk))
             }
             std::task::Poll::Ready(None) => std::task::Poll::Ready(None),
@@ -64,6 +67,6 @@ impl AsyncSource for AudioStream {
     }
 
     fn sample_rate(&self) -> u32 {
-        16000
+        super::transcription::SAMPLE_RATE
     }
 }
@@ -1,24 +1,34 @@
+use self::output_constraint::{constrained_chat, OutputConstraint};
+use self::remote_providers::{remote_provider, RemoteProvider};
+use self::transcription::{transcription_segment, WhisperSettings, SAMPLE_RATE};
 use self::{audio_stream::AudioStream, error::AIServiceError};
+use crate::errors::ExecutorError;
 use crate::graphql::graphql_types::ModelInput;
 #[allow(unused_imports)]
-use crate::graphql::graphql_types::{AIModelLoadingStatus, AITaskInput, TranscriptionTextFilter};
+use crate::graphql::graphql_types::{
+    AIModelLoadingStatus, AIPromptTokens, AITaskInput, TranscriptionSegment,
+    TranscriptionSegmentFilter,
+};
 use crate::pubsub::AI_MODEL_LOADING_STATUS;
-#[allow(unused_imports)]
 use crate::pubsub::AI_TRANSCRIPTION_TEXT_TOPIC;
-use crate::types::{AITask, LocalModel, Model, ModelType};
+use crate::types::{
+    AIMessage, AIMessageRole, AIOutputConstraint, AITask, LocalModel, Model, ModelApi, ModelType,
+};
 use crate::{db::Ad4mDb, pubsub::get_global_pubsub};
 use anyhow::anyhow;
 use candle_core::Device;
-use chat_gpt_lib_rs::{ChatGPTClient, ChatInput, Message, Role};
+use chat_gpt_lib_rs::{Message, Role};
 use deno_core::error::AnyError;
-use futures::{FutureExt, SinkExt};
+use futures::{FutureExt, SinkExt, StreamExt};
 use holochain::test_utils::itertools::Itertools;
 use kalosm::language::*;
 use kalosm::sound::TextStream;
 use kalosm::sound::*;
+use rodio::Source;
 use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
@@ -26,13 +36,16 @@ use tokio::sync::{mpsc, oneshot, Mutex};
 use tokio::time::sleep;
 
 mod audio_stream;
-mod error;
+pub mod error;
+mod output_constraint;
+mod remote_providers;
+mod sessions;
+mod transcription;
 use log::error;
+pub use transcription::TranscriptionAudio;
 
 pub type Result<T> = std::result::Result<T, AnyError>;
 
-static WHISPER_MODEL: WhisperSource = WhisperSource::Small;
-
 lazy_static! {
     static ref AI_SERVICE: Arc<Mutex<Option<AIService>>> = Arc::new(Mutex::new(None));
 }
@@ -42,11 +55,97 @@ struct TranscriptionSession {
     drop_tx: oneshot::Sender<()>,
 }
 
+/// How long the text of a finished prompt stream stays available to subscribers
+const FINISHED_PROMPT_STREAM_RETENTION: Duration = Duration::from_secs(300);
+
+struct PromptStreamSession {
+    cancelled: Arc<AtomicBool>,
+    buffer: Arc<PromptStreamBuffer>,
+}
+
+/// Text generated for a prompt stream, kept so that subscribers get all of it
+/// no matter when they subscribe
+#[derive(Debug)]
+struct PromptStreamBuffer {
+    stream_id: String,
+    state: std::sync::Mutex<PromptStreamBufferState>,
+}
+
+#[derive(Debug, Default)]
+struct PromptStreamBufferState {
+    events: Vec<AIPromptTokens>,
+    subscribers: Vec<futures_channel::mpsc::UnboundedSender<AIPromptTokens>>,
+}
+
+impl PromptStreamBuffer {
+    fn new(stream_id: String) -> Self {
+        PromptStreamBuffer {
+            stream_id,
+            state: std::sync::Mutex::new(PromptStreamBufferState::default()),
+        }
+    }
+
+    fn push(&self, text: String, done: bool, error: Option<String>) {
+        let tokens = AIPromptTokens {
+            stream_id: self.stream_id.clone(),
+            text,
+            done,
+            error,
+        };
+        let mut state = self.state.lock().expect("prompt stream lock");
+        state
+            .subscribers
+            .retain(|subscriber| subscriber.unbounded_send(tokens.clone()).is_ok());
+        if done {
+            // Ends the streams of all subscribers
+            state.subscribers.clear();
+        }
+        state.events.push(tokens);
+    }
+
+    /// Everything generated so far, followed by what gets generated until the stream is done
+    fn subscribe(&self) -> impl futures::Stream<Item = AIPromptTokens> {
+        let mut state = self.state.lock().expect("prompt stream lock");
+        let (sender, receiver) = futures_channel::mpsc::unbounded();
+        if !state
+            .events
+            .last()
+            .map(|tokens| tokens.done)
+            .unwrap_or(false)
+        {
+            state.subscribers.push(sender);
+        }
+        futures::stream::iter(state.events.clone()).chain(receiver)
+    }
+}
+
+/// Pushes the text of a local model's response to a prompt stream until it ends
+/// or the stream gets cancelled and returns the text pushed
+async fn push_prompt_tokens(
+    buffer: &PromptStreamBuffer,
+    cancelled: &AtomicBool,
+    tokens: impl futures::Stream<Item = String>,
+) -> String {
+    futures::pin_mut!(tokens);
+    let mut all_text = String::new();
+    while let Some(text) = tokens.next().await {
+        if cancelled.load(Ordering::Relaxed) {
+            break;
+        }
+        all_text.push_str(&text);
+        buffer.push(text, false, None);
+    }
+    all_text
+}
+
 #[derive(Clone)]
 pub struct AIService {
     embedding_channel: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<EmbeddingRequest>>>>,
     llm_channel: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<LLMTaskRequest>>>>,
     transcription_streams: Arc<Mutex<HashMap<String, TranscriptionSession>>>,
+    /// Loaded Whisper models by `WhisperSettings` key
+    whisper_models: Arc<Mutex<HashMap<String, Arc<Whisper>>>>,
+    prompt_streams: Arc<Mutex<HashMap<String, PromptStreamSession>>>,
 }
 
 struct EmbeddingRequest {
@@ -66,9 +165,21 @@ struct LLMTaskSpawnRequest {
 struct LLMTaskPromptRequest {
     pub task_id: String,
     pub prompt: String,
+    pub history: Vec<AIMessage>,
     pub result_sender: oneshot::Sender<Result<String>>,
 }
 
+#[allow(dead_code)]
+#[derive(Debug)]
+struct LLMTaskPromptStreamRequest {
+    pub task_id: String,
+    pub prompt: String,
+    pub history: Vec<AIMessage>,
+    pub buffer: Arc<PromptStreamBuffer>,
+    pub cancelled: Arc<AtomicBool>,
+    pub result_sender: oneshot::Sender<Result<()>>,
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 struct LLMTaskRemoveRequest {
@@ -87,13 +198,14 @@ struct LLMTaskShutdownRequest {
 enum LLMTaskRequest {
     Spawn(LLMTaskSpawnRequest),
     Prompt(LLMTaskPromptRequest),
+    PromptStream(LLMTaskPromptStreamRequest),
     Remove(LLMTaskRemoveRequest),
     Shutdown(LLMTaskShutdownRequest),
 }
 
 enum LlmModel {
     Local(Llama),
-    Remote((ChatGPTClient, String)),
+    Remote(Box<dyn RemoteProvider>),
 }
 
 async fn publish_model_status(
@@ -131,6 +243,74 @@ async fn publish_model_status(
         .await;
 }
 
+async fn publish_transcription_segment(stream_id: &str, segment: TranscriptionSegment) {
+    let segment = TranscriptionSegmentFilter {
+        stream_id: stream_id.to_string(),
+        segment,
+    };
+    get_global_pubsub()
+        .await
+        .publish(
+            &AI_TRANSCRIPTION_TEXT_TOPIC,
+            &serde_json::to_string(&segment)
+                .expect("TranscriptionSegmentFilter must be serializable"),
+        )
+        .await;
+}
+
+/// Chat messages for a prompt to a remote model: system prompt, examples,
+/// conversation history, prompt
+fn chat_messages(task: &AITask, history: &[AIMessage], prompt: String) -> Vec<Message> {
+    let mut messages = vec![Message {
+        role: Role::System,
+        content: task.system_prompt.clone(),
+    }];
+
+    for example in task.prompt_examples.iter() {
+        messages.push(Message {
+            role: Role::User,
+            content: example.input.clone(),
+        });
+        messages.push(Message {
+            role: Role::Assistant,
+            content: example.output.clone(),
+        })
+    }
+
+    for message in history {
+        messages.push(Message {
+            role: match message.role {
+                AIMessageRole::User => Role::User,
+                AIMessageRole::Assistant => Role::Assistant,
+            },
+            content: message.content.clone(),
+        });
+    }
+
+    messages.push(Message {
+        role: Role::User,
+        content: prompt,
+    });
+    messages
+}
+
+/// Prompt for a local model, which only sees a single input per run,
+/// with the conversation history written out as a transcript in front
+fn local_prompt(history: &[AIMessage], prompt: String) -> String {
+    if history.is_empty() {
+        return prompt;
+    }
+    let mut transcript = history
+        .iter()
+        .map(|message| match message.role {
+            AIMessageRole::User => format!("User: {}", message.content),
+            AIMessageRole::Assistant => format!("Assistant: {}", message.content),
+        })
+        .join("\n");
+    transcript.push_str(&format!("\nUser: {}\nAssistant:", prompt));
+    transcript
+}
+
 async fn handle_progress(model_id: String, loading: ModelLoadingProgress) {
     let progress = loading.progress() * 100.0;
     let status = if progress < 100.0 {
@@ -148,6 +328,8 @@ impl AIService {
             embedding_channel: Arc::new(Mutex::new(HashMap::new())),
             llm_channel: Arc::new(Mutex::new(HashMap::new())),
             transcription_streams: Arc::new(Mutex::new(HashMap::new())),
+            whisper_models: Arc::new(Mutex::new(HashMap::new())),
+            prompt_streams: Arc::new(Mutex::new(HashMap::new())),
         };
 
         let clone = service.clone();
@@ -179,6 +361,7 @@ impl AIService {
                         tokenizer_source: None,
                         huggingface_repo: None,
                         revision: None,
+                        language: None,
                     }),
                     api: None,
                 })
@@ -212,7 +395,7 @@ impl AIService {
         match model.model_type {
             ModelType::Llm => self.spawn_llm_model(model, None).await?,
             ModelType::Embedding => self.spawn_embedding_model(model).await,
-            ModelType::Transcription => Self::load_transcriber_model(&model).await,
+            ModelType::Transcription => self.load_transcriber_model(&model).await,
         };
         Ok(())
     }
@@ -375,21 +558,11 @@ impl AIService {
         Ok(llama)
     }
 
-    async fn build_remote_client(
-        model_id: String,
-        api_key: String,
-        base_url: Url,
-    ) -> ChatGPTClient {
-        let mut url = base_url;
-        if let Some(segments) = url.path_segments() {
-            if segments.clone().next() == Some("v1") {
-                url.set_path(&segments.skip(1).collect::<Vec<_>>().join("/"));
-            }
-        }
+    async fn build_remote_provider(model_id: String, api: ModelApi) -> Box<dyn RemoteProvider> {
         publish_model_status(model_id.clone(), 0.0, "Initializing", false, false).await;
-        let client = ChatGPTClient::new(&api_key, url.as_ref());
+        let provider = remote_provider(api);
         publish_model_status(model_id.clone(), 100.0, "Initializing", true, false).await;
-        client
+        provider
     }
 
     async fn spawn_llm_model(
@@ -427,10 +600,9 @@ impl AIService {
                                 .await
                                 .map(LlmModel::Local)
                         } else if let Some(api) = model_config.api {
-                            Ok(LlmModel::Remote((
-                                Self::build_remote_client(model_id, api.api_key, api.base_url).await,
-                                api.model
-                            )))
+                            Ok(LlmModel::Remote(
+                                Self::build_remote_provider(model_id, api).await,
+                            ))
                         } else {
                             Err(anyhow!("AI model definition {} doesn't have a body, and this error should have been caught above", model_config.name))
                         }
@@ -452,7 +624,10 @@ impl AIService {
                 };
 
                 let mut tasks = HashMap::<String, Task<Llama>>::new();
+                // Tasks with an output constraint also get a constrained variant for prompts
+                let mut constrained_tasks = HashMap::<String, Task<Llama, RegexParser>>::new();
                 let mut task_descriptions = HashMap::<String, AITask>::new();
+                let mut constraints = HashMap::<String, OutputConstraint>::new();
                 let idle_delay = Duration::from_millis(1);
 
                 rt.block_on(publish_model_status(
@@ -490,106 +665,115 @@ impl AIService {
                                 let _ = shutdown_request.result_sender.send(());
                                 break;
                             }
-                            LLMTaskRequest::Spawn(spawn_request) => match model {
-                                LlmModel::Remote(_) => {
-                                    task_descriptions.insert(
-                                        spawn_request.task.task_id.clone(),
-                                        spawn_request.task,
-                                    );
-                                    let _ = spawn_request.result_sender.send(Ok(()));
-                                }
-                                LlmModel::Local(ref mut llama) => {
-                                    rt.block_on(publish_model_status(
-                                        model_config.id.clone(),
-                                        100.0,
-                                        "Spawning task...",
-                                        true,
-                                        true,
-                                    ));
-                                    let task_description = spawn_request.task;
-
-                                    let task = llama
-                                        .task(task_description.system_prompt.clone())
-                                        .with_examples(
-                                            task_description
-                                                .prompt_examples
-                                                .clone()
-                                                .into_iter()
-                                                .map(|example| (example.input, example.output))
-                                                .collect::<Vec<(String, String)>>(),
-                                        );
+                            LLMTaskRequest::Spawn(spawn_request) => {
+                                let task_id = spawn_request.task.task_id.clone();
+                                let constraint =
+                                    match OutputConstraint::from_task(&spawn_request.task) {
+                                        Ok(constraint) => constraint,
+                                        Err(e) => {
+                                            let _ = spawn_request.result_sender.send(Err(e));
+                                            continue;
+                                        }
+                                    };
 
-                                    rt.block_on(task.run("Test example prompt").all_text());
+                                match model {
+                                    LlmModel::Remote(_) => {
+                                        task_descriptions
+                                            .insert(task_id.clone(), spawn_request.task);
+                                    }
+                                    LlmModel::Local(ref mut llama) => {
+                                        let parser = match constraint
+                                            .as_ref()
+                                            .map(|constraint| {
+                                                RegexParser::new(constraint.pattern())
+                                            })
+                                            .transpose()
+                                        {
+                                            Ok(parser) => parser,
+                                            Err(e) => {
+                                                let error =
+                                                    anyhow!("Invalid output constraint: {}", e);
+                                                let _ =
+                                                    spawn_request.result_sender.send(Err(error));
+                                                continue;
+                                            }
+                                        };
 
-                                    tasks.insert(task_description.task_id.clone(), task);
-                                    let _ = spawn_request.result_sender.send(Ok(()));
+                                        rt.block_on(publish_model_status(
+                                            model_config.id.clone(),
+                                            100.0,
+                                            "Spawning task...",
+                                            true,
+                                            true,
+                                        ));
+                                        let task_description = spawn_request.task;
+                                        let examples = task_description
+                                            .prompt_examples
+                                            .clone()
+                                            .into_iter()
+                                            .map(|example| (example.input, example.output))
+                                            .collect::<Vec<(String, String)>>();
+
+                                        let task = llama
+                                            .task(task_description.system_prompt.clone())
+                                            .with_examples(examples.clone());
+
+                                        rt.block_on(task.run("Test example prompt").all_text());
+
+                                        tasks.insert(task_id.clone(), task);
+
+                                        if let Some(parser) = parser {
+                                            constrained_tasks.insert(
+                                                task_id.clone(),
+                                                llama
+                                                    .task(task_description.system_prompt.clone())
+                                                    .with_examples(examples)
+                                                    .with_constraints(parser),
+                                            );
+                                        }
 
-                                    rt.block_on(publish_model_status(
-                                        model_config.id.clone(),
-                                        100.0,
-                                        "Ready",
-                                        true,
-                                        true,
-                                    ));
+                                        rt.block_on(publish_model_status(
+                                            model_config.id.clone(),
+                                            100.0,
+                                            "Ready",
+                                            true,
+                                            true,
+                                        ));
+                                    }
                                 }
-                            },
+
+                                match constraint {
+                                    Some(constraint) => {
+                                        constraints.insert(task_id, constraint);
+                                    }
+                                    None => {
+                                        constraints.remove(&task_id);
+                                        constrained_tasks.remove(&task_id);
+                                    }
+                                }
+                                let _ = spawn_request.result_sender.send(Ok(()));
+                            }
 
                             LLMTaskRequest::Prompt(prompt_request) => match model {
-                                LlmModel::Remote((ref mut remote_client, ref model_string)) => {
+                                LlmModel::Remote(ref provider) => {
                                     if let Some(task) =
                                         task_descriptions.get(&prompt_request.task_id)
                                     {
-                                        // System prompt
-                                        let mut messages = vec![Message {
-                                            role: Role::System,
-                                            content: task.system_prompt.clone(),
-                                        }];
-
-                                        // Examples
-                                        for example in task.prompt_examples.iter() {
-                                            messages.push(Message {
-                                                role: Role::User,
-                                                content: example.input.clone(),
-                                            });
-                                            messages.push(Message {
-                                                role: Role::Assistant,
-                                                content: example.output.clone(),
-                                            })
-                                        }
-
-                                        // Prompt
-                                        messages.push(Message {
-                                            role: Role::User,
-                                            content: prompt_request.prompt,
-                                        });
-
-                                        let chat_input = ChatInput {
-                                            model: chat_gpt_lib_rs::Model::Custom(
-                                                model_string.clone(),
-                                            ),
-                                            messages,
-                                            ..Default::default()
+                                        let messages = chat_messages(
+                                            task,
+                                            &prompt_request.history,
+                                            prompt_request.prompt,
+                                        );
+                                        let result = match constraints.get(&prompt_request.task_id)
+                                        {
+                                            Some(constraint) => rt.block_on(constrained_chat(
+                                                provider.as_ref(),
+                                                messages,
+                                                constraint,
+                                            )),
+                                            None => rt.block_on(provider.chat(messages)),
                                         };
-
-                                        match rt.block_on(remote_client.chat(chat_input)) {
-                                            Err(e) => {
-                                                let _ = prompt_request.result_sender.send(Err(
-                                                    anyhow!(
-                                                        "Error connecting to remote LLM API: {:?}",
-                                                        e
-                                                    ),
-                                                ));
-                                            }
-                                            Ok(response) => {
-                                                let result = response
-                                                    .choices
-                                                    .first()
-                                                    .map(|choice| choice.message.content.clone())
-                                                    .ok_or(anyhow!("Got response with no choice"));
-
-                                                let _ = prompt_request.result_sender.send(result);
-                                            }
-                                        }
+                                        let _ = prompt_request.result_sender.send(result);
                                     } else {
                                         let _ = prompt_request.result_sender.send(Err(anyhow!(
                                             "Task with ID {} not spawned",
@@ -607,9 +791,27 @@ impl AIService {
                                             true,
                                         ));
 
-                                        let result = rt.block_on(async {
-                                            task.run(prompt_request.prompt.clone()).all_text().await
-                                        });
+                                        let prompt = local_prompt(
+                                            &prompt_request.history,
+                                            prompt_request.prompt.clone(),
+                                        );
+                                        let result = match (
+                                            constrained_tasks.get(&prompt_request.task_id),
+                                            constraints.get(&prompt_request.task_id),
+                                        ) {
+                                            (Some(constrained_task), Some(constraint)) => {
+                                                let text = rt.block_on(
+                                                    constrained_task.run(prompt).all_text(),
+                                                );
+                                                constraint.validate(&text).map_err(|reason| {
+                                                    anyhow!(
+                                                        "Constrained response is invalid: {}",
+                                                        reason
+                                                    )
+                                                })
+                                            }
+                                            _ => Ok(rt.block_on(task.run(prompt).all_text())),
+                                        };
 
                                         rt.block_on(publish_model_status(
                                             model_config.id.clone(),
@@ -619,7 +821,7 @@ impl AIService {
                                             true,
                                         ));
 
-                                        let _ = prompt_request.result_sender.send(Ok(result));
+                                        let _ = prompt_request.result_sender.send(result);
                                     } else {
                                         let _ = prompt_request.result_sender.send(Err(anyhow!(
                                             "Task with ID {} not spawned",
@@ -629,9 +831,121 @@ impl AIService {
                                 }
                             },
 
+                            LLMTaskRequest::PromptStream(stream_request) => {
+                                let buffer = stream_request.buffer.clone();
+                                let cancelled = stream_request.cancelled.clone();
+                                let result = match model {
+                                    _ if cancelled.load(Ordering::Relaxed) => Ok(()),
+                                    LlmModel::Remote(ref provider) => {
+                                        match task_descriptions.get(&stream_request.task_id) {
+                                            Some(task) => {
+                                                let messages = chat_messages(
+                                                    task,
+                                                    &stream_request.history,
+                                                    stream_request.prompt,
+                                                );
+                                                match constraints.get(&stream_request.task_id) {
+                                                    // Constrained responses only get streamed once they are valid
+                                                    Some(constraint) => rt
+                                                        .block_on(constrained_chat(
+                                                            provider.as_ref(),
+                                                            messages,
+                                                            constraint,
+                                                        ))
+                                                        .map(|text| buffer.push(text, false, None)),
+                                                    None => rt.block_on(async {
+                                                        let mut tokens =
+                                                            provider.chat_stream(messages).await?;
+                                                        while let Some(text) = tokens.next().await {
+                                                            let text = text?;
+                                                            if cancelled.load(Ordering::Relaxed) {
+                                                                break;
+                                                            }
+                                                            buffer.push(text, false, None);
+                                                        }
+                                                        Ok::<(), AnyError>(())
+                                                    }),
+                                                }
+                                            }
+                                            None => Err(anyhow!(
+                                                "Task with ID {} not spawned",
+                                                stream_request.task_id
+                                            )),
+                                        }
+                                    }
+                                    LlmModel::Local(_) => {
+                                        match tasks.get(&stream_request.task_id) {
+                                            Some(task) => {
+                                                rt.block_on(publish_model_status(
+                                                    model_config.id.clone(),
+                                                    100.0,
+                                                    "Running inference...",
+                                                    true,
+                                                    true,
+                                                ));
+
+                                                let prompt = local_prompt(
+                                                    &stream_request.history,
+                                                    stream_request.prompt.clone(),
+                                                );
+                                                let result = match (
+                                                    constrained_tasks.get(&stream_request.task_id),
+                                                    constraints.get(&stream_request.task_id),
+                                                ) {
+                                                    (Some(constrained_task), Some(constraint)) => {
+                                                        let text = rt.block_on(push_prompt_tokens(
+                                                            &buffer,
+                                                            &cancelled,
+                                                            constrained_task.run(prompt),
+                                                        ));
+                                                        if cancelled.load(Ordering::Relaxed) {
+                                                            Ok(())
+                                                        } else {
+                                                            constraint
+                                                                .validate(&text)
+                                                                .map(|_| ())
+                                                                .map_err(|reason| {
+                                                                    anyhow!(
+                                                                        "Constrained response is invalid: {}",
+                                                                        reason
+                                                                    )
+                                                                })
+                                                        }
+                                                    }
+                                                    _ => {
+                                                        rt.block_on(push_prompt_tokens(
+                                                            &buffer,
+                                                            &cancelled,
+                                                            task.run(prompt),
+                                                        ));
+                                                        Ok(())
+                                                    }
+                                                };
+
+                                                rt.block_on(publish_model_status(
+                                                    model_config.id.clone(),
+                                                    100.0,
+                                                    "Ready",
+                                                    true,
+                                                    true,
+                                                ));
+                                                result
+                                            }
+                                            None => Err(anyhow!(
+                                                "Task with ID {} not spawned",
+                                                stream_request.task_id
+                                            )),
+                                        }
+                                    }
+                                };
+                                let _ = stream_request.result_sender.send(result);
+                            }
+
                             LLMTaskRequest::Remove(remove_request) => {
                                 let _ = tasks.remove(&remove_request.task_id);
+                                let _ = constrained_tasks.remove(&remove_request.task_id);
                                 let _ = task_descriptions.remove(&remove_request.task_id);
+                                let _ = constraints.remove(&remove_request.task_id);
                                 let _ = remove_request.result_sender.send(());
                             }
                         },
@@ -654,6 +968,12 @@ impl AIService {
     }
 
     pub async fn add_task(&self, task: AITaskInput) -> Result<AITask> {
+        let output_constraint: Option<AIOutputConstraint> =
+            task.output_constraint.clone().map(|c| c.into());
+        if let Some(constraint) = &output_constraint {
+            OutputConstraint::new(constraint)?;
+        }
+
         let task_id = Ad4mDb::with_global_instance(|db| {
             db.add_task(
                 task.name.clone(),
@@ -664,6 +984,7 @@ impl AIService {
                     .map(|p| p.clone().into())
                     .collect(),
                 task.meta_data.clone(),
+                output_constraint,
             )
         })
         .map_err(|e| AIServiceError::DatabaseError(e.to_string()))?;
@@ -695,10 +1016,7 @@ impl AIService {
                 result_sender: tx,
             }))?;
         } else {
-            return Err(anyhow::anyhow!(
-                "Model '{}' not found in LLM channel",
-                task.model_id
-            ));
+            return Err(ExecutorError::AiModelNotLoaded(task.model_id).into());
         }
 
         rx.await?
@@ -706,6 +1024,7 @@ impl AIService {
 
     pub async fn update_task(&self, task: AITask) -> Result<AITask> {
         let task_id = task.task_id.clone();
+        OutputConstraint::from_task(&task)?;
         Ad4mDb::with_global_instance(|db| {
             db.update_task(
                 task.task_id,
@@ -714,6 +1033,7 @@ impl AIService {
                 task.system_prompt,
                 task.prompt_examples,
                 task.meta_data,
+                task.output_constraint,
             )
         })
         .map_err(|e| AIServiceError::DatabaseError(e.to_string()))?;
@@ -751,7 +1071,7 @@ impl AIService {
                 result_sender: tx,
             }))?;
         } else {
-            return Err(anyhow::anyhow!("Model 'llama' not found in LLM channel"));
+            return Err(ExecutorError::AiModelNotLoaded(model_id).into());
         }
 
         rx.await?;
@@ -759,6 +1079,16 @@ impl AIService {
     }
 
     pub async fn prompt(&self, task_id: String, prompt: String) -> Result<String> {
+        self.prompt_with_history(task_id, Vec::new(), prompt).await
+    }
+
+    /// Prompts a task with earlier messages of a conversation as context
+    pub async fn prompt_with_history(
+        &self,
+        task_id: String,
+        history: Vec<AIMessage>,
+        prompt: String,
+    ) -> Result<String> {
         let (result_sender, rx) = oneshot::channel();
 
         // Retrieve the task to find the associated model_id
@@ -773,23 +1103,104 @@ impl AIService {
             sender.send(LLMTaskRequest::Prompt(LLMTaskPromptRequest {
                 task_id,
                 prompt,
+                history,
                 result_sender,
             }))?;
         } else {
-            return Err(anyhow::anyhow!(
-                "Model '{}' not found in LLM channel",
-                model_id
-            ));
+            return Err(ExecutorError::AiModelNotLoaded(model_id).into());
         }
 
         rx.await?
     }
 
+    /// Starts generating a response to `prompt` and returns the id of the stream
+    /// that `subscribe_prompt_stream()` delivers the generated text of
+    pub async fn prompt_stream(&self, task_id: String, prompt: String) -> Result<String> {
+        let task = Ad4mDb::with_global_instance(|db| db.get_task(task_id.clone()))
+            .map_err(|e| anyhow::anyhow!("Database error: {}", e))?
+            .ok_or_else(|| anyhow::anyhow!("Task not found for task_id: {}", task_id))?;
+
+        let model_id = Self::replace_model_variables(&task.model_id)?;
+
+        let stream_id = uuid::Uuid::new_v4().to_string();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let buffer = Arc::new(PromptStreamBuffer::new(stream_id.clone()));
+        let (result_sender, rx) = oneshot::channel();
+
+        self.prompt_streams.lock().await.insert(
+            stream_id.clone(),
+            PromptStreamSession {
+                cancelled: cancelled.clone(),
+                buffer: buffer.clone(),
+            },
+        );
+
+        let sent = match self.llm_channel.lock().await.get(&model_id) {
+            Some(sender) => sender
+                .send(LLMTaskRequest::PromptStream(LLMTaskPromptStreamRequest {
+                    task_id,
+                    prompt,
+                    history: Vec::new(),
+                    buffer: buffer.clone(),
+                    cancelled,
+                    result_sender,
+                }))
+                .map_err(|e| anyhow!(e.to_string())),
+            None => Err(ExecutorError::AiModelNotLoaded(model_id).into()),
+        };
+        if let Err(e) = sent {
+            self.prompt_streams.lock().await.remove(&stream_id);
+            return Err(e);
+        }
+
+        let prompt_streams = self.prompt_streams.clone();
+        let id = stream_id.clone();
+        tokio::spawn(async move {
+            let error = match rx.await {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(_) => Some("LLM model thread stopped".to_string()),
+            };
+            buffer.push(String::new(), true, error);
+            sleep(FINISHED_PROMPT_STREAM_RETENTION).await;
+            prompt_streams.lock().await.remove(&id);
+        });
+
+        Ok(stream_id)
+    }
+
+    /// Stops generating text for a prompt stream, which then ends with `done`
+    pub async fn cancel_prompt_stream(&self, stream_id: &str) -> Result<()> {
+        match self.prompt_streams.lock().await.get(stream_id) {
+            Some(session) => {
+                session.cancelled.store(true, Ordering::Relaxed);
+                Ok(())
+            }
+            None => Err(AIServiceError::StreamNotFound.into()),
+        }
+    }
+
+    /// All text of a prompt stream, from its start until it is done.
+    /// Finished streams can be subscribed to for `FINISHED_PROMPT_STREAM_RETENTION`.
+    pub async fn subscribe_prompt_stream(
+        &self,
+        stream_id: &str,
+    ) -> Result<impl futures::Stream<Item = AIPromptTokens>> {
+        match self.prompt_streams.lock().await.get(stream_id) {
+            Some(session) => Ok(session.buffer.subscribe()),
+            None => Err(AIServiceError::StreamNotFound.into()),
+        }
+    }
+
     // -------------------------------------
     // Embedding
     // -------------------------------------
 
     async fn spawn_embedding_model(&self, model_config: crate::types::Model) {
+        if let Some(api) = model_config.api.clone() {
+            return self.spawn_remote_embedding_model(model_config, api).await;
+        }
+
         let (bert_tx, mut bert_rx) = mpsc::unbounded_channel::<EmbeddingRequest>();
         let model_name = model_config.name.clone();
         thread::spawn({
@@ -845,6 +1256,24 @@ impl AIService {
             .insert(model_name, bert_tx);
     }
 
+    async fn spawn_remote_embedding_model(&self, model_config: crate::types::Model, api: ModelApi) {
+        let (embedding_tx, mut embedding_rx) = mpsc::unbounded_channel::<EmbeddingRequest>();
+        let provider = Self::build_remote_provider(model_config.id.clone(), api).await;
+        tokio::spawn(async move {
+            while let Some(request) = embedding_rx.recv().await {
+                let _ = request
+                    .result_sender
+                    .send(provider.embed(request.prompt).await);
+            }
+        });
+        publish_model_status(model_config.id.clone(), 100.0, "Ready", true, true).await;
+
+        self.embedding_channel
+            .lock()
+            .await
+            .insert(model_config.name, embedding_tx);
+    }
+
     pub async fn embed(&self, model_id: String, text: String) -> Result<Vec<f32>> {
         let (result_sender, rx) = oneshot::channel();
         let embedding_channel = self.embedding_channel.lock().await;
@@ -854,11 +1283,11 @@ impl AIService {
                 result_sender,
             })?;
         } else {
-            return Err(anyhow::anyhow!(
-                "Model '{}' not found in embedding channel. We have: {}",
-                model_id,
+            log::debug!(
+                "Embedding models loaded: {}",
                 embedding_channel.keys().join(",")
-            ));
+            );
+            return Err(ExecutorError::AiModelNotLoaded(model_id).into());
         }
 
         rx.await?
@@ -868,71 +1297,73 @@ impl AIService {
     // Whisper / Transcription
     // -------------------------------------
 
-    pub async fn open_transcription_stream(&self, _model_id: String) -> Result<String> {
+    pub async fn open_transcription_stream(&self, model_id: String) -> Result<String> {
+        let whisper = self
+            .whisper_model(WhisperSettings::for_model_id(&model_id)?)
+            .await?;
         let stream_id = uuid::Uuid::new_v4().to_string();
         let stream_id_clone = stream_id.clone();
         let (samples_tx, samples_rx) = futures_channel::mpsc::unbounded::<Vec<f32>>();
-        //TODO: use drop_rx to exit thread
         let (drop_tx, drop_rx) = oneshot::channel();
-        let (done_tx, done_rx) = oneshot::channel();
 
         thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
 
             rt.block_on(async {
-                let maybe_model = WhisperBuilder::default()
-                    .with_source(WHISPER_MODEL)
-                    .with_device(Self::new_candle_device())
-                    .build()
-                    .await;
-
-                if let Ok(whisper) = maybe_model {
-                    let audio_stream = AudioStream {
-                        read_data: Vec::new(),
-                        receiver: Box::pin(samples_rx.map(futures_util::stream::iter).flatten()),
-                    };
-
-                    let mut word_stream = audio_stream
-                        .voice_activity_stream()
-                        .rechunk_voice_activity()
-                        .with_end_window(Duration::from_millis(500))
-                        .transcribe(whisper);
-
-                    let _ = done_tx.send(Ok(()));
-
-                    tokio::select! {
-                        _ = drop_rx => {},
-                        _ = async {
-                            while let Some(segment) = word_stream.next().await {
-                                //println!("GOT segment: {}", segment.text());
-                                let stream_id_clone = stream_id_clone.clone();
-
-                                rt.spawn(async move {
-                                    let _ = get_global_pubsub()
-                                        .await
-                                        .publish(
-                                            &AI_TRANSCRIPTION_TEXT_TOPIC,
-                                            &serde_json::to_string(&TranscriptionTextFilter {
-                                                stream_id: stream_id_clone.clone(),
-                                                text: segment.text().to_string(),
-                                            })
-                                            .expect("TranscriptionTextFilter must be serializable"),
-                                        )
-                                        .await;
-                                });
+                let samples_read = Arc::new(AtomicUsize::new(0));
+                let audio_stream = AudioStream {
+                    samples_read: samples_read.clone(),
+                    receiver: Box::pin(samples_rx.map(futures_util::stream::iter).flatten()),
+                };
 
-                                sleep(Duration::from_millis(50)).await;
+                let end_window = Duration::from_millis(500);
+                let mut chunks = audio_stream
+                    .voice_activity_stream()
+                    .rechunk_voice_activity()
+                    .with_end_window(end_window);
+
+                // Closing the stream sends on drop_rx, which ends this thread
+                tokio::select! {
+                    _ = drop_rx => {},
+                    _ = async {
+                        while let Some(chunk) = chunks.next().await {
+                            // A chunk is complete once `end_window` of silence
+                            // has been read after it
+                            let chunk_duration = chunk
+                                .total_duration()
+                                .map(|duration| duration.as_secs_f64())
+                                .unwrap_or_default();
+                            let offset = (samples_read.load(Ordering::Relaxed) as f64
+                                / SAMPLE_RATE as f64
+                                - end_window.as_secs_f64()
+                                - chunk_duration)
+                                .max(0.0);
+
+                            let mut segments = match whisper.transcribe(chunk) {
+                                Ok(segments) => segments,
+                                Err(e) => {
+                                    error!(
+                                        "Error transcribing audio of stream {}: {}",
+                                        stream_id_clone, e
+                                    );
+                                    continue;
+                                }
+                            };
+                            while let Some(segment) = segments.next().await {
+                                publish_transcription_segment(
+                                    &stream_id_clone,
+                                    transcription_segment(&segment, offset),
+                                )
+                                .await;
                             }
-                        } => {}
-                    }
-                } else {
-                    let _ = done_tx.send(Err(maybe_model.err().unwrap()));
+
+                            sleep(Duration::from_millis(50)).await;
+                        }
+                    } => {}
                 }
             });
         });
 
-        done_rx.await??;
-
         self.transcription_streams.lock().await.insert(
             stream_id.clone(),
             TranscriptionSession {
@@ -974,20 +1405,40 @@ impl AIService {
         }
     }
 
-    async fn load_transcriber_model(model: &crate::types::Model) {
+    async fn load_transcriber_model(&self, model: &crate::types::Model) {
         let id = &model.id;
         publish_model_status(id.clone(), 0.0, "Loading", false, false).await;
 
-        let _ = WhisperBuilder::default()
-            .with_source(WHISPER_MODEL)
-            .with_device(Self::new_candle_device())
-            .build_with_loading_handler({
-                let name = id.clone();
-                move |progress| {
-                    tokio::spawn(handle_progress(name.clone(), progress));
+        let settings = match WhisperSettings::from_model(model) {
+            Ok(settings) => settings,
+            Err(e) => {
+                error!("Invalid transcription model {}: {}", id, e);
+                return;
+            }
+        };
+
+        let mut models = self.whisper_models.lock().await;
+        if !models.contains_key(&settings.key) {
+            let key = settings.key.clone();
+            let whisper = settings
+                .builder()
+                .build_with_loading_handler({
+                    let name = id.clone();
+                    move |progress| {
+                        tokio::spawn(handle_progress(name.clone(), progress));
+                    }
+                })
+                .await;
+            match whisper {
+                Ok(whisper) => {
+                    models.insert(key, Arc::new(whisper));
                 }
-            })
-            .await;
+                Err(e) => {
+                    error!("Failed to load transcription model {}: {}", id, e);
+                    return;
+                }
+            }
+        }
 
         publish_model_status(id.clone(), 100.0, "Loaded", true, false).await;
     }
@@ -1060,7 +1511,8 @@ impl AIService {
                 // TODO: Handle embedding model updates
             }
             ModelType::Transcription => {
-                // TODO: Handle transcription model updates
+                self.unload_whisper_model(&existing_model).await;
+                self.load_transcriber_model(&updated_model).await;
             }
         }
 
@@ -1102,7 +1554,7 @@ impl AIService {
                 // TODO: Handle embedding model removal
             }
             ModelType::Transcription => {
-                // TODO: Handle transcription model removal
+                self.unload_whisper_model(&existing_model).await;
             }
         }
 
@@ -1126,6 +1578,26 @@ mod tests {
     // -> need to refactor this so that services like AIService or PerspectiveInstance
     // get an DB reference passed in, so we can write proper unit tests.
 
+    #[tokio::test]
+    async fn prompt_stream_buffer_replays_to_late_subscribers() {
+        let buffer = PromptStreamBuffer::new("stream".to_string());
+        buffer.push("Hello".to_string(), false, None);
+        let live = buffer.subscribe();
+        buffer.push(" world".to_string(), false, None);
+        buffer.push(String::new(), true, None);
+
+        let text = |events: Vec<AIPromptTokens>| {
+            assert!(events.last().unwrap().done);
+            events
+                .into_iter()
+                .map(|tokens| tokens.text)
+                .collect::<String>()
+        };
+        assert_eq!(text(live.collect().await), "Hello world");
+        // Subscribing after the stream is done still gives all of it and ends
+        assert_eq!(text(buffer.subscribe().collect().await), "Hello world");
+    }
+
     #[ignore]
     #[tokio::test]
     async fn test_embedding() {
@@ -1152,7 +1624,8 @@ mod tests {
                     input: "Test string".into(),
                     output: "Yes, I'm working!".into()
                 }],
-                meta_data: None
+                meta_data: None,
+                output_constraint: None,
             }).await.expect("add_task to work without error");
 
         let response = service
@@ -1174,6 +1647,7 @@ mod tests {
                 model_id: "llama".into(),
                 system_prompt: "You are inside a test for tasks. Please make sure to create any non-zero length output".into(),
                 meta_data: None,
+                output_constraint: None,
                 prompt_examples: vec![AIPromptExamplesInput{
                     input: "Test string".into(),
                     output: "Yes, I'm working!".into()
@@ -1226,6 +1700,7 @@ mod tests {
                 tokenizer_source: None,
                 huggingface_repo: None,
                 revision: None,
+                language: None,
             }),
             api: None,
         };
@@ -1277,6 +1752,7 @@ mod tests {
                 tokenizer_source: None,
                 huggingface_repo: None,
                 revision: None,
+                language: None,
             }),
             api: None,
         };
@@ -1294,6 +1770,7 @@ mod tests {
                 system_prompt: "Test prompt".into(),
                 prompt_examples: vec![],
                 meta_data: None,
+                output_constraint: None,
             })
             .await
             .expect("task to be created");
@@ -0,0 +1,565 @@
+//! Constraints on the format of responses to a task's prompts. JSON Schemas get
+//! compiled to regular expressions, so that local models can be restricted to
+//! them while sampling. Responses of remote models get validated instead.
+
+use super::remote_providers::RemoteProvider;
+use super::Result;
+use crate::errors::ExecutorError;
+use crate::types::{AIOutputConstraint, AITask};
+use anyhow::anyhow;
+use chat_gpt_lib_rs::{Message, Role};
+use deno_core::error::AnyError;
+use regex::Regex;
+use serde_json::{Map, Value};
+
+/// How often a remote model gets to respond before giving up on a valid response
+const MAX_ATTEMPTS: usize = 3;
+
+/// Keywords that can be enforced while sampling
+const KEYWORDS: [&str; 8] = [
+    "type",
+    "properties",
+    "required",
+    "additionalProperties",
+    "items",
+    "enum",
+    "const",
+    "anyOf",
+];
+
+/// Keywords that only describe a schema
+const ANNOTATIONS: [&str; 6] = [
+    "$schema",
+    "$id",
+    "title",
+    "description",
+    "examples",
+    "default",
+];
+
+const STRING: &str = r#""([^"\\\x00-\x1F]|\\["\\/bfnrt]|\\u[0-9a-fA-F]{4})*""#;
+const INTEGER: &str = r"-?(0|[1-9][0-9]*)";
+const NUMBER: &str = r"-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?";
+
+fn invalid(message: String) -> AnyError {
+    ExecutorError::InvalidInput(message).into()
+}
+
+fn alternatives(patterns: Vec<String>) -> String {
+    format!("({})", patterns.join("|"))
+}
+
+fn literal_regex(value: &Value) -> String {
+    regex::escape(&value.to_string())
+}
+
+/// Regular expression for the compact JSON of instances of `schema`.
+/// Fails for schemas that use keywords which can't be enforced.
+fn schema_regex(schema: &Value) -> Result<String> {
+    let schema = schema
+        .as_object()
+        .ok_or_else(|| invalid(format!("Not a supported JSON Schema: {}", schema)))?;
+    if let Some(keyword) = schema.keys().find(|keyword| {
+        !KEYWORDS.contains(&keyword.as_str()) && !ANNOTATIONS.contains(&keyword.as_str())
+    }) {
+        return Err(invalid(format!(
+            "JSON Schema keyword {} is not supported in output constraints",
+            keyword
+        )));
+    }
+
+    if let Some(value) = schema.get("const") {
+        return Ok(literal_regex(value));
+    }
+    if let Some(values) = schema.get("enum") {
+        return match values.as_array() {
+            Some(values) if !values.is_empty() => {
+                Ok(alternatives(values.iter().map(literal_regex).collect()))
+            }
+            _ => Err(invalid("enum has to be a non-empty array".to_string())),
+        };
+    }
+    if let Some(schemas) = schema.get("anyOf") {
+        if schema.contains_key("type") {
+            return Err(invalid("anyOf can't be combined with type".to_string()));
+        }
+        return match schemas.as_array() {
+            Some(schemas) if !schemas.is_empty() => Ok(alternatives(
+                schemas.iter().map(schema_regex).collect::<Result<_>>()?,
+            )),
+            _ => Err(invalid("anyOf has to be a non-empty array".to_string())),
+        };
+    }
+
+    match schema.get("type") {
+        Some(Value::String(type_name)) => type_regex(type_name, schema),
+        Some(Value::Array(type_names)) if !type_names.is_empty() => Ok(alternatives(
+            type_names
+                .iter()
+                .map(|type_name| match type_name.as_str() {
+                    Some(type_name) => type_regex(type_name, schema),
+                    None => Err(invalid(format!("Not a JSON Schema type: {}", type_name))),
+                })
+                .collect::<Result<_>>()?,
+        )),
+        _ => Err(invalid(
+            "JSON Schemas in output constraints need a type, enum, const or anyOf".to_string(),
+        )),
+    }
+}
+
+fn type_regex(type_name: &str, schema: &Map<String, Value>) -> Result<String> {
+    Ok(match type_name {
+        "string" => STRING.to_string(),
+        "integer" => INTEGER.to_string(),
+        "number" => NUMBER.to_string(),
+        "boolean" => "(true|false)".to_string(),
+        "null" => "null".to_string(),
+        "array" => {
+            let items = schema
+                .get("items")
+                .ok_or_else(|| invalid("Arrays in output constraints need items".to_string()))?;
+            let item = schema_regex(items)?;
+            format!(r"\[({}(,{})*)?\]", item, item)
+        }
+        "object" => object_regex(schema)?,
+        _ => return Err(invalid(format!("Unknown JSON Schema type: {}", type_name))),
+    })
+}
+
+/// Objects get generated with all of their properties, in the order of the schema
+fn object_regex(schema: &Map<String, Value>) -> Result<String> {
+    let properties = schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .ok_or_else(|| invalid("Objects in output constraints need properties".to_string()))?;
+    if let Some(Value::Array(required)) = schema.get("required") {
+        if let Some(name) = required
+            .iter()
+            .find(|name| !matches!(name.as_str(), Some(name) if properties.contains_key(name)))
+        {
+            return Err(invalid(format!(
+                "Required property {} is missing in properties",
+                name
+            )));
+        }
+    }
+
+    let properties = properties
+        .iter()
+        .map(|(name, property)| {
+            Ok(format!(
+                "{}:{}",
+                literal_regex(&Value::String(name.clone())),
+                schema_regex(property)?
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(format!(r"\{{{}\}}", properties.join(",")))
+}
+
+fn has_type(value: &Value, type_name: &str) -> bool {
+    match type_name {
+        "string" => value.is_string(),
+        "integer" => matches!(value.as_f64(), Some(number) if number.fract() == 0.0),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => false,
+    }
+}
+
+/// Checks `value` against a schema that `schema_regex` accepted
+fn check_instance(value: &Value, schema: &Value, path: &str) -> std::result::Result<(), String> {
+    let schema = match schema.as_object() {
+        Some(schema) => schema,
+        None => return Ok(()),
+    };
+
+    if let Some(expected) = schema.get("const") {
+        if value != expected {
+            return Err(format!("{} has to be {}", path, expected));
+        }
+    }
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        if !values.contains(value) {
+            return Err(format!("{} has to be one of {}", path, schema["enum"]));
+        }
+    }
+    if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+        if !schemas
+            .iter()
+            .any(|schema| check_instance(value, schema, path).is_ok())
+        {
+            return Err(format!(
+                "{} doesn't match any of the schemas in anyOf",
+                path
+            ));
+        }
+    }
+    let type_matches = match schema.get("type") {
+        Some(Value::String(type_name)) => has_type(value, type_name),
+        Some(Value::Array(type_names)) => type_names
+            .iter()
+            .any(|type_name| matches!(type_name.as_str(), Some(t) if has_type(value, t))),
+        _ => true,
+    };
+    if !type_matches {
+        return Err(format!("{} has to be of type {}", path, schema["type"]));
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            check_instance(item, item_schema, &format!("{}[{}]", path, index))?;
+        }
+    }
+    if let Value::Object(object) = value {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(name) {
+                    return Err(format!("{} is missing property {}", path, name));
+                }
+            }
+        }
+        for (name, property) in object {
+            match properties.and_then(|properties| properties.get(name)) {
+                Some(property_schema) => {
+                    check_instance(property, property_schema, &format!("{}.{}", path, name))?
+                }
+                None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                    return Err(format!("{} has unexpected property {}", path, name))
+                }
+                None => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `text` without a surrounding Markdown code block, which models like to add
+fn strip_code_block(text: &str) -> &str {
+    let text = text.trim();
+    match text
+        .strip_prefix("```")
+        .and_then(|text| text.strip_suffix("```"))
+    {
+        Some(code) => code.strip_prefix("json").unwrap_or(code).trim(),
+        None => text,
+    }
+}
+
+pub enum OutputConstraint {
+    JsonSchema { schema: Value, pattern: String },
+    Regex { pattern: String, whole_match: Regex },
+}
+
+impl OutputConstraint {
+    /// Checks that `constraint` can be enforced and prepares it
+    pub fn new(constraint: &AIOutputConstraint) -> Result<Option<Self>> {
+        let constraint = match (&constraint.json_schema, &constraint.regex) {
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => {
+                return Err(invalid(
+                    "Output constraints can have a JSON Schema or a regex, not both".to_string(),
+                ))
+            }
+            (Some(schema), None) => {
+                let schema: Value = serde_json::from_str(schema)
+                    .map_err(|e| invalid(format!("Output JSON Schema is not valid JSON: {}", e)))?;
+                OutputConstraint::JsonSchema {
+                    pattern: schema_regex(&schema)?,
+                    schema,
+                }
+            }
+            (None, Some(pattern)) => OutputConstraint::Regex {
+                whole_match: Regex::new(&format!("^(?:{})$", pattern))
+                    .map_err(|e| invalid(format!("Invalid output regex: {}", e)))?,
+                pattern: pattern.clone(),
+            },
+        };
+        Ok(Some(constraint))
+    }
+
+    pub fn from_task(task: &AITask) -> Result<Option<Self>> {
+        match &task.output_constraint {
+            Some(constraint) => Self::new(constraint),
+            None => Ok(None),
+        }
+    }
+
+    /// Regular expression that local models are constrained to while sampling
+    pub fn pattern(&self) -> &str {
+        match self {
+            OutputConstraint::JsonSchema { pattern, .. } => pattern,
+            OutputConstraint::Regex { pattern, .. } => pattern,
+        }
+    }
+
+    pub fn json_schema(&self) -> Option<&Value> {
+        match self {
+            OutputConstraint::JsonSchema { schema, .. } => Some(schema),
+            OutputConstraint::Regex { .. } => None,
+        }
+    }
+
+    /// The response if it satisfies the constraint (JSON without a surrounding
+    /// code block), the reason why not otherwise
+    pub fn validate(&self, response: &str) -> std::result::Result<String, String> {
+        match self {
+            OutputConstraint::JsonSchema { schema, .. } => {
+                let json = strip_code_block(response);
+                let value: Value = serde_json::from_str(json)
+                    .map_err(|e| format!("Response is not valid JSON: {}", e))?;
+                check_instance(&value, schema, "$")?;
+                Ok(json.to_string())
+            }
+            OutputConstraint::Regex {
+                pattern,
+                whole_match,
+            } => {
+                if whole_match.is_match(response) {
+                    Ok(response.to_string())
+                } else {
+                    Err(format!("Response doesn't match {}", pattern))
+                }
+            }
+        }
+    }
+
+    /// Prompt that asks a model to correct an invalid response
+    fn correction_prompt(&self, reason: &str) -> String {
+        match self {
+            OutputConstraint::JsonSchema { schema, .. } => format!(
+                "{}. Respond again, with nothing but JSON that is valid against this JSON Schema: {}",
+                reason, schema
+            ),
+            OutputConstraint::Regex { pattern, .. } => format!(
+                "{}. Respond again, with nothing but text that matches the regular expression {}",
+                reason, pattern
+            ),
+        }
+    }
+}
+
+/// Chats with a remote model until it gives a response that satisfies
+/// `constraint`, telling it what was wrong with the previous one
+pub(super) async fn constrained_chat(
+    provider: &dyn RemoteProvider,
+    mut messages: Vec<Message>,
+    constraint: &OutputConstraint,
+) -> Result<String> {
+    let mut reason = String::new();
+    for _ in 0..MAX_ATTEMPTS {
+        let response = match constraint.json_schema() {
+            Some(schema) => provider.chat_json(messages.clone(), schema.clone()).await?,
+            None => provider.chat(messages.clone()).await?,
+        };
+        match constraint.validate(&response) {
+            Ok(response) => return Ok(response),
+            Err(invalid) => {
+                messages.push(Message {
+                    role: Role::Assistant,
+                    content: response,
+                });
+                messages.push(Message {
+                    role: Role::User,
+                    content: constraint.correction_prompt(&invalid),
+                });
+                reason = invalid;
+            }
+        }
+    }
+    Err(anyhow!(
+        "Model gave no valid response in {} attempts: {}",
+        MAX_ATTEMPTS,
+        reason
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::remote_providers::TokenStream;
+    use super::*;
+    use futures::future::BoxFuture;
+    use futures::FutureExt;
+    use std::sync::Mutex;
+
+    fn json_schema(schema: &str) -> OutputConstraint {
+        OutputConstraint::new(&AIOutputConstraint {
+            json_schema: Some(schema.to_string()),
+            regex: None,
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    const PERSON: &str = r#"{
+        "title": "Person",
+        "type": "object",
+        "properties": {
+            "age": {"type": "integer"},
+            "name": {"type": "string"},
+            "pet": {"anyOf": [{"type": "null"}, {"type": "string"}]},
+            "tags": {"type": "array", "items": {"enum": ["a", "b"]}}
+        },
+        "required": ["name", "age"],
+        "additionalProperties": false
+    }"#;
+
+    #[test]
+    fn compiles_json_schema_to_regex() {
+        let constraint = json_schema(PERSON);
+        let regex = Regex::new(&format!("^(?:{})$", constraint.pattern())).unwrap();
+
+        assert!(regex.is_match(r#"{"age":36,"name":"Ada \"A\"","pet":null,"tags":["a","b"]}"#));
+        assert!(regex.is_match(r#"{"age":-1,"name":"Bob","pet":"cat","tags":[]}"#));
+        assert!(!regex.is_match(r#"{"age":3.5,"name":"Ada","pet":null,"tags":[]}"#));
+        assert!(!regex.is_match(r#"{"age":36,"name":"Ada","pet":null,"tags":["c"]}"#));
+    }
+
+    #[test]
+    fn rejects_what_cant_be_enforced() {
+        for schema in [
+            r#"{"type": "string", "pattern": "^a"}"#,
+            r#"{"type": "object"}"#,
+            r#"{"type": "array"}"#,
+            r#"{"type": "object", "properties": {}, "required": ["name"]}"#,
+            "true",
+            "not json",
+        ] {
+            assert!(
+                OutputConstraint::new(&AIOutputConstraint {
+                    json_schema: Some(schema.to_string()),
+                    regex: None,
+                })
+                .is_err(),
+                "{}",
+                schema
+            );
+        }
+
+        assert!(OutputConstraint::new(&AIOutputConstraint {
+            json_schema: Some(r#"{"type": "string"}"#.to_string()),
+            regex: Some("a+".to_string()),
+        })
+        .is_err());
+        assert!(OutputConstraint::new(&AIOutputConstraint::default())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn validates_json_responses() {
+        let constraint = json_schema(PERSON);
+
+        assert_eq!(
+            constraint.validate("```json\n{\n  \"age\": 36,\n  \"name\": \"Ada\"\n}\n```"),
+            Ok("{\n  \"age\": 36,\n  \"name\": \"Ada\"\n}".to_string())
+        );
+        assert!(constraint
+            .validate(r#"{"name": "Ada"}"#)
+            .unwrap_err()
+            .contains("missing property age"));
+        assert!(constraint
+            .validate(r#"{"name": "Ada", "age": 36, "tags": ["c"]}"#)
+            .unwrap_err()
+            .contains("$.tags[0]"));
+        assert!(constraint
+            .validate(r#"{"name": "Ada", "age": 36, "job": "engineer"}"#)
+            .is_err());
+        assert!(constraint.validate("Sure! Here is the JSON").is_err());
+    }
+
+    #[test]
+    fn validates_whole_responses_against_regex() {
+        let constraint = OutputConstraint::new(&AIOutputConstraint {
+            json_schema: None,
+            regex: Some("yes|no".to_string()),
+        })
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(constraint.validate("no"), Ok("no".to_string()));
+        assert!(constraint.validate("no way").is_err());
+        assert!(OutputConstraint::new(&AIOutputConstraint {
+            json_schema: None,
+            regex: Some("(unclosed".to_string()),
+        })
+        .is_err());
+    }
+
+    /// Gives the prepared responses in order and records the chats it got
+    struct ScriptedProvider {
+        responses: Mutex<Vec<String>>,
+        chats: Mutex<Vec<Vec<Message>>>,
+    }
+
+    impl RemoteProvider for ScriptedProvider {
+        fn chat(&self, messages: Vec<Message>) -> BoxFuture<'_, Result<String>> {
+            self.chats.lock().unwrap().push(messages);
+            let response = self.responses.lock().unwrap().remove(0);
+            async move { Ok(response) }.boxed()
+        }
+
+        fn chat_json(
+            &self,
+            messages: Vec<Message>,
+            _schema: Value,
+        ) -> BoxFuture<'_, Result<String>> {
+            self.chat(messages)
+        }
+
+        fn chat_stream(&self, _messages: Vec<Message>) -> BoxFuture<'_, Result<TokenStream>> {
+            async { Err(anyhow!("ScriptedProvider does not stream")) }.boxed()
+        }
+
+        fn embed(&self, _text: String) -> BoxFuture<'_, Result<Vec<f32>>> {
+            async { Err(anyhow!("ScriptedProvider does not embed")) }.boxed()
+        }
+    }
+
+    fn scripted(responses: &[&str]) -> ScriptedProvider {
+        ScriptedProvider {
+            responses: Mutex::new(responses.iter().map(|r| r.to_string()).collect()),
+            chats: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn prompt() -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            content: "Who are you?".to_string(),
+        }]
+    }
+
+    #[tokio::test]
+    async fn asks_remote_model_to_correct_invalid_responses() {
+        let provider = scripted(&["I'm Ada", r#"{"name": "Ada", "age": 36}"#]);
+
+        let response = constrained_chat(&provider, prompt(), &json_schema(PERSON))
+            .await
+            .unwrap();
+        assert_eq!(response, r#"{"name": "Ada", "age": 36}"#);
+
+        let chats = provider.chats.lock().unwrap();
+        assert_eq!(chats.len(), 2);
+        assert_eq!(chats[1].len(), 3);
+        assert_eq!(chats[1][1].content, "I'm Ada");
+        assert!(chats[1][2]
+            .content
+            .starts_with("Response is not valid JSON"));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let provider = scripted(&["a", "b", "c"]);
+
+        assert!(constrained_chat(&provider, prompt(), &json_schema(PERSON))
+            .await
+            .is_err());
+        assert_eq!(provider.chats.lock().unwrap().len(), MAX_ATTEMPTS);
+    }
+}
@@ -0,0 +1,127 @@
+//! Remote APIs that LLM and embedding models can run on, one implementation
+//! of `RemoteProvider` per `ModelApiType`.
+
+use super::Result;
+use crate::types::{ModelApi, ModelApiType};
+use anyhow::anyhow;
+use chat_gpt_lib_rs::Message;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+
+mod ollama;
+mod openai;
+
+/// Generated text, piece by piece
+pub type TokenStream = BoxStream<'static, Result<String>>;
+
+pub trait RemoteProvider: Send + Sync {
+    /// Response of the model to the last message of a chat
+    fn chat(&self, messages: Vec<Message>) -> BoxFuture<'_, Result<String>>;
+
+    /// Like `chat` but asks the API for JSON that is valid against `schema`
+    fn chat_json(
+        &self,
+        messages: Vec<Message>,
+        schema: serde_json::Value,
+    ) -> BoxFuture<'_, Result<String>>;
+
+    /// Like `chat` but yields the response while it is being generated
+    fn chat_stream(&self, messages: Vec<Message>) -> BoxFuture<'_, Result<TokenStream>>;
+
+    fn embed(&self, text: String) -> BoxFuture<'_, Result<Vec<f32>>>;
+}
+
+pub fn remote_provider(api: ModelApi) -> Box<dyn RemoteProvider> {
+    match api.api_type {
+        ModelApiType::OpenAi => Box::new(openai::OpenAiProvider::new(api)),
+        ModelApiType::Ollama => Box::new(ollama::OllamaProvider::new(api)),
+    }
+}
+
+/// `{base_url}/{path}` without doubled slashes
+fn api_url(api: &ModelApi, path: &str) -> String {
+    format!("{}/{}", api.base_url.as_str().trim_end_matches('/'), path)
+}
+
+/// Sends a JSON POST request with the API key and custom headers of `api`
+/// and fails with the response body if the request was not successful
+async fn post_json(
+    api: &ModelApi,
+    url: String,
+    body: serde_json::Value,
+) -> Result<reqwest::Response> {
+    let mut request = reqwest::Client::new().post(url).json(&body);
+    if !api.api_key.is_empty() {
+        request = request.bearer_auth(&api.api_key);
+    }
+    for header in api.headers.iter() {
+        request = request.header(&header.name, &header.value);
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| anyhow!("Error connecting to remote model API: {:?}", e))?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(anyhow!("Remote model API returned {}: {}", status, body));
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod mock {
+    use std::sync::Mutex;
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+    use url::Url;
+    use warp::http::{HeaderMap, Method, Response};
+    use warp::Filter;
+
+    /// A request the mock server received
+    pub struct Request {
+        pub method: Method,
+        pub path: String,
+        pub headers: HeaderMap,
+        pub body: serde_json::Value,
+    }
+
+    /// Serves a single request on localhost with `body` and returns the
+    /// server's URL and the request it received
+    pub async fn serve_once(content_type: &str, body: &str) -> (Url, JoinHandle<Request>) {
+        let (request_tx, request_rx) = oneshot::channel();
+        let request_tx = Mutex::new(Some(request_tx));
+        let content_type = content_type.to_string();
+        let body = body.to_string();
+
+        let route = warp::method()
+            .and(warp::path::full())
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(
+                move |method: Method,
+                      path: warp::path::FullPath,
+                      headers: HeaderMap,
+                      request_body: warp::hyper::body::Bytes| {
+                    if let Some(request_tx) = request_tx.lock().unwrap().take() {
+                        let _ = request_tx.send(Request {
+                            method,
+                            path: path.as_str().to_string(),
+                            headers,
+                            body: serde_json::from_slice(&request_body).unwrap(),
+                        });
+                    }
+                    Response::builder()
+                        .header("content-type", content_type.as_str())
+                        .body(body.clone())
+                        .unwrap()
+                },
+            );
+        let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let url = Url::parse(&format!("http://{}", address)).unwrap();
+        (url, tokio::spawn(async { request_rx.await.unwrap() }))
+    }
+}
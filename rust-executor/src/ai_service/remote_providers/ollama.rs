@@ -0,0 +1,266 @@
+//! Ollama's native API. Streamed chat responses arrive as one JSON object per line.
+
+use super::{api_url, post_json, RemoteProvider, TokenStream};
+use crate::ai_service::Result;
+use crate::types::ModelApi;
+use anyhow::anyhow;
+use chat_gpt_lib_rs::Message;
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use std::collections::VecDeque;
+
+/// Message content of a chat response (or one line of a streamed one)
+fn message_content(line: &str) -> Result<Option<String>> {
+    let chunk: serde_json::Value = serde_json::from_str(line)
+        .map_err(|e| anyhow!("Invalid chat response from Ollama: {}", e))?;
+    if let Some(error) = chunk.get("error") {
+        return Err(anyhow!("Ollama error: {}", error));
+    }
+    Ok(chunk["message"]["content"].as_str().map(str::to_string))
+}
+
+struct ChatStream {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+    tokens: VecDeque<String>,
+    done: bool,
+}
+
+impl ChatStream {
+    fn push_lines(&mut self) -> Result<()> {
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(token) = message_content(&line)? {
+                if !token.is_empty() {
+                    self.tokens.push_back(token);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn next_token(&mut self) -> Result<Option<String>> {
+        loop {
+            if let Some(token) = self.tokens.pop_front() {
+                return Ok(Some(token));
+            }
+            if self.done {
+                return Ok(None);
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => {
+                    // The last line doesn't have to end with a newline
+                    self.buffer.push(b'\n');
+                    self.done = true;
+                }
+            }
+            self.push_lines()?;
+        }
+    }
+
+    fn into_stream(self) -> TokenStream {
+        futures::stream::unfold(self, |mut stream| async move {
+            match stream.next_token().await {
+                Ok(Some(token)) => Some((Ok(token), stream)),
+                Ok(None) => None,
+                Err(e) => {
+                    stream.done = true;
+                    stream.tokens.clear();
+                    Some((Err(e), stream))
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+pub struct OllamaProvider {
+    api: ModelApi,
+}
+
+impl OllamaProvider {
+    pub fn new(api: ModelApi) -> Self {
+        Self { api }
+    }
+
+    fn chat_request(&self, messages: Vec<Message>, stream: bool) -> serde_json::Value {
+        serde_json::json!({
+            "model": self.api.model,
+            "messages": messages,
+            "stream": stream,
+        })
+    }
+
+    async fn chat_response(&self, request: serde_json::Value) -> Result<String> {
+        let response = post_json(&self.api, api_url(&self.api, "api/chat"), request)
+            .await?
+            .text()
+            .await?;
+        message_content(&response)?.ok_or(anyhow!("Got response with no message"))
+    }
+}
+
+impl RemoteProvider for OllamaProvider {
+    fn chat(&self, messages: Vec<Message>) -> BoxFuture<'_, Result<String>> {
+        self.chat_response(self.chat_request(messages, false))
+            .boxed()
+    }
+
+    fn chat_json(
+        &self,
+        messages: Vec<Message>,
+        schema: serde_json::Value,
+    ) -> BoxFuture<'_, Result<String>> {
+        let mut request = self.chat_request(messages, false);
+        request["format"] = schema;
+        self.chat_response(request).boxed()
+    }
+
+    fn chat_stream(&self, messages: Vec<Message>) -> BoxFuture<'_, Result<TokenStream>> {
+        async move {
+            let response = post_json(
+                &self.api,
+                api_url(&self.api, "api/chat"),
+                self.chat_request(messages, true),
+            )
+            .await?;
+            Ok(ChatStream {
+                response,
+                buffer: Vec::new(),
+                tokens: VecDeque::new(),
+                done: false,
+            }
+            .into_stream())
+        }
+        .boxed()
+    }
+
+    fn embed(&self, text: String) -> BoxFuture<'_, Result<Vec<f32>>> {
+        async move {
+            let response: serde_json::Value = post_json(
+                &self.api,
+                api_url(&self.api, "api/embed"),
+                serde_json::json!({
+                    "model": self.api.model,
+                    "input": text,
+                }),
+            )
+            .await?
+            .json()
+            .await?;
+            serde_json::from_value(response["embeddings"][0].clone())
+                .map_err(|e| anyhow!("Invalid embedding from Ollama: {}", e))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::serve_once;
+    use super::*;
+    use crate::types::{ModelApiHeader, ModelApiType};
+    use chat_gpt_lib_rs::Role;
+    use url::Url;
+
+    fn api(base_url: Url) -> ModelApi {
+        ModelApi {
+            base_url,
+            api_key: String::new(),
+            model: "llama3.2".to_string(),
+            api_type: ModelApiType::Ollama,
+            headers: vec![ModelApiHeader {
+                name: "X-Team".to_string(),
+                value: "research".to_string(),
+            }],
+        }
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            content: "Hi".to_string(),
+        }]
+    }
+
+    #[tokio::test]
+    async fn chats() {
+        let (url, request) = serve_once(
+            "application/json",
+            r#"{"model":"llama3.2","message":{"role":"assistant","content":"Hello!"},"done":true}"#,
+        )
+        .await;
+
+        let response = OllamaProvider::new(api(url))
+            .chat(messages())
+            .await
+            .unwrap();
+        assert_eq!(response, "Hello!");
+
+        let request = request.await.unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/api/chat");
+        assert_eq!(request.headers["x-team"], "research");
+        assert!(!request.headers.contains_key("authorization"));
+        assert_eq!(request.body["stream"], false);
+        assert_eq!(request.body["messages"][0]["content"], "Hi");
+    }
+
+    #[tokio::test]
+    async fn sends_json_schema_as_format() {
+        let (url, request) = serve_once(
+            "application/json",
+            r#"{"message":{"role":"assistant","content":"{\"ok\":true}"},"done":true}"#,
+        )
+        .await;
+
+        let schema =
+            serde_json::json!({"type": "object", "properties": {"ok": {"type": "boolean"}}});
+        let response = OllamaProvider::new(api(url))
+            .chat_json(messages(), schema.clone())
+            .await
+            .unwrap();
+        assert_eq!(response, r#"{"ok":true}"#);
+        assert_eq!(request.await.unwrap().body["format"], schema);
+    }
+
+    #[tokio::test]
+    async fn streams_chat_lines() {
+        let (url, _request) = serve_once(
+            "application/x-ndjson",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"Hel\"},\"done\":false}\n\
+             {\"message\":{\"role\":\"assistant\",\"content\":\"lo\"},\"done\":false}\n\
+             {\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true}",
+        )
+        .await;
+
+        let tokens: Vec<String> = OllamaProvider::new(api(url))
+            .chat_stream(messages())
+            .await
+            .unwrap()
+            .map(|token| token.unwrap())
+            .collect()
+            .await;
+        assert_eq!(tokens, vec!["Hel", "lo"]);
+    }
+
+    #[tokio::test]
+    async fn embeds_text() {
+        let (url, request) =
+            serve_once("application/json", r#"{"embeddings":[[0.25,0.75]]}"#).await;
+
+        let embedding = OllamaProvider::new(api(url))
+            .embed("Hi".to_string())
+            .await
+            .unwrap();
+        assert_eq!(embedding, vec![0.25, 0.75]);
+        let request = request.await.unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/api/embed");
+    }
+}
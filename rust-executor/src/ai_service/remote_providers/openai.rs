@@ -0,0 +1,352 @@
+//! OpenAI and OpenAI-compatible servers (llama.cpp server, vLLM, LM Studio, ...).
+//! Streamed chat completions arrive as server-sent events.
+
+use super::{post_json, RemoteProvider, TokenStream};
+use crate::ai_service::Result;
+use crate::types::ModelApi;
+use anyhow::anyhow;
+use chat_gpt_lib_rs::Message;
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use std::collections::VecDeque;
+use url::Url;
+
+/// `{base_url}/v1/{path}`, whether or not the base URL already ends in `/v1`
+fn v1_url(base_url: &Url, path: &str) -> String {
+    let base = base_url.as_str().trim_end_matches('/');
+    if base.ends_with("/v1") {
+        format!("{}/{}", base, path)
+    } else {
+        format!("{}/v1/{}", base, path)
+    }
+}
+
+/// Splits a byte stream into the `data` payloads of server-sent events
+#[derive(Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// Returns the payloads of all events completed by `chunk`
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data
+                    .push(data.strip_prefix(' ').unwrap_or(data).to_string());
+            }
+            // Comments, `event:`, `id:` and `retry:` lines don't matter here
+        }
+        events
+    }
+}
+
+/// Text of the first choice of a streamed completion chunk
+fn delta_content(data: &str) -> Result<Option<String>> {
+    let chunk: serde_json::Value = serde_json::from_str(data)
+        .map_err(|e| anyhow!("Invalid completion chunk from remote LLM API: {}", e))?;
+    if let Some(error) = chunk.get("error") {
+        return Err(anyhow!("Remote LLM API error: {}", error));
+    }
+    Ok(chunk["choices"][0]["delta"]["content"]
+        .as_str()
+        .map(str::to_string))
+}
+
+struct ChatCompletionStream {
+    response: reqwest::Response,
+    parser: SseParser,
+    tokens: VecDeque<String>,
+    done: bool,
+}
+
+impl ChatCompletionStream {
+    /// Next piece of generated text, `None` once the completion is finished
+    async fn next_token(&mut self) -> Result<Option<String>> {
+        loop {
+            if let Some(token) = self.tokens.pop_front() {
+                return Ok(Some(token));
+            }
+            if self.done {
+                return Ok(None);
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => {
+                    for data in self.parser.push(&chunk) {
+                        if data == "[DONE]" {
+                            self.done = true;
+                            break;
+                        }
+                        if let Some(token) = delta_content(&data)? {
+                            if !token.is_empty() {
+                                self.tokens.push_back(token);
+                            }
+                        }
+                    }
+                }
+                None => self.done = true,
+            }
+        }
+    }
+
+    fn into_stream(self) -> TokenStream {
+        futures::stream::unfold(self, |mut stream| async move {
+            match stream.next_token().await {
+                Ok(Some(token)) => Some((Ok(token), stream)),
+                Ok(None) => None,
+                Err(e) => {
+                    stream.done = true;
+                    stream.tokens.clear();
+                    Some((Err(e), stream))
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+pub struct OpenAiProvider {
+    api: ModelApi,
+}
+
+impl OpenAiProvider {
+    pub fn new(api: ModelApi) -> Self {
+        Self { api }
+    }
+
+    async fn post(&self, path: &str, body: serde_json::Value) -> Result<reqwest::Response> {
+        post_json(&self.api, v1_url(&self.api.base_url, path), body).await
+    }
+
+    /// Message of the first choice of a chat completion
+    async fn complete(&self, request: serde_json::Value) -> Result<String> {
+        let response: serde_json::Value =
+            self.post("chat/completions", request).await?.json().await?;
+        response["choices"][0]["message"]["content"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or(anyhow!("Got response with no choice"))
+    }
+}
+
+impl RemoteProvider for OpenAiProvider {
+    fn chat(&self, messages: Vec<Message>) -> BoxFuture<'_, Result<String>> {
+        self.complete(serde_json::json!({
+            "model": self.api.model,
+            "messages": messages,
+        }))
+        .boxed()
+    }
+
+    fn chat_json(
+        &self,
+        messages: Vec<Message>,
+        schema: serde_json::Value,
+    ) -> BoxFuture<'_, Result<String>> {
+        self.complete(serde_json::json!({
+            "model": self.api.model,
+            "messages": messages,
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "response",
+                    "schema": schema,
+                },
+            },
+        }))
+        .boxed()
+    }
+
+    fn chat_stream(&self, messages: Vec<Message>) -> BoxFuture<'_, Result<TokenStream>> {
+        async move {
+            let response = self
+                .post(
+                    "chat/completions",
+                    serde_json::json!({
+                        "model": self.api.model,
+                        "messages": messages,
+                        "stream": true,
+                    }),
+                )
+                .await?;
+            Ok(ChatCompletionStream {
+                response,
+                parser: SseParser::default(),
+                tokens: VecDeque::new(),
+                done: false,
+            }
+            .into_stream())
+        }
+        .boxed()
+    }
+
+    fn embed(&self, text: String) -> BoxFuture<'_, Result<Vec<f32>>> {
+        async move {
+            let response: serde_json::Value = self
+                .post(
+                    "embeddings",
+                    serde_json::json!({
+                        "model": self.api.model,
+                        "input": text,
+                    }),
+                )
+                .await?
+                .json()
+                .await?;
+            serde_json::from_value(response["data"][0]["embedding"].clone())
+                .map_err(|e| anyhow!("Invalid embedding from remote API: {}", e))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::serve_once;
+    use super::*;
+    use crate::types::{ModelApiHeader, ModelApiType};
+    use chat_gpt_lib_rs::Role;
+
+    fn api(base_url: Url) -> ModelApi {
+        ModelApi {
+            base_url,
+            api_key: "secret".to_string(),
+            model: "qwen2.5".to_string(),
+            api_type: ModelApiType::OpenAi,
+            headers: vec![ModelApiHeader {
+                name: "X-Gateway".to_string(),
+                value: "lab".to_string(),
+            }],
+        }
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            content: "Hi".to_string(),
+        }]
+    }
+
+    #[test]
+    fn parses_tokens_split_across_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser
+            .push(b"data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\ndata: {\"choi")
+            .iter()
+            .all(|data| delta_content(data).unwrap().is_none()));
+        let events = parser.push(
+            "ces\":[{\"delta\":{\"content\":\"Hé\"}}]}\r\n\r\n: keep-alive\n\ndata: [DONE]\n\n"
+                .as_bytes(),
+        );
+        assert_eq!(events.len(), 2);
+        assert_eq!(delta_content(&events[0]).unwrap(), Some("Hé".to_string()));
+        assert_eq!(events[1], "[DONE]");
+        assert!(delta_content("{\"error\":{\"message\":\"quota\"}}").is_err());
+    }
+
+    #[test]
+    fn builds_v1_urls() {
+        for base in ["https://api.openai.com", "https://api.openai.com/v1/"] {
+            assert_eq!(
+                v1_url(&Url::parse(base).unwrap(), "chat/completions"),
+                "https://api.openai.com/v1/chat/completions"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn chats_with_custom_headers() {
+        let (url, request) = serve_once(
+            "application/json",
+            r#"{"choices":[{"message":{"role":"assistant","content":"Hello!"}}]}"#,
+        )
+        .await;
+
+        let response = OpenAiProvider::new(api(url))
+            .chat(messages())
+            .await
+            .unwrap();
+        assert_eq!(response, "Hello!");
+
+        let request = request.await.unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/v1/chat/completions");
+        assert_eq!(request.headers["authorization"], "Bearer secret");
+        assert_eq!(request.headers["x-gateway"], "lab");
+        assert_eq!(request.body["model"], "qwen2.5");
+        assert_eq!(request.body["messages"][0]["content"], "Hi");
+    }
+
+    #[tokio::test]
+    async fn asks_for_json_schema_response_format() {
+        let (url, request) = serve_once(
+            "application/json",
+            r#"{"choices":[{"message":{"role":"assistant","content":"{\"ok\":true}"}}]}"#,
+        )
+        .await;
+
+        let schema =
+            serde_json::json!({"type": "object", "properties": {"ok": {"type": "boolean"}}});
+        let response = OpenAiProvider::new(api(url))
+            .chat_json(messages(), schema.clone())
+            .await
+            .unwrap();
+        assert_eq!(response, r#"{"ok":true}"#);
+
+        let request = request.await.unwrap();
+        assert_eq!(request.body["response_format"]["type"], "json_schema");
+        assert_eq!(
+            request.body["response_format"]["json_schema"]["schema"],
+            schema
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_chat_completion() {
+        let (url, request) = serve_once(
+            "text/event-stream",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n\
+             data: [DONE]\n\n",
+        )
+        .await;
+
+        let tokens: Vec<String> = OpenAiProvider::new(api(url))
+            .chat_stream(messages())
+            .await
+            .unwrap()
+            .map(|token| token.unwrap())
+            .collect()
+            .await;
+        assert_eq!(tokens, vec!["Hel", "lo"]);
+        assert_eq!(request.await.unwrap().body["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn embeds_text() {
+        let (url, request) =
+            serve_once("application/json", r#"{"data":[{"embedding":[0.5,-1.0]}]}"#).await;
+
+        let embedding = OpenAiProvider::new(api(url))
+            .embed("Hi".to_string())
+            .await
+            .unwrap();
+        assert_eq!(embedding, vec![0.5, -1.0]);
+
+        let request = request.await.unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/v1/embeddings");
+        assert_eq!(request.body["input"], "Hi");
+    }
+}
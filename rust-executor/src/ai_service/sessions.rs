@@ -0,0 +1,269 @@
+//! Conversations with the model of a task. Their history is kept in `Ad4mDb`
+//! and sent along with every prompt, truncated to fit the model's context.
+
+use super::{AIService, Result};
+use crate::db::Ad4mDb;
+use crate::errors::ExecutorError;
+use crate::graphql::graphql_types::{AISessionInput, LinkStatus};
+use crate::perspectives::get_perspective;
+use crate::types::{AIContextTruncation, AIMessage, AIMessageRole, AISession, Link};
+use ad4m_client::literal::Literal;
+use log::error;
+
+const DEFAULT_MAX_MESSAGES: usize = 20;
+const DEFAULT_MAX_TOKENS: usize = 2048;
+
+/// Rough token count of a text, good enough to budget the context with
+fn estimated_tokens(text: &str) -> usize {
+    text.chars().count() / 4 + 1
+}
+
+/// The part of a session's history to send along with `prompt`
+fn truncate_history(session: &AISession, history: Vec<AIMessage>, prompt: &str) -> Vec<AIMessage> {
+    let keep = match session.truncation {
+        AIContextTruncation::KeepAll => history.len(),
+        AIContextTruncation::LastMessages => session
+            .max_messages
+            .map(|max| max.max(0) as usize)
+            .unwrap_or(DEFAULT_MAX_MESSAGES)
+            .min(history.len()),
+        AIContextTruncation::TokenBudget => {
+            let budget = session
+                .max_tokens
+                .map(|max| max.max(0) as usize)
+                .unwrap_or(DEFAULT_MAX_TOKENS);
+            let mut used = estimated_tokens(prompt);
+            history
+                .iter()
+                .rev()
+                .take_while(|message| {
+                    used += estimated_tokens(&message.content);
+                    used <= budget
+                })
+                .count()
+        }
+    };
+
+    // A conversation shouldn't start with an answer to a question that got cut off
+    let skip = history.len() - keep;
+    history
+        .into_iter()
+        .skip(skip)
+        .skip_while(|message| message.role == AIMessageRole::Assistant)
+        .collect()
+}
+
+fn transcript_links(session_id: &str, messages: &[AIMessage]) -> Result<Vec<Link>> {
+    messages
+        .iter()
+        .map(|message| {
+            let predicate = match message.role {
+                AIMessageRole::User => "ad4m://ai_user_message",
+                AIMessageRole::Assistant => "ad4m://ai_assistant_message",
+            };
+            Ok(Link {
+                source: format!("ai-session://{}", session_id),
+                predicate: Some(predicate.to_string()),
+                target: Literal::from_string(message.content.clone()).to_url()?,
+            })
+        })
+        .collect()
+}
+
+impl AIService {
+    pub fn create_session(&self, input: AISessionInput) -> Result<AISession> {
+        Ad4mDb::with_global_instance(|db| db.get_task(input.task_id.clone()))?
+            .ok_or_else(|| ExecutorError::NotFound(format!("Task not found: {}", input.task_id)))?;
+        if let Some(uuid) = &input.perspective_uuid {
+            if get_perspective(uuid).is_none() {
+                return Err(ExecutorError::PerspectiveNotFound(uuid.clone()).into());
+            }
+        }
+
+        let created_at = timestamp();
+        let session = AISession {
+            session_id: uuid::Uuid::new_v4().to_string(),
+            task_id: input.task_id,
+            name: input.name,
+            truncation: input.truncation.unwrap_or_default(),
+            max_messages: input.max_messages,
+            max_tokens: input.max_tokens,
+            perspective_uuid: input.perspective_uuid,
+            created_at: created_at.clone(),
+            updated_at: created_at,
+        };
+        Ad4mDb::with_global_instance(|db| db.add_ai_session(&session))?;
+        Ok(session)
+    }
+
+    pub fn get_sessions(&self, task_id: Option<String>) -> Result<Vec<AISession>> {
+        Ad4mDb::with_global_instance(|db| db.get_ai_sessions(task_id.as_deref()))
+    }
+
+    pub fn get_session_messages(&self, session_id: &str) -> Result<Vec<AIMessage>> {
+        self.get_session(session_id)?;
+        Ad4mDb::with_global_instance(|db| db.get_ai_session_messages(session_id))
+    }
+
+    pub fn delete_session(&self, session_id: &str) -> Result<()> {
+        if !Ad4mDb::with_global_instance(|db| db.remove_ai_session(session_id))? {
+            return Err(session_not_found(session_id));
+        }
+        Ok(())
+    }
+
+    /// Prompts the session's task with the (truncated) history of the session
+    /// and adds both the prompt and the response to it
+    pub async fn session_prompt(&self, session_id: &str, prompt: String) -> Result<String> {
+        let session = self.get_session(session_id)?;
+        let history = Ad4mDb::with_global_instance(|db| db.get_ai_session_messages(session_id))?;
+        let history = truncate_history(&session, history, &prompt);
+
+        let user_message = AIMessage {
+            role: AIMessageRole::User,
+            content: prompt.clone(),
+            created_at: timestamp(),
+        };
+        let response = self
+            .prompt_with_history(session.task_id.clone(), history, prompt)
+            .await?;
+        let assistant_message = AIMessage {
+            role: AIMessageRole::Assistant,
+            content: response.clone(),
+            created_at: timestamp(),
+        };
+
+        Ad4mDb::with_global_instance(|db| {
+            db.add_ai_session_message(session_id, &user_message)?;
+            db.add_ai_session_message(session_id, &assistant_message)
+        })?;
+
+        if let Some(uuid) = &session.perspective_uuid {
+            if let Err(e) =
+                store_transcript(uuid, session_id, &[user_message, assistant_message]).await
+            {
+                error!(
+                    "Failed to store transcript of AI session {}: {}",
+                    session_id, e
+                );
+            }
+        }
+
+        Ok(response)
+    }
+
+    fn get_session(&self, session_id: &str) -> Result<AISession> {
+        Ad4mDb::with_global_instance(|db| db.get_ai_session(session_id))?
+            .ok_or_else(|| session_not_found(session_id))
+    }
+}
+
+fn timestamp() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+fn session_not_found(session_id: &str) -> deno_core::error::AnyError {
+    ExecutorError::NotFound(format!("AI session not found: {}", session_id)).into()
+}
+
+/// Stores the messages as local links, private chats must not get shared with the neighbourhood
+async fn store_transcript(uuid: &str, session_id: &str, messages: &[AIMessage]) -> Result<()> {
+    let mut perspective = get_perspective(uuid)
+        .ok_or_else(|| ExecutorError::PerspectiveNotFound(uuid.to_string()))?;
+    perspective
+        .add_links(transcript_links(session_id, messages)?, LinkStatus::Local)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(
+        truncation: AIContextTruncation,
+        max_messages: Option<i32>,
+        max_tokens: Option<i32>,
+    ) -> AISession {
+        AISession {
+            session_id: "session".to_string(),
+            task_id: "task".to_string(),
+            name: None,
+            truncation,
+            max_messages,
+            max_tokens,
+            perspective_uuid: None,
+            created_at: String::new(),
+            updated_at: String::new(),
+        }
+    }
+
+    fn history() -> Vec<AIMessage> {
+        (0..6)
+            .map(|i| AIMessage {
+                role: if i % 2 == 0 {
+                    AIMessageRole::User
+                } else {
+                    AIMessageRole::Assistant
+                },
+                content: format!("message {:02}", i),
+                created_at: String::new(),
+            })
+            .collect()
+    }
+
+    fn contents(messages: Vec<AIMessage>) -> Vec<String> {
+        messages
+            .into_iter()
+            .map(|message| message.content)
+            .collect()
+    }
+
+    #[test]
+    fn keeps_whole_history_by_default() {
+        let kept = truncate_history(
+            &session(AIContextTruncation::KeepAll, None, None),
+            history(),
+            "",
+        );
+        assert_eq!(kept, history());
+    }
+
+    #[test]
+    fn keeps_last_messages_without_leading_answer() {
+        let kept = truncate_history(
+            &session(AIContextTruncation::LastMessages, Some(3), None),
+            history(),
+            "",
+        );
+        assert_eq!(contents(kept), vec!["message 04", "message 05"]);
+
+        let kept = truncate_history(
+            &session(AIContextTruncation::LastMessages, Some(4), None),
+            history(),
+            "",
+        );
+        assert_eq!(
+            contents(kept),
+            vec!["message 02", "message 03", "message 04", "message 05"]
+        );
+    }
+
+    #[test]
+    fn keeps_latest_messages_within_token_budget() {
+        // Every message and the prompt are estimated at 3 tokens
+        let kept = truncate_history(
+            &session(AIContextTruncation::TokenBudget, None, Some(12)),
+            history(),
+            "prompt 000",
+        );
+        assert_eq!(contents(kept), vec!["message 04", "message 05"]);
+
+        let kept = truncate_history(
+            &session(AIContextTruncation::TokenBudget, None, Some(2)),
+            history(),
+            "prompt 000",
+        );
+        assert!(kept.is_empty());
+    }
+}
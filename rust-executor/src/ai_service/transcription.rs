@@ -0,0 +1,315 @@
+//! Whisper transcription with the settings of the configured transcription model
+
+use super::{AIService, Result};
+use crate::db::Ad4mDb;
+use crate::errors::ExecutorError;
+use crate::graphql::graphql_types::TranscriptionSegment;
+use crate::types::{Model, ModelType};
+use deno_core::error::AnyError;
+use futures::StreamExt;
+use kalosm::sound::*;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::oneshot;
+
+/// Sample rate of the mono audio that transcription streams are fed with
+pub const SAMPLE_RATE: u32 = 16000;
+
+/// Weights used when no transcription model is configured
+const DEFAULT_WHISPER_WEIGHTS: &str = "whisper";
+
+pub enum TranscriptionAudio {
+    /// Mono samples at `SAMPLE_RATE`
+    Samples(Vec<f32>),
+    /// Contents of a WAV file, in any sample rate and channel layout
+    Wav(Vec<u8>),
+}
+
+/// File name of Whisper weights without the `whisper` prefix and separators,
+/// e.g. `base_en` for `Whisper-Base.en`
+fn whisper_size(file_name: &str) -> String {
+    let name = file_name.to_lowercase().replace(['-', '.'], "_");
+    match name
+        .strip_prefix("whisper")
+        .unwrap_or(&name)
+        .trim_start_matches('_')
+    {
+        "" => "small".to_string(),
+        "large" => "large_v2".to_string(),
+        size => size.to_string(),
+    }
+}
+
+/// Whisper weights named by the file name of a local transcription model,
+/// e.g. `whisper` (small), `whisper-tiny`, `base.en` or `distil-large-v2`
+fn whisper_source(file_name: &str) -> Result<WhisperSource> {
+    Ok(match whisper_size(file_name).as_str() {
+        "small" => WhisperSource::Small,
+        "small_en" => WhisperSource::SmallEn,
+        "tiny" => WhisperSource::Tiny,
+        "tiny_en" => WhisperSource::TinyEn,
+        "base" => WhisperSource::Base,
+        "base_en" => WhisperSource::BaseEn,
+        "medium" => WhisperSource::Medium,
+        "medium_en" => WhisperSource::MediumEn,
+        "large_v2" => WhisperSource::LargeV2,
+        "distil_medium_en" => WhisperSource::DistilMediumEn,
+        "distil_large_v2" => WhisperSource::DistilLargeV2,
+        _ => {
+            return Err(ExecutorError::InvalidInput(format!(
+                "Unknown Whisper model: {}",
+                file_name
+            ))
+            .into())
+        }
+    })
+}
+
+/// The transcription model with the given id or name, or the default
+/// transcription model for `default`
+fn transcription_model(model_id: &str) -> Result<Option<Model>> {
+    let model = Ad4mDb::with_global_instance(|db| -> Result<Option<Model>> {
+        if let Some(model) = db.get_model(model_id.to_string())? {
+            return Ok(Some(model));
+        }
+        if let Some(model) = db.get_models()?.into_iter().find(|model| {
+            model.model_type == ModelType::Transcription
+                && model.name.eq_ignore_ascii_case(model_id)
+        }) {
+            return Ok(Some(model));
+        }
+        match db.get_default_model(ModelType::Transcription)? {
+            Some(default_id) if model_id == "default" => db.get_model(default_id),
+            _ => Ok(None),
+        }
+    })?;
+
+    match model {
+        Some(model) if model.model_type != ModelType::Transcription => Err(
+            ExecutorError::InvalidInput(format!("{} is not a transcription model", model.name))
+                .into(),
+        ),
+        model => Ok(model),
+    }
+}
+
+pub(super) struct WhisperSettings {
+    source: WhisperSource,
+    language: Option<WhisperLanguage>,
+    /// Identifies the loaded model in `AIService::whisper_models`
+    pub(super) key: String,
+}
+
+impl WhisperSettings {
+    /// Settings for `model_id`, which is the id or name of a transcription model,
+    /// `default` for the default transcription model (Whisper small with language
+    /// detection if there is none) or the name of Whisper weights, e.g. `whisper-tiny`
+    pub(super) fn for_model_id(model_id: &str) -> Result<Self> {
+        if let Some(model) = transcription_model(model_id)? {
+            return Self::from_model(&model);
+        }
+        let weights = if model_id == "default" {
+            DEFAULT_WHISPER_WEIGHTS
+        } else {
+            model_id
+        };
+        let source = whisper_source(weights).map_err(|_| {
+            ExecutorError::NotFound(format!("Transcription model not found: {}", model_id))
+        })?;
+        Ok(Self {
+            source,
+            language: None,
+            key: whisper_size(weights),
+        })
+    }
+
+    pub(super) fn from_model(model: &Model) -> Result<Self> {
+        let local = model.local.as_ref().ok_or_else(|| {
+            ExecutorError::InvalidInput(format!(
+                "Transcription model {} has no local model file",
+                model.name
+            ))
+        })?;
+        let language = match local.language.as_deref() {
+            Some(language) => Some(language.parse::<WhisperLanguage>().map_err(|_| {
+                ExecutorError::InvalidInput(format!("Unknown transcription language: {}", language))
+            })?),
+            None => None,
+        };
+        let mut key = whisper_size(&local.file_name);
+        if let Some(language) = &local.language {
+            key = format!("{}:{}", key, language.to_lowercase());
+        }
+        Ok(Self {
+            source: whisper_source(&local.file_name)?,
+            language,
+            key,
+        })
+    }
+
+    pub(super) fn builder(self) -> WhisperBuilder {
+        WhisperBuilder::default()
+            .with_source(self.source)
+            .with_language(self.language)
+            .with_device(AIService::new_candle_device())
+    }
+}
+
+/// `segment` with its times moved by `offset` seconds
+pub(super) fn transcription_segment(segment: &Segment, offset: f64) -> TranscriptionSegment {
+    let start = offset + segment.start();
+    TranscriptionSegment {
+        text: segment.text().to_string(),
+        start,
+        end: start + segment.duration(),
+        confidence: 1.0 - segment.probability_of_no_speech(),
+    }
+}
+
+impl AIService {
+    /// The Whisper model with the given settings, loaded on first use and then
+    /// shared by all transcriptions
+    pub(super) async fn whisper_model(&self, settings: WhisperSettings) -> Result<Arc<Whisper>> {
+        let mut models = self.whisper_models.lock().await;
+        if let Some(whisper) = models.get(&settings.key) {
+            return Ok(whisper.clone());
+        }
+
+        let key = settings.key.clone();
+        let (model_tx, model_rx) = oneshot::channel();
+        thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            let _ = model_tx.send(rt.block_on(settings.builder().build()));
+        });
+        let whisper = Arc::new(model_rx.await??);
+        models.insert(key, whisper.clone());
+        Ok(whisper)
+    }
+
+    /// Unloads the Whisper model of a transcription model that got updated or removed
+    pub(super) async fn unload_whisper_model(&self, model: &Model) {
+        if let Ok(settings) = WhisperSettings::from_model(model) {
+            self.whisper_models.lock().await.remove(&settings.key);
+        }
+    }
+
+    /// Transcribes a whole recording at once
+    pub async fn transcribe_audio(
+        &self,
+        model_id: String,
+        audio: TranscriptionAudio,
+    ) -> Result<Vec<TranscriptionSegment>> {
+        let whisper = self
+            .whisper_model(WhisperSettings::for_model_id(&model_id)?)
+            .await?;
+        let (result_tx, result_rx) = oneshot::channel();
+
+        thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            let result = rt.block_on(async {
+                let mut segments = match audio {
+                    TranscriptionAudio::Samples(samples) => whisper
+                        .transcribe(rodio::buffer::SamplesBuffer::new(1, SAMPLE_RATE, samples))?,
+                    TranscriptionAudio::Wav(bytes) => {
+                        let wav = rodio::Decoder::new_wav(Cursor::new(bytes)).map_err(|e| {
+                            ExecutorError::InvalidInput(format!("Invalid WAV audio: {}", e))
+                        })?;
+                        whisper.transcribe(wav)?
+                    }
+                };
+
+                let mut result = Vec::new();
+                while let Some(segment) = segments.next().await {
+                    result.push(transcription_segment(&segment, 0.0));
+                }
+                Ok::<_, AnyError>(result)
+            });
+            let _ = result_tx.send(result);
+        });
+
+        result_rx.await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql::graphql_types::{LocalModelInput, ModelInput};
+
+    #[test]
+    fn whisper_source_from_file_name() {
+        assert!(matches!(
+            whisper_source("whisper"),
+            Ok(WhisperSource::Small)
+        ));
+        assert!(matches!(
+            whisper_source("Whisper-Tiny"),
+            Ok(WhisperSource::Tiny)
+        ));
+        assert!(matches!(
+            whisper_source("base.en"),
+            Ok(WhisperSource::BaseEn)
+        ));
+        assert!(matches!(
+            whisper_source("whisper_distil-large-v2"),
+            Ok(WhisperSource::DistilLargeV2)
+        ));
+        assert!(whisper_source("whisper-huge").is_err());
+    }
+
+    #[test]
+    fn settings_for_model_id() {
+        Ad4mDb::init_global_instance(":memory:").unwrap();
+        let model_id = Ad4mDb::with_global_instance(|db| {
+            db.add_model(&ModelInput {
+                name: "German Whisper".to_string(),
+                api: None,
+                local: Some(LocalModelInput {
+                    file_name: "whisper-base".to_string(),
+                    language: Some("de".to_string()),
+                    ..Default::default()
+                }),
+                model_type: ModelType::Transcription,
+            })
+        })
+        .unwrap();
+
+        let settings = WhisperSettings::for_model_id(&model_id).unwrap();
+        assert_eq!(settings.key, "base:de");
+        let settings = WhisperSettings::for_model_id("german whisper").unwrap();
+        assert_eq!(settings.key, "base:de");
+
+        // Without a default transcription model `default` means Whisper small
+        assert_eq!(
+            WhisperSettings::for_model_id("default").unwrap().key,
+            "small"
+        );
+        Ad4mDb::with_global_instance(|db| {
+            db.set_default_model(ModelType::Transcription, &model_id)
+        })
+        .unwrap();
+        assert_eq!(
+            WhisperSettings::for_model_id("default").unwrap().key,
+            "base:de"
+        );
+
+        // Whisper weights by name share the loaded model with equivalent names
+        assert_eq!(
+            WhisperSettings::for_model_id("Whisper").unwrap().key,
+            "small"
+        );
+        assert_eq!(
+            WhisperSettings::for_model_id("tiny.en").unwrap().key,
+            "tiny_en"
+        );
+
+        let error = WhisperSettings::for_model_id("unknown-model")
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref::<ExecutorError>(),
+            Some(ExecutorError::NotFound(_))
+        ));
+    }
+}
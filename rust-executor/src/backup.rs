@@ -0,0 +1,378 @@
+//! Encrypted archives of everything that makes up an agent (keystore, profile,
+//! app tokens and the Ad4mDb), used to move an agent to another machine.
+
+use std::fs;
+use std::path::Path;
+
+use base64::prelude::*;
+use deno_core::anyhow::anyhow;
+use deno_core::error::AnyError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::agent::capabilities::apps_map;
+use crate::agent::{signatures, AgentService};
+use crate::db::{snapshot, Ad4mDb, AD4M_DB_FILE_NAME};
+use crate::globals::AD4M_VERSION;
+use crate::perspectives;
+use crate::wallet::{self, check_passphrase_policy, Wallet};
+
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+/// Files of the app data directory that are backed up, relative to it
+const AGENT_FILE: &str = "ad4m/agent.json";
+const AGENT_PROFILE_FILE: &str = "ad4m/agentProfile.json";
+const APPS_DATA_FILE: &str = "apps_data.json";
+/// All paths a backup may contain, anything else is refused on restore
+const BACKUP_FILES: [&str; 4] = [
+    AGENT_FILE,
+    AGENT_PROFILE_FILE,
+    APPS_DATA_FILE,
+    AD4M_DB_FILE_NAME,
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct BackupEntry {
+    path: String,
+    sha256: String,
+    /// base64
+    content: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct BackupBundle {
+    format_version: u32,
+    ad4m_version: String,
+    created_at: String,
+    agent_did: Option<String>,
+    entries: Vec<BackupEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupSummary {
+    pub agent_did: Option<String>,
+    pub created_at: String,
+    pub ad4m_version: String,
+    pub files: Vec<String>,
+    /// Row counts of the backed up Ad4mDb tables
+    pub tables: Vec<(String, i64)>,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn agent_did(app_data_path: &Path) -> Option<String> {
+    let agent_file = fs::read_to_string(app_data_path.join(AGENT_FILE)).ok()?;
+    let agent: serde_json::Value = serde_json::from_str(&agent_file).ok()?;
+    agent["did"].as_str().map(|did| did.to_string())
+}
+
+impl BackupBundle {
+    fn db_snapshot(&self) -> Result<Option<Vec<u8>>, AnyError> {
+        self.entries
+            .iter()
+            .find(|entry| entry.path == AD4M_DB_FILE_NAME)
+            .map(|entry| Ok(BASE64_STANDARD.decode(&entry.content)?))
+            .transpose()
+    }
+
+    fn summary(&self) -> Result<BackupSummary, AnyError> {
+        let tables = match self.db_snapshot()? {
+            Some(db) => snapshot::snapshot_table_counts(&db)?,
+            None => vec![],
+        };
+        Ok(BackupSummary {
+            agent_did: self.agent_did.clone(),
+            created_at: self.created_at.clone(),
+            ad4m_version: self.ad4m_version.clone(),
+            files: self.entries.iter().map(|e| e.path.clone()).collect(),
+            tables,
+        })
+    }
+}
+
+/// Bundles the agent files in `app_data_path` and `db_snapshot` into an
+/// archive encrypted with `passphrase`
+pub fn create(
+    app_data_path: &Path,
+    db_snapshot: Vec<u8>,
+    passphrase: &str,
+) -> Result<(String, BackupSummary), AnyError> {
+    check_passphrase_policy(passphrase)?;
+    if !app_data_path.join(AGENT_FILE).exists() {
+        return Err(anyhow!("No agent found in {}", app_data_path.display()));
+    }
+
+    let mut entries = Vec::new();
+    for path in [AGENT_FILE, AGENT_PROFILE_FILE, APPS_DATA_FILE] {
+        if let Ok(content) = fs::read(app_data_path.join(path)) {
+            entries.push(BackupEntry {
+                path: path.to_string(),
+                sha256: sha256_hex(&content),
+                content: BASE64_STANDARD.encode(content),
+            });
+        }
+    }
+    entries.push(BackupEntry {
+        path: AD4M_DB_FILE_NAME.to_string(),
+        sha256: sha256_hex(&db_snapshot),
+        content: BASE64_STANDARD.encode(db_snapshot),
+    });
+
+    let bundle = BackupBundle {
+        format_version: BACKUP_FORMAT_VERSION,
+        ad4m_version: AD4M_VERSION.to_string(),
+        created_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        agent_did: agent_did(app_data_path),
+        entries,
+    };
+    let archive = wallet::encrypt(serde_json::to_string(&bundle)?, passphrase.to_string());
+    Ok((archive, bundle.summary()?))
+}
+
+/// Decrypts an archive and checks its format version and checksums
+fn open(archive: &str, passphrase: &str) -> Result<BackupBundle, AnyError> {
+    let bundle = wallet::decrypt(archive.trim().to_string(), passphrase.to_string())
+        .map_err(|_| anyhow!("Could not decrypt backup, wrong passphrase or not an AD4M backup"))?;
+    let bundle: BackupBundle = serde_json::from_str(&bundle)?;
+    if bundle.format_version > BACKUP_FORMAT_VERSION {
+        return Err(anyhow!(
+            "Backup format version {} is not supported by this version of AD4M",
+            bundle.format_version
+        ));
+    }
+    if !bundle.entries.iter().any(|e| e.path == AGENT_FILE) {
+        return Err(anyhow!("Backup does not contain an agent"));
+    }
+    for entry in &bundle.entries {
+        if !BACKUP_FILES.contains(&entry.path.as_str()) {
+            return Err(anyhow!("Backup contains unexpected file {}", entry.path));
+        }
+        let content = BASE64_STANDARD.decode(&entry.content)?;
+        if sha256_hex(&content) != entry.sha256 {
+            return Err(anyhow!("Backup entry {} is corrupt", entry.path));
+        }
+    }
+    Ok(bundle)
+}
+
+/// Decrypts and verifies an archive without restoring it
+pub fn inspect(archive: &str, passphrase: &str) -> Result<BackupSummary, AnyError> {
+    open(archive, passphrase)?.summary()
+}
+
+/// True if `app_data_path` already holds an agent, app tokens or perspectives
+pub fn has_agent_data(app_data_path: &Path) -> bool {
+    [AGENT_FILE, AGENT_PROFILE_FILE, APPS_DATA_FILE]
+        .iter()
+        .any(|path| app_data_path.join(path).exists())
+        || snapshot::snapshot_file(&app_data_path.join(AD4M_DB_FILE_NAME).to_string_lossy())
+            .and_then(|db| snapshot::snapshot_table_counts(&db))
+            .map(|tables| tables.iter().any(|(_, count)| *count > 0))
+            .unwrap_or(false)
+}
+
+/// Writes all entries but the database, which needs to be swapped differently
+/// depending on whether the executor is running
+fn write_files(bundle: &BackupBundle, app_data_path: &Path) -> Result<(), AnyError> {
+    for entry in bundle
+        .entries
+        .iter()
+        .filter(|e| e.path != AD4M_DB_FILE_NAME)
+    {
+        let path = app_data_path.join(&entry.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, BASE64_STANDARD.decode(&entry.content)?)?;
+    }
+    Ok(())
+}
+
+fn check_target(app_data_path: &Path, force: bool) -> Result<(), AnyError> {
+    if !force && has_agent_data(app_data_path) {
+        return Err(anyhow!(
+            "{} already contains an agent, restore with force to overwrite it",
+            app_data_path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Writes a backup of the (stopped) executor's data directory to `output`
+pub fn backup_data_dir(
+    app_data_path: &Path,
+    output: &Path,
+    passphrase: &str,
+) -> Result<BackupSummary, AnyError> {
+    let db_path = app_data_path.join(AD4M_DB_FILE_NAME);
+    let db_snapshot = snapshot::snapshot_file(&db_path.to_string_lossy())?;
+    let (archive, summary) = create(app_data_path, db_snapshot, passphrase)?;
+    fs::write(output, archive)?;
+    Ok(summary)
+}
+
+/// Restores a backup into the data directory of a stopped executor
+pub fn restore_data_dir(
+    app_data_path: &Path,
+    archive: &str,
+    passphrase: &str,
+    force: bool,
+) -> Result<BackupSummary, AnyError> {
+    let bundle = open(archive, passphrase)?;
+    check_target(app_data_path, force)?;
+    fs::create_dir_all(app_data_path)?;
+    if let Some(db) = bundle.db_snapshot()? {
+        snapshot::restore_file(
+            &app_data_path.join(AD4M_DB_FILE_NAME).to_string_lossy(),
+            &db,
+        )?;
+    }
+    write_files(&bundle, app_data_path)?;
+    bundle.summary()
+}
+
+/// Writes a backup of the running executor's agent to `output`
+pub fn backup_running(
+    app_data_path: &Path,
+    output: &Path,
+    passphrase: &str,
+) -> Result<BackupSummary, AnyError> {
+    let db_snapshot = Ad4mDb::with_global_instance(|db| db.snapshot())?;
+    let (archive, summary) = create(app_data_path, db_snapshot, passphrase)?;
+    fs::write(output, archive)?;
+    Ok(summary)
+}
+
+/// Restores a backup into the running executor and reloads the agent, which
+/// then needs to be unlocked with its passphrase.
+/// Refuses to replace the database under running perspectives, those
+/// executors have to be stopped and restored with `restore_data_dir`.
+pub fn restore_running(
+    app_data_path: &Path,
+    archive: &str,
+    passphrase: &str,
+    force: bool,
+) -> Result<BackupSummary, AnyError> {
+    let bundle = open(archive, passphrase)?;
+    check_target(app_data_path, force)?;
+    if !perspectives::all_perspectives().is_empty() {
+        return Err(anyhow!(
+            "Can't restore a backup while perspectives are running, stop the executor and restore its data directory instead"
+        ));
+    }
+
+    if let Some(db) = bundle.db_snapshot()? {
+        Ad4mDb::restore_global_instance(
+            &app_data_path.join(AD4M_DB_FILE_NAME).to_string_lossy(),
+            &db,
+        )?;
+    }
+    write_files(&bundle, app_data_path)?;
+
+    {
+        let wallet_instance = Wallet::instance();
+        let mut wallet = wallet_instance.lock().expect("wallet lock");
+        wallet.as_mut().expect("wallet instance").discard_keys();
+    }
+    AgentService::with_mutable_global_instance(|agent_service| agent_service.load());
+    apps_map::reload_from_file();
+    signatures::clear_caches();
+    signatures::load_from_db();
+    perspectives::initialize_from_db();
+    Ok(bundle.summary()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn data_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ad4m-backup-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("ad4m")).unwrap();
+        dir
+    }
+
+    fn db_snapshot() -> Vec<u8> {
+        let dir = data_dir();
+        let db_path = dir.join(AD4M_DB_FILE_NAME).to_string_lossy().into_owned();
+        crate::db::migrations::migrate_file(&db_path).unwrap();
+        let db = snapshot::snapshot_file(&db_path).unwrap();
+        let _ = fs::remove_dir_all(dir);
+        db
+    }
+
+    #[test]
+    fn backups_roundtrip_and_refuse_to_overwrite_an_agent() {
+        let source = data_dir();
+        fs::write(source.join(AGENT_FILE), r#"{"did":"did:key:test"}"#).unwrap();
+        fs::write(source.join(APPS_DATA_FILE), "{}").unwrap();
+
+        let (archive, summary) = create(&source, db_snapshot(), "backup-passphrase").unwrap();
+        assert_eq!(summary.agent_did, Some("did:key:test".to_string()));
+        assert!(summary.files.contains(&AD4M_DB_FILE_NAME.to_string()));
+        assert!(inspect(&archive, "wrong-passphrase").is_err());
+
+        let target = data_dir();
+        restore_data_dir(&target, &archive, "backup-passphrase", false).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.join(AGENT_FILE)).unwrap(),
+            r#"{"did":"did:key:test"}"#
+        );
+        assert!(target.join(AD4M_DB_FILE_NAME).exists());
+
+        assert!(restore_data_dir(&target, &archive, "backup-passphrase", false).is_err());
+        restore_data_dir(&target, &archive, "backup-passphrase", true).unwrap();
+
+        let _ = fs::remove_dir_all(source);
+        let _ = fs::remove_dir_all(target);
+    }
+
+    #[test]
+    fn tampered_backups_are_rejected() {
+        let source = data_dir();
+        fs::write(source.join(AGENT_FILE), r#"{"did":"did:key:test"}"#).unwrap();
+        let (archive, _) = create(&source, db_snapshot(), "backup-passphrase").unwrap();
+
+        let mut bundle = open(&archive, "backup-passphrase").unwrap();
+        bundle.entries[0].content = BASE64_STANDARD.encode(r#"{"did":"did:key:evil"}"#);
+        let tampered = wallet::encrypt(
+            serde_json::to_string(&bundle).unwrap(),
+            "backup-passphrase".to_string(),
+        );
+        assert!(inspect(&tampered, "backup-passphrase").is_err());
+        let _ = fs::remove_dir_all(source);
+    }
+
+    #[test]
+    fn backups_with_paths_outside_the_data_dir_are_rejected() {
+        let source = data_dir();
+        fs::write(source.join(AGENT_FILE), r#"{"did":"did:key:test"}"#).unwrap();
+        let (archive, _) = create(&source, db_snapshot(), "backup-passphrase").unwrap();
+
+        let mut bundle = open(&archive, "backup-passphrase").unwrap();
+        let content = b"echo pwned";
+        bundle.entries.push(BackupEntry {
+            path: "../escaped.sh".to_string(),
+            sha256: sha256_hex(content),
+            content: BASE64_STANDARD.encode(content),
+        });
+        let tampered = wallet::encrypt(
+            serde_json::to_string(&bundle).unwrap(),
+            "backup-passphrase".to_string(),
+        );
+
+        let target = data_dir();
+        assert!(inspect(&tampered, "backup-passphrase").is_err());
+        assert!(restore_data_dir(&target, &tampered, "backup-passphrase", false).is_err());
+        assert!(!target.join("../escaped.sh").exists());
+
+        let _ = fs::remove_dir_all(source);
+        let _ = fs::remove_dir_all(target);
+    }
+}
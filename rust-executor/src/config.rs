@@ -1,4 +1,6 @@
+use crate::prolog_service::pool::DEFAULT_ENGINE_POOL_SIZE;
 use crate::utils;
+use crate::wallet::KeystoreKdfParams;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -32,6 +34,18 @@ pub struct Ad4mConfig {
     pub auto_permit_cap_requests: Option<bool>,
     pub tls: Option<TlsConfig>,
     pub log_holochain_metrics: Option<bool>,
+    /// Number of Prolog engines that answer queries for each perspective
+    /// (default 1, more engines allow concurrent queries but hold all facts each)
+    pub prolog_engine_pool_size: Option<usize>,
+    /// Time limit of Prolog queries that don't set one, like those of subject
+    /// classes and SDNA (default 30000, 0 for no limit)
+    pub prolog_query_timeout_ms: Option<u64>,
+    /// Inference limit of Prolog queries that don't set one (default 50000000, 0 for no limit)
+    pub prolog_inference_limit: Option<u64>,
+    /// Argon2id costs for writing the agent keystore, defaults to the Argon2 defaults
+    pub keystore_kdf_params: Option<KeystoreKdfParams>,
+    /// Days that applied diffs are kept in the perspective history (default: forever)
+    pub perspective_history_retention_days: Option<u32>,
 }
 
 impl Ad4mConfig {
@@ -87,6 +101,9 @@ impl Ad4mConfig {
         if self.log_holochain_metrics.is_none() {
             self.log_holochain_metrics = Some(true);
         }
+        if self.prolog_engine_pool_size.is_none() {
+            self.prolog_engine_pool_size = Some(DEFAULT_ENGINE_POOL_SIZE);
+        }
     }
 
     pub fn get_json(&self) -> String {
@@ -116,6 +133,11 @@ impl Default for Ad4mConfig {
             auto_permit_cap_requests: None,
             tls: None,
             log_holochain_metrics: None,
+            prolog_engine_pool_size: None,
+            prolog_query_timeout_ms: None,
+            prolog_inference_limit: None,
+            keystore_kdf_params: None,
+            perspective_history_retention_days: None,
         };
         config.prepare();
         config
@@ -0,0 +1,132 @@
+-- Ad4mDb as written by executors before versioned migrations existed (no schema_version table)
+CREATE TABLE IF NOT EXISTS perspective_handle (
+   uuid TEXT PRIMARY KEY,
+   name TEXT,
+   neighbourhood TEXT,
+   shared_url TEXT,
+   state TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS link (
+   id INTEGER PRIMARY KEY,
+   perspective TEXT NOT NULL,
+   source TEXT NOT NULL,
+   predicate TEXT NOT NULL,
+   target TEXT NOT NULL,
+   author TEXT NOT NULL,
+   timestamp TEXT NOT NULL,
+   signature TEXT NOT NULL,
+   key TEXT NOT NULL,
+   status TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS expression (
+   id INTEGER PRIMARY KEY,
+   url TEXT NOT NULL UNIQUE,
+   data TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS perspective_diff (
+   id INTEGER PRIMARY KEY,
+   perspective TEXT NOT NULL,
+   additions TEXT NOT NULL,
+   removals TEXT NOT NULL,
+   is_pending BOOLEAN NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS trusted_agent (
+   id INTEGER PRIMARY KEY,
+   agent TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS known_link_languages (
+   id INTEGER PRIMARY KEY,
+   language TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS friends (
+   id INTEGER PRIMARY KEY,
+   friend TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS outbox (
+   id INTEGER PRIMARY KEY,
+   message TEXT NOT NULL,
+   recipient TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS entanglement_proof (
+   id INTEGER PRIMARY KEY,
+   proof TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS notifications (
+   id TEXT PRIMARY KEY,
+   granted BOOLEAN NOT NULL,
+   description TEXT NOT NULL,
+   appName TEXT NOT NULL,
+   appUrl TEXT NOT NULL,
+   appIconPath TEXT,
+   trigger TEXT NOT NULL,
+   perspective_ids TEXT NOT NULL,
+   webhookUrl TEXT NOT NULL,
+   webhookAuth TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS tasks (
+   id TEXT PRIMARY KEY,
+   name TEXT NOT NULL,
+   model_id TEXT NOT NULL,
+   system_prompt TEXT NOT NULL,
+   prompt_examples TEXT NOT NULL,
+   metadata TEXT NULL,
+   created_at TEXT NOT NULL,
+   updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS models (
+   id TEXT PRIMARY KEY,
+   name TEXT NOT NULL,
+   api_base_url TEXT,
+   api_key TEXT,
+   model TEXT,
+   api_type TEXT,
+   local_file_name TEXT,
+   local_tokenizer_repo TEXT,
+   local_tokenizer_revision TEXT,
+   local_tokenizer_file_name TEXT,
+   local_huggingface_repo TEXT,
+   local_revision TEXT,
+   type TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS model_status (
+   model TEXT PRIMARY KEY,
+   progress DOUBLE NOT NULL,
+   status TEXT NOT NULL,
+   downloaded BOOLEAN NOT NULL,
+   loaded BOOLEAN NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS default_models (
+   model_type TEXT PRIMARY KEY,
+   model_id TEXT NOT NULL
+);
+
+INSERT INTO perspective_handle (uuid, name, neighbourhood, shared_url, state)
+VALUES ('8c0f6a0e-3c8e-4a3c-9d8a-0d1b2c3d4e5f', 'Legacy perspective', NULL, NULL, '"Private"');
+
+INSERT INTO link (perspective, source, predicate, target, author, timestamp, signature, key, status)
+VALUES
+    ('8c0f6a0e-3c8e-4a3c-9d8a-0d1b2c3d4e5f', 'ad4m://self', 'ad4m://has_child', 'literal://string:first', 'did:key:legacy', '2023-05-01T10:00:00.000Z', 'sig1', 'key1', '"local"'),
+    ('8c0f6a0e-3c8e-4a3c-9d8a-0d1b2c3d4e5f', 'ad4m://self', 'ad4m://has_child', 'literal://string:second', 'did:key:legacy', '2023-05-02T10:00:00.000Z', 'sig2', 'key2', '"shared"'),
+    ('8c0f6a0e-3c8e-4a3c-9d8a-0d1b2c3d4e5f', 'literal://string:first', '', 'literal://string:third', 'did:key:legacy', '2023-05-03T10:00:00+02:00', 'sig3', 'key3', '"shared"');
+
+INSERT INTO trusted_agent (agent) VALUES ('did:key:trusted');
+INSERT INTO friends (friend) VALUES ('did:key:friend');
+
+INSERT INTO notifications (id, granted, description, appName, appUrl, appIconPath, trigger, perspective_ids, webhookUrl, webhookAuth)
+VALUES ('b1c2d3e4-0000-4000-8000-000000000001', 1, 'Legacy notification', 'Legacy App', 'https://legacy.app', NULL, 'triple(X, "ad4m://has_child", Y)', '["8c0f6a0e-3c8e-4a3c-9d8a-0d1b2c3d4e5f"]', '', '');
+
+INSERT INTO models (id, name, api_base_url, api_key, model, api_type, local_file_name, local_tokenizer_repo, local_tokenizer_revision, local_tokenizer_file_name, local_huggingface_repo, local_revision, type)
+VALUES ('c1c2d3e4-0000-4000-8000-000000000002', 'legacy-model', 'https://api.openai.com/v1', 'sk-legacy', 'gpt-4', '"OpenAi"', NULL, NULL, NULL, NULL, NULL, NULL, '"llm"');
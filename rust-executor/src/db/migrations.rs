@@ -0,0 +1,757 @@
+//! Versioned, forward-only schema migrations for the [`Ad4mDb`](super::Ad4mDb).
+//!
+//! The applied version is tracked in the `schema_version` table. Databases created
+//! before migrations existed have no such table and are treated as version 0; the
+//! first migration only uses `CREATE TABLE IF NOT EXISTS` so it is safe to run on them.
+//!
+//! To change the schema, append a new [`Migration`] to [`MIGRATIONS`] - never edit one
+//! that has already been released.
+
+use super::{index_link_text, timestamp_to_millis, Ad4mDbResult};
+use deno_core::anyhow::anyhow;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::path::Path;
+
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    up: fn(&Connection) -> Ad4mDbResult<()>,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Initial schema",
+        up: initial_schema,
+    },
+    Migration {
+        version: 2,
+        description: "Integer link timestamps and link indexes",
+        up: link_timestamp_ms_and_indexes,
+    },
+    Migration {
+        version: 3,
+        description: "Full-text search index and embeddings for link targets",
+        up: link_search_index,
+    },
+    Migration {
+        version: 4,
+        description: "Outbox for notification webhook deliveries",
+        up: notification_delivery_outbox,
+    },
+    Migration {
+        version: 5,
+        description: "Key rotation statements of agents",
+        up: key_rotations,
+    },
+    Migration {
+        version: 6,
+        description: "Revocations of linked devices",
+        up: device_revocations,
+    },
+    Migration {
+        version: 7,
+        description: "Commit attempts of pending perspective diffs and sync status",
+        up: perspective_sync_status,
+    },
+    Migration {
+        version: 8,
+        description: "Append-only history of applied perspective diffs",
+        up: perspective_history,
+    },
+    Migration {
+        version: 9,
+        description: "AI conversation sessions and their messages",
+        up: ai_sessions,
+    },
+    Migration {
+        version: 10,
+        description: "Custom HTTP headers of remote AI model APIs",
+        up: model_api_headers,
+    },
+    Migration {
+        version: 11,
+        description: "Language of local transcription models",
+        up: local_model_language,
+    },
+    Migration {
+        version: 12,
+        description: "Output constraints of AI tasks",
+        up: task_output_constraint,
+    },
+    Migration {
+        version: 13,
+        description: "Random webhook secret per notification",
+        up: notification_webhook_secret,
+    },
+];
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Result of comparing a database against the known migrations without changing it
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStatus {
+    pub current_version: u32,
+    pub latest_version: u32,
+    pub pending: Vec<(u32, String)>,
+}
+
+impl MigrationStatus {
+    pub fn is_up_to_date(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Dry-run: reports which migrations would be applied to the database at `db_path`.
+/// The database is opened read-only and is not created if it does not exist yet.
+pub fn check(db_path: &str) -> Ad4mDbResult<MigrationStatus> {
+    let current_version = if Path::new(db_path).exists() {
+        let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        current_version(&conn)?
+    } else {
+        0
+    };
+    status_for(current_version)
+}
+
+/// Opens the database file at `db_path`, creating it if needed, and migrates it.
+pub fn migrate_file(db_path: &str) -> Ad4mDbResult<MigrationStatus> {
+    let conn = Connection::open(db_path)?;
+    migrate(&conn, db_path)
+}
+
+/// Brings the schema of `conn` up to [`latest_version`].
+/// If `db_path` points to an existing database file that needs migrating, a copy of it
+/// is written next to it before any migration runs.
+pub fn migrate(conn: &Connection, db_path: &str) -> Ad4mDbResult<MigrationStatus> {
+    ensure_schema_version_table(conn)?;
+    let current = current_version(conn)?;
+    let status = status_for(current)?;
+
+    if status.is_up_to_date() {
+        return Ok(status);
+    }
+
+    if has_user_tables(conn)? {
+        if let Some(backup_path) = backup(conn, db_path, current)? {
+            log::info!(
+                "Backed up Ad4mDb (schema version {}) to {}",
+                current,
+                backup_path
+            );
+        }
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        log::info!(
+            "Applying Ad4mDb migration {}: {}",
+            migration.version,
+            migration.description
+        );
+        let tx = conn.unchecked_transaction()?;
+        (migration.up)(&tx).map_err(|e| {
+            anyhow!(
+                "Ad4mDb migration {} ({}) failed: {}",
+                migration.version,
+                migration.description,
+                e
+            )
+        })?;
+        tx.execute(
+            "INSERT INTO schema_version (version, applied_at) VALUES (?1, ?2)",
+            params![migration.version, chrono::Utc::now().to_rfc3339()],
+        )?;
+        tx.commit()?;
+    }
+
+    Ok(status)
+}
+
+fn status_for(current_version: u32) -> Ad4mDbResult<MigrationStatus> {
+    let latest = latest_version();
+    if current_version > latest {
+        return Err(anyhow!(
+            "Ad4mDb schema version {} is newer than the latest version {} known to this executor",
+            current_version,
+            latest
+        ));
+    }
+    Ok(MigrationStatus {
+        current_version,
+        latest_version: latest,
+        pending: MIGRATIONS
+            .iter()
+            .filter(|m| m.version > current_version)
+            .map(|m| (m.version, m.description.to_string()))
+            .collect(),
+    })
+}
+
+fn ensure_schema_version_table(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            applied_at TEXT NOT NULL
+         )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn current_version(conn: &Connection) -> Ad4mDbResult<u32> {
+    let has_table = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'")?
+        .exists([])?;
+    if !has_table {
+        return Ok(0);
+    }
+    let version: Option<u32> = conn
+        .query_row("SELECT MAX(version) FROM schema_version", [], |row| {
+            row.get(0)
+        })
+        .optional()?
+        .flatten();
+    Ok(version.unwrap_or(0))
+}
+
+fn has_user_tables(conn: &Connection) -> Ad4mDbResult<bool> {
+    Ok(conn
+        .prepare(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name NOT IN ('schema_version') AND name NOT LIKE 'sqlite_%'",
+        )?
+        .exists([])?)
+}
+
+fn backup(conn: &Connection, db_path: &str, version: u32) -> Ad4mDbResult<Option<String>> {
+    if db_path == ":memory:" || db_path.is_empty() || !Path::new(db_path).exists() {
+        return Ok(None);
+    }
+    let backup_path = format!(
+        "{}.backup-v{}-{}",
+        db_path,
+        version,
+        chrono::Utc::now().format("%Y%m%d%H%M%S")
+    );
+    conn.execute("VACUUM INTO ?1", params![backup_path])?;
+    Ok(Some(backup_path))
+}
+
+fn initial_schema(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS perspective_handle (
+            uuid TEXT PRIMARY KEY,
+            name TEXT,
+            neighbourhood TEXT,
+            shared_url TEXT,
+            state TEXT NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS link (
+            id INTEGER PRIMARY KEY,
+            perspective TEXT NOT NULL,
+            source TEXT NOT NULL,
+            predicate TEXT NOT NULL,
+            target TEXT NOT NULL,
+            author TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            signature TEXT NOT NULL,
+            key TEXT NOT NULL,
+            status TEXT NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS expression (
+            id INTEGER PRIMARY KEY,
+            url TEXT NOT NULL UNIQUE,
+            data TEXT NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS perspective_diff (
+            id INTEGER PRIMARY KEY,
+            perspective TEXT NOT NULL,
+            additions TEXT NOT NULL,
+            removals TEXT NOT NULL,
+            is_pending BOOLEAN NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS trusted_agent (
+            id INTEGER PRIMARY KEY,
+            agent TEXT NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS known_link_languages (
+            id INTEGER PRIMARY KEY,
+            language TEXT NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS friends (
+            id INTEGER PRIMARY KEY,
+            friend TEXT NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS outbox (
+            id INTEGER PRIMARY KEY,
+            message TEXT NOT NULL,
+            recipient TEXT NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS entanglement_proof (
+            id INTEGER PRIMARY KEY,
+            proof TEXT NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS notifications (
+            id TEXT PRIMARY KEY,
+            granted BOOLEAN NOT NULL,
+            description TEXT NOT NULL,
+            appName TEXT NOT NULL,
+            appUrl TEXT NOT NULL,
+            appIconPath TEXT,
+            trigger TEXT NOT NULL,
+            perspective_ids TEXT NOT NULL,
+            webhookUrl TEXT NOT NULL,
+            webhookAuth TEXT NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS tasks (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            model_id TEXT NOT NULL,
+            system_prompt TEXT NOT NULL,
+            prompt_examples TEXT NOT NULL,
+            metadata TEXT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS models (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            api_base_url TEXT,
+            api_key TEXT,
+            model TEXT,
+            api_type TEXT,
+            local_file_name TEXT,
+            local_tokenizer_repo TEXT,
+            local_tokenizer_revision TEXT,
+            local_tokenizer_file_name TEXT,
+            local_huggingface_repo TEXT,
+            local_revision TEXT,
+            type TEXT NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS model_status (
+            model TEXT PRIMARY KEY,
+            progress DOUBLE NOT NULL,
+            status TEXT NOT NULL,
+            downloaded BOOLEAN NOT NULL,
+            loaded BOOLEAN NOT NULL
+         );
+
+         CREATE TABLE IF NOT EXISTS default_models (
+            model_type TEXT PRIMARY KEY,
+            model_id TEXT NOT NULL
+         );",
+    )?;
+    Ok(())
+}
+
+fn link_timestamp_ms_and_indexes(conn: &Connection) -> Ad4mDbResult<()> {
+    // Unversioned databases written by executors that already indexed links have the column
+    let has_column = conn
+        .prepare("SELECT 1 FROM pragma_table_info('link') WHERE name = 'timestamp_ms'")?
+        .exists([])?;
+    if !has_column {
+        conn.execute("ALTER TABLE link ADD COLUMN timestamp_ms INTEGER", [])?;
+    }
+
+    let mut stmt = conn.prepare("SELECT id, timestamp FROM link WHERE timestamp_ms IS NULL")?;
+    let missing = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (id, timestamp) in missing {
+        conn.execute(
+            "UPDATE link SET timestamp_ms = ?1 WHERE id = ?2",
+            params![timestamp_to_millis(&timestamp), id],
+        )?;
+    }
+
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS link_perspective_source ON link (perspective, source, timestamp_ms);
+         CREATE INDEX IF NOT EXISTS link_perspective_target ON link (perspective, target, timestamp_ms);
+         CREATE INDEX IF NOT EXISTS link_perspective_predicate ON link (perspective, predicate, timestamp_ms);
+         CREATE INDEX IF NOT EXISTS link_perspective_timestamp ON link (perspective, timestamp_ms);",
+    )?;
+    Ok(())
+}
+
+fn link_search_index(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS link_search USING fts5 (
+            perspective UNINDEXED,
+            text,
+            tokenize = 'unicode61 remove_diacritics 2'
+         );
+
+         CREATE TABLE IF NOT EXISTS link_embedding (
+            link_id INTEGER NOT NULL,
+            model_id TEXT NOT NULL,
+            embedding BLOB NOT NULL,
+            PRIMARY KEY (link_id, model_id)
+         );",
+    )?;
+
+    let mut stmt = conn.prepare("SELECT id, perspective, target FROM link")?;
+    let links = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (id, perspective, target) in links {
+        index_link_text(conn, id, &perspective, &target)?;
+    }
+    Ok(())
+}
+
+fn notification_delivery_outbox(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS notification_delivery (
+            id INTEGER PRIMARY KEY,
+            notification_id TEXT NOT NULL,
+            webhook_url TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            status TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            created_at INTEGER NOT NULL,
+            next_attempt_at INTEGER,
+            last_attempt_at INTEGER,
+            last_response_status INTEGER,
+            last_error TEXT,
+            delivered_at INTEGER
+         );
+
+         CREATE INDEX IF NOT EXISTS notification_delivery_due ON notification_delivery (status, next_attempt_at);
+         CREATE INDEX IF NOT EXISTS notification_delivery_notification ON notification_delivery (notification_id, id);",
+    )?;
+    Ok(())
+}
+
+fn key_rotations(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS key_rotation (
+            agent_did TEXT NOT NULL,
+            old_key TEXT NOT NULL,
+            new_key TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            signature TEXT NOT NULL,
+            PRIMARY KEY (agent_did, old_key)
+         );",
+    )?;
+    Ok(())
+}
+
+fn device_revocations(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS device_revocation (
+            did TEXT NOT NULL,
+            device_key TEXT NOT NULL,
+            revoked_at TEXT NOT NULL,
+            signing_key TEXT NOT NULL,
+            signature TEXT NOT NULL,
+            PRIMARY KEY (did, device_key)
+         );",
+    )?;
+    Ok(())
+}
+
+fn perspective_sync_status(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute_batch(
+        "ALTER TABLE perspective_diff ADD COLUMN created_at INTEGER;
+         ALTER TABLE perspective_diff ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+         ALTER TABLE perspective_diff ADD COLUMN last_attempt_at INTEGER;
+         ALTER TABLE perspective_diff ADD COLUMN last_error TEXT;
+
+         CREATE TABLE IF NOT EXISTS perspective_sync (
+            perspective TEXT PRIMARY KEY,
+            committed_diffs INTEGER NOT NULL DEFAULT 0,
+            last_commit_at INTEGER,
+            last_error TEXT,
+            last_error_at INTEGER
+         );",
+    )?;
+    Ok(())
+}
+
+fn perspective_history(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS perspective_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            perspective TEXT NOT NULL,
+            additions TEXT NOT NULL,
+            removals TEXT NOT NULL,
+            local BOOLEAN NOT NULL,
+            applied_at INTEGER NOT NULL,
+            undoes INTEGER
+         );
+         CREATE INDEX IF NOT EXISTS perspective_history_applied_at
+            ON perspective_history (perspective, applied_at);",
+    )?;
+    Ok(())
+}
+
+fn ai_sessions(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ai_sessions (
+            id TEXT PRIMARY KEY,
+            task_id TEXT NOT NULL,
+            name TEXT,
+            truncation TEXT NOT NULL,
+            max_messages INTEGER,
+            max_tokens INTEGER,
+            perspective TEXT,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+         );
+         CREATE TABLE IF NOT EXISTS ai_session_messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            session_id TEXT NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            created_at TEXT NOT NULL
+         );
+         CREATE INDEX IF NOT EXISTS ai_session_messages_session
+            ON ai_session_messages (session_id);",
+    )?;
+    Ok(())
+}
+
+fn model_api_headers(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute("ALTER TABLE models ADD COLUMN api_headers TEXT", [])?;
+    Ok(())
+}
+
+fn local_model_language(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute("ALTER TABLE models ADD COLUMN local_language TEXT", [])?;
+    Ok(())
+}
+
+fn task_output_constraint(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute("ALTER TABLE tasks ADD COLUMN output_constraint TEXT", [])?;
+    Ok(())
+}
+
+fn notification_webhook_secret(conn: &Connection) -> Ad4mDbResult<()> {
+    conn.execute_batch(
+        "ALTER TABLE notifications ADD COLUMN webhook_secret TEXT;
+         UPDATE notifications SET webhook_secret = lower(hex(randomblob(32)));",
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Ad4mDb, AD4M_DB_FILE_NAME};
+    use crate::graphql::graphql_types::LinkQuery;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    const UNVERSIONED_FIXTURE: &str = include_str!("fixtures/v0_unversioned.sql");
+    const V1_FIXTURE: &str = include_str!("fixtures/v1.sql");
+    const FIXTURE_PERSPECTIVE: &str = "8c0f6a0e-3c8e-4a3c-9d8a-0d1b2c3d4e5f";
+
+    fn temp_data_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ad4m-migrations-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn db_from_fixture(dir: &Path, fixture: &str) -> String {
+        let path = dir.join(AD4M_DB_FILE_NAME).to_string_lossy().into_owned();
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(fixture)
+            .unwrap();
+        path
+    }
+
+    fn backups_in(dir: &Path) -> Vec<PathBuf> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.to_string_lossy().contains(".backup-v"))
+            .collect()
+    }
+
+    fn link_count(path: &str) -> i64 {
+        Connection::open(path)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM link", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn fresh_database_is_created_at_latest_version() {
+        let db = Ad4mDb::new(":memory:").unwrap();
+        assert_eq!(current_version(&db.conn).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn upgrades_unversioned_fixture() {
+        let dir = temp_data_dir();
+        let path = db_from_fixture(&dir, UNVERSIONED_FIXTURE);
+
+        let status = check(&path).unwrap();
+        assert_eq!(status.current_version, 0);
+        assert_eq!(status.pending.len(), MIGRATIONS.len());
+
+        let db = Ad4mDb::new(&path).unwrap();
+        assert_eq!(current_version(&db.conn).unwrap(), latest_version());
+
+        let perspectives = db.get_all_perspectives().unwrap();
+        assert_eq!(perspectives.len(), 1);
+        assert_eq!(perspectives[0].name, Some("Legacy perspective".to_string()));
+        assert_eq!(
+            db.get_all_trusted_agents().unwrap(),
+            vec!["did:key:trusted"]
+        );
+        assert_eq!(db.get_all_friends().unwrap(), vec!["did:key:friend"]);
+        let notifications = db.get_notifications().unwrap();
+        assert_eq!(notifications.len(), 1);
+        let secret = db
+            .get_notification_webhook_secret(&notifications[0].id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(secret.len(), 64);
+        assert!(db
+            .get_model("c1c2d3e4-0000-4000-8000-000000000002".to_string())
+            .unwrap()
+            .is_some());
+
+        let from: chrono::DateTime<chrono::Utc> =
+            chrono::DateTime::parse_from_rfc3339("2023-05-02T00:00:00Z")
+                .unwrap()
+                .into();
+        let links = db
+            .get_links_by_query(
+                FIXTURE_PERSPECTIVE,
+                &LinkQuery {
+                    from_date: Some(from.into()),
+                    ..Default::default()
+                },
+                false,
+            )
+            .unwrap();
+        let targets: Vec<String> = links.into_iter().map(|(l, _)| l.data.target).collect();
+        assert_eq!(
+            targets,
+            vec!["literal://string:second", "literal://string:third"]
+        );
+
+        let backups = backups_in(&dir);
+        assert_eq!(backups.len(), 1);
+        let backup = backups[0].to_string_lossy().into_owned();
+        assert!(backup.contains(".backup-v0-"));
+        assert_eq!(check(&backup).unwrap().current_version, 0);
+        assert_eq!(link_count(&backup), 3);
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn upgrades_v1_fixture() {
+        let dir = temp_data_dir();
+        let path = db_from_fixture(&dir, V1_FIXTURE);
+
+        let status = check(&path).unwrap();
+        assert_eq!(status.current_version, 1);
+        assert_eq!(
+            status.pending.iter().map(|(v, _)| *v).collect::<Vec<u32>>(),
+            (2..=latest_version()).collect::<Vec<u32>>()
+        );
+
+        let db = Ad4mDb::new(&path).unwrap();
+        assert_eq!(current_version(&db.conn).unwrap(), latest_version());
+        let unindexed: i64 = db
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM link WHERE timestamp_ms IS NULL",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(unindexed, 0);
+
+        // Literal targets of existing links were added to the search index
+        let found = db.search_links(FIXTURE_PERSPECTIVE, "second", 10).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0.data.target, "literal://string:second");
+        assert_eq!(found[0].2, "second");
+
+        let backups = backups_in(&dir);
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].to_string_lossy().contains(".backup-v1-"));
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn check_does_not_modify_database() {
+        let dir = temp_data_dir();
+        let path = db_from_fixture(&dir, UNVERSIONED_FIXTURE);
+
+        assert!(!check(&path).unwrap().is_up_to_date());
+        assert!(!check(&path).unwrap().is_up_to_date());
+        assert!(backups_in(&dir).is_empty());
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(current_version(&conn).unwrap(), 0);
+
+        let missing = dir.join("missing.sqlite").to_string_lossy().into_owned();
+        assert_eq!(check(&missing).unwrap().current_version, 0);
+        assert!(!Path::new(&missing).exists());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn migrating_twice_is_a_no_op() {
+        let dir = temp_data_dir();
+        let path = db_from_fixture(&dir, UNVERSIONED_FIXTURE);
+
+        drop(Ad4mDb::new(&path).unwrap());
+        let db = Ad4mDb::new(&path).unwrap();
+        assert!(check(&path).unwrap().is_up_to_date());
+        assert_eq!(backups_in(&dir).len(), 1);
+        assert_eq!(db.get_all_perspectives().unwrap().len(), 1);
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn refuses_database_from_newer_executor() {
+        let dir = temp_data_dir();
+        let path = db_from_fixture(&dir, V1_FIXTURE);
+        Connection::open(&path)
+            .unwrap()
+            .execute(
+                "INSERT INTO schema_version (version, applied_at) VALUES (?1, '')",
+                params![latest_version() + 1],
+            )
+            .unwrap();
+
+        assert!(check(&path).is_err());
+        assert!(Ad4mDb::new(&path).is_err());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,19 +1,28 @@
 use crate::graphql::graphql_types::{
-    AIModelLoadingStatus, EntanglementProof, LinkStatus, ModelInput, NotificationInput,
+    AIModelLoadingStatus, EntanglementProof, LinkQuery, LinkStatus, ModelInput, NotificationInput,
     PerspectiveExpression, PerspectiveHandle, SentMessage,
 };
 use crate::types::{
-    AIPromptExamples, AITask, Expression, ExpressionProof, Link, LinkExpression, LocalModel, Model,
-    ModelApi, ModelApiType, ModelType, Notification, PerspectiveDiff, TokenizerSource,
+    AIContextTruncation, AIMessage, AIOutputConstraint, AIPromptExamples, AISession, AITask,
+    DeviceRevocation, Expression, ExpressionProof, HistoryDiff, KeyRotation, Link, LinkExpression,
+    LocalModel, Model, ModelApi, ModelApiType, ModelType, Notification, NotificationDelivery,
+    NotificationDeliveryStatus, PendingDiff, PerspectiveDiff, PerspectiveSyncStatus,
+    TokenizerSource,
 };
+use ad4m_client::literal::{Literal, LiteralValue};
+use base64::prelude::*;
 use deno_core::anyhow::anyhow;
 use deno_core::error::AnyError;
-use rusqlite::{params, Connection, OptionalExtension};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use std::str::FromStr;
 use url::Url;
 
+pub mod migrations;
+pub mod snapshot;
+
 #[derive(Serialize, Deserialize)]
 struct LinkSchema {
     perspective: String,
@@ -34,188 +43,376 @@ struct ExpressionSchema {
 
 pub type Ad4mDbResult<T> = Result<T, AnyError>;
 
-use std::sync::{Arc, Mutex};
+/// File name of the SQLite database inside the app data directory
+pub const AD4M_DB_FILE_NAME: &str = "ad4m_db.sqlite";
 
-lazy_static! {
-    static ref AD4M_DB_INSTANCE: Arc<Mutex<Option<Ad4mDb>>> = Arc::new(Mutex::new(None));
+/// Integer representation of a link timestamp used for indexing and range queries.
+/// The original RFC3339 string is kept in the `timestamp` column since it is part
+/// of the signed expression.
+fn timestamp_to_millis(timestamp: &str) -> Option<i64> {
+    chrono::DateTime::parse_from_rfc3339(timestamp)
+        .map(|t| t.timestamp_millis())
+        .ok()
 }
 
-pub struct Ad4mDb {
-    conn: Connection,
+pub fn millis_to_timestamp(millis: i64) -> String {
+    chrono::DateTime::<chrono::Utc>::from_timestamp_millis(millis)
+        .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+        .unwrap_or_default()
 }
 
-impl Ad4mDb {
-    pub fn init_global_instance(db_path: &str) -> Ad4mDbResult<()> {
-        let mut db_instance = AD4M_DB_INSTANCE.lock().unwrap();
-        *db_instance = Some(Ad4mDb::new(db_path)?);
-        Ok(())
-    }
+const NOTIFICATION_DELIVERY_COLUMNS: &str = "id, notification_id, webhook_url, payload, status, attempts, created_at, next_attempt_at, last_attempt_at, last_response_status, last_error, delivered_at";
 
-    pub fn global_instance() -> Arc<Mutex<Option<Ad4mDb>>> {
-        AD4M_DB_INSTANCE.clone()
-    }
+/// Maps a row of `SELECT {NOTIFICATION_DELIVERY_COLUMNS}`
+fn notification_delivery_from_row(row: &rusqlite::Row) -> rusqlite::Result<NotificationDelivery> {
+    let status = NotificationDeliveryStatus::from_str(&row.get::<_, String>(4)?).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(
+            4,
+            rusqlite::types::Type::Text,
+            Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+        )
+    })?;
+    Ok(NotificationDelivery {
+        id: row.get::<_, i64>(0)?.to_string(),
+        notification_id: row.get(1)?,
+        webhook_url: row.get(2)?,
+        payload: row.get(3)?,
+        status,
+        attempts: row.get(5)?,
+        created_at: millis_to_timestamp(row.get(6)?),
+        next_attempt_at: row.get::<_, Option<i64>>(7)?.map(millis_to_timestamp),
+        last_attempt_at: row.get::<_, Option<i64>>(8)?.map(millis_to_timestamp),
+        last_response_status: row.get(9)?,
+        last_error: row.get(10)?,
+        delivered_at: row.get::<_, Option<i64>>(11)?.map(millis_to_timestamp),
+    })
+}
 
-    fn new(db_path: &str) -> Ad4mDbResult<Self> {
-        let conn = Connection::open(db_path)?;
+/// Maps a row of `SELECT perspective, source, predicate, target, author, timestamp, signature, key, status`
+fn link_and_status_from_row(row: &rusqlite::Row) -> rusqlite::Result<(LinkExpression, LinkStatus)> {
+    let status: LinkStatus = serde_json::from_str(&row.get::<_, String>(8)?).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(8, rusqlite::types::Type::Text, Box::new(e))
+    })?;
+    let link_expression = LinkExpression {
+        data: Link {
+            source: row.get(1)?,
+            predicate: row.get(2).map(|p: Option<String>| match p.as_deref() {
+                Some("") => None,
+                _ => p,
+            })?,
+            target: row.get(3)?,
+        },
+        proof: ExpressionProof {
+            signature: row.get(6)?,
+            key: row.get(7)?,
+        },
+        author: row.get(4)?,
+        timestamp: row.get(5)?,
+        status: Some(status.clone()),
+    };
+    Ok((link_expression, status))
+}
 
-        // Create tables if they don't exist
+fn history_diff_from_row(row: &rusqlite::Row) -> rusqlite::Result<HistoryDiff> {
+    let links = |index: usize| -> rusqlite::Result<Vec<LinkExpression>> {
+        serde_json::from_str(&row.get::<_, String>(index)?).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(
+                index,
+                rusqlite::types::Type::Text,
+                Box::new(e),
+            )
+        })
+    };
+    Ok(HistoryDiff {
+        id: row.get(0)?,
+        diff: PerspectiveDiff {
+            additions: links(1)?,
+            removals: links(2)?,
+        },
+        local: row.get(3)?,
+        applied_at: row.get(4)?,
+        undoes: row.get(5)?,
+        undone_by: row.get(6)?,
+    })
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS perspective_handle (
-                uuid TEXT PRIMARY KEY,
-                name TEXT,
-                neighbourhood TEXT,
-                shared_url TEXT,
-                state TEXT NOT NULL
-             )",
-            [],
-        )?;
+/// Same link, regardless of the status it is stored with
+fn same_link(a: &LinkExpression, b: &LinkExpression) -> bool {
+    a.author == b.author
+        && a.timestamp == b.timestamp
+        && a.data == b.data
+        && a.proof.signature == b.proof.signature
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS link (
-                id INTEGER PRIMARY KEY,
-                perspective TEXT NOT NULL,
-                source TEXT NOT NULL,
-                predicate TEXT NOT NULL,
-                target TEXT NOT NULL,
-                author TEXT NOT NULL,
-                timestamp TEXT NOT NULL,
-                signature TEXT NOT NULL,
-                key TEXT NOT NULL,
-                status TEXT NOT NULL
-             )",
-            [],
-        )?;
+fn ai_session_from_row(row: &rusqlite::Row) -> rusqlite::Result<AISession> {
+    Ok(AISession {
+        session_id: row.get(0)?,
+        task_id: row.get(1)?,
+        name: row.get(2)?,
+        truncation: serde_json::from_str(&row.get::<_, String>(3)?)
+            .unwrap_or(AIContextTruncation::KeepAll),
+        max_messages: row.get(4)?,
+        max_tokens: row.get(5)?,
+        perspective_uuid: row.get(6)?,
+        created_at: row.get(7)?,
+        updated_at: row.get(8)?,
+    })
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS expression (
-                id INTEGER PRIMARY KEY,
-                url TEXT NOT NULL UNIQUE,
-                data TEXT NOT NULL
-             )",
-            [],
-        )?;
+/// JSON of a task's output constraint, stored in `tasks.output_constraint`
+fn output_constraint_json(constraint: &Option<AIOutputConstraint>) -> Option<String> {
+    constraint
+        .as_ref()
+        .map(|constraint| serde_json::to_string(constraint).unwrap())
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS perspective_diff (
-                id INTEGER PRIMARY KEY,
-                perspective TEXT NOT NULL,
-                additions TEXT NOT NULL,
-                removals TEXT NOT NULL,
-                is_pending BOOLEAN NOT NULL
-             )",
-            [],
-        )?;
+/// JSON of the custom headers of a model's API, stored in `models.api_headers`
+fn model_api_headers(model: &ModelInput) -> Option<String> {
+    model
+        .api
+        .as_ref()
+        .and_then(|api| api.headers.as_ref())
+        .map(|headers| serde_json::to_string(headers).unwrap())
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS trusted_agent (
-                id INTEGER PRIMARY KEY,
-                agent TEXT NOT NULL
-             )",
-            [],
-        )?;
+const AI_SESSION_COLUMNS: &str =
+    "id, task_id, name, truncation, max_messages, max_tokens, perspective, created_at, updated_at";
+
+const HISTORY_COLUMNS: &str = "h.id, h.additions, h.removals, h.local, h.applied_at, h.undoes,
+    (SELECT MAX(u.id) FROM perspective_history u WHERE u.perspective = h.perspective AND u.undoes = h.id)";
+
+/// Text that gets indexed for search for a link target: the decoded value of
+/// `literal://` URLs. Other targets are not searchable.
+fn searchable_text(target: &str) -> Option<String> {
+    Literal::from_url(target.to_string())
+        .and_then(|literal| literal.get())
+        .map(|value| match value {
+            LiteralValue::String(string) => string,
+            other => other.to_string(),
+        })
+        .ok()
+        .filter(|text| !text.trim().is_empty())
+}
 
+fn index_link_text(
+    conn: &Connection,
+    link_id: i64,
+    perspective_uuid: &str,
+    target: &str,
+) -> Ad4mDbResult<()> {
+    if let Some(text) = searchable_text(target) {
         conn.execute(
-            "CREATE TABLE IF NOT EXISTS known_link_languages (
-                id INTEGER PRIMARY KEY,
-                language TEXT NOT NULL
-             )",
-            [],
+            "INSERT INTO link_search (rowid, perspective, text) VALUES (?1, ?2, ?3)",
+            params![link_id, perspective_uuid, text],
         )?;
+    }
+    Ok(())
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS friends (
-                id INTEGER PRIMARY KEY,
-                friend TEXT NOT NULL
-             )",
-            [],
-        )?;
+fn unindex_link_text(conn: &Connection, link_id: i64) -> Ad4mDbResult<()> {
+    conn.execute("DELETE FROM link_search WHERE rowid = ?1", [link_id])?;
+    conn.execute("DELETE FROM link_embedding WHERE link_id = ?1", [link_id])?;
+    Ok(())
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS outbox (
-                id INTEGER PRIMARY KEY,
-                message TEXT NOT NULL,
-                recipient TEXT NOT NULL
-             )",
-            [],
-        )?;
+/// Turns free text into an FTS5 query that matches rows containing all words,
+/// so that user input can't inject FTS5 query syntax.
+fn fts_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS entanglement_proof (
-                id INTEGER PRIMARY KEY,
-                proof TEXT NOT NULL
-             )",
-            [],
-        )?;
+fn embedding_to_blob(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
 
-        // Start Generation Here
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS notifications (
-                id TEXT PRIMARY KEY,
-                granted BOOLEAN NOT NULL,
-                description TEXT NOT NULL,
-                appName TEXT NOT NULL,
-                appUrl TEXT NOT NULL,
-                appIconPath TEXT,
-                trigger TEXT NOT NULL,
-                perspective_ids TEXT NOT NULL,
-                webhookUrl TEXT NOT NULL,
-                webhookAuth TEXT NOT NULL
-             )",
-            [],
-        )?;
+fn embedding_from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS tasks (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                model_id TEXT NOT NULL,
-                system_prompt TEXT NOT NULL,
-                prompt_examples TEXT NOT NULL,
-                metadata TEXT NULL,
-                created_at TEXT NOT NULL,
-                updated_at TEXT NOT NULL
-             )",
-            [],
-        )?;
+/// Position of a link in the (`timestamp_ms`, `id`) order used for paging.
+/// Handed out to clients as an opaque string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkCursor {
+    pub timestamp_ms: Option<i64>,
+    pub id: i64,
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS models (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                api_base_url TEXT,
-                api_key TEXT,
-                model TEXT,
-                api_type TEXT,
-                local_file_name TEXT,
-                local_tokenizer_repo TEXT,
-                local_tokenizer_revision TEXT,
-                local_tokenizer_file_name TEXT,
-                local_huggingface_repo TEXT,
-                local_revision TEXT,
-                type TEXT NOT NULL
-            )",
-            [],
-        )?;
+impl LinkCursor {
+    pub fn encode(&self) -> String {
+        let timestamp = self.timestamp_ms.map(|t| t.to_string()).unwrap_or_default();
+        BASE64_URL_SAFE_NO_PAD.encode(format!("{}:{}", timestamp, self.id))
+    }
+
+    pub fn decode(cursor: &str) -> Ad4mDbResult<Self> {
+        let invalid = || anyhow!("Invalid link cursor: {}", cursor);
+        let bytes = BASE64_URL_SAFE_NO_PAD
+            .decode(cursor)
+            .map_err(|_| invalid())?;
+        let decoded = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let (timestamp, id) = decoded.split_once(':').ok_or_else(invalid)?;
+        Ok(LinkCursor {
+            timestamp_ms: match timestamp {
+                "" => None,
+                t => Some(t.parse().map_err(|_| invalid())?),
+            },
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+
+    /// SQL condition selecting links strictly greater (or smaller) than this cursor.
+    /// Links without `timestamp_ms` sort first, like SQLite orders NULLs.
+    fn sql_condition(&self, greater: bool, values: &mut Vec<SqlValue>) -> String {
+        match (self.timestamp_ms, greater) {
+            (Some(t), true) => {
+                values.extend([
+                    SqlValue::Integer(t),
+                    SqlValue::Integer(t),
+                    SqlValue::Integer(self.id),
+                ]);
+                "(timestamp_ms > ? OR (timestamp_ms = ? AND id > ?))".to_string()
+            }
+            (Some(t), false) => {
+                values.extend([
+                    SqlValue::Integer(t),
+                    SqlValue::Integer(t),
+                    SqlValue::Integer(self.id),
+                ]);
+                "(timestamp_ms < ? OR timestamp_ms IS NULL OR (timestamp_ms = ? AND id < ?))"
+                    .to_string()
+            }
+            (None, true) => {
+                values.push(SqlValue::Integer(self.id));
+                "(timestamp_ms IS NOT NULL OR id > ?)".to_string()
+            }
+            (None, false) => {
+                values.push(SqlValue::Integer(self.id));
+                "(timestamp_ms IS NULL AND id < ?)".to_string()
+            }
+        }
+    }
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS model_status (
-                model TEXT PRIMARY KEY,
-                progress DOUBLE NOT NULL,
-                status TEXT NOT NULL,
-                downloaded BOOLEAN NOT NULL,
-                loaded BOOLEAN NOT NULL
-            )",
-            [],
-        )?;
+pub struct LinksPage {
+    pub links: Vec<(LinkExpression, LinkStatus)>,
+    /// Cursor of each link in `links`
+    pub cursors: Vec<LinkCursor>,
+    pub start_cursor: Option<LinkCursor>,
+    pub end_cursor: Option<LinkCursor>,
+    pub has_more: bool,
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS default_models (
-                model_type TEXT PRIMARY KEY,
-                model_id TEXT NOT NULL
-            )",
-            [],
-        )?;
+/// SQL and parameters of a [`Ad4mDb::get_links_page`] query
+struct LinksPageSql {
+    sql: String,
+    values: Vec<SqlValue>,
+    /// Whether the rows come in reverse of the requested order
+    backwards: bool,
+    limit: Option<usize>,
+}
+
+fn links_page_sql(
+    perspective_uuid: &str,
+    query: &LinkQuery,
+    descending: bool,
+) -> Ad4mDbResult<LinksPageSql> {
+    let after = query.after.as_deref().map(LinkCursor::decode).transpose()?;
+    let before = query
+        .before
+        .as_deref()
+        .map(LinkCursor::decode)
+        .transpose()?;
+
+    let mut sql = String::from(
+        "SELECT perspective, source, predicate, target, author, timestamp, signature, key, status, id, timestamp_ms FROM link WHERE perspective = ?",
+    );
+    let mut values: Vec<SqlValue> = vec![SqlValue::Text(perspective_uuid.to_string())];
+
+    if let Some(source) = &query.source {
+        sql.push_str(" AND source = ?");
+        values.push(SqlValue::Text(source.clone()));
+    }
+    if let Some(predicate) = &query.predicate {
+        sql.push_str(" AND predicate = ?");
+        values.push(SqlValue::Text(predicate.clone()));
+    }
+    if let Some(target) = &query.target {
+        sql.push_str(" AND target = ?");
+        values.push(SqlValue::Text(target.clone()));
+    }
+    if let Some(from_date) = &query.from_date {
+        let from_date: chrono::DateTime<chrono::Utc> = from_date.clone().into();
+        sql.push_str(" AND timestamp_ms >= ?");
+        values.push(SqlValue::Integer(from_date.timestamp_millis()));
+    }
+    if let Some(until_date) = &query.until_date {
+        let until_date: chrono::DateTime<chrono::Utc> = until_date.clone().into();
+        sql.push_str(" AND timestamp_ms <= ?");
+        values.push(SqlValue::Integer(until_date.timestamp_millis()));
+    }
+    // "after" means later in the requested order, which is a smaller key when descending
+    if let Some(cursor) = &after {
+        sql.push_str(" AND ");
+        sql.push_str(&cursor.sql_condition(!descending, &mut values));
+    }
+    if let Some(cursor) = &before {
+        sql.push_str(" AND ");
+        sql.push_str(&cursor.sql_condition(descending, &mut values));
+    }
+
+    // Paging backwards from a `before` cursor: walk the index in the opposite direction
+    // so LIMIT picks the links closest to the cursor, then restore the requested order.
+    let backwards = before.is_some() && after.is_none();
+    if descending != backwards {
+        sql.push_str(" ORDER BY timestamp_ms DESC, id DESC");
+    } else {
+        sql.push_str(" ORDER BY timestamp_ms ASC, id ASC");
+    }
+
+    let limit = query.limit.map(|l| l.max(0) as usize);
+    if let Some(limit) = limit {
+        // One extra row tells us whether there is another page
+        sql.push_str(" LIMIT ?");
+        values.push(SqlValue::Integer(limit as i64 + 1));
+    }
+
+    Ok(LinksPageSql {
+        sql,
+        values,
+        backwards,
+        limit,
+    })
+}
+
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    static ref AD4M_DB_INSTANCE: Arc<Mutex<Option<Ad4mDb>>> = Arc::new(Mutex::new(None));
+}
+
+pub struct Ad4mDb {
+    conn: Connection,
+}
+
+impl Ad4mDb {
+    pub fn init_global_instance(db_path: &str) -> Ad4mDbResult<()> {
+        let mut db_instance = AD4M_DB_INSTANCE.lock().unwrap();
+        *db_instance = Some(Ad4mDb::new(db_path)?);
+        Ok(())
+    }
+
+    pub fn global_instance() -> Arc<Mutex<Option<Ad4mDb>>> {
+        AD4M_DB_INSTANCE.clone()
+    }
+
+    fn new(db_path: &str) -> Ad4mDbResult<Self> {
+        let conn = Connection::open(db_path)?;
+
+        migrations::migrate(&conn, db_path)?;
 
         Ok(Self { conn })
     }
@@ -273,18 +470,25 @@ impl Ad4mDb {
         system_prompt: String,
         prompt_examples: Vec<AIPromptExamples>,
         metadata: Option<String>,
+        output_constraint: Option<AIOutputConstraint>,
     ) -> Result<String, rusqlite::Error> {
         let created_at = chrono::Utc::now().to_string();
         let updated_at = created_at.clone();
         let id = uuid::Uuid::new_v4().to_string();
         self.conn.execute(
-            "INSERT INTO tasks (id, name, model_id, system_prompt, prompt_examples, metadata, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-            params![id, name, model_id, system_prompt, serde_json::to_string(&prompt_examples).unwrap(), metadata, created_at, updated_at],
+            "INSERT INTO tasks (id, name, model_id, system_prompt, prompt_examples, metadata, created_at, updated_at, output_constraint) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![id, name, model_id, system_prompt, serde_json::to_string(&prompt_examples).unwrap(), metadata, created_at, updated_at, output_constraint_json(&output_constraint)],
         )?;
         Ok(id)
     }
 
     pub fn remove_task(&self, id: String) -> Result<(), rusqlite::Error> {
+        self.conn.execute(
+            "DELETE FROM ai_session_messages WHERE session_id IN (SELECT id FROM ai_sessions WHERE task_id = ?1)",
+            [&id],
+        )?;
+        self.conn
+            .execute("DELETE FROM ai_sessions WHERE task_id = ?1", [&id])?;
         self.conn.execute("DELETE FROM tasks WHERE id = ?", [id])?;
         Ok(())
     }
@@ -303,6 +507,9 @@ impl Ad4mDb {
                 system_prompt: row.get(3)?,
                 prompt_examples,
                 meta_data: row.get(5)?,
+                output_constraint: row
+                    .get::<_, Option<String>>(8)?
+                    .map(|constraint| serde_json::from_str(&constraint).unwrap()),
                 created_at: row.get(6)?,
                 updated_at: row.get(7)?,
             }))
@@ -323,6 +530,9 @@ impl Ad4mDb {
                 system_prompt: row.get(3)?,
                 prompt_examples,
                 meta_data: row.get(5)?,
+                output_constraint: row
+                    .get::<_, Option<String>>(8)?
+                    .map(|constraint| serde_json::from_str(&constraint).unwrap()),
                 created_at: row.get(6)?,
                 updated_at: row.get(7)?,
             };
@@ -344,23 +554,130 @@ impl Ad4mDb {
         system_prompt: String,
         prompt_examples: Vec<AIPromptExamples>,
         metadata: Option<String>,
+        output_constraint: Option<AIOutputConstraint>,
     ) -> Result<bool, rusqlite::Error> {
         let updated_at = chrono::Utc::now().to_string();
 
         let result = self.conn.execute(
-            "UPDATE tasks SET name = ?2, model_id = ?3, system_prompt = ?4, prompt_examples = ?5, metadata = ?6, updated_at = ?7 WHERE id = ?1",
-            params![id, name, model_id, system_prompt, serde_json::to_string(&prompt_examples).unwrap(), metadata, updated_at],
+            "UPDATE tasks SET name = ?2, model_id = ?3, system_prompt = ?4, prompt_examples = ?5, metadata = ?6, updated_at = ?7, output_constraint = ?8 WHERE id = ?1",
+            params![id, name, model_id, system_prompt, serde_json::to_string(&prompt_examples).unwrap(), metadata, updated_at, output_constraint_json(&output_constraint)],
         )?;
         Ok(result > 0)
     }
 
+    pub fn add_ai_session(&self, session: &AISession) -> Ad4mDbResult<()> {
+        self.conn.execute(
+            &format!(
+                "INSERT INTO ai_sessions ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                AI_SESSION_COLUMNS
+            ),
+            params![
+                session.session_id,
+                session.task_id,
+                session.name,
+                serde_json::to_string(&session.truncation)?,
+                session.max_messages,
+                session.max_tokens,
+                session.perspective_uuid,
+                session.created_at,
+                session.updated_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_ai_session(&self, id: &str) -> Ad4mDbResult<Option<AISession>> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM ai_sessions WHERE id = ?1",
+                    AI_SESSION_COLUMNS
+                ),
+                [id],
+                ai_session_from_row,
+            )
+            .optional()?)
+    }
+
+    /// Sessions of one task or of all tasks, most recently used first
+    pub fn get_ai_sessions(&self, task_id: Option<&str>) -> Ad4mDbResult<Vec<AISession>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM ai_sessions WHERE ?1 IS NULL OR task_id = ?1 ORDER BY updated_at DESC",
+            AI_SESSION_COLUMNS
+        ))?;
+        let sessions = stmt
+            .query_map([task_id], ai_session_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(sessions)
+    }
+
+    /// Removes a session with its messages, returns false if there was none
+    pub fn remove_ai_session(&self, id: &str) -> Ad4mDbResult<bool> {
+        self.conn.execute(
+            "DELETE FROM ai_session_messages WHERE session_id = ?1",
+            [id],
+        )?;
+        Ok(self
+            .conn
+            .execute("DELETE FROM ai_sessions WHERE id = ?1", [id])?
+            > 0)
+    }
+
+    pub fn add_ai_session_message(
+        &self,
+        session_id: &str,
+        message: &AIMessage,
+    ) -> Ad4mDbResult<()> {
+        self.conn.execute(
+            "INSERT INTO ai_session_messages (session_id, role, content, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                session_id,
+                serde_json::to_string(&message.role)?,
+                message.content,
+                message.created_at,
+            ],
+        )?;
+        self.conn.execute(
+            "UPDATE ai_sessions SET updated_at = ?2 WHERE id = ?1",
+            params![session_id, message.created_at],
+        )?;
+        Ok(())
+    }
+
+    /// Messages of a session, oldest first
+    pub fn get_ai_session_messages(&self, session_id: &str) -> Ad4mDbResult<Vec<AIMessage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT role, content, created_at FROM ai_session_messages WHERE session_id = ?1 ORDER BY id",
+        )?;
+        let messages = stmt
+            .query_map([session_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(|(role, content, created_at)| {
+                Ok(AIMessage {
+                    role: serde_json::from_str(&role)?,
+                    content,
+                    created_at,
+                })
+            })
+            .collect::<Ad4mDbResult<Vec<_>>>()?;
+        Ok(messages)
+    }
+
     pub fn add_notification(
         &self,
         notification: NotificationInput,
     ) -> Result<String, rusqlite::Error> {
         let id = uuid::Uuid::new_v4().to_string();
         self.conn.execute(
-            "INSERT INTO notifications (id, granted, description, appName, appUrl, appIconPath, trigger, perspective_ids, webhookUrl, webhookAuth) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            "INSERT INTO notifications (id, granted, description, appName, appUrl, appIconPath, trigger, perspective_ids, webhookUrl, webhookAuth, webhook_secret) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, lower(hex(randomblob(32))))",
             params![
                 id,
                 false,
@@ -425,6 +742,18 @@ impl Ad4mDb {
         }
     }
 
+    /// Random secret generated when the notification was added, see `notification_webhooks`
+    pub fn get_notification_webhook_secret(&self, id: &str) -> Ad4mDbResult<Option<String>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT webhook_secret FROM notifications WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
     pub fn remove_notification(&self, id: String) -> Result<(), rusqlite::Error> {
         self.conn
             .execute("DELETE FROM notifications WHERE id = ?", [id])?;
@@ -454,6 +783,177 @@ impl Ad4mDb {
         Ok(result > 0)
     }
 
+    /// Queues a webhook call in the outbox, due immediately
+    pub fn add_notification_delivery(
+        &self,
+        notification_id: &str,
+        webhook_url: &str,
+        payload: &str,
+        now: i64,
+    ) -> Ad4mDbResult<String> {
+        self.conn.execute(
+            "INSERT INTO notification_delivery (notification_id, webhook_url, payload, status, attempts, created_at, next_attempt_at) VALUES (?1, ?2, ?3, ?4, 0, ?5, ?5)",
+            params![
+                notification_id,
+                webhook_url,
+                payload,
+                NotificationDeliveryStatus::Pending.to_string(),
+                now
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid().to_string())
+    }
+
+    /// Pending deliveries whose next attempt is due at `now`, oldest first
+    pub fn get_due_notification_deliveries(
+        &self,
+        now: i64,
+        limit: usize,
+    ) -> Ad4mDbResult<Vec<NotificationDelivery>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM notification_delivery WHERE status = ?1 AND next_attempt_at <= ?2 ORDER BY next_attempt_at, id LIMIT ?3",
+            NOTIFICATION_DELIVERY_COLUMNS
+        ))?;
+        let deliveries = stmt
+            .query_map(
+                params![
+                    NotificationDeliveryStatus::Pending.to_string(),
+                    now,
+                    limit as i64
+                ],
+                notification_delivery_from_row,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(deliveries)
+    }
+
+    /// Time (in ms) of the earliest pending attempt, if any
+    pub fn next_notification_delivery_due(&self) -> Ad4mDbResult<Option<i64>> {
+        let next: Option<i64> = self.conn.query_row(
+            "SELECT MIN(next_attempt_at) FROM notification_delivery WHERE status = ?1",
+            params![NotificationDeliveryStatus::Pending.to_string()],
+            |row| row.get(0),
+        )?;
+        Ok(next)
+    }
+
+    /// Records the outcome of an attempt.
+    /// `next_attempt_at` is only used if the delivery stays pending.
+    pub fn record_notification_delivery_attempt(
+        &self,
+        id: &str,
+        status: &NotificationDeliveryStatus,
+        attempted_at: i64,
+        response_status: Option<u16>,
+        error: Option<String>,
+        next_attempt_at: Option<i64>,
+    ) -> Ad4mDbResult<()> {
+        let next_attempt_at = match status {
+            NotificationDeliveryStatus::Pending => next_attempt_at,
+            _ => None,
+        };
+        let delivered_at = match status {
+            NotificationDeliveryStatus::Delivered => Some(attempted_at),
+            _ => None,
+        };
+        self.conn.execute(
+            "UPDATE notification_delivery SET status = ?2, attempts = attempts + 1, last_attempt_at = ?3, last_response_status = ?4, last_error = ?5, next_attempt_at = ?6, delivered_at = ?7 WHERE id = ?1",
+            params![
+                id,
+                status.to_string(),
+                attempted_at,
+                response_status,
+                error,
+                next_attempt_at,
+                delivered_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Delivery history of a notification, newest first
+    pub fn get_notification_deliveries(
+        &self,
+        notification_id: &str,
+        limit: usize,
+    ) -> Ad4mDbResult<Vec<NotificationDelivery>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM notification_delivery WHERE notification_id = ?1 ORDER BY id DESC LIMIT ?2",
+            NOTIFICATION_DELIVERY_COLUMNS
+        ))?;
+        let deliveries = stmt
+            .query_map(
+                params![notification_id, limit as i64],
+                notification_delivery_from_row,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(deliveries)
+    }
+
+    pub fn add_key_rotation(&self, rotation: &KeyRotation) -> Ad4mDbResult<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO key_rotation (agent_did, old_key, new_key, timestamp, signature) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                rotation.agent_did,
+                rotation.old_key,
+                rotation.new_key,
+                rotation.timestamp,
+                rotation.signature
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_key_rotations(&self) -> Ad4mDbResult<Vec<KeyRotation>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT agent_did, old_key, new_key, timestamp, signature FROM key_rotation ORDER BY timestamp",
+        )?;
+        let rotations = stmt
+            .query_map([], |row| {
+                Ok(KeyRotation {
+                    agent_did: row.get(0)?,
+                    old_key: row.get(1)?,
+                    new_key: row.get(2)?,
+                    timestamp: row.get(3)?,
+                    signature: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rotations)
+    }
+
+    pub fn add_device_revocation(&self, revocation: &DeviceRevocation) -> Ad4mDbResult<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO device_revocation (did, device_key, revoked_at, signing_key, signature) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                revocation.did,
+                revocation.device_key,
+                revocation.revoked_at,
+                revocation.signing_key,
+                revocation.signature
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_device_revocations(&self) -> Ad4mDbResult<Vec<DeviceRevocation>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT did, device_key, revoked_at, signing_key, signature FROM device_revocation ORDER BY revoked_at",
+        )?;
+        let revocations = stmt
+            .query_map([], |row| {
+                Ok(DeviceRevocation {
+                    did: row.get(0)?,
+                    device_key: row.get(1)?,
+                    revoked_at: row.get(2)?,
+                    signing_key: row.get(3)?,
+                    signature: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(revocations)
+    }
+
     pub fn add_entanglement_proofs(
         &self,
         proofs: Vec<EntanglementProof>,
@@ -710,6 +1210,10 @@ impl Ad4mDb {
     pub fn remove_perspective(&self, uuid: &str) -> Ad4mDbResult<()> {
         self.conn
             .execute("DELETE FROM perspective_handle WHERE uuid = ?1", [uuid])?;
+        self.conn.execute(
+            "DELETE FROM perspective_history WHERE perspective = ?1",
+            [uuid],
+        )?;
         Ok(())
     }
 
@@ -720,8 +1224,8 @@ impl Ad4mDb {
         status: &LinkStatus,
     ) -> Ad4mDbResult<()> {
         self.conn.execute(
-            "INSERT INTO link (perspective, source, predicate, target, author, timestamp, signature, key, status)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            "INSERT INTO link (perspective, source, predicate, target, author, timestamp, signature, key, status, timestamp_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
             params![
                 perspective_uuid,
                 link.data.source,
@@ -732,8 +1236,15 @@ impl Ad4mDb {
                 link.proof.signature,
                 link.proof.key,
                 serde_json::to_string(status)?,
+                timestamp_to_millis(&link.timestamp),
             ],
         )?;
+        index_link_text(
+            &self.conn,
+            self.conn.last_insert_rowid(),
+            perspective_uuid,
+            &link.data.target,
+        )?;
         Ok(())
     }
 
@@ -743,10 +1254,11 @@ impl Ad4mDb {
         links: Vec<LinkExpression>,
         status: &LinkStatus,
     ) -> Ad4mDbResult<()> {
+        let tx = self.conn.unchecked_transaction()?;
         for link in links.iter() {
-            self.conn.execute(
-                "INSERT INTO link (perspective, source, predicate, target, author, timestamp, signature, key, status)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            tx.execute(
+                "INSERT INTO link (perspective, source, predicate, target, author, timestamp, signature, key, status, timestamp_ms)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
                 params![
                     perspective_uuid,
                     link.data.source,
@@ -757,9 +1269,17 @@ impl Ad4mDb {
                     link.proof.signature,
                     link.proof.key,
                     serde_json::to_string(&status)?,
+                    timestamp_to_millis(&link.timestamp),
                 ],
             )?;
+            index_link_text(
+                &tx,
+                tx.last_insert_rowid(),
+                perspective_uuid,
+                &link.data.target,
+            )?;
         }
+        tx.commit()?;
         Ok(())
     }
 
@@ -769,8 +1289,10 @@ impl Ad4mDb {
         old_link: &LinkExpression,
         new_link: &LinkExpression,
     ) -> Ad4mDbResult<()> {
-        self.conn.execute(
-            "UPDATE link SET source = ?1, predicate = ?2, target = ?3, author = ?4, timestamp = ?5, signature = ?6, key = ?7
+        let tx = self.conn.unchecked_transaction()?;
+        let ids = Self::link_ids(&tx, perspective_uuid, old_link)?;
+        tx.execute(
+            "UPDATE link SET source = ?1, predicate = ?2, target = ?3, author = ?4, timestamp = ?5, signature = ?6, key = ?7, timestamp_ms = ?14
              WHERE perspective = ?8 AND source = ?9 AND predicate = ?10 AND target = ?11 AND author = ?12 AND timestamp = ?13",
             params![
                 new_link.data.source,
@@ -786,13 +1308,47 @@ impl Ad4mDb {
                 old_link.data.target,
                 old_link.author,
                 old_link.timestamp,
+                timestamp_to_millis(&new_link.timestamp),
             ],
         )?;
+        for id in ids {
+            unindex_link_text(&tx, id)?;
+            index_link_text(&tx, id, perspective_uuid, &new_link.data.target)?;
+        }
+        tx.commit()?;
         Ok(())
     }
 
+    fn link_ids(
+        conn: &Connection,
+        perspective_uuid: &str,
+        link: &LinkExpression,
+    ) -> Ad4mDbResult<Vec<i64>> {
+        let mut stmt = conn.prepare(
+            "SELECT id FROM link WHERE perspective = ?1 AND source = ?2 AND predicate = ?3 AND target = ?4 AND author = ?5 AND timestamp = ?6",
+        )?;
+        let ids = stmt
+            .query_map(
+                params![
+                    perspective_uuid,
+                    link.data.source,
+                    link.data.predicate.as_ref().unwrap_or(&"".to_string()),
+                    link.data.target,
+                    link.author,
+                    link.timestamp,
+                ],
+                |row| row.get(0),
+            )?
+            .collect::<Result<Vec<i64>, _>>()?;
+        Ok(ids)
+    }
+
     pub fn remove_link(&self, perspective_uuid: &str, link: &LinkExpression) -> Ad4mDbResult<()> {
-        self.conn.execute(
+        let tx = self.conn.unchecked_transaction()?;
+        for id in Self::link_ids(&tx, perspective_uuid, link)? {
+            unindex_link_text(&tx, id)?;
+        }
+        tx.execute(
             "DELETE FROM link WHERE perspective = ?1 AND source = ?2 AND predicate = ?3 AND target = ?4 AND author = ?5 AND timestamp = ?6",
             params![
                 perspective_uuid,
@@ -803,6 +1359,7 @@ impl Ad4mDb {
                 link.timestamp,
             ],
         )?;
+        tx.commit()?;
         Ok(())
     }
 
@@ -969,34 +1526,190 @@ impl Ad4mDb {
         Ok(links?)
     }
 
-    pub fn add_pending_diff(
+    /// Runs a `LinkQuery` as a single SQL statement. Filtering on source, predicate, target
+    /// and the date range as well as ordering and limiting all happen inside SQLite using the
+    /// `link` table indexes. Links with equal timestamps are returned in insertion order.
+    pub fn get_links_by_query(
         &self,
         perspective_uuid: &str,
-        diff: &PerspectiveDiff,
-    ) -> Ad4mDbResult<()> {
-        self.conn.execute(
-            "INSERT INTO perspective_diff (perspective, additions, removals, is_pending)
-             VALUES (?1, ?2, ?3, ?4)",
-            params![
-                perspective_uuid,
-                serde_json::to_string(&diff.additions)?,
-                serde_json::to_string(&diff.removals)?,
-                true,
-            ],
+        query: &LinkQuery,
+        descending: bool,
+    ) -> Ad4mDbResult<Vec<(LinkExpression, LinkStatus)>> {
+        Ok(self
+            .get_links_page(perspective_uuid, query, descending)?
+            .links)
+    }
+
+    /// Like [`Ad4mDb::get_links_by_query`] but also honours the `after`/`before` cursors of
+    /// the query and returns the cursors of the first and last link of the page.
+    /// Links are ordered by (`timestamp_ms`, `id`) which makes paging deterministic even
+    /// when many links share the same timestamp.
+    pub fn get_links_page(
+        &self,
+        perspective_uuid: &str,
+        query: &LinkQuery,
+        descending: bool,
+    ) -> Ad4mDbResult<LinksPage> {
+        let LinksPageSql {
+            sql,
+            values,
+            backwards,
+            limit,
+        } = links_page_sql(perspective_uuid, query, descending)?;
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let row_iter = stmt.query_map(params_from_iter(values.iter()), |row| {
+            let (link, status) = link_and_status_from_row(row)?;
+            let cursor = LinkCursor {
+                timestamp_ms: row.get(10)?,
+                id: row.get(9)?,
+            };
+            Ok((link, status, cursor))
+        })?;
+        let mut rows = row_iter.collect::<Result<Vec<_>, _>>()?;
+
+        let has_more = match limit {
+            Some(limit) if rows.len() > limit => {
+                rows.truncate(limit);
+                true
+            }
+            _ => false,
+        };
+        if backwards {
+            rows.reverse();
+        }
+
+        let start_cursor = rows.first().map(|(_, _, cursor)| cursor.clone());
+        let end_cursor = rows.last().map(|(_, _, cursor)| cursor.clone());
+        let (links, cursors) = rows
+            .into_iter()
+            .map(|(link, status, cursor)| ((link, status), cursor))
+            .unzip();
+        Ok(LinksPage {
+            links,
+            cursors,
+            start_cursor,
+            end_cursor,
+            has_more,
+        })
+    }
+
+    /// Full-text search over the decoded literal targets of a perspective's links,
+    /// best matches first. Returns the matched text and a relevance score with each link.
+    pub fn search_links(
+        &self,
+        perspective_uuid: &str,
+        text: &str,
+        limit: usize,
+    ) -> Ad4mDbResult<Vec<(LinkExpression, LinkStatus, String, f64)>> {
+        let query = fts_query(text);
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT link.perspective, link.source, link.predicate, link.target, link.author, link.timestamp, link.signature, link.key, link.status, link_search.text, -bm25(link_search)
+             FROM link_search JOIN link ON link.id = link_search.rowid
+             WHERE link_search MATCH ?1 AND link_search.perspective = ?2
+             ORDER BY bm25(link_search) LIMIT ?3",
         )?;
-        Ok(())
+        let results = stmt
+            .query_map(params![query, perspective_uuid, limit as i64], |row| {
+                let (link, status) = link_and_status_from_row(row)?;
+                Ok((link, status, row.get(9)?, row.get(10)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(results)
     }
 
-    pub fn get_pending_diffs(
+    /// Up to `limit` link texts that have no embedding of `model_id` yet, oldest first
+    pub fn get_link_texts_without_embedding(
         &self,
         perspective_uuid: &str,
-        max_count: Option<usize>,
-    ) -> Ad4mDbResult<(PerspectiveDiff, Vec<u64>)> {
+        model_id: &str,
+        limit: usize,
+    ) -> Ad4mDbResult<Vec<(i64, String)>> {
         let mut stmt = self.conn.prepare(
-            "SELECT additions, removals, id FROM perspective_diff WHERE perspective = ?1 AND is_pending = ?2",
+            "SELECT link_search.rowid, link_search.text FROM link_search
+             WHERE link_search.perspective = ?1 AND NOT EXISTS (
+                SELECT 1 FROM link_embedding WHERE link_id = link_search.rowid AND model_id = ?2
+             )
+             ORDER BY link_search.rowid LIMIT ?3",
         )?;
-        let diffs_iter = stmt.query_map(params![perspective_uuid, true], |row| {
-            let additions: Vec<LinkExpression> =
+        let texts = stmt
+            .query_map(params![perspective_uuid, model_id, limit as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(texts)
+    }
+
+    pub fn set_link_embedding(
+        &self,
+        link_id: i64,
+        model_id: &str,
+        embedding: &[f32],
+    ) -> Ad4mDbResult<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO link_embedding (link_id, model_id, embedding) VALUES (?1, ?2, ?3)",
+            params![link_id, model_id, embedding_to_blob(embedding)],
+        )?;
+        Ok(())
+    }
+
+    /// All links of a perspective that have an embedding for the given model,
+    /// together with their searchable text and the embedding
+    pub fn get_link_embeddings(
+        &self,
+        perspective_uuid: &str,
+        model_id: &str,
+    ) -> Ad4mDbResult<Vec<(LinkExpression, LinkStatus, String, Vec<f32>)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT link.perspective, link.source, link.predicate, link.target, link.author, link.timestamp, link.signature, link.key, link.status, link_search.text, link_embedding.embedding
+             FROM link_embedding
+             JOIN link ON link.id = link_embedding.link_id
+             JOIN link_search ON link_search.rowid = link_embedding.link_id
+             WHERE link.perspective = ?1 AND link_embedding.model_id = ?2",
+        )?;
+        let results = stmt
+            .query_map(params![perspective_uuid, model_id], |row| {
+                let (link, status) = link_and_status_from_row(row)?;
+                let blob: Vec<u8> = row.get(10)?;
+                Ok((link, status, row.get(9)?, embedding_from_blob(&blob)))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(results)
+    }
+
+    /// Queues a diff for committing later and returns its id
+    pub fn add_pending_diff(
+        &self,
+        perspective_uuid: &str,
+        diff: &PerspectiveDiff,
+    ) -> Ad4mDbResult<u64> {
+        self.conn.execute(
+            "INSERT INTO perspective_diff (perspective, additions, removals, is_pending, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                perspective_uuid,
+                serde_json::to_string(&diff.additions)?,
+                serde_json::to_string(&diff.removals)?,
+                true,
+                chrono::Utc::now().timestamp_millis(),
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid() as u64)
+    }
+
+    pub fn get_pending_diffs(
+        &self,
+        perspective_uuid: &str,
+        max_count: Option<usize>,
+    ) -> Ad4mDbResult<(PerspectiveDiff, Vec<u64>)> {
+        let mut stmt = self.conn.prepare(
+            "SELECT additions, removals, id FROM perspective_diff WHERE perspective = ?1 AND is_pending = ?2",
+        )?;
+        let diffs_iter = stmt.query_map(params![perspective_uuid, true], |row| {
+            let additions: Vec<LinkExpression> =
                 serde_json::from_str(&row.get::<_, String>(0).unwrap()).unwrap();
             let removals: Vec<LinkExpression> =
                 serde_json::from_str(&row.get::<_, String>(1).unwrap()).unwrap();
@@ -1073,6 +1786,213 @@ impl Ad4mDb {
         Ok(())
     }
 
+    pub fn record_diffs_committed(
+        &self,
+        perspective_uuid: &str,
+        count: usize,
+        committed_at: i64,
+    ) -> Ad4mDbResult<()> {
+        self.conn.execute(
+            "INSERT INTO perspective_sync (perspective, committed_diffs, last_commit_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(perspective) DO UPDATE SET committed_diffs = committed_diffs + ?2, last_commit_at = ?3",
+            params![perspective_uuid, count as i64, committed_at],
+        )?;
+        Ok(())
+    }
+
+    /// Counts a failed commit attempt for each of the pending diffs `ids`
+    pub fn record_diff_commit_failure(
+        &self,
+        perspective_uuid: &str,
+        ids: &[u64],
+        error: &str,
+        attempted_at: i64,
+    ) -> Ad4mDbResult<()> {
+        let id_list = ids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        self.conn.execute(
+            &format!(
+                "UPDATE perspective_diff SET attempts = attempts + 1, last_attempt_at = ?2, last_error = ?3
+                 WHERE perspective = ?1 AND id IN ({})",
+                id_list
+            ),
+            params![perspective_uuid, attempted_at, error],
+        )?;
+        self.conn.execute(
+            "INSERT INTO perspective_sync (perspective, last_error, last_error_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(perspective) DO UPDATE SET last_error = ?2, last_error_at = ?3",
+            params![perspective_uuid, error, attempted_at],
+        )?;
+        Ok(())
+    }
+
+    /// Pending and committed diff counts of a perspective.
+    /// `shared_url` is left for the caller to fill in.
+    pub fn get_perspective_sync_status(
+        &self,
+        perspective_uuid: &str,
+    ) -> Ad4mDbResult<PerspectiveSyncStatus> {
+        let mut status = self
+            .conn
+            .query_row(
+                "SELECT committed_diffs, last_commit_at, last_error, last_error_at FROM perspective_sync WHERE perspective = ?1",
+                params![perspective_uuid],
+                |row| {
+                    Ok(PerspectiveSyncStatus {
+                        committed_diffs: row.get(0)?,
+                        last_commit_at: row.get::<_, Option<i64>>(1)?.map(millis_to_timestamp),
+                        last_error: row.get(2)?,
+                        last_error_at: row.get::<_, Option<i64>>(3)?.map(millis_to_timestamp),
+                        ..Default::default()
+                    })
+                },
+            )
+            .optional()?
+            .unwrap_or_default();
+        status.uuid = perspective_uuid.to_string();
+        status.pending_diffs = self.conn.query_row(
+            "SELECT COUNT(*) FROM perspective_diff WHERE perspective = ?1 AND is_pending = ?2",
+            params![perspective_uuid, true],
+            |row| row.get(0),
+        )?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT id, additions, removals, created_at, attempts, last_attempt_at, last_error FROM perspective_diff
+             WHERE perspective = ?1 AND is_pending = ?2 AND attempts > 0 ORDER BY id",
+        )?;
+        status.failed_diffs = stmt
+            .query_map(params![perspective_uuid, true], |row| {
+                let count = |json: String| {
+                    serde_json::from_str::<Vec<JsonValue>>(&json)
+                        .map(|items| items.len() as i32)
+                        .unwrap_or(0)
+                };
+                Ok(PendingDiff {
+                    id: row.get::<_, i64>(0)?.to_string(),
+                    additions: count(row.get(1)?),
+                    removals: count(row.get(2)?),
+                    created_at: row.get::<_, Option<i64>>(3)?.map(millis_to_timestamp),
+                    attempts: row.get(4)?,
+                    last_attempt_at: row.get::<_, Option<i64>>(5)?.map(millis_to_timestamp),
+                    last_error: row.get(6)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(status)
+    }
+
+    /// Appends an applied diff to the perspective's history and returns its id.
+    /// The links of `diff` are expected to carry their status.
+    pub fn add_history_diff(
+        &self,
+        perspective_uuid: &str,
+        diff: &PerspectiveDiff,
+        local: bool,
+        undoes: Option<u64>,
+        applied_at: i64,
+    ) -> Ad4mDbResult<u64> {
+        self.conn.execute(
+            "INSERT INTO perspective_history (perspective, additions, removals, local, applied_at, undoes)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                perspective_uuid,
+                serde_json::to_string(&diff.additions)?,
+                serde_json::to_string(&diff.removals)?,
+                local,
+                applied_at,
+                undoes,
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid() as u64)
+    }
+
+    /// Drops the diffs of a perspective's history that were applied before `before` (in ms)
+    pub fn prune_history(&self, perspective_uuid: &str, before: i64) -> Ad4mDbResult<usize> {
+        Ok(self.conn.execute(
+            "DELETE FROM perspective_history WHERE perspective = ?1 AND applied_at < ?2",
+            params![perspective_uuid, before],
+        )?)
+    }
+
+    pub fn get_history_diff(
+        &self,
+        perspective_uuid: &str,
+        id: u64,
+    ) -> Ad4mDbResult<Option<HistoryDiff>> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM perspective_history h WHERE h.perspective = ?1 AND h.id = ?2",
+                    HISTORY_COLUMNS
+                ),
+                params![perspective_uuid, id],
+                history_diff_from_row,
+            )
+            .optional()?)
+    }
+
+    /// History of a perspective, newest diff first
+    pub fn get_history(
+        &self,
+        perspective_uuid: &str,
+        since: Option<i64>,
+        limit: Option<usize>,
+    ) -> Ad4mDbResult<Vec<HistoryDiff>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM perspective_history h WHERE h.perspective = ?1 AND h.applied_at > ?2
+             ORDER BY h.id DESC LIMIT ?3",
+            HISTORY_COLUMNS
+        ))?;
+        let diffs = stmt
+            .query_map(
+                params![
+                    perspective_uuid,
+                    since.unwrap_or(i64::MIN),
+                    limit.map(|l| l as i64).unwrap_or(-1)
+                ],
+                history_diff_from_row,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(diffs)
+    }
+
+    /// The links of a perspective as they were at `at` (in ms), found by reverting
+    /// the diffs applied since then on the current links.
+    /// Links that existed before the history was recorded (or pruned) count as always present.
+    pub fn get_links_at(
+        &self,
+        perspective_uuid: &str,
+        at: i64,
+    ) -> Ad4mDbResult<Vec<(LinkExpression, LinkStatus)>> {
+        let mut links = self.get_all_links(perspective_uuid)?;
+        // Links without predicate are stored with an empty one, diffs keep `None`
+        for (link, _) in links.iter_mut() {
+            if link.data.predicate.as_deref() == Some("") {
+                link.data.predicate = None;
+            }
+        }
+        for entry in self.get_history(perspective_uuid, Some(at), None)? {
+            links.retain(|(link, _)| {
+                !entry
+                    .diff
+                    .additions
+                    .iter()
+                    .any(|added| same_link(link, added))
+            });
+            for removed in entry.diff.removals {
+                if !links.iter().any(|(link, _)| same_link(link, &removed)) {
+                    let status = removed.status.clone().unwrap_or_default();
+                    links.push((removed, status));
+                }
+            }
+        }
+        Ok(links)
+    }
+
     // Expression Methods
 
     pub fn _add_expression<T: Serialize>(
@@ -1103,8 +2023,8 @@ impl Ad4mDb {
     pub fn add_model(&self, model: &ModelInput) -> Ad4mDbResult<String> {
         let id = uuid::Uuid::new_v4().to_string();
         self.conn.execute(
-            "INSERT INTO models (id, name, api_base_url, api_key, model, api_type, local_file_name, local_tokenizer_repo, local_tokenizer_revision, local_tokenizer_file_name, local_huggingface_repo, local_revision, type)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            "INSERT INTO models (id, name, api_base_url, api_key, model, api_type, local_file_name, local_tokenizer_repo, local_tokenizer_revision, local_tokenizer_file_name, local_huggingface_repo, local_revision, type, api_headers, local_language)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
             params![
                 id,
                 model.name,
@@ -1119,6 +2039,8 @@ impl Ad4mDb {
                 model.local.as_ref().and_then(|local| local.huggingface_repo.clone()),
                 model.local.as_ref().and_then(|local| local.revision.clone()),
                 serde_json::to_string(&model.model_type).unwrap(),
+                model_api_headers(model),
+                model.local.as_ref().and_then(|local| local.language.clone()),
             ],
         )?;
         Ok(id)
@@ -1139,6 +2061,10 @@ impl Ad4mDb {
                         api_key,
                         model,
                         api_type: ModelApiType::from_str(&api_type).unwrap(),
+                        headers: row
+                            .get::<_, Option<String>>(13)?
+                            .map(|headers| serde_json::from_str(&headers).unwrap())
+                            .unwrap_or_default(),
                     })
                 } else {
                     None
@@ -1166,6 +2092,7 @@ impl Ad4mDb {
                         tokenizer_source,
                         huggingface_repo: row.get::<_, Option<String>>(10)?,
                         revision: row.get::<_, Option<String>>(11)?,
+                        language: row.get::<_, Option<String>>(14)?,
                     })
                 } else {
                     None
@@ -1197,6 +2124,10 @@ impl Ad4mDb {
                     api_key,
                     model,
                     api_type: ModelApiType::from_str(&api_type).unwrap(),
+                    headers: row
+                        .get::<_, Option<String>>(13)?
+                        .map(|headers| serde_json::from_str(&headers).unwrap())
+                        .unwrap_or_default(),
                 })
             } else {
                 None
@@ -1223,6 +2154,7 @@ impl Ad4mDb {
                         tokenizer_source,
                         huggingface_repo: row.get::<_, Option<String>>(10)?,
                         revision: row.get::<_, Option<String>>(11)?,
+                        language: row.get::<_, Option<String>>(14)?,
                     })
                 } else {
                     None
@@ -1276,7 +2208,9 @@ impl Ad4mDb {
                 local_tokenizer_file_name = ?9,
                 local_huggingface_repo = ?10,
                 local_revision = ?11,
-                type = ?12
+                type = ?12,
+                api_headers = ?14,
+                local_language = ?15
              WHERE id = ?13",
             params![
                 model.name,
@@ -1291,7 +2225,12 @@ impl Ad4mDb {
                 local_huggingface_repo,
                 local_revision,
                 serde_json::to_string(&model.model_type).unwrap(),
-                id
+                id,
+                model_api_headers(model),
+                model
+                    .local
+                    .as_ref()
+                    .and_then(|local| local.language.clone()),
             ],
         )?;
         Ok(())
@@ -1343,8 +2282,13 @@ impl Ad4mDb {
 mod tests {
     use super::*;
     use crate::{
-        graphql::graphql_types::{LocalModelInput, ModelApiInput, TokenizerSourceInput},
-        types::{ExpressionProof, Link, LinkExpression, ModelApiType, ModelType},
+        graphql::graphql_types::{
+            LocalModelInput, ModelApiHeaderInput, ModelApiInput, TokenizerSourceInput,
+        },
+        types::{
+            AIMessageRole, ExpressionProof, Link, LinkExpression, ModelApiHeader, ModelApiType,
+            ModelType,
+        },
     };
     use chrono::Utc;
     use fake::{Fake, Faker};
@@ -1486,6 +2430,487 @@ mod tests {
         assert!(db.get_link(&p_uuid, &link1).unwrap().is_none());
     }
 
+    fn link_at(source: &str, predicate: &str, target: &str, minutes: i64) -> LinkExpression {
+        let base = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        LinkExpression {
+            data: Link {
+                source: source.to_string(),
+                predicate: Some(predicate.to_string()),
+                target: target.to_string(),
+            },
+            proof: ExpressionProof {
+                signature: "signature".to_string(),
+                key: "key".to_string(),
+            },
+            author: "did:test:key".to_string(),
+            timestamp: (base + chrono::Duration::minutes(minutes)).to_rfc3339(),
+            status: Some(LinkStatus::Shared),
+        }
+    }
+
+    #[test]
+    fn can_query_links_with_filters_order_and_limit() {
+        let db = Ad4mDb::new(":memory:").unwrap();
+        let p_uuid = Uuid::new_v4().to_string();
+        let links: Vec<LinkExpression> = (0..6)
+            .map(|i| {
+                let predicate = if i % 2 == 0 { "p://even" } else { "p://odd" };
+                link_at("s://a", predicate, &format!("t://{}", i), i)
+            })
+            .collect();
+        db.add_many_links(&p_uuid, links.clone(), &LinkStatus::Shared)
+            .unwrap();
+        db.add_link(&Uuid::new_v4().to_string(), &links[0], &LinkStatus::Shared)
+            .unwrap();
+
+        let targets = |result: Vec<(LinkExpression, LinkStatus)>| {
+            result
+                .into_iter()
+                .map(|(l, _)| l.data.target)
+                .collect::<Vec<String>>()
+        };
+
+        let all = db
+            .get_links_by_query(&p_uuid, &LinkQuery::default(), false)
+            .unwrap();
+        assert_eq!(
+            targets(all),
+            (0..6).map(|i| format!("t://{}", i)).collect::<Vec<_>>()
+        );
+
+        let even = db
+            .get_links_by_query(
+                &p_uuid,
+                &LinkQuery {
+                    source: Some("s://a".to_string()),
+                    predicate: Some("p://even".to_string()),
+                    ..Default::default()
+                },
+                true,
+            )
+            .unwrap();
+        assert_eq!(targets(even), vec!["t://4", "t://2", "t://0"]);
+
+        let from: chrono::DateTime<Utc> = chrono::DateTime::parse_from_rfc3339(&links[1].timestamp)
+            .unwrap()
+            .into();
+        let until: chrono::DateTime<Utc> =
+            chrono::DateTime::parse_from_rfc3339(&links[4].timestamp)
+                .unwrap()
+                .into();
+        let range = db
+            .get_links_by_query(
+                &p_uuid,
+                &LinkQuery {
+                    from_date: Some(from.into()),
+                    until_date: Some(until.into()),
+                    limit: Some(2),
+                    ..Default::default()
+                },
+                false,
+            )
+            .unwrap();
+        assert_eq!(targets(range), vec!["t://1", "t://2"]);
+
+        let by_target = db
+            .get_links_by_query(
+                &p_uuid,
+                &LinkQuery {
+                    target: Some("t://3".to_string()),
+                    ..Default::default()
+                },
+                false,
+            )
+            .unwrap();
+        assert_eq!(by_target, vec![(links[3].clone(), LinkStatus::Shared)]);
+    }
+
+    #[test]
+    fn can_page_links_with_cursors() {
+        let db = Ad4mDb::new(":memory:").unwrap();
+        let p_uuid = Uuid::new_v4().to_string();
+        // Two groups of links sharing the same timestamp
+        let links: Vec<LinkExpression> = (0..7)
+            .map(|i| link_at("s://a", "p://b", &format!("t://{}", i), i / 4))
+            .collect();
+        db.add_many_links(&p_uuid, links, &LinkStatus::Shared)
+            .unwrap();
+
+        let page = |after: Option<String>, before: Option<String>, descending: bool| {
+            db.get_links_page(
+                &p_uuid,
+                &LinkQuery {
+                    after,
+                    before,
+                    limit: Some(3),
+                    ..Default::default()
+                },
+                descending,
+            )
+            .unwrap()
+        };
+        let targets = |page: &LinksPage| {
+            page.links
+                .iter()
+                .map(|(l, _)| l.data.target.clone())
+                .collect::<Vec<String>>()
+        };
+
+        let first = page(None, None, false);
+        assert_eq!(targets(&first), vec!["t://0", "t://1", "t://2"]);
+        assert!(first.has_more);
+        let second = page(first.end_cursor.as_ref().map(|c| c.encode()), None, false);
+        assert_eq!(targets(&second), vec!["t://3", "t://4", "t://5"]);
+        assert!(second.has_more);
+        let third = page(second.end_cursor.as_ref().map(|c| c.encode()), None, false);
+        assert_eq!(targets(&third), vec!["t://6"]);
+        assert!(!third.has_more);
+
+        let previous = page(
+            None,
+            second.start_cursor.as_ref().map(|c| c.encode()),
+            false,
+        );
+        assert_eq!(targets(&previous), vec!["t://0", "t://1", "t://2"]);
+        assert!(!previous.has_more);
+
+        let desc_first = page(None, None, true);
+        assert_eq!(targets(&desc_first), vec!["t://6", "t://5", "t://4"]);
+        let desc_second = page(
+            desc_first.end_cursor.as_ref().map(|c| c.encode()),
+            None,
+            true,
+        );
+        assert_eq!(targets(&desc_second), vec!["t://3", "t://2", "t://1"]);
+        let desc_previous = page(
+            None,
+            desc_second.start_cursor.as_ref().map(|c| c.encode()),
+            true,
+        );
+        assert_eq!(targets(&desc_previous), vec!["t://6", "t://5", "t://4"]);
+    }
+
+    #[test]
+    fn link_cursor_roundtrip_and_invalid_cursor() {
+        for cursor in [
+            LinkCursor {
+                timestamp_ms: Some(1_700_000_000_000),
+                id: 42,
+            },
+            LinkCursor {
+                timestamp_ms: None,
+                id: 7,
+            },
+        ] {
+            assert_eq!(LinkCursor::decode(&cursor.encode()).unwrap(), cursor);
+        }
+        assert!(LinkCursor::decode("not a cursor").is_err());
+
+        let db = Ad4mDb::new(":memory:").unwrap();
+        let query = LinkQuery {
+            after: Some("garbage".to_string()),
+            ..Default::default()
+        };
+        assert!(db.get_links_page("p", &query, false).is_err());
+    }
+
+    #[test]
+    fn can_search_literal_link_targets() {
+        let db = Ad4mDb::new(":memory:").unwrap();
+        let p = "search-perspective";
+        let title = link_at(
+            "ad4m://self",
+            "todo://title",
+            "literal://string:Buy%20fresh%20milk",
+            0,
+        );
+        let other = link_at(
+            "ad4m://self",
+            "todo://title",
+            "literal://string:Walk%20the%20dog",
+            1,
+        );
+        let url = link_at("ad4m://self", "todo://ref", "https://milk.example", 2);
+        db.add_link(p, &title, &LinkStatus::Shared).unwrap();
+        db.add_many_links(p, vec![other.clone(), url], &LinkStatus::Local)
+            .unwrap();
+        db.add_link("other-perspective", &title, &LinkStatus::Shared)
+            .unwrap();
+
+        let found = db.search_links(p, "milk", 10).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0.data.target, title.data.target);
+        assert_eq!(found[0].2, "Buy fresh milk");
+
+        // All words have to match and FTS syntax in the input is treated as text
+        assert_eq!(db.search_links(p, "fresh dog", 10).unwrap().len(), 0);
+        assert_eq!(db.search_links(p, "\"dog\" OR", 10).unwrap().len(), 0);
+        assert_eq!(db.search_links(p, "   ", 10).unwrap().len(), 0);
+
+        let mut updated = other.clone();
+        updated.data.target = "literal://string:Walk%20the%20cat".to_string();
+        db.update_link(p, &other, &updated).unwrap();
+        assert_eq!(db.search_links(p, "dog", 10).unwrap().len(), 0);
+        assert_eq!(db.search_links(p, "cat", 10).unwrap().len(), 1);
+
+        db.remove_link(p, &title).unwrap();
+        assert_eq!(db.search_links(p, "milk", 10).unwrap().len(), 0);
+        assert_eq!(
+            db.search_links("other-perspective", "milk", 10)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn stores_link_embeddings_per_model() {
+        let db = Ad4mDb::new(":memory:").unwrap();
+        let p = "embedding-perspective";
+        db.add_link(
+            p,
+            &link_at("s", "p", "literal://string:hello", 0),
+            &LinkStatus::Shared,
+        )
+        .unwrap();
+        db.add_link(
+            p,
+            &link_at("s", "p", "literal://number:42", 1),
+            &LinkStatus::Shared,
+        )
+        .unwrap();
+        db.add_link(
+            p,
+            &link_at("s", "p", "ad4m://not-text", 2),
+            &LinkStatus::Shared,
+        )
+        .unwrap();
+
+        let missing = db.get_link_texts_without_embedding(p, "model", 10).unwrap();
+        assert_eq!(
+            missing.iter().map(|(_, t)| t.as_str()).collect::<Vec<_>>(),
+            vec!["hello", "42"]
+        );
+        assert_eq!(
+            db.get_link_texts_without_embedding(p, "model", 1).unwrap(),
+            missing[..1].to_vec()
+        );
+
+        db.set_link_embedding(missing[0].0, "model", &[0.5, -1.0])
+            .unwrap();
+        assert_eq!(
+            db.get_link_texts_without_embedding(p, "model", 10)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            db.get_link_texts_without_embedding(p, "other-model", 10)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let embeddings = db.get_link_embeddings(p, "model").unwrap();
+        assert_eq!(embeddings.len(), 1);
+        assert_eq!(embeddings[0].2, "hello");
+        assert_eq!(embeddings[0].3, vec![0.5, -1.0]);
+    }
+
+    #[test]
+    fn link_queries_use_perspective_indexes() {
+        let db = Ad4mDb::new(":memory:").unwrap();
+
+        let query_plan = |query: LinkQuery| -> String {
+            let LinksPageSql { sql, values, .. } = links_page_sql("p", &query, true).unwrap();
+            let mut stmt = db
+                .conn
+                .prepare(&format!("EXPLAIN QUERY PLAN {}", sql))
+                .unwrap();
+            let details = stmt
+                .query_map(params_from_iter(values.iter()), |row| {
+                    row.get::<_, String>(3)
+                })
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            details.join("\n")
+        };
+
+        for (query, index) in [
+            (
+                LinkQuery {
+                    source: Some("s://1".to_string()),
+                    limit: Some(5),
+                    ..Default::default()
+                },
+                "link_perspective_source",
+            ),
+            (
+                LinkQuery {
+                    predicate: Some("p://1".to_string()),
+                    ..Default::default()
+                },
+                "link_perspective_predicate",
+            ),
+            (
+                LinkQuery {
+                    target: Some("t://1".to_string()),
+                    ..Default::default()
+                },
+                "link_perspective_target",
+            ),
+            (LinkQuery::default(), "link_perspective_timestamp"),
+        ] {
+            let plan = query_plan(query);
+            assert!(
+                plan.contains(&format!("USING INDEX {}", index))
+                    || plan.contains(&format!("USING COVERING INDEX {}", index)),
+                "expected {} in query plan:\n{}",
+                index,
+                plan
+            );
+        }
+    }
+
+    #[test]
+    fn tracks_commit_attempts_of_pending_diffs() {
+        let db = Ad4mDb::new(":memory:").unwrap();
+        let p_uuid = Uuid::new_v4().to_string();
+        let diff = PerspectiveDiff {
+            additions: vec![construct_dummy_link_expression(LinkStatus::Shared)],
+            removals: vec![],
+        };
+        let first = db.add_pending_diff(&p_uuid, &diff).unwrap();
+        let second = db.add_pending_diff(&p_uuid, &diff).unwrap();
+
+        let status = db.get_perspective_sync_status(&p_uuid).unwrap();
+        assert_eq!(status.pending_diffs, 2);
+        assert_eq!(status.committed_diffs, 0);
+        assert!(status.failed_diffs.is_empty());
+
+        db.record_diff_commit_failure(&p_uuid, &[first], "timeout", 1000)
+            .unwrap();
+        db.record_diff_commit_failure(&p_uuid, &[first], "offline", 2000)
+            .unwrap();
+        let status = db.get_perspective_sync_status(&p_uuid).unwrap();
+        assert_eq!(status.last_error, Some("offline".to_string()));
+        assert_eq!(status.failed_diffs.len(), 1);
+        assert_eq!(status.failed_diffs[0].id, first.to_string());
+        assert_eq!(status.failed_diffs[0].attempts, 2);
+        assert_eq!(status.failed_diffs[0].additions, 1);
+
+        db.clear_pending_diffs(&p_uuid, vec![first, second])
+            .unwrap();
+        db.record_diffs_committed(&p_uuid, 2, 3000).unwrap();
+        let status = db.get_perspective_sync_status(&p_uuid).unwrap();
+        assert_eq!(status.pending_diffs, 0);
+        assert_eq!(status.committed_diffs, 2);
+        assert!(status.last_commit_at.is_some());
+        assert!(status.failed_diffs.is_empty());
+    }
+
+    #[test]
+    fn reconstructs_links_at_a_point_in_history() {
+        let db = Ad4mDb::new(":memory:").unwrap();
+        let p_uuid = Uuid::new_v4().to_string();
+        let before_history = construct_dummy_link_expression(LinkStatus::Shared);
+        let first = construct_dummy_link_expression(LinkStatus::Local);
+        // Still in the link table, so it has to be matched against the diff adding it
+        let mut second = construct_dummy_link_expression(LinkStatus::Shared);
+        second.data.predicate = None;
+        db.add_link(&p_uuid, &before_history, &LinkStatus::Shared)
+            .unwrap();
+
+        db.add_link(&p_uuid, &first, &LinkStatus::Local).unwrap();
+        let added = db
+            .add_history_diff(
+                &p_uuid,
+                &PerspectiveDiff::from_additions(vec![first.clone()]),
+                true,
+                None,
+                1000,
+            )
+            .unwrap();
+        db.add_link(&p_uuid, &second, &LinkStatus::Shared).unwrap();
+        db.remove_link(&p_uuid, &first).unwrap();
+        db.add_history_diff(
+            &p_uuid,
+            &PerspectiveDiff::from(vec![second.clone()], vec![first.clone()]),
+            false,
+            None,
+            2000,
+        )
+        .unwrap();
+
+        let links_at = |at: i64| {
+            let mut targets = db
+                .get_links_at(&p_uuid, at)
+                .unwrap()
+                .into_iter()
+                .map(|(link, _)| link.data.target)
+                .collect::<Vec<String>>();
+            targets.sort();
+            targets
+        };
+        let sorted = |mut targets: Vec<&String>| {
+            targets.sort();
+            targets.into_iter().cloned().collect::<Vec<String>>()
+        };
+        assert_eq!(links_at(500), vec![before_history.data.target.clone()]);
+        assert_eq!(
+            links_at(1500),
+            sorted(vec![&before_history.data.target, &first.data.target])
+        );
+        assert_eq!(
+            links_at(2500),
+            sorted(vec![&before_history.data.target, &second.data.target])
+        );
+        let restored = db.get_links_at(&p_uuid, 1500).unwrap();
+        let (_, status) = restored
+            .iter()
+            .find(|(link, _)| link.data.target == first.data.target)
+            .unwrap();
+        assert_eq!(status, &LinkStatus::Local);
+
+        let undo = db
+            .add_history_diff(
+                &p_uuid,
+                &PerspectiveDiff::from_removals(vec![first.clone()]),
+                true,
+                Some(added),
+                3000,
+            )
+            .unwrap();
+        let history = db.get_history(&p_uuid, None, None).unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].id, undo);
+        assert_eq!(history[0].undoes, Some(added));
+        assert!(!history[1].local);
+        assert_eq!(history[2].undone_by, Some(undo));
+        assert_eq!(
+            db.get_history(&p_uuid, Some(1000), Some(1)).unwrap().len(),
+            1
+        );
+        assert_eq!(
+            db.get_history_diff(&p_uuid, added)
+                .unwrap()
+                .unwrap()
+                .diff
+                .additions,
+            vec![first]
+        );
+
+        assert_eq!(db.prune_history(&p_uuid, 2500).unwrap(), 2);
+        let history = db.get_history(&p_uuid, None, None).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].id, undo);
+        db.remove_perspective(&p_uuid).unwrap();
+        assert!(db.get_history(&p_uuid, None, None).unwrap().is_empty());
+    }
+
     #[test]
     fn can_get_and_remove_pending_diffs() {
         let db = Ad4mDb::new(":memory:").unwrap();
@@ -1647,6 +3072,7 @@ mod tests {
                 system_prompt.clone(),
                 prompt_examples.clone(),
                 None,
+                None,
             )
             .unwrap();
 
@@ -1673,6 +3099,104 @@ mod tests {
         assert!(all_tasks_after_removal.is_empty());
     }
 
+    #[test]
+    fn task_output_constraint_is_stored() {
+        let db = Ad4mDb::new(":memory:").unwrap();
+        let constraint = AIOutputConstraint {
+            json_schema: Some(r#"{"type":"object","properties":{"ok":{"type":"boolean"}}}"#.into()),
+            regex: None,
+        };
+        let task_id = db
+            .add_task(
+                "JSON".to_string(),
+                "model".to_string(),
+                "Answer in JSON".to_string(),
+                vec![],
+                None,
+                Some(constraint.clone()),
+            )
+            .unwrap();
+        assert_eq!(
+            db.get_task(task_id.clone())
+                .unwrap()
+                .unwrap()
+                .output_constraint,
+            Some(constraint)
+        );
+
+        db.update_task(
+            task_id.clone(),
+            "JSON".to_string(),
+            "model".to_string(),
+            "Answer in JSON".to_string(),
+            vec![],
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(db.get_tasks().unwrap()[0].output_constraint, None);
+    }
+
+    #[test]
+    fn ai_sessions_keep_their_messages_until_removed() {
+        let db = Ad4mDb::new(":memory:").unwrap();
+        let task_id = db
+            .add_task(
+                "Chat".to_string(),
+                "model".to_string(),
+                "Be brief".to_string(),
+                vec![],
+                None,
+                None,
+            )
+            .unwrap();
+        let session = AISession {
+            session_id: Uuid::new_v4().to_string(),
+            task_id: task_id.clone(),
+            name: Some("First chat".to_string()),
+            truncation: AIContextTruncation::LastMessages,
+            max_messages: Some(4),
+            max_tokens: None,
+            perspective_uuid: None,
+            created_at: Utc::now().to_string(),
+            updated_at: Utc::now().to_string(),
+        };
+        db.add_ai_session(&session).unwrap();
+        assert_eq!(
+            db.get_ai_session(&session.session_id).unwrap(),
+            Some(session.clone())
+        );
+
+        for (role, content) in [
+            (AIMessageRole::User, "Hi"),
+            (AIMessageRole::Assistant, "Hello!"),
+        ] {
+            db.add_ai_session_message(
+                &session.session_id,
+                &AIMessage {
+                    role,
+                    content: content.to_string(),
+                    created_at: Utc::now().to_string(),
+                },
+            )
+            .unwrap();
+        }
+        let messages = db.get_ai_session_messages(&session.session_id).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, AIMessageRole::User);
+        assert_eq!(messages[1].content, "Hello!");
+        assert_eq!(db.get_ai_sessions(Some(&task_id)).unwrap().len(), 1);
+        assert!(db.get_ai_sessions(Some("other task")).unwrap().is_empty());
+
+        db.remove_task(task_id).unwrap();
+        assert!(db.get_ai_sessions(None).unwrap().is_empty());
+        assert!(db
+            .get_ai_session_messages(&session.session_id)
+            .unwrap()
+            .is_empty());
+        assert!(!db.remove_ai_session(&session.session_id).unwrap());
+    }
+
     #[test]
     fn test_models_crud() {
         let db = Ad4mDb::new(":memory:").unwrap();
@@ -1685,6 +3209,7 @@ mod tests {
                 api_key: "test_api_key".to_string(),
                 model: "llama3".to_string(),
                 api_type: ModelApiType::OpenAi.to_string(),
+                headers: None,
             }),
             local: None,
             model_type: ModelType::Llm,
@@ -1703,6 +3228,7 @@ mod tests {
                 }),
                 huggingface_repo: Some("huggingface/test".to_string()),
                 revision: Some("main".to_string()),
+                language: None,
             }),
             model_type: ModelType::Llm,
         };
@@ -1777,6 +3303,42 @@ mod tests {
             .all(|m| m.name != "Test Model API" && m.name != "Test Model Local"));
     }
 
+    #[test]
+    fn model_api_headers_are_stored() {
+        let db = Ad4mDb::new(":memory:").unwrap();
+        let mut model = ModelInput {
+            name: "Ollama".to_string(),
+            api: Some(ModelApiInput {
+                base_url: "http://localhost:11434".to_string(),
+                api_key: String::new(),
+                model: "llama3".to_string(),
+                api_type: ModelApiType::Ollama.to_string(),
+                headers: Some(vec![ModelApiHeaderInput {
+                    name: "X-Team".to_string(),
+                    value: "research".to_string(),
+                }]),
+            }),
+            local: None,
+            model_type: ModelType::Embedding,
+        };
+        let id = db.add_model(&model).unwrap();
+
+        let api = db.get_model(id.clone()).unwrap().unwrap().api.unwrap();
+        assert_eq!(api.api_type, ModelApiType::Ollama);
+        assert_eq!(
+            api.headers,
+            vec![ModelApiHeader {
+                name: "X-Team".to_string(),
+                value: "research".to_string(),
+            }]
+        );
+
+        model.api.as_mut().unwrap().headers = None;
+        db.update_model(&id, &model).unwrap();
+        let api = db.get_model(id).unwrap().unwrap().api.unwrap();
+        assert!(api.headers.is_empty());
+    }
+
     #[test]
     fn test_update_model() {
         let db = Ad4mDb::new(":memory:").unwrap();
@@ -1789,6 +3351,7 @@ mod tests {
                 api_key: "test_key".to_string(),
                 model: "llama3".to_string(),
                 api_type: ModelApiType::OpenAi.to_string(),
+                headers: None,
             }),
             local: None,
             model_type: ModelType::Llm,
@@ -1805,6 +3368,7 @@ mod tests {
                 api_key: "test_key".to_string(),
                 model: "llama4".to_string(),
                 api_type: ModelApiType::OpenAi.to_string(),
+                headers: None,
             }),
             local: None,
             model_type: ModelType::Embedding,
@@ -1842,6 +3406,7 @@ mod tests {
                 api_key: "llm_key".to_string(),
                 model: "llama".to_string(),
                 api_type: ModelApiType::OpenAi.to_string(),
+                headers: None,
             }),
             local: None,
             model_type: ModelType::Llm,
@@ -1858,6 +3423,7 @@ mod tests {
                 }),
                 huggingface_repo: Some("huggingface/test".to_string()),
                 revision: Some("main".to_string()),
+                language: None,
             }),
             api: None,
             model_type: ModelType::Embedding,
@@ -1870,6 +3436,7 @@ mod tests {
                 api_key: "transcribe_key".to_string(),
                 model: "llama".to_string(),
                 api_type: ModelApiType::OpenAi.to_string(),
+                headers: None,
             }),
             local: None,
             model_type: ModelType::Transcription,
@@ -1969,6 +3536,7 @@ mod tests {
                 api_key: "test-key".to_string(),
                 model: "llama".to_string(),
                 api_type: ModelApiType::OpenAi.to_string(),
+                headers: None,
             }),
             local: None,
             model_type: ModelType::Llm,
@@ -2006,6 +3574,7 @@ mod tests {
                 }),
                 huggingface_repo: Some("huggingface/test".to_string()),
                 revision: Some("main".to_string()),
+                language: None,
             }),
             model_type: ModelType::Transcription,
         };
@@ -0,0 +1,142 @@
+//! Whole-database copies used by agent backups.
+
+use super::{migrations, Ad4mDb, Ad4mDbResult, AD4M_DB_INSTANCE};
+use deno_core::anyhow::anyhow;
+use rusqlite::{params, Connection, OpenFlags};
+use std::fs;
+use std::path::Path;
+
+/// Tables reported in backup summaries, with the name they are shown as
+pub const BACKUP_TABLES: &[(&str, &str)] = &[
+    ("perspectives", "perspective_handle"),
+    ("links", "link"),
+    ("trustedAgents", "trusted_agent"),
+    ("friends", "friends"),
+    ("notifications", "notifications"),
+    ("models", "models"),
+    ("tasks", "tasks"),
+];
+
+fn snapshot_connection(conn: &Connection) -> Ad4mDbResult<Vec<u8>> {
+    let snapshot_path =
+        std::env::temp_dir().join(format!("ad4m-db-snapshot-{}.sqlite", uuid::Uuid::new_v4()));
+    let snapshot_path = snapshot_path.to_string_lossy().into_owned();
+    conn.execute("VACUUM INTO ?1", params![snapshot_path])?;
+    let snapshot = fs::read(&snapshot_path);
+    let _ = fs::remove_file(&snapshot_path);
+    Ok(snapshot?)
+}
+
+/// Number of rows of each of the `BACKUP_TABLES` in a database snapshot
+pub fn snapshot_table_counts(snapshot: &[u8]) -> Ad4mDbResult<Vec<(String, i64)>> {
+    with_snapshot(snapshot, |conn| {
+        let mut counts = Vec::new();
+        for (name, table) in BACKUP_TABLES {
+            let exists = conn
+                .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1")?
+                .exists(params![table])?;
+            let count = if exists {
+                conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                    row.get(0)
+                })?
+            } else {
+                0
+            };
+            counts.push((name.to_string(), count));
+        }
+        Ok(counts)
+    })
+}
+
+/// Writes `snapshot` to a temporary file and opens it read-only
+fn with_snapshot<F, R>(snapshot: &[u8], func: F) -> Ad4mDbResult<R>
+where
+    F: FnOnce(&Connection) -> Ad4mDbResult<R>,
+{
+    let path =
+        std::env::temp_dir().join(format!("ad4m-db-snapshot-{}.sqlite", uuid::Uuid::new_v4()));
+    fs::write(&path, snapshot)?;
+    let result = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(Into::into)
+        .and_then(|conn| func(&conn));
+    let _ = fs::remove_file(&path);
+    result
+}
+
+fn check_integrity(conn: &Connection) -> Ad4mDbResult<()> {
+    let result: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+    if result != "ok" {
+        return Err(anyhow!("Database snapshot is corrupt: {}", result));
+    }
+    Ok(())
+}
+
+/// Copies the database file at `db_path` without opening it for writing
+pub fn snapshot_file(db_path: &str) -> Ad4mDbResult<Vec<u8>> {
+    let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    snapshot_connection(&conn)
+}
+
+/// Replaces the database file at `db_path` with `snapshot` and migrates it.
+/// The current file is left untouched if the snapshot fails its integrity check.
+pub fn restore_file(db_path: &str, snapshot: &[u8]) -> Ad4mDbResult<()> {
+    with_snapshot(snapshot, check_integrity)?;
+
+    let staged_path = format!("{}.restore", db_path);
+    fs::write(&staged_path, snapshot)?;
+    for suffix in ["-wal", "-shm"] {
+        let path = format!("{}{}", db_path, suffix);
+        if Path::new(&path).exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    fs::rename(&staged_path, db_path)?;
+    migrations::migrate_file(db_path)?;
+    Ok(())
+}
+
+impl Ad4mDb {
+    /// Consistent copy of the whole database
+    pub fn snapshot(&self) -> Ad4mDbResult<Vec<u8>> {
+        snapshot_connection(&self.conn)
+    }
+
+    /// Swaps the database of the running executor for `snapshot`.
+    /// The global instance stays locked until the restored file is open again.
+    pub fn restore_global_instance(db_path: &str, snapshot: &[u8]) -> Ad4mDbResult<()> {
+        let mut db_instance = AD4M_DB_INSTANCE.lock().unwrap();
+        *db_instance = None;
+        let result = restore_file(db_path, snapshot);
+        *db_instance = Some(Ad4mDb::new(db_path)?);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql::graphql_types::PerspectiveHandle;
+
+    #[test]
+    fn snapshots_can_be_restored_to_a_file() {
+        let dir = std::env::temp_dir().join(format!("ad4m-snapshot-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("source.sqlite").to_string_lossy().into_owned();
+        let db = Ad4mDb::new(&db_path).unwrap();
+        let handle = PerspectiveHandle::new_from_name("backed up".to_string());
+        db.add_perspective(&handle).unwrap();
+
+        let snapshot = db.snapshot().unwrap();
+        let counts = snapshot_table_counts(&snapshot).unwrap();
+        assert!(counts.contains(&("perspectives".to_string(), 1)));
+
+        let restored_path = dir.join("restored.sqlite").to_string_lossy().into_owned();
+        restore_file(&restored_path, &snapshot).unwrap();
+        let restored = Ad4mDb::new(&restored_path).unwrap();
+        assert_eq!(restored.get_all_perspectives().unwrap().len(), 1);
+
+        assert!(restore_file(&restored_path, b"not a database").is_err());
+        assert_eq!(restored.get_all_perspectives().unwrap().len(), 1);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
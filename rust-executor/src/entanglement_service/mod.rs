@@ -1,8 +1,14 @@
 use crate::{
-    agent::{sign_string_hex, AgentService},
+    agent::{
+        self, sign_string_hex,
+        signatures::{self, DEVICE_KEY_TYPE},
+        AgentService,
+    },
     db::Ad4mDb,
     graphql::graphql_types::EntanglementProof,
+    types::DeviceRevocation,
 };
+use deno_core::{anyhow::anyhow, error::AnyError};
 
 pub(crate) mod entanglement_service_extension;
 
@@ -50,3 +56,110 @@ pub fn get_entanglement_proofs() -> Vec<EntanglementProof> {
         .map_err(|e| e.to_string())
         .unwrap_or_default()
 }
+
+/// Run on the new device: asks the agent `did` to let this executor's key sign
+/// for it. The returned request goes to `authorize_device()` on the agent's
+/// executor.
+pub fn request_device_link(did: String) -> Result<EntanglementProof, AnyError> {
+    Ok(EntanglementProof {
+        did: did.clone(),
+        did_signing_key_id: String::new(),
+        device_key_type: DEVICE_KEY_TYPE.to_string(),
+        device_key: agent::signing_key_did(),
+        device_key_signed_by_did: String::new(),
+        did_signed_by_device_key: Some(sign_string_hex(did)?),
+    })
+}
+
+/// Run on the agent's executor: signs the device key of a link request.
+/// The returned proof goes to `accept_device_link()` on the new device.
+pub fn authorize_device(request: EntanglementProof) -> Result<EntanglementProof, AnyError> {
+    let did = agent::did();
+    if request.did != did {
+        return Err(anyhow!(
+            "Device link request is for {}, not {}",
+            request.did,
+            did
+        ));
+    }
+    let proof = EntanglementProof {
+        device_key_signed_by_did: sign_string_hex(request.device_key.clone())?,
+        did_signing_key_id: agent::signing_key_id(),
+        ..request
+    };
+    signatures::add_device_authorization(&proof)?;
+    Ad4mDb::with_global_instance(|db| db.add_entanglement_proofs(vec![proof.clone()]))?;
+    Ok(proof)
+}
+
+/// Run on the new device: stores the proof issued by `authorize_device()`
+pub fn accept_device_link(authorization: EntanglementProof) -> Result<(), AnyError> {
+    if authorization.device_key != agent::signing_key_did() {
+        return Err(anyhow!("Device authorization is for another device"));
+    }
+    signatures::add_device_authorization(&authorization)?;
+    Ad4mDb::with_global_instance(|db| db.add_entanglement_proofs(vec![authorization]))?;
+    Ok(())
+}
+
+/// Run on the agent's executor after a main key rotation: signs the keys of
+/// all linked devices that are not revoked with the new key and replaces the
+/// stored authorizations. The returned ones need to be published again.
+pub fn reauthorize_devices() -> Result<Vec<EntanglementProof>, AnyError> {
+    let did = agent::did();
+    let signing_key_id = agent::signing_key_id();
+    let revoked: Vec<String> = signatures::device_keys(&did)
+        .into_iter()
+        .filter(|device| device.revoked_at.is_some())
+        .map(|device| device.key)
+        .collect();
+    let outdated: Vec<EntanglementProof> = get_entanglement_proofs()
+        .into_iter()
+        .filter(|proof| {
+            proof.did == did
+                && proof.device_key_type == DEVICE_KEY_TYPE
+                && proof.did_signing_key_id != signing_key_id
+                && !revoked.contains(&proof.device_key)
+        })
+        .collect();
+
+    let mut authorizations = Vec::new();
+    for proof in &outdated {
+        let authorization = EntanglementProof {
+            device_key_signed_by_did: sign_string_hex(proof.device_key.clone())?,
+            did_signing_key_id: signing_key_id.clone(),
+            ..proof.clone()
+        };
+        signatures::add_device_authorization(&authorization)?;
+        authorizations.push(authorization);
+    }
+
+    Ad4mDb::with_global_instance(|db| {
+        db.remove_entanglement_proofs(outdated)?;
+        db.add_entanglement_proofs(authorizations.clone())
+    })?;
+    Ok(authorizations)
+}
+
+/// Stops accepting signatures of a linked device made from now on
+pub fn revoke_device(device_key: String) -> Result<DeviceRevocation, AnyError> {
+    let mut revocation = DeviceRevocation {
+        did: agent::did(),
+        device_key,
+        revoked_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        signing_key: agent::signing_key_id(),
+        signature: String::new(),
+    };
+    if !signatures::device_keys(&revocation.did)
+        .iter()
+        .any(|device| device.key == revocation.device_key)
+    {
+        return Err(anyhow!("{} is not a linked device", revocation.device_key));
+    }
+    revocation.signature = hex::encode(agent::sign(&signatures::device_revocation_message(
+        &revocation,
+    ))?);
+    signatures::add_device_revocation(&revocation)?;
+    Ad4mDb::with_global_instance(|db| db.add_device_revocation(&revocation))?;
+    Ok(revocation)
+}
@@ -0,0 +1,158 @@
+//! Errors reported to clients with a stable code in the GraphQL `extensions.code`,
+//! so they don't have to match on error messages.
+//!
+//! Return an [`ExecutorError`] (wrapped in an `AnyError` where needed) for failures
+//! clients are expected to handle, and convert errors with [`field_error()`] at the
+//! GraphQL boundary. Everything else is reported as `INTERNAL_ERROR`.
+
+use crate::ai_service::error::AIServiceError;
+use crate::prolog_service::error::PrologQueryTimeout;
+use coasys_juniper::{graphql_value, FieldError};
+use deno_core::error::AnyError;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecutorError {
+    /// The request's capabilities don't cover what it tried to do
+    CapabilityDenied(String),
+    /// No perspective with the given UUID
+    PerspectiveNotFound(String),
+    /// The perspective with the given UUID has no link language (yet)
+    LinkLanguageMissing(String),
+    /// A Prolog query failed, e.g. with a syntax or type error
+    PrologError(String),
+    AgentNotInitialized,
+    AgentLocked,
+    /// The AI model with the given id isn't loaded
+    AiModelNotLoaded(String),
+    NotFound(String),
+    InvalidInput(String),
+}
+
+impl ExecutorError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ExecutorError::CapabilityDenied(_) => "CAPABILITY_DENIED",
+            ExecutorError::PerspectiveNotFound(_) => "PERSPECTIVE_NOT_FOUND",
+            ExecutorError::LinkLanguageMissing(_) => "LINK_LANGUAGE_MISSING",
+            ExecutorError::PrologError(_) => "PROLOG_ERROR",
+            ExecutorError::AgentNotInitialized => "AGENT_NOT_INITIALIZED",
+            ExecutorError::AgentLocked => "AGENT_LOCKED",
+            ExecutorError::AiModelNotLoaded(_) => "AI_MODEL_NOT_LOADED",
+            ExecutorError::NotFound(_) => "NOT_FOUND",
+            ExecutorError::InvalidInput(_) => "INVALID_INPUT",
+        }
+    }
+
+    pub fn field_error(&self) -> FieldError {
+        let code = self.code();
+        let extensions = match self {
+            ExecutorError::PerspectiveNotFound(uuid) | ExecutorError::LinkLanguageMissing(uuid) => {
+                graphql_value!({ "code": code, "uuid": (uuid.clone()) })
+            }
+            ExecutorError::AiModelNotLoaded(model) => {
+                graphql_value!({ "code": code, "model": (model.clone()) })
+            }
+            _ => graphql_value!({ "code": code }),
+        };
+        FieldError::new(self.to_string(), extensions)
+    }
+}
+
+impl Error for ExecutorError {}
+
+impl fmt::Display for ExecutorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutorError::CapabilityDenied(message)
+            | ExecutorError::PrologError(message)
+            | ExecutorError::NotFound(message)
+            | ExecutorError::InvalidInput(message) => write!(f, "{}", message),
+            ExecutorError::PerspectiveNotFound(uuid) => {
+                write!(f, "No perspective found with uuid {}", uuid)
+            }
+            ExecutorError::LinkLanguageMissing(uuid) => {
+                write!(f, "Perspective {} has no link language", uuid)
+            }
+            ExecutorError::AgentNotInitialized => write!(f, "Agent not initialized"),
+            ExecutorError::AgentLocked => write!(f, "Agent not unlocked"),
+            ExecutorError::AiModelNotLoaded(model) => write!(f, "Model '{}' is not loaded", model),
+        }
+    }
+}
+
+/// GraphQL error for `error` with the code of the `ExecutorError` (or other
+/// typed error) it is, or `INTERNAL_ERROR`
+pub fn field_error(error: AnyError) -> FieldError {
+    if let Some(error) = error.downcast_ref::<ExecutorError>() {
+        return error.field_error();
+    }
+    if let Some(error) = error.downcast_ref::<AIServiceError>() {
+        let code = match error {
+            AIServiceError::TaskNotFound
+            | AIServiceError::StreamNotFound
+            | AIServiceError::ModelNotFound => "NOT_FOUND",
+            _ => "INTERNAL_ERROR",
+        };
+        return FieldError::new(error.to_string(), graphql_value!({ "code": code }));
+    }
+    if let Some(timeout) = error.downcast_ref::<PrologQueryTimeout>() {
+        return FieldError::new(
+            timeout.to_string(),
+            graphql_value!({ "code": "PROLOG_QUERY_TIMEOUT", "reason": (timeout.reason()) }),
+        );
+    }
+    FieldError::new(
+        error.to_string(),
+        graphql_value!({ "code": "INTERNAL_ERROR" }),
+    )
+}
+
+/// `INTERNAL_ERROR` for failures that aren't an `AnyError`, e.g. `serde_json` or `String` errors
+pub fn internal_error<E: fmt::Display>(error: E) -> FieldError {
+    FieldError::new(
+        error.to_string(),
+        graphql_value!({ "code": "INTERNAL_ERROR" }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coasys_juniper::Value;
+    use deno_core::anyhow::anyhow;
+    use std::time::Duration;
+
+    fn code(error: FieldError) -> Option<String> {
+        error
+            .extensions()
+            .as_object_value()
+            .and_then(|extensions| extensions.get_field_value("code"))
+            .and_then(Value::as_string_value)
+            .map(str::to_string)
+    }
+
+    #[test]
+    fn errors_carry_their_code() {
+        let error = field_error(ExecutorError::PerspectiveNotFound("1234".to_string()).into());
+        assert_eq!(error.message(), "No perspective found with uuid 1234");
+        assert_eq!(code(error), Some("PERSPECTIVE_NOT_FOUND".to_string()));
+
+        let timeout = PrologQueryTimeout::TimeLimitExceeded(Duration::from_millis(10));
+        assert_eq!(
+            code(field_error(timeout.into())),
+            Some("PROLOG_QUERY_TIMEOUT".to_string())
+        );
+        assert_eq!(
+            code(field_error(anyhow!("Something broke"))),
+            Some("INTERNAL_ERROR".to_string())
+        );
+        assert_eq!(
+            code(internal_error(
+                serde_json::from_str::<u32>("x").unwrap_err()
+            )),
+            Some("INTERNAL_ERROR".to_string())
+        );
+    }
+}
@@ -1,13 +1,14 @@
 use crate::agent::capabilities::{AuthInfo, Capability};
-use crate::agent::signatures::verify;
+use crate::agent::signatures::{verify, DeviceKey};
+use crate::backup::BackupSummary;
 use crate::js_core::JsCoreHandle;
 use crate::types::{
-    AIPromptExamples, AITask, DecoratedExpressionProof, DecoratedLinkExpression, Expression,
-    ExpressionProof, Link, ModelType, Notification, TriggeredNotification,
-};
-use coasys_juniper::{
-    FieldError, FieldResult, GraphQLEnum, GraphQLInputObject, GraphQLObject, GraphQLScalar,
+    AIContextTruncation, AIOutputConstraint, AIPromptExamples, AITask, DecoratedExpressionProof,
+    DecoratedLinkExpression, Expression, ExpressionProof, Link, ModelType, Notification,
+    TriggeredNotification,
 };
+use crate::wallet::{KeyInfo, MAIN_KEY};
+use coasys_juniper::{FieldResult, GraphQLEnum, GraphQLInputObject, GraphQLObject, GraphQLScalar};
 use deno_core::anyhow::anyhow;
 use deno_core::error::AnyError;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -52,6 +53,81 @@ pub struct Apps {
     pub request_id: String,
     pub revoked: Option<bool>,
     pub token: String,
+    pub token_expires_at: Option<String>,
+    pub rotated_at: Option<String>,
+    pub rotation_count: i32,
+}
+
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: String,
+}
+
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentKey {
+    pub name: String,
+    pub did: String,
+    pub created_at: Option<String>,
+    pub retired_at: Option<String>,
+    pub is_main: bool,
+}
+
+impl From<KeyInfo> for AgentKey {
+    fn from(key: KeyInfo) -> Self {
+        AgentKey {
+            is_main: key.name == MAIN_KEY,
+            name: key.name,
+            did: key.did,
+            created_at: key
+                .created_at
+                .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)),
+            retired_at: key
+                .retired_at
+                .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)),
+        }
+    }
+}
+
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupTableCount {
+    pub name: String,
+    pub count: i32,
+}
+
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentBackup {
+    pub path: String,
+    pub agent_did: Option<String>,
+    pub created_at: String,
+    pub ad4m_version: String,
+    pub files: Vec<String>,
+    pub tables: Vec<BackupTableCount>,
+}
+
+impl AgentBackup {
+    pub fn new(path: String, summary: BackupSummary) -> Self {
+        AgentBackup {
+            path,
+            agent_did: summary.agent_did,
+            created_at: summary.created_at,
+            ad4m_version: summary.ad4m_version,
+            files: summary.files,
+            tables: summary
+                .tables
+                .into_iter()
+                .map(|(name, count)| BackupTableCount {
+                    name,
+                    count: count as i32,
+                })
+                .collect(),
+        }
+    }
 }
 
 #[derive(GraphQLInputObject, Default, Debug, Deserialize, Serialize)]
@@ -96,7 +172,7 @@ impl From<chrono::DateTime<chrono::Utc>> for DateTime {
     }
 }
 
-#[derive(GraphQLObject, Default, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[derive(GraphQLObject, Default, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct EntanglementProof {
     #[graphql(name = "deviceKey")]
@@ -130,6 +206,39 @@ pub struct EntanglementProofInput {
     pub did_signing_key_id: String,
 }
 
+impl From<EntanglementProofInput> for EntanglementProof {
+    fn from(input: EntanglementProofInput) -> Self {
+        EntanglementProof {
+            did: input.did,
+            did_signing_key_id: input.did_signing_key_id,
+            device_key_type: input.device_key_type,
+            device_key: input.device_key,
+            device_key_signed_by_did: input.device_key_signed_by_did,
+            did_signed_by_device_key: Some(input.did_signed_by_device_key),
+        }
+    }
+}
+
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentDevice {
+    pub device_key: String,
+    pub authorized: bool,
+    pub revoked_at: Option<String>,
+}
+
+impl From<DeviceKey> for AgentDevice {
+    fn from(device: DeviceKey) -> Self {
+        AgentDevice {
+            device_key: device.key,
+            authorized: device.authorized,
+            revoked_at: device
+                .revoked_at
+                .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)),
+        }
+    }
+}
+
 #[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ExceptionInfo {
@@ -290,14 +399,62 @@ pub struct LinkInput {
 #[derive(GraphQLInputObject, Default, Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct LinkQuery {
+    /// Cursor of a link from a previous query: only return links that come after it
+    pub after: Option<String>,
+    /// Cursor of a link from a previous query: only return links that come before it
+    pub before: Option<String>,
+    /// "asc" or "desc" by timestamp. If not set, links are returned in descending
+    /// order when `from_date` is later than `until_date` and ascending otherwise.
+    pub direction: Option<String>,
     pub from_date: Option<DateTime>,
     pub limit: Option<i32>,
     pub predicate: Option<String>,
     pub source: Option<String>,
+    /// Matched against the stored target, so it never matches encrypted links
     pub target: Option<String>,
     pub until_date: Option<DateTime>,
 }
 
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkSearchResult {
+    pub link: DecoratedLinkExpression,
+    /// Decoded literal target text that matched
+    pub text: String,
+    /// Relevance, higher is better. BM25 for full-text, cosine similarity for semantic search
+    pub score: f64,
+}
+
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PerspectiveImportResult {
+    pub perspective: PerspectiveHandle,
+    pub imported_links: i32,
+    /// Links that were not imported because their signature did not verify
+    pub invalid_links: Vec<DecoratedLinkExpression>,
+    pub archive_signature_valid: bool,
+}
+
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PrologEngineMetrics {
+    /// Number of queries waiting for or running on each engine of the perspective
+    pub queue_depths: Vec<i32>,
+    /// Number of queries since the engines were (re)started
+    pub queries_total: f64,
+}
+
+/// Sequence numbers of the last link events published at the time of the
+/// query. Only a starting point for the first subscription: the sequenced link
+/// subscriptions deliver the sequence of every event to resume from after that.
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkEventSequences {
+    pub added: f64,
+    pub removed: f64,
+    pub updated: f64,
+}
+
 #[derive(GraphQLInputObject, Default, Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct LinkMutations {
@@ -338,6 +495,23 @@ impl DecoratedPerspectiveDiff {
     }
 }
 
+/// A diff from the history of a perspective
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PerspectiveHistoryEntry {
+    pub id: String,
+    pub additions: Vec<DecoratedLinkExpression>,
+    pub removals: Vec<DecoratedLinkExpression>,
+    /// Authors of the added and removed links
+    pub authors: Vec<String>,
+    /// Made by this agent, as opposed to received from the neighbourhood
+    pub local: bool,
+    pub applied_at: String,
+    /// Id of the diff this one undid
+    pub undoes: Option<String>,
+    pub undone_by: Option<String>,
+}
+
 #[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Neighbourhood {
@@ -406,6 +580,7 @@ impl TryFrom<LinkExpressionInput> for DecoratedLinkExpression {
                 invalid: input.proof.invalid,
             },
             status: input.status,
+            cursor: None,
         })
     }
 }
@@ -586,6 +761,24 @@ pub struct LinkUpdated {
     pub old_link: DecoratedLinkExpression,
 }
 
+/// A link added or removed event together with its sequence number,
+/// to be passed as `fromSequence` when resubscribing after a reconnect
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SequencedLinkExpression {
+    pub sequence: f64,
+    pub link: DecoratedLinkExpression,
+}
+
+/// A link updated event together with its sequence number,
+/// to be passed as `fromSequence` when resubscribing after a reconnect
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SequencedLinkUpdated {
+    pub sequence: f64,
+    pub update: LinkUpdated,
+}
+
 #[derive(Default, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PerspectiveStateFilter {
@@ -600,6 +793,30 @@ pub struct ModelApiInput {
     pub api_key: String,
     pub model: String,
     pub api_type: String,
+    pub headers: Option<Vec<ModelApiHeaderInput>>,
+}
+
+#[derive(GraphQLInputObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelApiHeaderInput {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(GraphQLInputObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AIOutputConstraintInput {
+    pub json_schema: Option<String>,
+    pub regex: Option<String>,
+}
+
+impl From<AIOutputConstraintInput> for AIOutputConstraint {
+    fn from(input: AIOutputConstraintInput) -> AIOutputConstraint {
+        AIOutputConstraint {
+            json_schema: input.json_schema,
+            regex: input.regex,
+        }
+    }
 }
 
 #[derive(GraphQLInputObject, Default, Debug, Deserialize, Serialize, Clone)]
@@ -634,6 +851,7 @@ pub struct LocalModelInput {
     pub tokenizer_source: Option<TokenizerSourceInput>,
     pub huggingface_repo: Option<String>,
     pub revision: Option<String>,
+    pub language: Option<String>,
 }
 
 #[derive(GraphQLInputObject, Default, Debug, Deserialize, Serialize, Clone)]
@@ -662,6 +880,18 @@ pub struct AITaskInput {
     pub system_prompt: String,
     pub prompt_examples: Vec<AIPromptExamplesInput>,
     pub meta_data: Option<String>,
+    pub output_constraint: Option<AIOutputConstraintInput>,
+}
+
+#[derive(GraphQLInputObject, Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AISessionInput {
+    pub task_id: String,
+    pub name: Option<String>,
+    pub truncation: Option<AIContextTruncation>,
+    pub max_messages: Option<i32>,
+    pub max_tokens: Option<i32>,
+    pub perspective_uuid: Option<String>,
 }
 
 impl From<AITaskInput> for AITask {
@@ -680,6 +910,7 @@ impl From<AITaskInput> for AITask {
                 .map(|p| p.into())
                 .collect(),
             meta_data: input.meta_data,
+            output_constraint: input.output_constraint.map(|c| c.into()),
             created_at,
             updated_at,
         }
@@ -698,6 +929,26 @@ pub struct TranscriptionTextFilter {
     pub text: String,
 }
 
+/// A piece of transcribed speech. Times are in seconds from the start of the audio.
+#[derive(GraphQLObject, Default, Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptionSegment {
+    pub text: String,
+    pub start: f64,
+    pub end: f64,
+    /// Probability that the segment is speech rather than noise, between 0 and 1
+    pub confidence: f64,
+}
+
+/// What gets published on `AI_TRANSCRIPTION_TEXT_TOPIC`.
+/// `TranscriptionTextFilter` reads the same events, ignoring the timing.
+#[derive(Default, Debug, Deserialize, Serialize)]
+pub struct TranscriptionSegmentFilter {
+    pub stream_id: String,
+    #[serde(flatten)]
+    pub segment: TranscriptionSegment,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub enum JsResultType<T>
 where
@@ -714,7 +965,7 @@ where
     pub fn get_graphql_result(self) -> FieldResult<T> {
         match self {
             JsResultType::Ok(result) => Ok(result),
-            JsResultType::Error(error) => Err(FieldError::from(error.clone())),
+            JsResultType::Error(error) => Err(crate::errors::internal_error(error)),
         }
     }
 }
@@ -745,6 +996,20 @@ impl GetFilter for TranscriptionTextFilter {
     }
 }
 
+impl GetValue for TranscriptionSegmentFilter {
+    type Value = TranscriptionSegment;
+
+    fn get_value(&self) -> Self::Value {
+        self.segment.clone()
+    }
+}
+
+impl GetFilter for TranscriptionSegmentFilter {
+    fn get_filter(&self) -> Option<String> {
+        Some(self.stream_id.clone())
+    }
+}
+
 impl GetValue for Option<Apps> {
     type Value = Option<Apps>;
 
@@ -979,3 +1244,14 @@ impl GetFilter for AIModelLoadingStatus {
         None
     }
 }
+
+/// Text generated for a prompt stream since the previous event.
+/// The last event of a stream has `done` set, and `error` if generation failed.
+#[derive(GraphQLObject, Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AIPromptTokens {
+    pub stream_id: String,
+    pub text: String,
+    pub done: bool,
+    pub error: Option<String>,
+}
@@ -10,15 +10,17 @@ use reqwest::header::ACCESS_CONTROL_ALLOW_ORIGIN;
 use subscription_resolvers::*;
 use warp::reply::with_header;
 
-use crate::agent::capabilities::capabilities_from_token;
+use crate::agent::capabilities::{capabilities_from_token, check_capability, Capability};
+use crate::errors::ExecutorError;
 use crate::js_core::JsCoreHandle;
+use crate::perspectives::{get_perspective, perspective_instance::PerspectiveInstance};
 use crate::Ad4mConfig;
 
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::{convert::Infallible, io::Write};
 
-use coasys_juniper::{InputValue, RootNode};
+use coasys_juniper::{FieldResult, InputValue, RootNode};
 use coasys_juniper_graphql_transport_ws::ConnectionConfig;
 use coasys_juniper_warp::{playground_filter, subscriptions::serve_graphql_transport_ws};
 use deno_core::error::AnyError;
@@ -34,6 +36,19 @@ fn schema() -> Schema {
     Schema::new(Query, Mutation, Subscription)
 }
 
+/// `check_capability()` for resolvers, failing with `CAPABILITY_DENIED`
+fn require_capability(
+    capabilities: &Result<Vec<Capability>, String>,
+    expected: &Capability,
+) -> FieldResult<()> {
+    check_capability(capabilities, expected).map_err(|e| e.field_error())
+}
+
+fn get_perspective_with_uuid_field_error(uuid: &str) -> FieldResult<PerspectiveInstance> {
+    get_perspective(uuid)
+        .ok_or_else(|| ExecutorError::PerspectiveNotFound(uuid.to_string()).field_error())
+}
+
 fn _reply_with_header(
     reply: impl warp::Reply,
     name: &'static str,
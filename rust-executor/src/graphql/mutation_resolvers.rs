@@ -2,14 +2,18 @@
 
 use crate::{
     agent::create_signed_expression,
-    ai_service::AIService,
+    ai_service::{AIService, TranscriptionAudio},
     neighbourhoods::{self, install_neighbourhood},
     perspectives::{
-        add_perspective, get_perspective,
-        perspective_instance::{PerspectiveInstance, SdnaType},
+        add_perspective,
+        archive::{export_perspective, import_perspective},
+        perspective_instance::SdnaType,
         remove_perspective, update_perspective,
     },
-    types::{AITask, DecoratedLinkExpression, Link, LinkExpression, ModelType},
+    types::{
+        AISession, AITask, DecoratedLinkExpression, DeviceRevocation, KeyRotation, Link,
+        LinkExpression, ModelType, PerspectiveSyncStatus,
+    },
 };
 use crate::{
     db::Ad4mDb,
@@ -20,26 +24,73 @@ use crate::{
 use coasys_juniper::{graphql_object, graphql_value, FieldError, FieldResult};
 
 use super::graphql_types::*;
+use super::{get_perspective_with_uuid_field_error, require_capability};
+use crate::errors::{field_error, internal_error, ExecutorError};
 use crate::{
-    agent::{self, capabilities::*, AgentService},
+    agent::{
+        self,
+        capabilities::*,
+        signatures::{
+            DEVICE_AUTHORIZATION_PREDICATE, DEVICE_REVOCATION_PREDICATE, KEY_ROTATION_PREDICATE,
+        },
+        AgentService,
+    },
+    backup,
     entanglement_service::{
-        add_entanglement_proofs, delete_entanglement_proof, get_entanglement_proofs,
+        self, add_entanglement_proofs, delete_entanglement_proof, get_entanglement_proofs,
         sign_device_key,
     },
     holochain_service::{agent_infos_from_str, get_holochain_service},
     pubsub::{get_global_pubsub, AGENT_STATUS_CHANGED_TOPIC},
+    wallet::check_passphrase_policy,
 };
+use ad4m_client::literal::Literal;
 use base64::prelude::*;
+use serde::Serialize;
+use std::path::Path;
 
 pub struct Mutation;
 
-fn get_perspective_with_uuid_field_error(uuid: &str) -> FieldResult<PerspectiveInstance> {
-    get_perspective(uuid).ok_or_else(|| {
-        FieldError::new(
-            "Perspective not found",
-            graphql_value!({ "uuid": uuid.to_owned() }),
-        )
+/// Adds a signed statement about our keys to the agent's public perspective,
+/// so other agents can keep verifying what we sign
+async fn publish_agent_statement<T: Serialize>(
+    context: &RequestContext,
+    predicate: &str,
+    statement: &T,
+) -> FieldResult<()> {
+    let link = create_signed_expression(Link {
+        source: agent::did(),
+        predicate: Some(predicate.to_string()),
+        target: Literal::from_json(serde_json::to_value(statement).map_err(internal_error)?)
+            .to_url()
+            .map_err(field_error)?,
+    })
+    .map_err(field_error)?;
+    let mut links = AgentService::with_global_instance(|agent_service| {
+        agent_service
+            .agent
+            .as_ref()
+            .and_then(|agent| agent.perspective.as_ref())
+            .map(|perspective| perspective.links.clone())
+            .unwrap_or_default()
     })
+    .into_iter()
+    .map(serde_json::to_value)
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(internal_error)?;
+    links.push(serde_json::to_value(&link).map_err(internal_error)?);
+
+    let mut js = context.js_handle.clone();
+    let script = format!(
+        r#"JSON.stringify(
+            await core.callResolver("Mutation", "agentUpdatePublicPerspective", {{ perspective: {} }})
+        )"#,
+        serde_json::json!({ "links": links })
+    );
+    let result = js.execute(script).await.map_err(field_error)?;
+    let result: JsResultType<Agent> = serde_json::from_str(&result).map_err(internal_error)?;
+    result.get_graphql_result()?;
+    Ok(())
 }
 
 fn link_status_from_input(status: Option<String>) -> Result<LinkStatus, FieldError> {
@@ -47,10 +98,10 @@ fn link_status_from_input(status: Option<String>) -> Result<LinkStatus, FieldErr
         Some("shared") => Ok(LinkStatus::Shared),
         Some("local") => Ok(LinkStatus::Local),
         None => Ok(LinkStatus::Shared),
-        _ => Err(FieldError::new(
-            "Invalid status, must be either 'shared' or 'local'",
-            graphql_value!({ "invalid_status": status }),
-        )),
+        _ => Err(ExecutorError::InvalidInput(
+            "Invalid status, must be either 'shared' or 'local'".to_string(),
+        )
+        .field_error()),
     }
 }
 
@@ -61,7 +112,7 @@ impl Mutation {
         context: &RequestContext,
         agents: Vec<String>,
     ) -> FieldResult<Vec<String>> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &RUNTIME_TRUSTED_AGENTS_CREATE_CAPABILITY,
         )?;
@@ -140,7 +191,8 @@ impl Mutation {
         context: &RequestContext,
         passphrase: String,
     ) -> FieldResult<AgentStatus> {
-        check_capability(&context.capabilities, &AGENT_CREATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_CREATE_CAPABILITY)?;
+        check_passphrase_policy(&passphrase).map_err(field_error)?;
         let agent = AgentService::with_mutable_global_instance(|agent_service| {
             agent_service.create_new_keys();
             agent_service.save(passphrase.clone());
@@ -155,7 +207,7 @@ impl Mutation {
             )"#,
             passphrase
         );
-        js.execute(script).await?;
+        js.execute(script).await.map_err(field_error)?;
 
         get_global_pubsub()
             .await
@@ -170,6 +222,162 @@ impl Mutation {
         Ok(agent)
     }
 
+    async fn agent_create_key(
+        &self,
+        context: &RequestContext,
+        name: String,
+        passphrase: String,
+    ) -> FieldResult<AgentKey> {
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        let key = AgentService::with_global_instance(|agent_service| {
+            agent_service.create_key(name, passphrase)
+        })
+        .map_err(field_error)?;
+        Ok(key.into())
+    }
+
+    async fn agent_retire_key(
+        &self,
+        context: &RequestContext,
+        name: String,
+        passphrase: String,
+    ) -> FieldResult<AgentKey> {
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        let key = AgentService::with_global_instance(|agent_service| {
+            agent_service.retire_key(name, passphrase)
+        })
+        .map_err(field_error)?;
+        Ok(key.into())
+    }
+
+    async fn agent_rotate_main_key(
+        &self,
+        context: &RequestContext,
+        passphrase: String,
+    ) -> FieldResult<KeyRotation> {
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        let rotation = AgentService::with_mutable_global_instance(|agent_service| {
+            agent_service.rotate_main_key(passphrase)
+        })
+        .map_err(field_error)?;
+
+        // Other agents need the statement to verify what we signed with the old key
+        publish_agent_statement(context, KEY_ROTATION_PREDICATE, &rotation).await?;
+
+        // Devices authorized by the old key have to be authorized by the new one
+        for authorization in entanglement_service::reauthorize_devices().map_err(field_error)? {
+            publish_agent_statement(context, DEVICE_AUTHORIZATION_PREDICATE, &authorization)
+                .await?;
+        }
+
+        Ok(rotation)
+    }
+
+    async fn agent_request_device_link(
+        &self,
+        context: &RequestContext,
+        did: String,
+    ) -> FieldResult<EntanglementProof> {
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        Ok(entanglement_service::request_device_link(did).map_err(field_error)?)
+    }
+
+    async fn agent_authorize_device(
+        &self,
+        context: &RequestContext,
+        request: EntanglementProofInput,
+    ) -> FieldResult<EntanglementProof> {
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        let authorization =
+            entanglement_service::authorize_device(request.into()).map_err(field_error)?;
+        publish_agent_statement(context, DEVICE_AUTHORIZATION_PREDICATE, &authorization).await?;
+        Ok(authorization)
+    }
+
+    async fn agent_link_device(
+        &self,
+        context: &RequestContext,
+        authorization: EntanglementProofInput,
+        passphrase: String,
+    ) -> FieldResult<AgentStatus> {
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        let authorization: EntanglementProof = authorization.into();
+        entanglement_service::accept_device_link(authorization.clone()).map_err(field_error)?;
+        let status = AgentService::with_mutable_global_instance(|agent_service| {
+            agent_service.become_device_of(authorization.did, passphrase)?;
+            Ok::<_, deno_core::error::AnyError>(agent_service.dump())
+        })
+        .map_err(field_error)?;
+
+        get_global_pubsub()
+            .await
+            .publish(
+                &AGENT_STATUS_CHANGED_TOPIC,
+                &serde_json::to_string(&status).unwrap(),
+            )
+            .await;
+
+        Ok(status)
+    }
+
+    async fn agent_revoke_device(
+        &self,
+        context: &RequestContext,
+        device_key: String,
+    ) -> FieldResult<DeviceRevocation> {
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        let revocation = entanglement_service::revoke_device(device_key).map_err(field_error)?;
+        publish_agent_statement(context, DEVICE_REVOCATION_PREDICATE, &revocation).await?;
+        Ok(revocation)
+    }
+
+    async fn agent_create_backup(
+        &self,
+        context: &RequestContext,
+        passphrase: String,
+        path: String,
+    ) -> FieldResult<AgentBackup> {
+        require_capability(&context.capabilities, &ALL_CAPABILITY)?;
+        let app_path = AgentService::with_global_instance(|agent_service| {
+            agent_service.app_path().to_string()
+        });
+        let summary = backup::backup_running(Path::new(&app_path), Path::new(&path), &passphrase)
+            .map_err(field_error)?;
+        Ok(AgentBackup::new(path, summary))
+    }
+
+    async fn agent_restore_backup(
+        &self,
+        context: &RequestContext,
+        passphrase: String,
+        path: String,
+        force: Option<bool>,
+    ) -> FieldResult<AgentBackup> {
+        require_capability(&context.capabilities, &ALL_CAPABILITY)?;
+        let app_path = AgentService::with_global_instance(|agent_service| {
+            agent_service.app_path().to_string()
+        });
+        let archive = std::fs::read_to_string(&path).map_err(internal_error)?;
+        let summary = backup::restore_running(
+            Path::new(&app_path),
+            &archive,
+            &passphrase,
+            force.unwrap_or(false),
+        )
+        .map_err(field_error)?;
+
+        let status = AgentService::with_global_instance(|agent_service| agent_service.dump());
+        get_global_pubsub()
+            .await
+            .publish(
+                &AGENT_STATUS_CHANGED_TOPIC,
+                &serde_json::to_string(&status).unwrap(),
+            )
+            .await;
+
+        Ok(AgentBackup::new(path, summary))
+    }
+
     async fn agent_lock(
         &self,
         _context: &RequestContext,
@@ -196,8 +404,8 @@ impl Mutation {
         context: &RequestContext,
         request_id: String,
     ) -> FieldResult<Vec<Apps>> {
-        check_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
-        apps_map::remove_app(&request_id)?;
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        apps_map::remove_app(&request_id).map_err(internal_error)?;
         Ok(apps_map::get_apps())
     }
 
@@ -206,7 +414,7 @@ impl Mutation {
         context: &RequestContext,
         auth_info: AuthInfoInput,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &AGENT_AUTH_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_AUTH_CAPABILITY)?;
         let auth_info: AuthInfo = auth_info.into();
         let request_id = agent::capabilities::request_capability(auth_info.clone()).await;
         if context.auto_permit_cap_requests {
@@ -216,7 +424,8 @@ impl Mutation {
                 agent::capabilities::permit_capability(AuthInfoExtended {
                     request_id: request_id.clone(),
                     auth: auth_info,
-                })?;
+                })
+                .map_err(internal_error)?;
             println!("--------------------------------------");
             println!("Random number challenge: {}", random_number_challenge);
             println!("======================================");
@@ -231,9 +440,11 @@ impl Mutation {
         context: &RequestContext,
         auth: String,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &AGENT_PERMIT_CAPABILITY)?;
-        let auth: AuthInfoExtended = serde_json::from_str(&auth)?;
-        let random_number_challenge = agent::capabilities::permit_capability(auth)?;
+        require_capability(&context.capabilities, &AGENT_PERMIT_CAPABILITY)?;
+        let auth: AuthInfoExtended = serde_json::from_str(&auth)
+            .map_err(|e| ExecutorError::InvalidInput(e.to_string()).field_error())?;
+        let random_number_challenge =
+            agent::capabilities::permit_capability(auth).map_err(internal_error)?;
         Ok(random_number_challenge)
     }
 
@@ -243,9 +454,46 @@ impl Mutation {
         rand: String,
         request_id: String,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &AGENT_AUTH_CAPABILITY)?;
-        let cap_token = agent::capabilities::generate_capability_token(request_id, rand).await?;
-        Ok(cap_token)
+        require_capability(&context.capabilities, &AGENT_AUTH_CAPABILITY)?;
+        // Callers of this mutation never see the refresh token,
+        // so their access token keeps the long validity it always had
+        let tokens = agent::capabilities::generate_capability_token(
+            request_id,
+            rand,
+            DEFAULT_TOKEN_VALID_PERIOD,
+        )
+        .await
+        .map_err(internal_error)?;
+        Ok(tokens.access_token)
+    }
+
+    async fn agent_generate_capability_tokens(
+        &self,
+        context: &RequestContext,
+        rand: String,
+        request_id: String,
+    ) -> FieldResult<CapabilityTokens> {
+        require_capability(&context.capabilities, &AGENT_AUTH_CAPABILITY)?;
+        let tokens = agent::capabilities::generate_capability_token(
+            request_id,
+            rand,
+            ACCESS_TOKEN_VALID_PERIOD,
+        )
+        .await
+        .map_err(internal_error)?;
+        Ok(tokens)
+    }
+
+    async fn agent_refresh_capability(
+        &self,
+        _context: &RequestContext,
+        refresh_token: String,
+    ) -> FieldResult<CapabilityTokens> {
+        // The refresh token is the credential, the request's own token might have expired
+        let tokens = agent::capabilities::refresh_capability(refresh_token)
+            .await
+            .map_err(internal_error)?;
+        Ok(tokens)
     }
 
     async fn agent_revoke_token(
@@ -253,8 +501,8 @@ impl Mutation {
         context: &RequestContext,
         request_id: String,
     ) -> FieldResult<Vec<Apps>> {
-        check_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
-        apps_map::revoke_app(&request_id)?;
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        apps_map::revoke_app(&request_id).map_err(internal_error)?;
         Ok(apps_map::get_apps())
     }
 
@@ -263,8 +511,10 @@ impl Mutation {
         context: &RequestContext,
         message: String,
     ) -> FieldResult<AgentSignature> {
-        check_capability(&context.capabilities, &AGENT_SIGN_CAPABILITY)?;
-        Ok(agent::AgentSignature::from_message(message)?.into())
+        require_capability(&context.capabilities, &AGENT_SIGN_CAPABILITY)?;
+        Ok(agent::AgentSignature::from_message(message)
+            .map_err(field_error)?
+            .into())
     }
 
     async fn agent_unlock(
@@ -273,14 +523,14 @@ impl Mutation {
         passphrase: String,
         holochain: bool,
     ) -> FieldResult<AgentStatus> {
-        check_capability(&context.capabilities, &AGENT_SIGN_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_SIGN_CAPABILITY)?;
 
         let agent_instance = AgentService::global_instance();
         {
             let agent_service = agent_instance.lock().expect("agent lock");
             let agent_ref: &AgentService = agent_service.as_ref().expect("agent instance");
 
-            agent_ref.unlock(passphrase.clone())?
+            agent_ref.unlock(passphrase.clone()).map_err(field_error)?
         }
 
         if agent_instance
@@ -297,7 +547,7 @@ impl Mutation {
                 )"#,
                 passphrase, holochain
             );
-            js.execute(script).await?;
+            js.execute(script).await.map_err(field_error)?;
         }
 
         let mut agent = {
@@ -332,7 +582,7 @@ impl Mutation {
         context: &RequestContext,
         direct_message_language: String,
     ) -> FieldResult<Agent> {
-        check_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let script = format!(
             r#"JSON.stringify(
@@ -340,8 +590,8 @@ impl Mutation {
             )"#,
             direct_message_language
         );
-        let result = js.execute(script).await?;
-        let result: JsResultType<Agent> = serde_json::from_str(&result)?;
+        let result = js.execute(script).await.map_err(field_error)?;
+        let result: JsResultType<Agent> = serde_json::from_str(&result).map_err(internal_error)?;
         result.get_graphql_result()
     }
 
@@ -350,17 +600,17 @@ impl Mutation {
         context: &RequestContext,
         perspective: PerspectiveInput,
     ) -> FieldResult<Agent> {
-        check_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
         let mut js = context.js_handle.clone();
-        let perspective_json = serde_json::to_string(&perspective)?;
+        let perspective_json = serde_json::to_string(&perspective).map_err(internal_error)?;
         let script = format!(
             r#"JSON.stringify(
                 await core.callResolver("Mutation", "agentUpdatePublicPerspective", {{ perspective: {} }})
             )"#,
             perspective_json
         );
-        let result = js.execute(script).await?;
-        let result: JsResultType<Agent> = serde_json::from_str(&result)?;
+        let result = js.execute(script).await.map_err(field_error)?;
+        let result: JsResultType<Agent> = serde_json::from_str(&result).map_err(internal_error)?;
         result.get_graphql_result()
     }
 
@@ -369,7 +619,7 @@ impl Mutation {
         context: &RequestContext,
         agents: Vec<String>,
     ) -> FieldResult<Vec<String>> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &RUNTIME_TRUSTED_AGENTS_DELETE_CAPABILITY,
         )?;
@@ -387,7 +637,7 @@ impl Mutation {
         content: String,
         language_address: String,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &EXPRESSION_CREATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &EXPRESSION_CREATE_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let script = format!(
             r#"JSON.stringify(
@@ -395,8 +645,8 @@ impl Mutation {
             )"#,
             content, language_address
         );
-        let result = js.execute(script).await?;
-        let result: JsResultType<String> = serde_json::from_str(&result)?;
+        let result = js.execute(script).await.map_err(field_error)?;
+        let result: JsResultType<String> = serde_json::from_str(&result).map_err(internal_error)?;
         result.get_graphql_result()
     }
 
@@ -406,9 +656,10 @@ impl Mutation {
         interaction_call: InteractionCall,
         url: String,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &EXPRESSION_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &EXPRESSION_UPDATE_CAPABILITY)?;
         let mut js = context.js_handle.clone();
-        let interaction_call_json = serde_json::to_string(&interaction_call)?;
+        let interaction_call_json =
+            serde_json::to_string(&interaction_call).map_err(internal_error)?;
         let script = format!(
             r#"JSON.stringify(
             await core.callResolver(
@@ -418,8 +669,8 @@ impl Mutation {
             ))"#,
             interaction_call_json, url
         );
-        let result = js.execute(script).await?;
-        let result: JsResultType<String> = serde_json::from_str(&result)?;
+        let result = js.execute(script).await.map_err(field_error)?;
+        let result: JsResultType<String> = serde_json::from_str(&result).map_err(internal_error)?;
         result.get_graphql_result()
     }
 
@@ -429,7 +680,7 @@ impl Mutation {
         source_language_hash: String,
         template_data: String,
     ) -> FieldResult<LanguageRef> {
-        check_capability(&context.capabilities, &LANGUAGE_CREATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &LANGUAGE_CREATE_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let script = format!(
             r#"JSON.stringify(
@@ -440,8 +691,9 @@ impl Mutation {
             ))"#,
             source_language_hash, template_data
         );
-        let result = js.execute(script).await?;
-        let result: JsResultType<LanguageRef> = serde_json::from_str(&result)?;
+        let result = js.execute(script).await.map_err(field_error)?;
+        let result: JsResultType<LanguageRef> =
+            serde_json::from_str(&result).map_err(internal_error)?;
         result.get_graphql_result()
     }
 
@@ -451,9 +703,9 @@ impl Mutation {
         language_meta: LanguageMetaInput,
         language_path: String,
     ) -> FieldResult<LanguageMeta> {
-        check_capability(&context.capabilities, &LANGUAGE_CREATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &LANGUAGE_CREATE_CAPABILITY)?;
         let mut js = context.js_handle.clone();
-        let language_meta_json = serde_json::to_string(&language_meta)?;
+        let language_meta_json = serde_json::to_string(&language_meta).map_err(internal_error)?;
         let script = format!(
             r#"JSON.stringify(
             await core.callResolver(
@@ -464,9 +716,10 @@ impl Mutation {
             language_meta_json, language_path
         );
 
-        let result = js.execute(script).await?;
+        let result = js.execute(script).await.map_err(field_error)?;
         println!("language_publish result: {:?}", result);
-        let result: JsResultType<LanguageMeta> = serde_json::from_str(&result)?;
+        let result: JsResultType<LanguageMeta> =
+            serde_json::from_str(&result).map_err(internal_error)?;
         println!("language_publish result 1: {:?}", result);
         result.get_graphql_result()
     }
@@ -476,7 +729,7 @@ impl Mutation {
         context: &RequestContext,
         address: String,
     ) -> FieldResult<bool> {
-        check_capability(&context.capabilities, &LANGUAGE_DELETE_CAPABILITY)?;
+        require_capability(&context.capabilities, &LANGUAGE_DELETE_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let script = format!(
             r#"JSON.stringify(
@@ -487,8 +740,8 @@ impl Mutation {
             ))"#,
             address
         );
-        let result = js.execute(script).await?;
-        let result: JsResultType<bool> = serde_json::from_str(&result)?;
+        let result = js.execute(script).await.map_err(field_error)?;
+        let result: JsResultType<bool> = serde_json::from_str(&result).map_err(internal_error)?;
         result.get_graphql_result()
     }
 
@@ -498,7 +751,7 @@ impl Mutation {
         language_address: String,
         settings: String,
     ) -> FieldResult<bool> {
-        check_capability(&context.capabilities, &LANGUAGE_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &LANGUAGE_UPDATE_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let script = format!(
             r#"JSON.stringify(
@@ -509,8 +762,8 @@ impl Mutation {
             ))"#,
             language_address, settings
         );
-        let result = js.execute(script).await?;
-        let result: JsResultType<bool> = serde_json::from_str(&result)?;
+        let result = js.execute(script).await.map_err(field_error)?;
+        let result: JsResultType<bool> = serde_json::from_str(&result).map_err(internal_error)?;
         result.get_graphql_result()
     }
 
@@ -519,8 +772,8 @@ impl Mutation {
         context: &RequestContext,
         url: String,
     ) -> FieldResult<PerspectiveHandle> {
-        check_capability(&context.capabilities, &NEIGHBOURHOOD_READ_CAPABILITY)?;
-        Ok(install_neighbourhood(url).await?)
+        require_capability(&context.capabilities, &NEIGHBOURHOOD_READ_CAPABILITY)?;
+        Ok(install_neighbourhood(url).await.map_err(field_error)?)
     }
 
     async fn neighbourhood_publish_from_perspective(
@@ -530,13 +783,14 @@ impl Mutation {
         meta: PerspectiveInput,
         #[allow(non_snake_case)] perspectiveUUID: String,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &NEIGHBOURHOOD_CREATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &NEIGHBOURHOOD_CREATE_CAPABILITY)?;
         let url = neighbourhoods::neighbourhood_publish_from_perspective(
             &perspectiveUUID,
             link_language,
             meta.into(),
         )
-        .await?;
+        .await
+        .map_err(field_error)?;
 
         Ok(url)
     }
@@ -548,17 +802,13 @@ impl Mutation {
         #[allow(non_snake_case)] perspectiveUUID: String,
     ) -> FieldResult<bool> {
         let uuid = perspectiveUUID;
-        check_capability(&context.capabilities, &NEIGHBOURHOOD_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &NEIGHBOURHOOD_UPDATE_CAPABILITY)?;
         let perspective = Perspective::from(payload);
-        let perspective = create_signed_expression(perspective)?;
-        get_perspective(&uuid)
-            .ok_or(FieldError::from(format!(
-                "No perspective found with uuid {}",
-                uuid
-            )))?
+        let perspective = create_signed_expression(perspective).map_err(field_error)?;
+        get_perspective_with_uuid_field_error(&uuid)?
             .send_broadcast(perspective.into())
             .await
-            .map_err(|e| FieldError::from(e.to_string()))?;
+            .map_err(field_error)?;
         Ok(true)
     }
 
@@ -569,7 +819,7 @@ impl Mutation {
         #[allow(non_snake_case)] perspectiveUUID: String,
     ) -> FieldResult<bool> {
         let uuid = perspectiveUUID;
-        check_capability(&context.capabilities, &NEIGHBOURHOOD_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &NEIGHBOURHOOD_UPDATE_CAPABILITY)?;
         let perspective = Perspective {
             links: payload
                 .links
@@ -581,15 +831,11 @@ impl Mutation {
                 .map(|l| DecoratedLinkExpression::from((l, LinkStatus::Shared)))
                 .collect::<Vec<DecoratedLinkExpression>>(),
         };
-        let perspective = create_signed_expression(perspective)?;
-        get_perspective(&uuid)
-            .ok_or(FieldError::from(format!(
-                "No perspective found with uuid {}",
-                uuid
-            )))?
+        let perspective = create_signed_expression(perspective).map_err(field_error)?;
+        get_perspective_with_uuid_field_error(&uuid)?
             .send_broadcast(perspective.into())
             .await
-            .map_err(|e| FieldError::from(e.to_string()))?;
+            .map_err(field_error)?;
         Ok(true)
     }
 
@@ -601,17 +847,13 @@ impl Mutation {
         remote_agent_did: String,
     ) -> FieldResult<bool> {
         let uuid = perspectiveUUID;
-        check_capability(&context.capabilities, &NEIGHBOURHOOD_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &NEIGHBOURHOOD_UPDATE_CAPABILITY)?;
         let perspective = Perspective::from(payload);
-        let perspective = create_signed_expression(perspective)?;
-        get_perspective(&uuid)
-            .ok_or(FieldError::from(format!(
-                "No perspective found with uuid {}",
-                uuid
-            )))?
+        let perspective = create_signed_expression(perspective).map_err(field_error)?;
+        get_perspective_with_uuid_field_error(&uuid)?
             .send_signal(remote_agent_did, perspective.into())
             .await
-            .map_err(|e| FieldError::from(e.to_string()))?;
+            .map_err(field_error)?;
         Ok(true)
     }
 
@@ -623,7 +865,7 @@ impl Mutation {
         remote_agent_did: String,
     ) -> FieldResult<bool> {
         let uuid = perspectiveUUID;
-        check_capability(&context.capabilities, &NEIGHBOURHOOD_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &NEIGHBOURHOOD_UPDATE_CAPABILITY)?;
         let perspective = Perspective {
             links: payload
                 .links
@@ -635,15 +877,11 @@ impl Mutation {
                 .map(|l| DecoratedLinkExpression::from((l, LinkStatus::Shared)))
                 .collect::<Vec<DecoratedLinkExpression>>(),
         };
-        let perspective = create_signed_expression(perspective)?;
-        get_perspective(&uuid)
-            .ok_or(FieldError::from(format!(
-                "No perspective found with uuid {}",
-                uuid
-            )))?
+        let perspective = create_signed_expression(perspective).map_err(field_error)?;
+        get_perspective_with_uuid_field_error(&uuid)?
             .send_signal(remote_agent_did, perspective.into())
             .await
-            .map_err(|e| FieldError::from(e.to_string()))?;
+            .map_err(field_error)?;
         Ok(true)
     }
 
@@ -654,17 +892,13 @@ impl Mutation {
         status: PerspectiveInput,
     ) -> FieldResult<bool> {
         let uuid = perspectiveUUID;
-        check_capability(&context.capabilities, &NEIGHBOURHOOD_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &NEIGHBOURHOOD_UPDATE_CAPABILITY)?;
         let perspective = Perspective::from(status);
-        let perspective = create_signed_expression(perspective)?;
-        get_perspective(&uuid)
-            .ok_or(FieldError::from(format!(
-                "No perspective found with uuid {}",
-                uuid
-            )))?
+        let perspective = create_signed_expression(perspective).map_err(field_error)?;
+        get_perspective_with_uuid_field_error(&uuid)?
             .set_online_status(perspective.into())
             .await
-            .map_err(|e| FieldError::from(e.to_string()))?;
+            .map_err(field_error)?;
         Ok(true)
     }
 
@@ -675,7 +909,7 @@ impl Mutation {
         status: PerspectiveUnsignedInput,
     ) -> FieldResult<bool> {
         let uuid = perspectiveUUID;
-        check_capability(&context.capabilities, &NEIGHBOURHOOD_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &NEIGHBOURHOOD_UPDATE_CAPABILITY)?;
         let perspective = Perspective {
             links: status
                 .links
@@ -687,15 +921,11 @@ impl Mutation {
                 .map(|l| DecoratedLinkExpression::from((l, LinkStatus::Shared)))
                 .collect::<Vec<DecoratedLinkExpression>>(),
         };
-        let perspective = create_signed_expression(perspective)?;
-        get_perspective(&uuid)
-            .ok_or(FieldError::from(format!(
-                "No perspective found with uuid {}",
-                uuid
-            )))?
+        let perspective = create_signed_expression(perspective).map_err(field_error)?;
+        get_perspective_with_uuid_field_error(&uuid)?
             .set_online_status(perspective.into())
             .await
-            .map_err(|e| FieldError::from(e.to_string()))?;
+            .map_err(field_error)?;
         Ok(true)
     }
 
@@ -704,12 +934,54 @@ impl Mutation {
         context: &RequestContext,
         name: String,
     ) -> FieldResult<PerspectiveHandle> {
-        check_capability(&context.capabilities, &PERSPECTIVE_CREATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &PERSPECTIVE_CREATE_CAPABILITY)?;
         let handle = PerspectiveHandle::new_from_name(name.clone());
-        add_perspective(handle.clone(), None).await?;
+        add_perspective(handle.clone(), None)
+            .await
+            .map_err(internal_error)?;
         Ok(handle)
     }
 
+    async fn perspective_cancel_prolog_query(
+        &self,
+        context: &RequestContext,
+        uuid: String,
+        query_id: String,
+    ) -> FieldResult<bool> {
+        require_capability(
+            &context.capabilities,
+            &perspective_query_capability(vec![uuid.clone()]),
+        )?;
+        let perspective = get_perspective_with_uuid_field_error(&uuid)?;
+        Ok(perspective.cancel_prolog_query(&query_id).await)
+    }
+
+    async fn perspective_export(
+        &self,
+        context: &RequestContext,
+        uuid: String,
+    ) -> FieldResult<String> {
+        require_capability(
+            &context.capabilities,
+            &perspective_query_capability(vec![uuid.clone()]),
+        )?;
+        Ok(export_perspective(&uuid).await.map_err(field_error)?)
+    }
+
+    async fn perspective_import(
+        &self,
+        context: &RequestContext,
+        archive: String,
+        allow_invalid_signature: Option<bool>,
+    ) -> FieldResult<PerspectiveImportResult> {
+        require_capability(&context.capabilities, &PERSPECTIVE_CREATE_CAPABILITY)?;
+        Ok(
+            import_perspective(&archive, allow_invalid_signature.unwrap_or(false))
+                .await
+                .map_err(field_error)?,
+        )
+    }
+
     async fn perspective_add_link(
         &self,
         context: &RequestContext,
@@ -717,7 +989,7 @@ impl Mutation {
         uuid: String,
         status: Option<String>,
     ) -> FieldResult<DecoratedLinkExpression> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
@@ -725,7 +997,27 @@ impl Mutation {
         let mut perspective = get_perspective_with_uuid_field_error(&uuid)?;
         Ok(perspective
             .add_link(link.into(), link_status_from_input(status)?)
-            .await?)
+            .await
+            .map_err(field_error)?)
+    }
+
+    async fn perspective_add_encrypted_link(
+        &self,
+        context: &RequestContext,
+        link: LinkInput,
+        uuid: String,
+        recipients: Vec<String>,
+    ) -> FieldResult<DecoratedLinkExpression> {
+        require_capability(
+            &context.capabilities,
+            &perspective_update_capability(vec![uuid.clone()]),
+        )?;
+
+        let mut perspective = get_perspective_with_uuid_field_error(&uuid)?;
+        Ok(perspective
+            .add_encrypted_link(link.into(), recipients)
+            .await
+            .map_err(field_error)?)
     }
 
     async fn perspective_add_link_expression(
@@ -735,15 +1027,16 @@ impl Mutation {
         uuid: String,
         status: Option<String>,
     ) -> FieldResult<DecoratedLinkExpression> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
         let mut perspective = get_perspective_with_uuid_field_error(&uuid)?;
-        let link = crate::types::LinkExpression::try_from(link)?;
+        let link = crate::types::LinkExpression::try_from(link).map_err(field_error)?;
         Ok(perspective
             .add_link_expression(link, link_status_from_input(status)?)
-            .await?)
+            .await
+            .map_err(field_error)?)
     }
 
     async fn perspective_add_links(
@@ -753,7 +1046,7 @@ impl Mutation {
         uuid: String,
         status: Option<String>,
     ) -> FieldResult<Vec<DecoratedLinkExpression>> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
@@ -763,7 +1056,8 @@ impl Mutation {
                 links.into_iter().map(|l| l.into()).collect(),
                 link_status_from_input(status)?,
             )
-            .await?)
+            .await
+            .map_err(field_error)?)
     }
 
     async fn perspective_link_mutations(
@@ -773,14 +1067,15 @@ impl Mutation {
         uuid: String,
         status: Option<String>,
     ) -> FieldResult<DecoratedPerspectiveDiff> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
         let mut perspective = get_perspective_with_uuid_field_error(&uuid)?;
         Ok(perspective
             .link_mutations(mutations, link_status_from_input(status)?)
-            .await?)
+            .await
+            .map_err(field_error)?)
     }
 
     async fn perspective_publish_snapshot(
@@ -788,7 +1083,7 @@ impl Mutation {
         context: &RequestContext,
         uuid: String,
     ) -> FieldResult<String> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
@@ -800,7 +1095,7 @@ impl Mutation {
         context: &RequestContext,
         uuid: String,
     ) -> FieldResult<bool> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_delete_capability(vec![uuid.clone()]),
         )?;
@@ -813,13 +1108,13 @@ impl Mutation {
         link: LinkExpressionInput,
         uuid: String,
     ) -> FieldResult<bool> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
         let mut perspective = get_perspective_with_uuid_field_error(&uuid)?;
-        let link = crate::types::LinkExpression::try_from(link)?;
-        perspective.remove_link(link).await?;
+        let link = crate::types::LinkExpression::try_from(link).map_err(field_error)?;
+        perspective.remove_link(link).await.map_err(field_error)?;
         Ok(true)
     }
 
@@ -829,7 +1124,7 @@ impl Mutation {
         links: Vec<LinkExpressionInput>,
         uuid: String,
     ) -> FieldResult<Vec<DecoratedLinkExpression>> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
@@ -837,25 +1132,60 @@ impl Mutation {
         let links = links
             .into_iter()
             .map(LinkExpression::try_from)
-            .collect::<Result<Vec<_>, _>>()?;
-        let removed_links = perspective.remove_links(links).await?;
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(field_error)?;
+        let removed_links = perspective.remove_links(links).await.map_err(field_error)?;
         Ok(removed_links)
     }
 
+    /// Commits pending diffs right away. A failed commit shows up as
+    /// `lastError` of the returned status.
+    async fn perspective_retry_sync(
+        &self,
+        context: &RequestContext,
+        uuid: String,
+    ) -> FieldResult<PerspectiveSyncStatus> {
+        require_capability(
+            &context.capabilities,
+            &perspective_update_capability(vec![uuid.clone()]),
+        )?;
+        let perspective = get_perspective_with_uuid_field_error(&uuid)?;
+        perspective.retry_sync().await.map_err(field_error)?;
+        Ok(perspective.sync_status().await.map_err(field_error)?)
+    }
+
+    /// Reverts a diff this agent made, see `perspectiveHistory`
+    async fn perspective_undo(
+        &self,
+        context: &RequestContext,
+        uuid: String,
+        diff_id: String,
+    ) -> FieldResult<DecoratedPerspectiveDiff> {
+        require_capability(
+            &context.capabilities,
+            &perspective_update_capability(vec![uuid.clone()]),
+        )?;
+        let diff_id = diff_id.parse::<u64>().map_err(|_| {
+            ExecutorError::InvalidInput(format!("Invalid diff id: {}", diff_id)).field_error()
+        })?;
+        let mut perspective = get_perspective_with_uuid_field_error(&uuid)?;
+        Ok(perspective.undo(diff_id).await.map_err(field_error)?)
+    }
+
     async fn perspective_update(
         &self,
         context: &RequestContext,
         name: String,
         uuid: String,
     ) -> FieldResult<PerspectiveHandle> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
         let perspective = get_perspective_with_uuid_field_error(&uuid)?;
         let mut handle = perspective.persisted.lock().await.clone();
         handle.name = Some(name);
-        update_perspective(&handle).await?;
+        update_perspective(&handle).await.map_err(field_error)?;
         Ok(handle)
     }
 
@@ -866,7 +1196,7 @@ impl Mutation {
         old_link: LinkExpressionInput,
         uuid: String,
     ) -> FieldResult<DecoratedLinkExpression> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
@@ -876,7 +1206,8 @@ impl Mutation {
                 LinkExpression::from_input_without_proof(old_link),
                 new_link.into(),
             )
-            .await?)
+            .await
+            .map_err(field_error)?)
     }
 
     async fn perspective_add_sdna(
@@ -887,14 +1218,21 @@ impl Mutation {
         sdna_code: String,
         sdna_type: String,
     ) -> FieldResult<bool> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
         let mut perspective = get_perspective_with_uuid_field_error(&uuid)?;
-        let sdna_type = SdnaType::from_string(&sdna_type)
-            .map_err(|e| FieldError::new(e, graphql_value!({ "invalid_sdna_type": sdna_type })))?;
-        perspective.add_sdna(name, sdna_code, sdna_type).await?;
+        let sdna_type = SdnaType::from_string(&sdna_type).map_err(|e| {
+            FieldError::new(
+                e,
+                graphql_value!({ "code": "INVALID_INPUT", "invalid_sdna_type": sdna_type }),
+            )
+        })?;
+        perspective
+            .add_sdna(name, sdna_code, sdna_type)
+            .await
+            .map_err(field_error)?;
         Ok(true)
     }
 
@@ -906,16 +1244,24 @@ impl Mutation {
         expression: String,
         parameters: Option<String>,
     ) -> FieldResult<bool> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
 
-        let commands: Vec<Command> = serde_json::from_str(&commands)
-            .map_err(|e| FieldError::new(e, graphql_value!({ "invalid_commands": commands })))?;
+        let commands: Vec<Command> = serde_json::from_str(&commands).map_err(|e| {
+            FieldError::new(
+                e,
+                graphql_value!({ "code": "INVALID_INPUT", "invalid_commands": commands }),
+            )
+        })?;
         let parameters: Vec<Parameter> = if let Some(p) = parameters {
-            serde_json::from_str(&p)
-                .map_err(|e| FieldError::new(e, graphql_value!({ "invalid_parameters": p })))?
+            serde_json::from_str(&p).map_err(|e| {
+                FieldError::new(
+                    e,
+                    graphql_value!({ "code": "INVALID_INPUT", "invalid_parameters": p }),
+                )
+            })?
         } else {
             Vec::new()
         };
@@ -923,7 +1269,8 @@ impl Mutation {
         let mut perspective = get_perspective_with_uuid_field_error(&uuid)?;
         perspective
             .execute_commands(commands, expression, parameters)
-            .await?;
+            .await
+            .map_err(field_error)?;
         Ok(true)
     }
 
@@ -934,7 +1281,7 @@ impl Mutation {
         subject_class: String,
         expression_address: String,
     ) -> FieldResult<bool> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
@@ -943,7 +1290,7 @@ impl Mutation {
             serde_json::from_str(&subject_class).map_err(|e| {
                 FieldError::new(
                     e,
-                    graphql_value!({ "invalid_subject_class": subject_class }),
+                    graphql_value!({ "code": "INVALID_INPUT", "invalid_subject_class": subject_class }),
                 )
             })?;
 
@@ -951,7 +1298,8 @@ impl Mutation {
 
         perspective
             .create_subject(subject_class, expression_address)
-            .await?;
+            .await
+            .map_err(field_error)?;
         Ok(true)
     }
 
@@ -962,7 +1310,7 @@ impl Mutation {
         subject_class: String,
         expression_address: String,
     ) -> FieldResult<String> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_update_capability(vec![uuid.clone()]),
         )?;
@@ -971,7 +1319,7 @@ impl Mutation {
             serde_json::from_str(&subject_class).map_err(|e| {
                 FieldError::new(
                     e,
-                    graphql_value!({ "invalid_subject_class": subject_class }),
+                    graphql_value!({ "code": "INVALID_INPUT", "invalid_subject_class": subject_class }),
                 )
             })?;
 
@@ -979,7 +1327,8 @@ impl Mutation {
 
         let result = perspective
             .get_subject_data(subject_class, expression_address)
-            .await?;
+            .await
+            .map_err(field_error)?;
         Ok(result)
     }
 
@@ -988,7 +1337,7 @@ impl Mutation {
         context: &RequestContext,
         dids: Vec<String>,
     ) -> FieldResult<Vec<String>> {
-        check_capability(&context.capabilities, &RUNTIME_FRIENDS_CREATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &RUNTIME_FRIENDS_CREATE_CAPABILITY)?;
         let cloned_did = dids.clone();
         let friends = RuntimeService::with_global_instance(|runtime_service| {
             runtime_service.add_friend(dids);
@@ -997,7 +1346,7 @@ impl Mutation {
 
         // TODO: remove this when language controller is moved.
         let mut js = context.js_handle.clone();
-        let dids_json = serde_json::to_string(&cloned_did)?;
+        let dids_json = serde_json::to_string(&cloned_did).map_err(internal_error)?;
         let script = format!(
             r#"JSON.stringify(
             await core.callResolver(
@@ -1007,9 +1356,10 @@ impl Mutation {
             ))"#,
             dids_json,
         );
-        let result = js.execute(script).await?;
+        let result = js.execute(script).await.map_err(field_error)?;
         // TODO: what is this for? result is not used.. should this error if it can't be parsed?
-        let result: JsResultType<Vec<String>> = serde_json::from_str(&result)?;
+        let result: JsResultType<Vec<String>> =
+            serde_json::from_str(&result).map_err(internal_error)?;
         result.get_graphql_result()?;
 
         Ok(friends)
@@ -1020,7 +1370,7 @@ impl Mutation {
         context: &RequestContext,
         addresses: Vec<String>,
     ) -> FieldResult<Vec<String>> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &RUNTIME_KNOWN_LINK_LANGUAGES_CREATE_CAPABILITY,
         )?;
@@ -1038,7 +1388,7 @@ impl Mutation {
         did: String,
         message: PerspectiveInput,
     ) -> FieldResult<bool> {
-        check_capability(&context.capabilities, &RUNTIME_MESSAGES_CREATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &RUNTIME_MESSAGES_CREATE_CAPABILITY)?;
 
         let friends =
             RuntimeService::with_global_instance(|runtime_service| runtime_service.get_friends());
@@ -1050,7 +1400,7 @@ impl Mutation {
         }
 
         let mut js = context.js_handle.clone();
-        let message_json = serde_json::to_string(&message)?;
+        let message_json = serde_json::to_string(&message).map_err(internal_error)?;
         let script = format!(
             r#"JSON.stringify(
             await core.callResolver(
@@ -1060,8 +1410,8 @@ impl Mutation {
             ))"#,
             did, message_json,
         );
-        let result = js.execute(script).await?;
-        let result: JsResultType<bool> = serde_json::from_str(&result)?;
+        let result = js.execute(script).await.map_err(field_error)?;
+        let result: JsResultType<bool> = serde_json::from_str(&result).map_err(internal_error)?;
         let get_graphql_result = result.get_graphql_result()?;
         Ok(get_graphql_result)
     }
@@ -1071,12 +1421,12 @@ impl Mutation {
         context: &RequestContext,
         agent_infos: String,
     ) -> FieldResult<bool> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &RUNTIME_HC_AGENT_INFO_CREATE_CAPABILITY,
         )?;
 
-        let agent_infos = agent_infos_from_str(agent_infos.as_str())?;
+        let agent_infos = agent_infos_from_str(agent_infos.as_str()).map_err(field_error)?;
         log::info!("Adding HC agent infos: {:?}", agent_infos);
 
         get_holochain_service()
@@ -1086,7 +1436,8 @@ impl Mutation {
             .map_err(|e| {
                 log::error!("Failed to add agent infos: {:?}", e);
                 e
-            })?;
+            })
+            .map_err(field_error)?;
 
         Ok(true)
     }
@@ -1102,7 +1453,7 @@ impl Mutation {
     }
 
     async fn runtime_quit(&self, context: &RequestContext) -> FieldResult<bool> {
-        check_capability(&context.capabilities, &RUNTIME_QUIT_CAPABILITY)?;
+        require_capability(&context.capabilities, &RUNTIME_QUIT_CAPABILITY)?;
         std::process::exit(0);
     }
 
@@ -1111,7 +1462,7 @@ impl Mutation {
         context: &RequestContext,
         dids: Vec<String>,
     ) -> FieldResult<Vec<String>> {
-        check_capability(&context.capabilities, &RUNTIME_FRIENDS_DELETE_CAPABILITY)?;
+        require_capability(&context.capabilities, &RUNTIME_FRIENDS_DELETE_CAPABILITY)?;
 
         RuntimeService::with_global_instance(|runtime_service| {
             runtime_service.remove_friend(dids.clone());
@@ -1125,7 +1476,7 @@ impl Mutation {
         context: &RequestContext,
         addresses: Vec<String>,
     ) -> FieldResult<Vec<String>> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &RUNTIME_KNOWN_LINK_LANGUAGES_DELETE_CAPABILITY,
         )?;
@@ -1142,9 +1493,9 @@ impl Mutation {
         context: &RequestContext,
         status: PerspectiveInput,
     ) -> FieldResult<bool> {
-        check_capability(&context.capabilities, &RUNTIME_MY_STATUS_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &RUNTIME_MY_STATUS_UPDATE_CAPABILITY)?;
         let mut js = context.js_handle.clone();
-        let status_json = serde_json::to_string(&status)?;
+        let status_json = serde_json::to_string(&status).map_err(internal_error)?;
         let script = format!(
             r#"JSON.stringify(
             await core.callResolver(
@@ -1154,8 +1505,8 @@ impl Mutation {
             ))"#,
             status_json,
         );
-        let result = js.execute(script).await?;
-        let result: JsResultType<bool> = serde_json::from_str(&result)?;
+        let result = js.execute(script).await.map_err(field_error)?;
+        let result: JsResultType<bool> = serde_json::from_str(&result).map_err(internal_error)?;
         result.get_graphql_result()
     }
 
@@ -1164,8 +1515,10 @@ impl Mutation {
         context: &RequestContext,
         notification: NotificationInput,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
-        Ok(RuntimeService::request_install_notification(notification).await?)
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        Ok(RuntimeService::request_install_notification(notification)
+            .await
+            .map_err(field_error)?)
     }
 
     async fn runtime_update_notification(
@@ -1174,11 +1527,12 @@ impl Mutation {
         id: String,
         notification: NotificationInput,
     ) -> FieldResult<bool> {
-        check_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
 
         let notification = Notification::from_input_and_id(id.clone(), notification);
 
-        Ad4mDb::with_global_instance(|db| db.update_notification(id, &notification))?;
+        Ad4mDb::with_global_instance(|db| db.update_notification(id, &notification))
+            .map_err(field_error)?;
 
         Ok(true)
     }
@@ -1188,8 +1542,8 @@ impl Mutation {
         context: &RequestContext,
         id: String,
     ) -> FieldResult<bool> {
-        check_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
-        Ad4mDb::with_global_instance(|db| db.remove_notification(id))?;
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        Ad4mDb::with_global_instance(|db| db.remove_notification(id)).map_err(field_error)?;
         Ok(true)
     }
 
@@ -1198,15 +1552,18 @@ impl Mutation {
         context: &RequestContext,
         id: String,
     ) -> FieldResult<bool> {
-        check_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
         let mut notification = Ad4mDb::with_global_instance(|db| db.get_notification(id.clone()))
-            .map_err(|e| e.to_string())?
-            .ok_or("Notification with given id not found")?;
+            .map_err(field_error)?
+            .ok_or_else(|| {
+                ExecutorError::NotFound("Notification with given id not found".to_string())
+                    .field_error()
+            })?;
 
         notification.granted = true;
 
         Ad4mDb::with_global_instance(|db| db.update_notification(id, &notification))
-            .map_err(|e| e.to_string())?;
+            .map_err(field_error)?;
 
         Ok(true)
     }
@@ -1216,8 +1573,13 @@ impl Mutation {
         context: &RequestContext,
         model: ModelInput,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
-        let id = AIService::global_instance().await?.add_model(model).await?;
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        let id = AIService::global_instance()
+            .await
+            .map_err(field_error)?
+            .add_model(model)
+            .await
+            .map_err(field_error)?;
         Ok(id)
     }
 
@@ -1227,18 +1589,17 @@ impl Mutation {
         model_id: String,
         model: ModelInput,
     ) -> FieldResult<bool> {
-        check_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
 
         // Update the model using AIService
         AIService::global_instance()
-            .await?
+            .await
+            .map_err(field_error)?
             .update_model(model_id, model)
             .await
             .map_err(|e| {
-                FieldError::new(
-                    "Failed to update model",
-                    graphql_value!({ "error": e.to_string() }),
-                )
+                let message = format!("Failed to update model: {}", e);
+                FieldError::new(message, field_error(e).extensions().clone())
             })?;
 
         Ok(true)
@@ -1249,18 +1610,17 @@ impl Mutation {
         context: &RequestContext,
         model_id: String,
     ) -> FieldResult<bool> {
-        check_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
 
         // Remove the model using AIService
         AIService::global_instance()
-            .await?
+            .await
+            .map_err(field_error)?
             .remove_model(model_id)
             .await
             .map_err(|e| {
-                FieldError::new(
-                    "Failed to remove model",
-                    graphql_value!({ "error": e.to_string() }),
-                )
+                let message = format!("Failed to remove model: {}", e);
+                FieldError::new(message, field_error(e).extensions().clone())
             })?;
 
         Ok(true)
@@ -1272,21 +1632,22 @@ impl Mutation {
         model_type: ModelType,
         model_id: String,
     ) -> FieldResult<bool> {
-        check_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
 
         let maybe_model = Ad4mDb::with_global_instance(|db| db.get_model(model_id.clone()))
-            .map_err(|e| e.to_string())?;
+            .map_err(field_error)?;
         if maybe_model.is_none() {
-            return Err(FieldError::new(
-                "Model not found",
-                graphql_value!({ "model_id": model_id }),
-            ));
+            return Err(
+                ExecutorError::NotFound(format!("Model not found: {}", model_id)).field_error(),
+            );
         };
 
         AIService::global_instance()
-            .await?
+            .await
+            .map_err(field_error)?
             .set_default_model(model_type, model_id)
-            .await?;
+            .await
+            .map_err(field_error)?;
 
         Ok(true)
     }
@@ -1296,11 +1657,13 @@ impl Mutation {
         context: &RequestContext,
         task: AITaskInput,
     ) -> FieldResult<AITask> {
-        check_capability(&context.capabilities, &AI_CREATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AI_CREATE_CAPABILITY)?;
         Ok(AIService::global_instance()
-            .await?
+            .await
+            .map_err(field_error)?
             .add_task(task.clone())
-            .await?)
+            .await
+            .map_err(field_error)?)
     }
 
     async fn ai_remove_task(
@@ -1308,21 +1671,21 @@ impl Mutation {
         context: &RequestContext,
         task_id: String,
     ) -> FieldResult<AITask> {
-        check_capability(&context.capabilities, &AI_DELETE_CAPABILITY)?;
-        if let Some(task) = AIService::get_tasks()?
+        require_capability(&context.capabilities, &AI_DELETE_CAPABILITY)?;
+        if let Some(task) = AIService::get_tasks()
+            .map_err(field_error)?
             .into_iter()
             .find(|t| t.task_id == task_id)
         {
             AIService::global_instance()
-                .await?
+                .await
+                .map_err(field_error)?
                 .delete_task(task_id.clone())
-                .await?;
+                .await
+                .map_err(field_error)?;
             Ok(task)
         } else {
-            Err(FieldError::new(
-                "Task not found",
-                graphql_value!({ "task_id": task_id }),
-            ))
+            Err(ExecutorError::NotFound(format!("Task not found: {}", task_id)).field_error())
         }
     }
 
@@ -1332,13 +1695,15 @@ impl Mutation {
         task_id: String,
         task: AITaskInput,
     ) -> FieldResult<AITask> {
-        check_capability(&context.capabilities, &AI_UPDATE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AI_UPDATE_CAPABILITY)?;
         let mut task: AITask = task.into();
         task.task_id = task_id;
         Ok(AIService::global_instance()
-            .await?
+            .await
+            .map_err(field_error)?
             .update_task(task.clone())
-            .await?)
+            .await
+            .map_err(field_error)?)
     }
 
     async fn ai_prompt(
@@ -1347,11 +1712,94 @@ impl Mutation {
         task_id: String,
         prompt: String,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &AI_PROMPT_CAPABILITY)?;
+        require_capability(&context.capabilities, &AI_PROMPT_CAPABILITY)?;
         Ok(AIService::global_instance()
-            .await?
+            .await
+            .map_err(field_error)?
             .prompt(task_id, prompt)
-            .await?)
+            .await
+            .map_err(field_error)?)
+    }
+
+    /// Like `aiPrompt` but returns right away with a stream id.
+    /// The generated text is delivered by the `aiPromptTokens` subscription.
+    async fn ai_prompt_stream(
+        &self,
+        context: &RequestContext,
+        task_id: String,
+        prompt: String,
+    ) -> FieldResult<String> {
+        require_capability(&context.capabilities, &AI_PROMPT_CAPABILITY)?;
+        AIService::global_instance()
+            .await
+            .map_err(field_error)?
+            .prompt_stream(task_id, prompt)
+            .await
+            .map_err(field_error)
+    }
+
+    async fn ai_cancel_prompt_stream(
+        &self,
+        context: &RequestContext,
+        stream_id: String,
+    ) -> FieldResult<bool> {
+        require_capability(&context.capabilities, &AI_PROMPT_CAPABILITY)?;
+        AIService::global_instance()
+            .await
+            .map_err(field_error)?
+            .cancel_prompt_stream(&stream_id)
+            .await
+            .map_err(field_error)?;
+        Ok(true)
+    }
+
+    async fn ai_create_session(
+        &self,
+        context: &RequestContext,
+        session: AISessionInput,
+    ) -> FieldResult<AISession> {
+        require_capability(&context.capabilities, &AI_CREATE_CAPABILITY)?;
+        if let Some(uuid) = &session.perspective_uuid {
+            require_capability(
+                &context.capabilities,
+                &perspective_update_capability(vec![uuid.clone()]),
+            )?;
+        }
+        AIService::global_instance()
+            .await
+            .map_err(field_error)?
+            .create_session(session)
+            .map_err(field_error)
+    }
+
+    /// Prompts the task of a session with the session's history as context
+    async fn ai_session_prompt(
+        &self,
+        context: &RequestContext,
+        session_id: String,
+        prompt: String,
+    ) -> FieldResult<String> {
+        require_capability(&context.capabilities, &AI_PROMPT_CAPABILITY)?;
+        AIService::global_instance()
+            .await
+            .map_err(field_error)?
+            .session_prompt(&session_id, prompt)
+            .await
+            .map_err(field_error)
+    }
+
+    async fn ai_delete_session(
+        &self,
+        context: &RequestContext,
+        session_id: String,
+    ) -> FieldResult<bool> {
+        require_capability(&context.capabilities, &AI_DELETE_CAPABILITY)?;
+        AIService::global_instance()
+            .await
+            .map_err(field_error)?
+            .delete_session(&session_id)
+            .map_err(field_error)?;
+        Ok(true)
     }
 
     async fn ai_embed(
@@ -1360,13 +1808,15 @@ impl Mutation {
         model_id: String,
         text: String,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &AI_PROMPT_CAPABILITY)?;
+        require_capability(&context.capabilities, &AI_PROMPT_CAPABILITY)?;
         let vector = AIService::global_instance()
-            .await?
+            .await
+            .map_err(field_error)?
             .embed(model_id, text)
-            .await?;
+            .await
+            .map_err(field_error)?;
         let json_string = serde_json::to_string(&vector)
-            .map_err(|e| FieldError::from(format!("Failed to serialize vector: {}", e)))?;
+            .map_err(|e| internal_error(format!("Failed to serialize vector: {}", e)))?;
 
         // Compress the JSON string using zlib compression
         let compressed_bytes = deflate::deflate_bytes_zlib(json_string.as_bytes());
@@ -1382,11 +1832,13 @@ impl Mutation {
         context: &RequestContext,
         model_id: String,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &AI_TRANSCRIBE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AI_TRANSCRIBE_CAPABILITY)?;
         Ok(AIService::global_instance()
-            .await?
+            .await
+            .map_err(field_error)?
             .open_transcription_stream(model_id)
-            .await?)
+            .await
+            .map_err(field_error)?)
     }
 
     // note: f32 does not implement IsInputType, so I'm taking f64 here
@@ -1396,25 +1848,64 @@ impl Mutation {
         stream_id: String,
         audio: Vec<f64>,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &AI_TRANSCRIBE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AI_TRANSCRIBE_CAPABILITY)?;
         let audio_f32: Vec<f32> = audio.into_iter().map(|x| x as f32).collect();
         AIService::global_instance()
-            .await?
+            .await
+            .map_err(field_error)?
             .feed_transcription_stream(&stream_id, audio_f32)
-            .await?;
+            .await
+            .map_err(field_error)?;
         Ok(String::from("true"))
     }
 
+    /// Transcribes a whole recording, given either as 16 kHz mono samples
+    /// or as a base64 encoded WAV file
+    async fn ai_transcribe_audio(
+        &self,
+        context: &RequestContext,
+        model_id: String,
+        samples: Option<Vec<f64>>,
+        wav_base64: Option<String>,
+    ) -> FieldResult<Vec<TranscriptionSegment>> {
+        require_capability(&context.capabilities, &AI_TRANSCRIBE_CAPABILITY)?;
+        let audio = match (samples, wav_base64) {
+            (Some(samples), None) => {
+                TranscriptionAudio::Samples(samples.into_iter().map(|x| x as f32).collect())
+            }
+            (None, Some(wav_base64)) => {
+                TranscriptionAudio::Wav(BASE64_STANDARD.decode(wav_base64).map_err(|e| {
+                    ExecutorError::InvalidInput(format!("wavBase64 is not valid base64: {}", e))
+                        .field_error()
+                })?)
+            }
+            _ => {
+                return Err(ExecutorError::InvalidInput(
+                    "Exactly one of samples and wavBase64 is required".to_string(),
+                )
+                .field_error())
+            }
+        };
+        Ok(AIService::global_instance()
+            .await
+            .map_err(field_error)?
+            .transcribe_audio(model_id, audio)
+            .await
+            .map_err(field_error)?)
+    }
+
     async fn ai_close_transcription_stream(
         &self,
         context: &RequestContext,
         stream_id: String,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &AI_TRANSCRIBE_CAPABILITY)?;
+        require_capability(&context.capabilities, &AI_TRANSCRIBE_CAPABILITY)?;
         AIService::global_instance()
-            .await?
+            .await
+            .map_err(field_error)?
             .close_transcription_stream(&stream_id)
-            .await?;
+            .await
+            .map_err(field_error)?;
         Ok(String::from("true"))
     }
 }
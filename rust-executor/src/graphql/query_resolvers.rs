@@ -1,31 +1,45 @@
 #![allow(non_snake_case)]
 use super::graphql_types::*;
-use crate::agent::{capabilities::*, signatures};
+use super::{get_perspective_with_uuid_field_error, require_capability};
+use crate::agent::{self, capabilities::*, signatures};
 use crate::ai_service::AIService;
-use crate::types::{AITask, ModelType};
+use crate::errors::{field_error, ExecutorError};
+use crate::types::{AIMessage, AISession, AITask, ModelType};
 use crate::{agent::AgentService, entanglement_service::get_entanglement_proofs};
 use crate::{
     db::Ad4mDb,
     holochain_service::get_holochain_service,
-    perspectives::{all_perspectives, get_perspective, utils::prolog_resolution_to_string},
-    runtime_service::RuntimeService,
-    types::{DecoratedLinkExpression, Model, Notification},
+    perspectives::{
+        all_perspectives, get_perspective, search::search_links, utils::prolog_resolution_to_string,
+    },
+    prolog_service::engine::PrologQueryOptions,
+    pubsub::{
+        get_global_pubsub, PERSPECTIVE_LINK_ADDED_TOPIC, PERSPECTIVE_LINK_REMOVED_TOPIC,
+        PERSPECTIVE_LINK_UPDATED_TOPIC,
+    },
+    runtime_service::{notification_webhooks::webhook_secret, RuntimeService},
+    types::{
+        DecoratedLinkExpression, KeyRotation, Model, Notification, NotificationDelivery,
+        PerspectiveSyncStatus,
+    },
+    wallet::Wallet,
 };
 use base64::prelude::*;
-use coasys_juniper::{graphql_object, FieldError, FieldResult, Value};
+use coasys_juniper::{graphql_object, FieldResult};
 use std::env;
+use std::time::Duration;
 
 pub struct Query;
 
 #[graphql_object(context = RequestContext)]
 impl Query {
     async fn agent(&self, context: &RequestContext) -> FieldResult<Agent> {
-        check_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
         AgentService::with_global_instance(|agent_service| {
             let mut agent = agent_service
                 .agent
                 .clone()
-                .ok_or(FieldError::new("Agent not found", Value::null()))?;
+                .ok_or(ExecutorError::AgentNotInitialized.field_error())?;
 
             if agent.perspective.is_some() {
                 agent.perspective.as_mut().unwrap().verify_link_signatures();
@@ -41,7 +55,7 @@ impl Query {
         context: &RequestContext,
         did: String,
     ) -> FieldResult<Option<Agent>> {
-        check_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
         let agent_instance = AgentService::global_instance();
         let did_match = {
             let agent_service = agent_instance.lock().expect("agent lock");
@@ -62,7 +76,7 @@ impl Query {
                 ))
                 .await?;
             let result: JsResultType<Option<Agent>> = serde_json::from_str(&result)?;
-            result.get_graphql_result()
+            Ok(result.get_graphql_result()?)
         } else {
             let agent_service = agent_instance.lock().expect("agent lock");
             let agent_ref: &AgentService = agent_service.as_ref().expect("agent instance");
@@ -71,7 +85,7 @@ impl Query {
     }
 
     async fn agent_get_apps(&self, context: &RequestContext) -> FieldResult<Vec<Apps>> {
-        check_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
         Ok(apps_map::get_apps())
     }
 
@@ -83,19 +97,50 @@ impl Query {
         Ok(proofs)
     }
 
+    async fn agent_keys(&self, context: &RequestContext) -> FieldResult<Vec<AgentKey>> {
+        require_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
+        let wallet = Wallet::instance();
+        let wallet_lock = wallet.lock().expect("wallet lock");
+        let wallet_ref = wallet_lock.as_ref().expect("wallet instance");
+        let mut keys: Vec<AgentKey> = wallet_ref
+            .list_keys()
+            .into_iter()
+            .map(AgentKey::from)
+            .collect();
+        keys.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        Ok(keys)
+    }
+
+    async fn agent_key_rotations(
+        &self,
+        context: &RequestContext,
+        did: String,
+    ) -> FieldResult<Vec<KeyRotation>> {
+        require_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
+        Ok(signatures::key_rotations(&did))
+    }
+
+    async fn agent_devices(&self, context: &RequestContext) -> FieldResult<Vec<AgentDevice>> {
+        require_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
+        Ok(signatures::device_keys(&agent::did())
+            .into_iter()
+            .map(AgentDevice::from)
+            .collect())
+    }
+
     async fn agent_is_locked(&self, _context: &RequestContext) -> FieldResult<bool> {
         AgentService::with_global_instance(|agent_service| {
             let _agent = agent_service
                 .agent
                 .clone()
-                .ok_or(FieldError::new("Agent not found", Value::null()))?;
+                .ok_or(ExecutorError::AgentNotInitialized.field_error())?;
 
             Ok(!agent_service.is_unlocked())
         })
     }
 
     async fn agent_status(&self, context: &RequestContext) -> FieldResult<AgentStatus> {
-        check_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
 
         AgentService::with_global_instance(|agent_service| Ok(agent_service.dump()))
     }
@@ -105,7 +150,7 @@ impl Query {
         context: &RequestContext,
         url: String,
     ) -> FieldResult<Option<ExpressionRendered>> {
-        check_capability(&context.capabilities, &EXPRESSION_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &EXPRESSION_READ_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let result = js
             .execute(format!(
@@ -122,7 +167,7 @@ impl Query {
         context: &RequestContext,
         url: String,
     ) -> FieldResult<Vec<InteractionMeta>> {
-        check_capability(&context.capabilities, &EXPRESSION_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &EXPRESSION_READ_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let result = js
             .execute(format!(
@@ -144,7 +189,7 @@ impl Query {
             .map(|url| format!("\"{}\"", url))
             .collect::<Vec<String>>()
             .join(",");
-        check_capability(&context.capabilities, &EXPRESSION_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &EXPRESSION_READ_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let result = js
             .execute(format!(
@@ -161,7 +206,7 @@ impl Query {
         context: &RequestContext,
         url: String,
     ) -> FieldResult<Option<String>> {
-        check_capability(&context.capabilities, &EXPRESSION_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &EXPRESSION_READ_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let result = js
             .execute(format!(
@@ -174,7 +219,7 @@ impl Query {
     }
 
     async fn get_trusted_agents(&self, context: &RequestContext) -> FieldResult<Vec<String>> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &RUNTIME_TRUSTED_AGENTS_READ_CAPABILITY,
         )?;
@@ -190,7 +235,7 @@ impl Query {
         context: &RequestContext,
         address: String,
     ) -> FieldResult<LanguageHandle> {
-        check_capability(&context.capabilities, &LANGUAGE_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &LANGUAGE_READ_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let result = js
             .execute(format!(
@@ -207,7 +252,7 @@ impl Query {
         context: &RequestContext,
         address: String,
     ) -> FieldResult<LanguageMeta> {
-        check_capability(&context.capabilities, &LANGUAGE_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &LANGUAGE_READ_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let result = js
             .execute(format!(
@@ -224,7 +269,7 @@ impl Query {
         context: &RequestContext,
         address: String,
     ) -> FieldResult<String> {
-        check_capability(&context.capabilities, &LANGUAGE_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &LANGUAGE_READ_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let result = js
             .execute(format!(
@@ -242,7 +287,7 @@ impl Query {
         filter: Option<String>,
     ) -> FieldResult<Vec<LanguageHandle>> {
         let filter_string = filter.map_or("null".to_string(), |f| f.to_string());
-        check_capability(&context.capabilities, &LANGUAGE_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &LANGUAGE_READ_CAPABILITY)?;
         let mut js = context.js_handle.clone();
         let result = js
             .execute(format!(
@@ -260,12 +305,8 @@ impl Query {
         #[allow(non_snake_case)] perspectiveUUID: String,
     ) -> FieldResult<bool> {
         let uuid = perspectiveUUID;
-        check_capability(&context.capabilities, &NEIGHBOURHOOD_READ_CAPABILITY)?;
-        Ok(get_perspective(&uuid)
-            .ok_or(FieldError::from(format!(
-                "No perspective found with uuid {}",
-                uuid
-            )))?
+        require_capability(&context.capabilities, &NEIGHBOURHOOD_READ_CAPABILITY)?;
+        Ok(get_perspective_with_uuid_field_error(&uuid)?
             .has_telepresence_adapter()
             .await)
     }
@@ -276,15 +317,11 @@ impl Query {
         #[allow(non_snake_case)] perspectiveUUID: String,
     ) -> FieldResult<Vec<OnlineAgent>> {
         let uuid = perspectiveUUID;
-        check_capability(&context.capabilities, &NEIGHBOURHOOD_READ_CAPABILITY)?;
-        get_perspective(&uuid)
-            .ok_or(FieldError::from(format!(
-                "No perspective found with uuid {}",
-                uuid
-            )))?
+        require_capability(&context.capabilities, &NEIGHBOURHOOD_READ_CAPABILITY)?;
+        get_perspective_with_uuid_field_error(&uuid)?
             .online_agents()
             .await
-            .map_err(|e| FieldError::from(e.to_string()))
+            .map_err(field_error)
     }
 
     async fn neighbourhood_other_agents(
@@ -293,15 +330,11 @@ impl Query {
         #[allow(non_snake_case)] perspectiveUUID: String,
     ) -> FieldResult<Vec<String>> {
         let uuid = perspectiveUUID;
-        check_capability(&context.capabilities, &NEIGHBOURHOOD_READ_CAPABILITY)?;
-        get_perspective(&uuid)
-            .ok_or(FieldError::from(format!(
-                "No perspective found with uuid {}",
-                uuid
-            )))?
+        require_capability(&context.capabilities, &NEIGHBOURHOOD_READ_CAPABILITY)?;
+        get_perspective_with_uuid_field_error(&uuid)?
             .others()
             .await
-            .map_err(|e| FieldError::from(e.to_string()))
+            .map_err(field_error)
     }
 
     async fn perspective(
@@ -309,7 +342,7 @@ impl Query {
         context: &RequestContext,
         uuid: String,
     ) -> FieldResult<Option<PerspectiveHandle>> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_query_capability(vec![uuid.clone()]),
         )?;
@@ -327,18 +360,48 @@ impl Query {
         query: LinkQuery,
         uuid: String,
     ) -> FieldResult<Vec<DecoratedLinkExpression>> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_query_capability(vec![uuid.clone()]),
         )?;
 
-        Ok(get_perspective(&uuid)
-            .ok_or(FieldError::from(format!(
-                "No perspective found with uuid {}",
-                uuid
-            )))?
-            .get_links(&query)
-            .await?)
+        Ok(get_perspective_with_uuid_field_error(&uuid)?
+            .get_links_with_cursors(&query)
+            .await
+            .map_err(field_error)?)
+    }
+
+    async fn perspective_prolog_engine_metrics(
+        &self,
+        context: &RequestContext,
+        uuid: String,
+    ) -> FieldResult<PrologEngineMetrics> {
+        require_capability(
+            &context.capabilities,
+            &perspective_query_capability(vec![uuid.clone()]),
+        )?;
+        let perspective = get_perspective_with_uuid_field_error(&uuid)?;
+        Ok(perspective
+            .prolog_engine_metrics()
+            .await
+            .map(|metrics| PrologEngineMetrics {
+                queue_depths: metrics.queue_depths.iter().map(|d| *d as i32).collect(),
+                queries_total: metrics.queries_total as f64,
+            })
+            .unwrap_or_default())
+    }
+
+    async fn perspective_link_event_sequences(
+        &self,
+        context: &RequestContext,
+    ) -> FieldResult<LinkEventSequences> {
+        require_capability(&context.capabilities, &PERSPECTIVE_SUBSCRIBE_CAPABILITY)?;
+        let pubsub = get_global_pubsub().await;
+        Ok(LinkEventSequences {
+            added: pubsub.last_sequence(&PERSPECTIVE_LINK_ADDED_TOPIC).await as f64,
+            removed: pubsub.last_sequence(&PERSPECTIVE_LINK_REMOVED_TOPIC).await as f64,
+            updated: pubsub.last_sequence(&PERSPECTIVE_LINK_UPDATED_TOPIC).await as f64,
+        })
     }
 
     async fn perspective_query_prolog(
@@ -346,21 +409,47 @@ impl Query {
         context: &RequestContext,
         query: String,
         uuid: String,
+        timeout_ms: Option<i32>,
+        inference_limit: Option<i32>,
+        query_id: Option<String>,
     ) -> FieldResult<String> {
-        check_capability(
+        require_capability(
+            &context.capabilities,
+            &perspective_query_capability(vec![uuid.clone()]),
+        )?;
+
+        // 0 (or less) turns a limit off
+        let mut options = PrologQueryOptions::default();
+        if let Some(timeout_ms) = timeout_ms {
+            options.timeout = (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms as u64));
+        }
+        if let Some(inference_limit) = inference_limit {
+            options.inference_limit = (inference_limit > 0).then_some(inference_limit as u64);
+        }
+
+        let resolution = get_perspective_with_uuid_field_error(&uuid)?
+            .prolog_query_with_options(query, options, query_id)
+            .await
+            .map_err(field_error)?;
+
+        Ok(prolog_resolution_to_string(resolution))
+    }
+
+    async fn perspective_search(
+        &self,
+        context: &RequestContext,
+        uuid: String,
+        text: String,
+        mode: Option<String>,
+        limit: Option<i32>,
+    ) -> FieldResult<Vec<LinkSearchResult>> {
+        require_capability(
             &context.capabilities,
             &perspective_query_capability(vec![uuid.clone()]),
         )?;
 
-        Ok(prolog_resolution_to_string(
-            get_perspective(&uuid)
-                .ok_or(FieldError::from(format!(
-                    "No perspective found with uuid {}",
-                    uuid
-                )))?
-                .prolog_query(query)
-                .await?,
-        ))
+        get_perspective_with_uuid_field_error(&uuid)?;
+        Ok(search_links(&uuid, &text, mode, limit).await?)
     }
 
     async fn perspective_snapshot(
@@ -368,24 +457,21 @@ impl Query {
         context: &RequestContext,
         uuid: String,
     ) -> FieldResult<Perspective> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_query_capability(vec![uuid.clone()]),
         )?;
 
-        let all_links = get_perspective(&uuid)
-            .ok_or(FieldError::from(format!(
-                "No perspective found with uuid {}",
-                uuid
-            )))?
+        let all_links = get_perspective_with_uuid_field_error(&uuid)?
             .get_links(&LinkQuery::default())
-            .await?;
+            .await
+            .map_err(field_error)?;
 
         Ok(Perspective { links: all_links })
     }
 
     async fn perspectives(&self, context: &RequestContext) -> FieldResult<Vec<PerspectiveHandle>> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &perspective_query_capability(vec!["*".into()]),
         )?;
@@ -398,12 +484,81 @@ impl Query {
         Ok(result)
     }
 
+    async fn perspective_sync_status(
+        &self,
+        context: &RequestContext,
+        uuid: String,
+    ) -> FieldResult<PerspectiveSyncStatus> {
+        require_capability(
+            &context.capabilities,
+            &perspective_query_capability(vec![uuid.clone()]),
+        )?;
+        let perspective = get_perspective_with_uuid_field_error(&uuid)?;
+        Ok(perspective.sync_status().await.map_err(field_error)?)
+    }
+
+    /// The links of a perspective as they were at `timestamp`
+    async fn perspective_links_at(
+        &self,
+        context: &RequestContext,
+        uuid: String,
+        timestamp: DateTime,
+    ) -> FieldResult<Vec<DecoratedLinkExpression>> {
+        require_capability(
+            &context.capabilities,
+            &perspective_query_capability(vec![uuid.clone()]),
+        )?;
+        let perspective = get_perspective_with_uuid_field_error(&uuid)?;
+        Ok(perspective
+            .get_links_at(timestamp.into())
+            .await
+            .map_err(field_error)?)
+    }
+
+    /// Diffs applied to a perspective, newest first
+    async fn perspective_history(
+        &self,
+        context: &RequestContext,
+        uuid: String,
+        since: Option<DateTime>,
+        limit: Option<i32>,
+    ) -> FieldResult<Vec<PerspectiveHistoryEntry>> {
+        require_capability(
+            &context.capabilities,
+            &perspective_query_capability(vec![uuid.clone()]),
+        )?;
+        let perspective = get_perspective_with_uuid_field_error(&uuid)?;
+        Ok(perspective
+            .history(since.map(Into::into), limit.map(|l| l.max(0) as usize))
+            .await
+            .map_err(field_error)?)
+    }
+
+    /// Sync status of all perspectives that are shared as neighbourhoods
+    async fn perspective_sync_statuses(
+        &self,
+        context: &RequestContext,
+    ) -> FieldResult<Vec<PerspectiveSyncStatus>> {
+        require_capability(
+            &context.capabilities,
+            &perspective_query_capability(vec!["*".into()]),
+        )?;
+
+        let mut result = Vec::new();
+        for p in all_perspectives().iter() {
+            if p.persisted.lock().await.shared_url.is_some() {
+                result.push(p.sync_status().await.map_err(field_error)?);
+            }
+        }
+        Ok(result)
+    }
+
     async fn runtime_friend_status(
         &self,
         context: &RequestContext,
         did: String,
     ) -> FieldResult<PerspectiveExpression> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &RUNTIME_FRIEND_STATUS_READ_CAPABILITY,
         )?;
@@ -430,7 +585,7 @@ impl Query {
     }
 
     async fn runtime_friends(&self, context: &RequestContext) -> FieldResult<Vec<String>> {
-        check_capability(&context.capabilities, &RUNTIME_FRIENDS_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &RUNTIME_FRIENDS_READ_CAPABILITY)?;
 
         RuntimeService::with_global_instance(|runtime_service| {
             let friends = runtime_service.get_friends();
@@ -439,7 +594,7 @@ impl Query {
     }
 
     async fn runtime_hc_agent_infos(&self, context: &RequestContext) -> FieldResult<String> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &RUNTIME_HC_AGENT_INFO_READ_CAPABILITY,
         )?;
@@ -462,7 +617,7 @@ impl Query {
             agent_service
                 .agent
                 .clone()
-                .ok_or(FieldError::new("Agent not found", Value::null()))?;
+                .ok_or(ExecutorError::AgentNotInitialized.field_error())?;
 
             Ok(RuntimeInfo {
                 is_initialized: agent_service.is_initialized(),
@@ -476,7 +631,7 @@ impl Query {
         &self,
         context: &RequestContext,
     ) -> FieldResult<Vec<String>> {
-        check_capability(
+        require_capability(
             &context.capabilities,
             &RUNTIME_KNOWN_LINK_LANGUAGES_READ_CAPABILITY,
         )?;
@@ -492,7 +647,7 @@ impl Query {
         context: &RequestContext,
         filter: Option<String>,
     ) -> FieldResult<Vec<PerspectiveExpression>> {
-        check_capability(&context.capabilities, &RUNTIME_MESSAGES_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &RUNTIME_MESSAGES_READ_CAPABILITY)?;
         let filter_str = filter
             .map(|val| format!(r#"{{ filter: "{}" }}"#, val))
             .unwrap_or_else(|| String::from("{ filter: null }"));
@@ -512,7 +667,7 @@ impl Query {
         context: &RequestContext,
         _filter: Option<String>,
     ) -> FieldResult<Vec<SentMessage>> {
-        check_capability(&context.capabilities, &RUNTIME_MESSAGES_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &RUNTIME_MESSAGES_READ_CAPABILITY)?;
 
         RuntimeService::with_global_instance(|runtime_service| {
             let outbox = runtime_service.get_outbox();
@@ -528,31 +683,44 @@ impl Query {
         _did_signing_key_id: String,
         signed_data: String,
     ) -> FieldResult<bool> {
-        check_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
-        signatures::verify_string_signed_by_did(&did, &data, &signed_data)
-            .map_err(|e| e.to_string())
-            .map_err(|e| coasys_juniper::FieldError::new(e, coasys_juniper::Value::Null))
+        require_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
+        signatures::verify_string_signed_by_did(&did, &data, &signed_data).map_err(field_error)
     }
 
     async fn runtime_notifications(
         &self,
         context: &RequestContext,
     ) -> FieldResult<Vec<Notification>> {
-        check_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
-        let notifications_result = Ad4mDb::with_global_instance(|db| db.get_notifications());
-        if let Err(e) = notifications_result {
-            return Err(FieldError::new(e.to_string(), Value::null()));
-        }
-        Ok(notifications_result.unwrap())
+        require_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
+        Ad4mDb::with_global_instance(|db| db.get_notifications()).map_err(field_error)
+    }
+
+    async fn runtime_notification_deliveries(
+        &self,
+        context: &RequestContext,
+        notification_id: String,
+        limit: Option<i32>,
+    ) -> FieldResult<Vec<NotificationDelivery>> {
+        require_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
+        let limit = limit.map(|l| l.max(0) as usize).unwrap_or(50);
+        Ok(Ad4mDb::with_global_instance(|db| {
+            db.get_notification_deliveries(&notification_id, limit)
+        })?)
+    }
+
+    /// Secret that the HMAC in the `X-Ad4m-Signature` header of webhook calls is keyed with
+    async fn runtime_notification_webhook_secret(
+        &self,
+        context: &RequestContext,
+        notification_id: String,
+    ) -> FieldResult<String> {
+        require_capability(&context.capabilities, &AGENT_UPDATE_CAPABILITY)?;
+        webhook_secret(&notification_id).map_err(field_error)
     }
 
     async fn ai_get_models(&self, context: &RequestContext) -> FieldResult<Vec<Model>> {
-        check_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
-        let models_result = Ad4mDb::with_global_instance(|db| db.get_models());
-        match models_result {
-            Ok(models) => Ok(models),
-            Err(e) => Err(FieldError::new(e.to_string(), Value::null())),
-        }
+        require_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
+        Ad4mDb::with_global_instance(|db| db.get_models()).map_err(field_error)
     }
 
     async fn ai_get_default_model(
@@ -560,26 +728,49 @@ impl Query {
         context: &RequestContext,
         model_type: ModelType,
     ) -> FieldResult<Option<Model>> {
-        check_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &AGENT_READ_CAPABILITY)?;
 
         let default_id = Ad4mDb::with_global_instance(|db| db.get_default_model(model_type))
-            .map_err(|e| FieldError::new(e.to_string(), Value::null()))?;
+            .map_err(field_error)?;
 
         Ok(if let Some(id) = default_id {
-            Ad4mDb::with_global_instance(|db| db.get_model(id))
-                .map_err(|e| FieldError::new(e.to_string(), Value::null()))?
+            Ad4mDb::with_global_instance(|db| db.get_model(id)).map_err(field_error)?
         } else {
             None
         })
     }
 
     async fn ai_tasks(&self, context: &RequestContext) -> FieldResult<Vec<AITask>> {
-        check_capability(&context.capabilities, &AI_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &AI_READ_CAPABILITY)?;
 
-        match AIService::get_tasks() {
-            Ok(tasks) => Ok(tasks),
-            Err(e) => Err(FieldError::new(e.to_string(), Value::null())),
-        }
+        AIService::get_tasks().map_err(field_error)
+    }
+
+    /// Conversation sessions, most recently used first
+    async fn ai_sessions(
+        &self,
+        context: &RequestContext,
+        task_id: Option<String>,
+    ) -> FieldResult<Vec<AISession>> {
+        require_capability(&context.capabilities, &AI_READ_CAPABILITY)?;
+        AIService::global_instance()
+            .await
+            .map_err(field_error)?
+            .get_sessions(task_id)
+            .map_err(field_error)
+    }
+
+    async fn ai_session_messages(
+        &self,
+        context: &RequestContext,
+        session_id: String,
+    ) -> FieldResult<Vec<AIMessage>> {
+        require_capability(&context.capabilities, &AI_READ_CAPABILITY)?;
+        AIService::global_instance()
+            .await
+            .map_err(field_error)?
+            .get_session_messages(&session_id)
+            .map_err(field_error)
     }
 
     async fn ai_model_loading_status(
@@ -587,11 +778,8 @@ impl Query {
         context: &RequestContext,
         model: String,
     ) -> FieldResult<AIModelLoadingStatus> {
-        check_capability(&context.capabilities, &AI_READ_CAPABILITY)?;
+        require_capability(&context.capabilities, &AI_READ_CAPABILITY)?;
 
-        match AIService::model_status(model).await {
-            Ok(status) => Ok(status),
-            Err(e) => Err(FieldError::new(e.to_string(), Value::null())),
-        }
+        AIService::model_status(model).await.map_err(field_error)
     }
 }
@@ -1,16 +1,18 @@
 #![allow(non_snake_case)]
 use coasys_juniper::FieldResult;
 use futures::stream;
-use futures::stream::Stream;
+use futures::stream::{Stream, StreamExt};
 use std::pin::Pin;
 
 use crate::{
+    ai_service::AIService,
+    errors::field_error,
     pubsub::{
-        get_global_pubsub, subscribe_and_process, AGENT_STATUS_CHANGED_TOPIC, AGENT_UPDATED_TOPIC,
-        AI_MODEL_LOADING_STATUS, AI_TRANSCRIPTION_TEXT_TOPIC, APPS_CHANGED,
-        EXCEPTION_OCCURRED_TOPIC, NEIGHBOURHOOD_SIGNAL_TOPIC, PERSPECTIVE_ADDED_TOPIC,
-        PERSPECTIVE_LINK_ADDED_TOPIC, PERSPECTIVE_LINK_REMOVED_TOPIC,
-        PERSPECTIVE_LINK_UPDATED_TOPIC, PERSPECTIVE_REMOVED_TOPIC,
+        get_global_pubsub, subscribe_and_process, subscribe_and_process_sequenced,
+        AGENT_STATUS_CHANGED_TOPIC, AGENT_UPDATED_TOPIC, AI_MODEL_LOADING_STATUS,
+        AI_TRANSCRIPTION_TEXT_TOPIC, APPS_CHANGED, EXCEPTION_OCCURRED_TOPIC,
+        NEIGHBOURHOOD_SIGNAL_TOPIC, PERSPECTIVE_ADDED_TOPIC, PERSPECTIVE_LINK_ADDED_TOPIC,
+        PERSPECTIVE_LINK_REMOVED_TOPIC, PERSPECTIVE_LINK_UPDATED_TOPIC, PERSPECTIVE_REMOVED_TOPIC,
         PERSPECTIVE_SYNC_STATE_CHANGE_TOPIC, PERSPECTIVE_UPDATED_TOPIC,
         RUNTIME_MESSAGED_RECEIVED_TOPIC, RUNTIME_NOTIFICATION_TRIGGERED_TOPIC,
     },
@@ -18,17 +20,33 @@ use crate::{
 };
 
 use super::graphql_types::*;
+use super::require_capability;
 use crate::agent::capabilities::*;
 
 pub struct Subscription;
 
+/// Drops events of perspectives the subscriber has no capability for
+fn scope_to_perspectives<V: Send + 'static>(
+    stream: Pin<Box<dyn Stream<Item = FieldResult<V>> + Send>>,
+    scope: PointerScope,
+    perspective_uuid: fn(&V) -> &String,
+) -> Pin<Box<dyn Stream<Item = FieldResult<V>> + Send>> {
+    Box::pin(stream.filter(move |item| {
+        let keep = match item {
+            Ok(value) => scope.contains(perspective_uuid(value)),
+            Err(_) => true,
+        };
+        futures::future::ready(keep)
+    }))
+}
+
 #[coasys_juniper::graphql_subscription(context = RequestContext)]
 impl Subscription {
     async fn agent_status_changed(
         &self,
         context: &RequestContext,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<AgentStatus>> + Send>> {
-        match check_capability(&context.capabilities, &AGENT_SUBSCRIBE_CAPABILITY) {
+        match require_capability(&context.capabilities, &AGENT_SUBSCRIBE_CAPABILITY) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
                 let pubsub = get_global_pubsub().await;
@@ -42,7 +60,7 @@ impl Subscription {
         &self,
         context: &RequestContext,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<Option<Apps>>> + Send>> {
-        match check_capability(&context.capabilities, &AGENT_SUBSCRIBE_CAPABILITY) {
+        match require_capability(&context.capabilities, &AGENT_SUBSCRIBE_CAPABILITY) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
                 let pubsub = get_global_pubsub().await;
@@ -56,7 +74,7 @@ impl Subscription {
         &self,
         context: &RequestContext,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<Agent>> + Send>> {
-        match check_capability(&context.capabilities, &AGENT_SUBSCRIBE_CAPABILITY) {
+        match require_capability(&context.capabilities, &AGENT_SUBSCRIBE_CAPABILITY) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
                 let pubsub = get_global_pubsub().await;
@@ -70,7 +88,7 @@ impl Subscription {
         &self,
         context: &RequestContext,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<ExceptionInfo>> + Send>> {
-        match check_capability(
+        match require_capability(
             &context.capabilities,
             &RUNTIME_EXCEPTION_SUBSCRIBE_CAPABILITY,
         ) {
@@ -88,7 +106,10 @@ impl Subscription {
         context: &RequestContext,
         perspectiveUUID: String,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<PerspectiveExpression>> + Send>> {
-        match check_capability(&context.capabilities, &NEIGHBOURHOOD_READ_CAPABILITY) {
+        match require_capability(
+            &context.capabilities,
+            &perspective_subscribe_capability(vec![perspectiveUUID.clone()]),
+        ) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
                 let pubsub = get_global_pubsub().await;
@@ -107,12 +128,15 @@ impl Subscription {
         &self,
         context: &RequestContext,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<PerspectiveHandle>> + Send>> {
-        match check_capability(&context.capabilities, &PERSPECTIVE_SUBSCRIBE_CAPABILITY) {
-            Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
-            Ok(_) => {
+        match capability_pointer_scope(&context.capabilities, &PERSPECTIVE_SUBSCRIBE_CAPABILITY) {
+            Err(e) => Box::pin(stream::once(async move { Err(e.field_error()) })),
+            Ok(scope) => {
                 let pubsub = get_global_pubsub().await;
                 let topic = &PERSPECTIVE_ADDED_TOPIC;
-                subscribe_and_process::<PerspectiveHandle>(pubsub, topic.to_string(), None).await
+                let stream =
+                    subscribe_and_process::<PerspectiveHandle>(pubsub, topic.to_string(), None)
+                        .await;
+                scope_to_perspectives(stream, scope, |handle| &handle.uuid)
             }
         }
     }
@@ -122,7 +146,10 @@ impl Subscription {
         context: &RequestContext,
         uuid: String,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<DecoratedLinkExpression>> + Send>> {
-        match check_capability(&context.capabilities, &PERSPECTIVE_SUBSCRIBE_CAPABILITY) {
+        match require_capability(
+            &context.capabilities,
+            &perspective_subscribe_capability(vec![uuid.clone()]),
+        ) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
                 let pubsub = get_global_pubsub().await;
@@ -142,7 +169,10 @@ impl Subscription {
         context: &RequestContext,
         uuid: String,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<DecoratedLinkExpression>> + Send>> {
-        match check_capability(&context.capabilities, &PERSPECTIVE_SUBSCRIBE_CAPABILITY) {
+        match require_capability(
+            &context.capabilities,
+            &perspective_subscribe_capability(vec![uuid.clone()]),
+        ) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
                 let pubsub = get_global_pubsub().await;
@@ -162,7 +192,10 @@ impl Subscription {
         context: &RequestContext,
         uuid: String,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<LinkUpdated>> + Send>> {
-        match check_capability(&context.capabilities, &PERSPECTIVE_SUBSCRIBE_CAPABILITY) {
+        match require_capability(
+            &context.capabilities,
+            &perspective_subscribe_capability(vec![uuid.clone()]),
+        ) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
                 let pubsub = get_global_pubsub().await;
@@ -177,16 +210,110 @@ impl Subscription {
         }
     }
 
-    async fn perspective_removed(
+    async fn perspective_link_added_sequenced(
         &self,
         context: &RequestContext,
-    ) -> Pin<Box<dyn Stream<Item = FieldResult<String>> + Send>> {
-        match check_capability(&context.capabilities, &PERSPECTIVE_SUBSCRIBE_CAPABILITY) {
+        uuid: String,
+        from_sequence: Option<f64>,
+    ) -> Pin<Box<dyn Stream<Item = FieldResult<SequencedLinkExpression>> + Send>> {
+        match require_capability(
+            &context.capabilities,
+            &perspective_subscribe_capability(vec![uuid.clone()]),
+        ) {
+            Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
+            Ok(_) => {
+                let pubsub = get_global_pubsub().await;
+                let topic = &PERSPECTIVE_LINK_ADDED_TOPIC;
+                let stream = subscribe_and_process_sequenced::<PerspectiveLinkFilter>(
+                    pubsub,
+                    topic.to_string(),
+                    Some(uuid),
+                    from_sequence.map(|s| s as u64),
+                )
+                .await;
+                Box::pin(stream.map(|result| {
+                    result.map(|(sequence, link)| SequencedLinkExpression {
+                        sequence: sequence as f64,
+                        link,
+                    })
+                }))
+            }
+        }
+    }
+
+    async fn perspective_link_removed_sequenced(
+        &self,
+        context: &RequestContext,
+        uuid: String,
+        from_sequence: Option<f64>,
+    ) -> Pin<Box<dyn Stream<Item = FieldResult<SequencedLinkExpression>> + Send>> {
+        match require_capability(
+            &context.capabilities,
+            &perspective_subscribe_capability(vec![uuid.clone()]),
+        ) {
+            Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
+            Ok(_) => {
+                let pubsub = get_global_pubsub().await;
+                let topic = &PERSPECTIVE_LINK_REMOVED_TOPIC;
+                let stream = subscribe_and_process_sequenced::<PerspectiveLinkFilter>(
+                    pubsub,
+                    topic.to_string(),
+                    Some(uuid),
+                    from_sequence.map(|s| s as u64),
+                )
+                .await;
+                Box::pin(stream.map(|result| {
+                    result.map(|(sequence, link)| SequencedLinkExpression {
+                        sequence: sequence as f64,
+                        link,
+                    })
+                }))
+            }
+        }
+    }
+
+    async fn perspective_link_updated_sequenced(
+        &self,
+        context: &RequestContext,
+        uuid: String,
+        from_sequence: Option<f64>,
+    ) -> Pin<Box<dyn Stream<Item = FieldResult<SequencedLinkUpdated>> + Send>> {
+        match require_capability(
+            &context.capabilities,
+            &perspective_subscribe_capability(vec![uuid.clone()]),
+        ) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
+                let pubsub = get_global_pubsub().await;
+                let topic = &PERSPECTIVE_LINK_UPDATED_TOPIC;
+                let stream = subscribe_and_process_sequenced::<PerspectiveLinkUpdatedFilter>(
+                    pubsub,
+                    topic.to_string(),
+                    Some(uuid),
+                    from_sequence.map(|s| s as u64),
+                )
+                .await;
+                Box::pin(stream.map(|result| {
+                    result.map(|(sequence, update)| SequencedLinkUpdated {
+                        sequence: sequence as f64,
+                        update,
+                    })
+                }))
+            }
+        }
+    }
+
+    async fn perspective_removed(
+        &self,
+        context: &RequestContext,
+    ) -> Pin<Box<dyn Stream<Item = FieldResult<String>> + Send>> {
+        match capability_pointer_scope(&context.capabilities, &PERSPECTIVE_SUBSCRIBE_CAPABILITY) {
+            Err(e) => Box::pin(stream::once(async move { Err(e.field_error()) })),
+            Ok(scope) => {
                 let pubsub = get_global_pubsub().await;
                 let topic = &PERSPECTIVE_REMOVED_TOPIC;
-                subscribe_and_process::<String>(pubsub, topic.to_string(), None).await
+                let stream = subscribe_and_process::<String>(pubsub, topic.to_string(), None).await;
+                scope_to_perspectives(stream, scope, |uuid| uuid)
             }
         }
     }
@@ -196,7 +323,10 @@ impl Subscription {
         context: &RequestContext,
         uuid: String,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<String>> + Send>> {
-        match check_capability(&context.capabilities, &PERSPECTIVE_SUBSCRIBE_CAPABILITY) {
+        match require_capability(
+            &context.capabilities,
+            &perspective_subscribe_capability(vec![uuid.clone()]),
+        ) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
                 let pubsub = get_global_pubsub().await;
@@ -215,12 +345,15 @@ impl Subscription {
         &self,
         context: &RequestContext,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<PerspectiveHandle>> + Send>> {
-        match check_capability(&context.capabilities, &PERSPECTIVE_SUBSCRIBE_CAPABILITY) {
-            Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
-            Ok(_) => {
+        match capability_pointer_scope(&context.capabilities, &PERSPECTIVE_SUBSCRIBE_CAPABILITY) {
+            Err(e) => Box::pin(stream::once(async move { Err(e.field_error()) })),
+            Ok(scope) => {
                 let pubsub = get_global_pubsub().await;
                 let topic = &PERSPECTIVE_UPDATED_TOPIC;
-                subscribe_and_process::<PerspectiveHandle>(pubsub, topic.to_string(), None).await
+                let stream =
+                    subscribe_and_process::<PerspectiveHandle>(pubsub, topic.to_string(), None)
+                        .await;
+                scope_to_perspectives(stream, scope, |handle| &handle.uuid)
             }
         }
     }
@@ -229,7 +362,10 @@ impl Subscription {
         &self,
         context: &RequestContext,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<PerspectiveExpression>> + Send>> {
-        match check_capability(&context.capabilities, &PERSPECTIVE_SUBSCRIBE_CAPABILITY) {
+        match require_capability(
+            &context.capabilities,
+            &RUNTIME_MESSAGES_SUBSCRIBE_CAPABILITY,
+        ) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
                 let pubsub = get_global_pubsub().await;
@@ -244,7 +380,7 @@ impl Subscription {
         &self,
         context: &RequestContext,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<TriggeredNotification>> + Send>> {
-        match check_capability(&context.capabilities, &AGENT_READ_CAPABILITY) {
+        match require_capability(&context.capabilities, &AGENT_READ_CAPABILITY) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
                 let pubsub = get_global_pubsub().await;
@@ -260,7 +396,7 @@ impl Subscription {
         context: &RequestContext,
         stream_id: String,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<String>> + Send>> {
-        match check_capability(&context.capabilities, &AI_TRANSCRIBE_CAPABILITY) {
+        match require_capability(&context.capabilities, &AI_TRANSCRIBE_CAPABILITY) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
                 let pubsub = get_global_pubsub().await;
@@ -275,11 +411,57 @@ impl Subscription {
         }
     }
 
+    /// Like `aiTranscriptionText` but with the timing and confidence of each segment
+    async fn ai_transcription_segments(
+        &self,
+        context: &RequestContext,
+        stream_id: String,
+    ) -> Pin<Box<dyn Stream<Item = FieldResult<TranscriptionSegment>> + Send>> {
+        match require_capability(&context.capabilities, &AI_TRANSCRIBE_CAPABILITY) {
+            Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
+            Ok(_) => {
+                let pubsub = get_global_pubsub().await;
+                let topic = &AI_TRANSCRIPTION_TEXT_TOPIC;
+                subscribe_and_process::<TranscriptionSegmentFilter>(
+                    pubsub,
+                    topic.to_string(),
+                    Some(stream_id),
+                )
+                .await
+            }
+        }
+    }
+
+    /// Text generated for a stream started with `aiPromptStream`, including
+    /// what was generated before subscribing. Ends after the event with `done`.
+    async fn ai_prompt_tokens(
+        &self,
+        context: &RequestContext,
+        stream_id: String,
+    ) -> Pin<Box<dyn Stream<Item = FieldResult<AIPromptTokens>> + Send>> {
+        match require_capability(&context.capabilities, &AI_PROMPT_CAPABILITY) {
+            Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
+            Ok(_) => {
+                let tokens = match AIService::global_instance().await {
+                    Ok(service) => service.subscribe_prompt_stream(&stream_id).await,
+                    Err(e) => Err(e),
+                };
+                match tokens {
+                    Ok(tokens) => Box::pin(tokens.map(Ok)),
+                    Err(e) => {
+                        let error = field_error(e);
+                        Box::pin(stream::once(async move { Err(error) }))
+                    }
+                }
+            }
+        }
+    }
+
     async fn ai_model_loading_status(
         &self,
         context: &RequestContext,
     ) -> Pin<Box<dyn Stream<Item = FieldResult<AIModelLoadingStatus>> + Send>> {
-        match check_capability(&context.capabilities, &AI_ALL_CAPABILITY) {
+        match require_capability(&context.capabilities, &AI_ALL_CAPABILITY) {
             Err(e) => Box::pin(stream::once(async move { Err(e.into()) })),
             Ok(_) => {
                 let pubsub = get_global_pubsub().await;
@@ -289,3 +471,128 @@ impl Subscription {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::js_core::JsCoreHandle;
+    use crate::pubsub::PERSPECTIVE_LINK_ADDED_TOPIC;
+    use crate::test_utils::setup_wallet;
+    use std::time::Duration;
+
+    async fn scoped_context(perspective_uuid: &str) -> RequestContext {
+        apps_map::set_data_file_path(
+            std::env::temp_dir()
+                .join(format!("ad4m-apps-{}.json", uuid::Uuid::new_v4()))
+                .to_string_lossy()
+                .to_string(),
+        );
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let rand = permit_capability(AuthInfoExtended {
+            request_id: request_id.clone(),
+            auth: AuthInfo {
+                app_name: "scoped-app".to_string(),
+                app_desc: "only sees one perspective".to_string(),
+                capabilities: Some(vec![perspective_subscribe_capability(vec![
+                    perspective_uuid.to_string(),
+                ])]),
+                ..Default::default()
+            },
+        })
+        .expect("could not permit capability");
+        let tokens = generate_capability_token(request_id, rand, ACCESS_TOKEN_VALID_PERIOD)
+            .await
+            .expect("could not generate tokens");
+
+        RequestContext {
+            capabilities: capabilities_from_token(
+                tokens.access_token,
+                Some("admin-credential".to_string()),
+            ),
+            js_handle: JsCoreHandle::detached(),
+            auto_permit_cap_requests: false,
+        }
+    }
+
+    fn handle(uuid: &str) -> PerspectiveHandle {
+        PerspectiveHandle {
+            uuid: uuid.to_string(),
+            ..Default::default()
+        }
+    }
+
+    async fn next_or_timeout<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+        tokio::time::timeout(Duration::from_secs(1), stream.next())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    #[tokio::test]
+    async fn pointer_scoped_token_only_receives_events_of_its_perspectives() {
+        setup_wallet();
+        let allowed = uuid::Uuid::new_v4().to_string();
+        let other = uuid::Uuid::new_v4().to_string();
+        let context = scoped_context(&allowed).await;
+
+        let mut denied = Subscription
+            .perspective_link_added(&context, other.clone())
+            .await;
+        assert!(next_or_timeout(&mut denied).await.unwrap().is_err());
+        let mut denied_signals = Subscription
+            .neighbourhood_signal(&context, other.clone())
+            .await;
+        assert!(next_or_timeout(&mut denied_signals).await.unwrap().is_err());
+
+        let mut links = Subscription
+            .perspective_link_added(&context, allowed.clone())
+            .await;
+        let mut updated = Subscription.perspective_updated(&context).await;
+        let mut removed = Subscription.perspective_removed(&context).await;
+
+        let pubsub = get_global_pubsub().await;
+        for uuid in [&other, &allowed] {
+            pubsub
+                .publish(
+                    &PERSPECTIVE_LINK_ADDED_TOPIC,
+                    &serde_json::to_string(&PerspectiveLinkFilter {
+                        perspective: handle(uuid),
+                        link: DecoratedLinkExpression::default(),
+                    })
+                    .unwrap(),
+                )
+                .await;
+            pubsub
+                .publish(
+                    &PERSPECTIVE_UPDATED_TOPIC,
+                    &serde_json::to_string(&handle(uuid)).unwrap(),
+                )
+                .await;
+            pubsub
+                .publish(
+                    &PERSPECTIVE_REMOVED_TOPIC,
+                    &serde_json::to_string(uuid).unwrap(),
+                )
+                .await;
+        }
+
+        assert!(next_or_timeout(&mut links).await.unwrap().is_ok());
+        assert_eq!(
+            next_or_timeout(&mut updated).await.unwrap().unwrap().uuid,
+            allowed
+        );
+        assert_eq!(
+            next_or_timeout(&mut removed).await.unwrap().unwrap(),
+            allowed
+        );
+
+        // Nothing of the other perspective got through
+        assert!(next_or_timeout(&mut links).await.is_none());
+        assert!(next_or_timeout(&mut updated).await.is_none());
+        assert!(next_or_timeout(&mut removed).await.is_none());
+
+        // Direct messages aren't bound to a perspective
+        let mut messages = Subscription.runtime_message_received(&context).await;
+        assert!(next_or_timeout(&mut messages).await.unwrap().is_err());
+    }
+}
@@ -5,6 +5,7 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use super::utils::ad4m_data_directory;
+use crate::db::{migrations, AD4M_DB_FILE_NAME};
 use crate::globals::{AD4M_VERSION, MAINNET_JSON, OLDEST_VERSION};
 
 /// Sets up the ad4m data directory and config files ready for the executor to consume
@@ -62,6 +63,42 @@ pub fn init(
     Ok(())
 }
 
+/// Applies pending schema migrations to the executor's database.
+/// With `dry_run` set, only reports the migrations that would be applied.
+pub fn migrate_db(data_path: Option<String>, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let app_data_path = match data_path {
+        Some(data_path) => Path::new(&data_path).to_path_buf(),
+        None => ad4m_data_directory(),
+    };
+    let db_path = app_data_path
+        .join(AD4M_DB_FILE_NAME)
+        .to_string_lossy()
+        .into_owned();
+
+    let status = migrations::check(&db_path)?;
+    println!(
+        "Database {} is at schema version {} (latest: {})",
+        db_path, status.current_version, status.latest_version
+    );
+    if status.is_up_to_date() {
+        println!("No migrations pending");
+        return Ok(());
+    }
+
+    for (version, description) in &status.pending {
+        println!("  pending migration {}: {}", version, description);
+    }
+
+    if !dry_run {
+        migrations::migrate_file(&db_path)?;
+        println!(
+            "Migrated database to schema version {}",
+            status.latest_version
+        );
+    }
+    Ok(())
+}
+
 fn write_seed_config(
     app_data_path: &Path,
     network_bootstrap_seed: Option<String>,
@@ -97,6 +97,23 @@ impl JsCoreHandle {
     }
 }
 
+#[cfg(test)]
+impl JsCoreHandle {
+    /// A handle without a running JS core behind it, for testing resolvers
+    /// that don't call into the JS core
+    pub(crate) fn detached() -> JsCoreHandle {
+        let (broadcast_tx, rx) = broadcast::channel::<JsCoreResponse>(1);
+        let (tx, _) = mpsc::unbounded_channel::<JsCoreRequest>();
+        let (tx_module_load, _) = mpsc::unbounded_channel::<JsCoreRequest>();
+        JsCoreHandle {
+            rx,
+            tx,
+            tx_module_load,
+            broadcast_tx,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct JsCoreRequest {
     script: String,
@@ -1,12 +1,15 @@
 mod byte_array;
 pub mod language;
 
+use deno_core::anyhow::anyhow;
 use deno_core::error::AnyError;
 use std::sync::{Arc, Mutex};
 
 use crate::types::Address;
 use crate::{
-    graphql::graphql_types::{DecoratedNeighbourhoodExpression, Neighbourhood},
+    graphql::graphql_types::{
+        Agent, DecoratedNeighbourhoodExpression, JsResultType, Neighbourhood,
+    },
     js_core::JsCoreHandle,
 };
 use language::Language;
@@ -123,4 +126,31 @@ impl LanguageController {
             Ok(None)
         }
     }
+
+    /// Fetches another agent's profile, including its public perspective,
+    /// from the agent language
+    pub async fn get_agent(did: &str) -> Result<Option<Agent>, AnyError> {
+        let mut js_core = LANGUAGE_CONTROLLER_INSTANCE
+            .lock()
+            .unwrap()
+            .as_ref()
+            .ok_or(anyhow!("LanguageController not initialized"))?
+            .js_core
+            .clone();
+        js_core
+            .execute("await core.waitForLanguages()".into())
+            .await?;
+
+        let script = format!(
+            r#"JSON.stringify(
+                await core.callResolver("Query", "agentByDID", {{ did: "{}" }})
+            )"#,
+            did,
+        );
+        let result: String = js_core.execute(script).await?;
+        match serde_json::from_str::<JsResultType<Option<Agent>>>(&result)? {
+            JsResultType::Ok(agent) => Ok(agent),
+            JsResultType::Error(error) => Err(anyhow!(error)),
+        }
+    }
 }
@@ -14,8 +14,10 @@ mod wallet;
 
 pub mod agent;
 pub mod ai_service;
+pub mod backup;
 mod dapp_server;
 mod db;
+pub mod errors;
 pub mod init;
 pub mod languages;
 mod neighbourhoods;
@@ -32,14 +34,21 @@ use log::{error, info, warn};
 use js_core::JsCore;
 
 use crate::{
-    agent::AgentService, ai_service::AIService, dapp_server::serve_dapp, db::Ad4mDb,
-    languages::LanguageController, prolog_service::init_prolog_service,
-    runtime_service::RuntimeService,
+    agent::AgentService,
+    ai_service::AIService,
+    dapp_server::serve_dapp,
+    db::Ad4mDb,
+    languages::LanguageController,
+    prolog_service::{
+        engine::set_default_query_limits, init_prolog_service, pool::set_engine_pool_size,
+    },
+    runtime_service::{notification_webhooks, RuntimeService},
 };
 pub use config::Ad4mConfig;
 pub use holochain_service::run_local_hc_services;
 use libc::{sigaction, sigemptyset, sighandler_t, SA_ONSTACK, SIGURG};
 use std::ptr;
+pub use wallet::KeystoreKdfParams;
 
 extern "C" fn handle_sigurg(_: libc::c_int) {
     //println!("Received SIGURG signal, but ignoring it.");
@@ -73,7 +82,7 @@ pub async fn run(mut config: Ad4mConfig) -> JoinHandle<()> {
             .as_ref()
             .map(|path| {
                 std::path::Path::new(path)
-                    .join("ad4m_db.sqlite")
+                    .join(db::AD4M_DB_FILE_NAME)
                     .to_string_lossy()
                     .into_owned()
             })
@@ -82,12 +91,19 @@ pub async fn run(mut config: Ad4mConfig) -> JoinHandle<()> {
     )
     .expect("Failed to initialize Ad4mDb");
 
+    agent::signatures::load_from_db();
+
     info!("Initializing AI service...");
     AIService::init_global_instance()
         .await
         .expect("Couldn't initialize AI service");
 
     info!("Initializing Agent service...");
+    if let Some(kdf_params) = config.keystore_kdf_params {
+        if let Err(e) = wallet::set_keystore_kdf_params(kdf_params) {
+            error!("Ignoring keystoreKdfParams from config: {}", e);
+        }
+    }
     AgentService::init_global_instance(config.app_data_path.clone().unwrap());
 
     info!("Initializing Runtime service...");
@@ -123,6 +139,13 @@ pub async fn run(mut config: Ad4mConfig) -> JoinHandle<()> {
 
     info!("Initializing Prolog service...");
     init_prolog_service().await;
+    if let Some(pool_size) = config.prolog_engine_pool_size {
+        set_engine_pool_size(pool_size);
+    }
+    set_default_query_limits(
+        config.prolog_query_timeout_ms,
+        config.prolog_inference_limit,
+    );
 
     info!("Starting js_core...");
     let mut js_core_handle = JsCore::start(config.clone()).await;
@@ -130,7 +153,11 @@ pub async fn run(mut config: Ad4mConfig) -> JoinHandle<()> {
     info!("js_core initialized.");
 
     LanguageController::init_global_instance(js_core_handle.clone());
+    if let Some(days) = config.perspective_history_retention_days {
+        perspectives::set_history_retention_days(days);
+    }
     perspectives::initialize_from_db();
+    tokio::spawn(notification_webhooks::run_delivery_loop());
 
     let app_dir = config
         .app_data_path
@@ -0,0 +1,274 @@
+use super::sdna::is_sdna_link;
+use super::{add_perspective, get_perspective};
+use crate::agent::{create_signed_expression, signatures};
+use crate::errors::ExecutorError;
+use crate::graphql::graphql_types::{LinkQuery, PerspectiveHandle, PerspectiveImportResult};
+use crate::types::{DecoratedLinkExpression, Expression, ExpressionProof, Link};
+use deno_core::anyhow::anyhow;
+use deno_core::error::AnyError;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+pub const PERSPECTIVE_ARCHIVE_VERSION: u32 = 1;
+
+/// Self-contained snapshot of a perspective as produced by `perspectiveExport`.
+/// The archive itself is wrapped in an `Expression` signed by the exporting agent.
+#[derive(Default, Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PerspectiveArchive {
+    pub version: u32,
+    /// Handle of the exported perspective, including name and neighbourhood URL
+    pub handle: PerspectiveHandle,
+    /// All links apart from the SDNA links, with their signatures and statuses
+    pub links: Vec<DecoratedLinkExpression>,
+    /// Links that declare and hold the perspective's Social DNA
+    pub sdna_links: Vec<DecoratedLinkExpression>,
+}
+
+fn is_sdna_declaration_or_code(link: &Link, sdna_names: &[String]) -> bool {
+    is_sdna_link(link)
+        || (link.predicate.as_deref() == Some("ad4m://sdna") && sdna_names.contains(&link.source))
+}
+
+fn link_signature_is_valid(link: &DecoratedLinkExpression) -> bool {
+    let expression = Expression::<Link> {
+        author: link.author.clone(),
+        timestamp: link.timestamp.clone(),
+        data: link.data.normalize(),
+        proof: ExpressionProof {
+            key: link.proof.key.clone(),
+            signature: link.proof.signature.clone(),
+        },
+    };
+    signatures::verify(&expression).unwrap_or(false)
+}
+
+/// Verifies every link's signature and splits them into valid and invalid ones.
+/// The `valid`/`invalid` flags on each proof get set according to the result.
+fn split_by_signature(
+    links: Vec<DecoratedLinkExpression>,
+) -> (Vec<DecoratedLinkExpression>, Vec<DecoratedLinkExpression>) {
+    let mut valid_links = Vec::new();
+    let mut invalid_links = Vec::new();
+    for mut link in links {
+        let valid = link_signature_is_valid(&link);
+        link.proof.valid = Some(valid);
+        link.proof.invalid = Some(!valid);
+        if valid {
+            valid_links.push(link);
+        } else {
+            invalid_links.push(link);
+        }
+    }
+    (valid_links, invalid_links)
+}
+
+fn sign_archive(archive: PerspectiveArchive) -> Result<String, AnyError> {
+    let expression = create_signed_expression(archive)?;
+    Ok(serde_json::to_string(&expression)?)
+}
+
+fn parse_archive(archive: &str) -> Result<(PerspectiveArchive, bool), AnyError> {
+    let expression: Expression<PerspectiveArchive> = serde_json::from_str(archive)
+        .map_err(|e| anyhow!("Could not parse perspective archive: {}", e))?;
+    if expression.data.version > PERSPECTIVE_ARCHIVE_VERSION {
+        return Err(anyhow!(
+            "Perspective archive version {} is not supported by this executor (latest: {})",
+            expression.data.version,
+            PERSPECTIVE_ARCHIVE_VERSION
+        ));
+    }
+    let signature_valid = signatures::verify(&expression).unwrap_or(false);
+    Ok((expression.data, signature_valid))
+}
+
+/// Exports the perspective with the given uuid as a signed JSON archive.
+pub async fn export_perspective(uuid: &str) -> Result<String, AnyError> {
+    let perspective =
+        get_perspective(uuid).ok_or(anyhow!("No perspective found with uuid {}", uuid))?;
+    let handle = perspective.persisted.lock().await.clone();
+    let all_links = perspective.get_links(&LinkQuery::default()).await?;
+
+    let sdna_names = all_links
+        .iter()
+        .filter(|l| is_sdna_link(&l.data))
+        .map(|l| l.data.target.clone())
+        .collect::<Vec<String>>();
+    let (sdna_links, links): (Vec<_>, Vec<_>) = all_links
+        .into_iter()
+        .partition(|l| is_sdna_declaration_or_code(&l.data, &sdna_names));
+
+    sign_archive(PerspectiveArchive {
+        version: PERSPECTIVE_ARCHIVE_VERSION,
+        handle,
+        links,
+        sdna_links,
+    })
+}
+
+/// Fails unless the archive's signature is valid or the caller accepts invalid ones
+fn check_archive_signature(
+    uuid: &str,
+    signature_valid: bool,
+    allow_invalid_signature: bool,
+) -> Result<(), AnyError> {
+    if signature_valid {
+        return Ok(());
+    }
+    if !allow_invalid_signature {
+        return Err(ExecutorError::InvalidInput(format!(
+            "Signature of perspective archive for {} is invalid",
+            uuid
+        ))
+        .into());
+    }
+    warn!(
+        "Importing perspective archive for {} despite its invalid signature",
+        uuid
+    );
+    Ok(())
+}
+
+/// Restores a perspective from an archive created by `export_perspective()`.
+/// The original uuid is kept unless a perspective with that uuid already exists.
+/// Archives with an invalid signature are rejected unless `allow_invalid_signature` is set.
+/// Links with invalid signatures are not imported but returned in the result.
+pub async fn import_perspective(
+    archive: &str,
+    allow_invalid_signature: bool,
+) -> Result<PerspectiveImportResult, AnyError> {
+    let (archive, archive_signature_valid) = parse_archive(archive)?;
+    check_archive_signature(
+        &archive.handle.uuid,
+        archive_signature_valid,
+        allow_invalid_signature,
+    )?;
+
+    let mut handle = archive.handle;
+    if get_perspective(&handle.uuid).is_some() {
+        handle.uuid = uuid::Uuid::new_v4().to_string();
+    }
+
+    let mut links = archive.sdna_links;
+    links.extend(archive.links);
+    signatures::resolve_unknown_keys(
+        links
+            .iter()
+            .map(|link| (link.author.clone(), link.proof.key.clone())),
+    )
+    .await;
+    let (valid_links, invalid_links) = split_by_signature(links);
+    if !invalid_links.is_empty() {
+        warn!(
+            "Skipping {} links with invalid signatures while importing perspective {}",
+            invalid_links.len(),
+            handle.uuid
+        );
+    }
+
+    add_perspective(handle.clone(), None)
+        .await
+        .map_err(|e| anyhow!(e))?;
+    let mut perspective = get_perspective(&handle.uuid)
+        .ok_or(anyhow!("No perspective found with uuid {}", handle.uuid))?;
+    let imported_links = perspective.import_links(valid_links).await?;
+
+    Ok(PerspectiveImportResult {
+        perspective: handle,
+        imported_links: imported_links.len() as i32,
+        invalid_links,
+        archive_signature_valid,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql::graphql_types::LinkStatus;
+    use crate::test_utils::setup_wallet;
+    use crate::types::LinkExpression;
+
+    fn signed_link(source: &str, predicate: Option<&str>, target: &str) -> DecoratedLinkExpression {
+        let link = Link {
+            source: source.to_string(),
+            predicate: predicate.map(|p| p.to_string()),
+            target: target.to_string(),
+        };
+        let expression: LinkExpression = create_signed_expression(link).unwrap().into();
+        DecoratedLinkExpression::from((expression, LinkStatus::Local))
+    }
+
+    #[test]
+    fn reports_links_with_invalid_signatures() {
+        setup_wallet();
+        let valid = signed_link("ad4m://self", Some("test://p"), "test://valid");
+        let mut tampered = signed_link("ad4m://self", Some("test://p"), "test://original");
+        tampered.data.target = "test://tampered".to_string();
+
+        let (valid_links, invalid_links) = split_by_signature(vec![valid.clone(), tampered]);
+        assert_eq!(valid_links.len(), 1);
+        assert_eq!(valid_links[0].data.target, valid.data.target);
+        assert_eq!(invalid_links.len(), 1);
+        assert_eq!(invalid_links[0].data.target, "test://tampered");
+        assert_eq!(invalid_links[0].proof.invalid, Some(true));
+    }
+
+    #[test]
+    fn archive_signature_roundtrip() {
+        setup_wallet();
+        let archive = PerspectiveArchive {
+            version: PERSPECTIVE_ARCHIVE_VERSION,
+            handle: PerspectiveHandle::new_from_name("Archived".to_string()),
+            links: vec![signed_link("test://a", None, "test://b")],
+            sdna_links: vec![],
+        };
+
+        let signed = sign_archive(archive.clone()).unwrap();
+        let (parsed, signature_valid) = parse_archive(&signed).unwrap();
+        assert!(signature_valid);
+        assert_eq!(parsed.handle.uuid, archive.handle.uuid);
+        assert_eq!(parsed.links, archive.links);
+
+        let tampered = signed.replace("Archived", "Tampered");
+        let (_, signature_valid) = parse_archive(&tampered).unwrap();
+        assert!(!signature_valid);
+
+        assert!(parse_archive("not an archive").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_archive_signature_unless_allowed() {
+        assert!(check_archive_signature("uuid", true, false).is_ok());
+        let error = check_archive_signature("uuid", false, false).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ExecutorError>(),
+            Some(&ExecutorError::InvalidInput(
+                "Signature of perspective archive for uuid is invalid".to_string()
+            ))
+        );
+        assert!(check_archive_signature("uuid", false, true).is_ok());
+    }
+
+    #[test]
+    fn splits_sdna_links_from_other_links() {
+        let names = vec!["literal://string:Todo".to_string()];
+        let declaration = Link {
+            source: "ad4m://self".to_string(),
+            predicate: Some("ad4m://has_subject_class".to_string()),
+            target: "literal://string:Todo".to_string(),
+        };
+        let code = Link {
+            source: "literal://string:Todo".to_string(),
+            predicate: Some("ad4m://sdna".to_string()),
+            target: "literal://string:code".to_string(),
+        };
+        let other = Link {
+            source: "ad4m://self".to_string(),
+            predicate: Some("test://p".to_string()),
+            target: "test://t".to_string(),
+        };
+        assert!(is_sdna_declaration_or_code(&declaration, &names));
+        assert!(is_sdna_declaration_or_code(&code, &names));
+        assert!(!is_sdna_declaration_or_code(&other, &names));
+    }
+}
@@ -0,0 +1,258 @@
+//! Links whose target only a set of recipient agents can read.
+//!
+//! The author signs the plain link, then its target gets replaced by an envelope
+//! holding one copy of the target per recipient key, each sealed with an ephemeral
+//! X25519 key to the recipient's published agent key. Everyone stores and syncs the
+//! envelope, recipients decrypt it when reading and then get a link with a valid
+//! signature again. Other members of the neighbourhood don't see the link at all.
+
+use crate::agent::signatures;
+use crate::graphql::graphql_types::LinkStatus;
+use crate::types::LinkExpression;
+use crate::wallet::{self, Wallet};
+use base64::Engine;
+use crypto_box::aead::Aead;
+use crypto_box::{Nonce, PublicKey, SalsaBox, SecretKey};
+use deno_core::anyhow::anyhow;
+use deno_core::error::AnyError;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const ENCRYPTED_TARGET_PREFIX: &str = "ad4m-encrypted://";
+const ENVELOPE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Envelope {
+    version: u32,
+    ephemeral_key: String,
+    recipients: Vec<SealedTarget>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SealedTarget {
+    did: String,
+    /// did:key of the recipient key the target is sealed to
+    key: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+fn b64() -> base64::engine::GeneralPurpose {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+}
+
+pub fn is_encrypted(link: &LinkExpression) -> bool {
+    link.data.target.starts_with(ENCRYPTED_TARGET_PREFIX)
+}
+
+/// Keys an agent can currently decrypt with: its main key and those of its linked devices
+fn recipient_keys(did: &str) -> Vec<String> {
+    let mut keys = vec![signatures::current_key(did)];
+    keys.extend(
+        signatures::device_keys(did)
+            .into_iter()
+            .filter(|device| device.authorized && device.revoked_at.is_none())
+            .map(|device| device.key),
+    );
+    keys
+}
+
+/// Replaces the target of a signed link with an envelope that only `recipients`
+/// and the link's author can open
+pub fn encrypt(
+    mut link: LinkExpression,
+    recipients: &[String],
+) -> Result<LinkExpression, AnyError> {
+    if is_encrypted(&link) {
+        return Err(anyhow!("Link is already encrypted"));
+    }
+    let mut dids = vec![link.author.clone()];
+    for did in recipients {
+        if !dids.contains(did) {
+            dids.push(did.clone());
+        }
+    }
+
+    let mut ephemeral_secret = [0u8; 32];
+    OsRng.fill_bytes(&mut ephemeral_secret);
+    let ephemeral_key = SecretKey::from(ephemeral_secret);
+    let mut sealed = Vec::new();
+    for did in dids {
+        for key in recipient_keys(&did) {
+            let public_key = wallet::key_agreement_public_key(&key)
+                .map_err(|e| anyhow!("Can't encrypt to {}: {}", did, e))?;
+            let mut nonce = Nonce::default();
+            OsRng.fill_bytes(&mut nonce);
+            let ciphertext = SalsaBox::new(&public_key, &ephemeral_key)
+                .encrypt(&nonce, link.data.target.as_bytes())
+                .map_err(|e| anyhow!("Couldn't encrypt link target: {}", e))?;
+            sealed.push(SealedTarget {
+                did: did.clone(),
+                key,
+                nonce: b64().encode(nonce),
+                ciphertext: b64().encode(ciphertext),
+            });
+        }
+    }
+
+    let envelope = Envelope {
+        version: ENVELOPE_VERSION,
+        ephemeral_key: b64().encode(PublicKey::from(&ephemeral_key).as_bytes()),
+        recipients: sealed,
+    };
+    link.data.target = format!(
+        "{}{}",
+        ENCRYPTED_TARGET_PREFIX,
+        b64().encode(serde_json::to_vec(&envelope)?)
+    );
+    Ok(link)
+}
+
+/// Opens the envelope with one of our wallet keys, given by did:key -> key name
+fn open(
+    wallet: &Wallet,
+    our_keys: &HashMap<String, String>,
+    link: &LinkExpression,
+) -> Option<LinkExpression> {
+    let encoded = link.data.target.strip_prefix(ENCRYPTED_TARGET_PREFIX)?;
+    let envelope: Envelope = serde_json::from_slice(&b64().decode(encoded).ok()?).ok()?;
+    if envelope.version > ENVELOPE_VERSION {
+        return None;
+    }
+    let ephemeral_key: [u8; 32] = b64()
+        .decode(&envelope.ephemeral_key)
+        .ok()?
+        .try_into()
+        .ok()?;
+    let ephemeral_key = PublicKey::from(ephemeral_key);
+
+    envelope.recipients.iter().find_map(|sealed| {
+        let key_name = our_keys.get(&sealed.key)?;
+        let target = wallet.open_box(
+            key_name,
+            &ephemeral_key,
+            &b64().decode(&sealed.nonce).ok()?,
+            &b64().decode(&sealed.ciphertext).ok()?,
+        )?;
+        let mut plain = link.clone();
+        plain.data.target = String::from_utf8(target).ok()?;
+        Some(plain)
+    })
+}
+
+fn with_our_keys<F, R>(func: F) -> R
+where
+    F: FnOnce(Option<(&Wallet, &HashMap<String, String>)>) -> R,
+{
+    let wallet_instance = Wallet::instance();
+    let wallet = wallet_instance.lock().expect("wallet lock");
+    match wallet.as_ref() {
+        Some(wallet) => {
+            let our_keys = wallet
+                .list_keys()
+                .into_iter()
+                .map(|key| (key.did, key.name))
+                .collect::<HashMap<_, _>>();
+            func(Some((wallet, &our_keys)))
+        }
+        None => func(None),
+    }
+}
+
+/// Decrypts the encrypted links among `links` and drops the ones we are not a
+/// recipient of. Other links are passed through unchanged.
+pub fn reveal(links: Vec<(LinkExpression, LinkStatus)>) -> Vec<(LinkExpression, LinkStatus)> {
+    reveal_with(
+        links
+            .into_iter()
+            .map(|(link, status)| (link, status, ()))
+            .collect(),
+    )
+    .into_iter()
+    .map(|(link, status, _)| (link, status))
+    .collect()
+}
+
+/// Like `reveal()`, keeping what comes with each link (e.g. its paging cursor)
+pub fn reveal_with<T>(
+    links: Vec<(LinkExpression, LinkStatus, T)>,
+) -> Vec<(LinkExpression, LinkStatus, T)> {
+    if !links.iter().any(|(link, _, _)| is_encrypted(link)) {
+        return links;
+    }
+    with_our_keys(|keys| {
+        links
+            .into_iter()
+            .filter_map(|(link, status, extra)| {
+                if !is_encrypted(&link) {
+                    return Some((link, status, extra));
+                }
+                let (wallet, our_keys) = keys?;
+                open(wallet, our_keys, &link).map(|plain| (plain, status, extra))
+            })
+            .collect()
+    })
+}
+
+/// Like `reveal()` for a single link, but keeps it encrypted if we can't open it
+pub fn revealed(link: LinkExpression) -> LinkExpression {
+    if !is_encrypted(&link) {
+        return link;
+    }
+    with_our_keys(|keys| keys.and_then(|(wallet, our_keys)| open(wallet, our_keys, &link)))
+        .unwrap_or(link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{create_signed_expression, did};
+    use crate::test_utils::{setup_agent, setup_wallet};
+    use crate::types::{DecoratedLinkExpression, Link};
+
+    #[test]
+    fn only_recipients_can_read_encrypted_links() {
+        setup_wallet();
+        setup_agent();
+        let link: LinkExpression = create_signed_expression(Link {
+            source: "ad4m://self".to_string(),
+            predicate: Some("ad4m://message".to_string()),
+            target: "literal://string:secret".to_string(),
+        })
+        .unwrap()
+        .into();
+
+        let stranger = did_key::generate::<did_key::Ed25519KeyPair>(None);
+        let stranger_did =
+            did_key::DIDCore::get_did_document(&stranger, did_key::Config::default()).id;
+        let encrypted = encrypt(link.clone(), &[stranger_did]).unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.data.target.contains("secret"));
+        assert_eq!(encrypted.author, did());
+
+        // The author is always a recipient and gets the signed plain link back
+        let revealed = reveal(vec![(encrypted.clone(), LinkStatus::Shared)]);
+        assert_eq!(revealed.len(), 1);
+        assert_eq!(revealed[0].0, link);
+        let decorated = DecoratedLinkExpression::from(revealed[0].clone());
+        assert_eq!(decorated.proof.valid, Some(true));
+
+        // None of our keys among the recipients
+        let other = did_key::generate::<did_key::Ed25519KeyPair>(None);
+        let other_did = did_key::DIDCore::get_did_document(&other, did_key::Config::default()).id;
+        let foreign = encrypt(
+            LinkExpression {
+                author: other_did,
+                ..link.clone()
+            },
+            &[],
+        )
+        .unwrap();
+        assert!(reveal(vec![(foreign.clone(), LinkStatus::Shared)]).is_empty());
+        assert_eq!(revealed(foreign.clone()), foreign);
+    }
+}
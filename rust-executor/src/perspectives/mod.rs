@@ -1,8 +1,12 @@
+pub mod archive;
+pub mod encrypted_links;
 pub mod perspective_instance;
 pub mod sdna;
+pub mod search;
 pub mod utils;
 use crate::graphql::graphql_types::{PerspectiveExpression, PerspectiveHandle, PerspectiveState};
 use lazy_static::lazy_static;
+pub use perspective_instance::set_history_retention_days;
 use perspective_instance::PerspectiveInstance;
 use std::collections::HashMap;
 use std::sync::RwLock;
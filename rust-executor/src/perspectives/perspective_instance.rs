@@ -1,26 +1,35 @@
-use super::sdna::{generic_link_fact, init_engine_facts, is_sdna_link};
+use super::encrypted_links;
+use super::sdna::{fact_updates_from_diff, init_engine_facts};
+use super::search;
 use super::update_perspective;
 use super::utils::{
     prolog_get_all_string_bindings, prolog_get_first_string_binding, prolog_resolution_to_string,
 };
 use crate::agent::{self, create_signed_expression};
+use crate::errors::ExecutorError;
 use crate::graphql::graphql_types::{
     DecoratedPerspectiveDiff, ExpressionRendered, JsResultType, LinkMutations, LinkQuery,
     LinkStatus, NeighbourhoodSignalFilter, OnlineAgent, PerspectiveExpression, PerspectiveHandle,
-    PerspectiveLinkFilter, PerspectiveLinkUpdatedFilter, PerspectiveState, PerspectiveStateFilter,
+    PerspectiveHistoryEntry, PerspectiveLinkFilter, PerspectiveLinkUpdatedFilter, PerspectiveState,
+    PerspectiveStateFilter,
 };
 use crate::languages::language::Language;
 use crate::languages::LanguageController;
 use crate::perspectives::utils::{prolog_get_first_binding, prolog_value_to_json_string};
-use crate::prolog_service::engine::PrologEngine;
+use crate::prolog_service::engine::PrologQueryOptions;
+use crate::prolog_service::error::PrologQueryTimeout;
+use crate::prolog_service::pool::{engine_pool_size, PrologEnginePool, PrologEnginePoolMetrics};
 use crate::pubsub::{
     get_global_pubsub, NEIGHBOURHOOD_SIGNAL_TOPIC, PERSPECTIVE_LINK_ADDED_TOPIC,
     PERSPECTIVE_LINK_REMOVED_TOPIC, PERSPECTIVE_LINK_UPDATED_TOPIC,
     PERSPECTIVE_SYNC_STATE_CHANGE_TOPIC, RUNTIME_NOTIFICATION_TRIGGERED_TOPIC,
 };
-use crate::{db::Ad4mDb, types::*};
+use crate::runtime_service::notification_webhooks;
+use crate::{
+    db::{millis_to_timestamp, Ad4mDb, LinksPage},
+    types::*,
+};
 use ad4m_client::literal::Literal;
-use chrono::DateTime;
 use deno_core::anyhow::anyhow;
 use deno_core::error::AnyError;
 use json5;
@@ -28,9 +37,10 @@ use scryer_prolog::{QueryMatch, QueryResolution};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{oneshot, Mutex, RwLock};
 use tokio::time::sleep;
 use tokio::{join, time};
 
@@ -39,6 +49,14 @@ static MAX_PENDING_DIFFS_COUNT: usize = 150;
 static MAX_PENDING_SECONDS: u64 = 3;
 static IMMEDIATE_COMMITS_COUNT: usize = 20;
 
+/// How long applied diffs stay in the perspective history, 0 keeps them forever
+static HISTORY_RETENTION_MS: AtomicI64 = AtomicI64::new(0);
+
+/// Makes recording history drop diffs older than `days` (see `Ad4mConfig`)
+pub fn set_history_retention_days(days: u32) {
+    HISTORY_RETENTION_MS.store(days as i64 * 24 * 60 * 60 * 1000, Ordering::Relaxed);
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub enum SdnaType {
     SubjectClass,
@@ -143,11 +161,13 @@ pub struct PerspectiveInstance {
     pub is_fast_polling: bool,
     pub retries: u32,
 
-    prolog_engine: Arc<Mutex<Option<PrologEngine>>>,
+    prolog_engine_pool: Arc<RwLock<Option<PrologEnginePool>>>,
     prolog_needs_rebuild: Arc<Mutex<bool>>,
     is_teardown: Arc<Mutex<bool>>,
     sdna_change_mutex: Arc<Mutex<()>>,
     prolog_update_mutex: Arc<RwLock<()>>,
+    /// Cancellation handles of running Prolog queries that were given a query id
+    running_prolog_queries: Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>,
     link_language: Arc<Mutex<Option<Language>>>,
     links_have_changed: Arc<Mutex<bool>>,
     commit_debounce_timer: Arc<Mutex<Option<tokio::time::Instant>>>,
@@ -162,11 +182,12 @@ impl PerspectiveInstance {
             created_from_join: created_from_join.unwrap_or(false),
             is_fast_polling: false,
             retries: 0,
-            prolog_engine: Arc::new(Mutex::new(None)),
+            prolog_engine_pool: Arc::new(RwLock::new(None)),
             prolog_needs_rebuild: Arc::new(Mutex::new(true)),
             is_teardown: Arc::new(Mutex::new(false)),
             sdna_change_mutex: Arc::new(Mutex::new(())),
             prolog_update_mutex: Arc::new(RwLock::new(())),
+            running_prolog_queries: Arc::new(Mutex::new(HashMap::new())),
             link_language: Arc::new(Mutex::new(None)),
             links_have_changed: Arc::new(Mutex::new(false)),
             commit_debounce_timer: Arc::new(Mutex::new(None)),
@@ -180,6 +201,7 @@ impl PerspectiveInstance {
             self.notification_check_loop(),
             self.nh_sync_loop(),
             self.pending_diffs_loop(),
+            self.embedding_index_loop(),
         );
     }
 
@@ -310,6 +332,31 @@ impl PerspectiveInstance {
         }
     }
 
+    /// Embeds new links for semantic search in batches, so that searching
+    /// only has to embed the query
+    async fn embedding_index_loop(&self) {
+        let uuid = self.persisted.lock().await.uuid.clone();
+        let mut interval = time::interval(Duration::from_secs(5));
+
+        while !*self.is_teardown.lock().await {
+            interval.tick().await;
+            loop {
+                match search::index_embeddings(&uuid).await {
+                    // More links might be waiting, go on without waiting for the next tick
+                    Ok(count) if count == search::EMBEDDING_BATCH_SIZE => {}
+                    Ok(_) => break,
+                    Err(e) => {
+                        log::debug!("Couldn't index embeddings of perspective {}: {}", uuid, e);
+                        break;
+                    }
+                }
+                if *self.is_teardown.lock().await {
+                    break;
+                }
+            }
+        }
+    }
+
     async fn has_new_diffs_in_last_second(&self) -> bool {
         let timer = self.commit_debounce_timer.lock().await;
         timer
@@ -325,36 +372,85 @@ impl PerspectiveInstance {
     async fn commit_pending_diffs(&self) -> Result<(), AnyError> {
         let uuid = self.persisted.lock().await.uuid.clone();
 
-        let (pending_diffs, pending_ids) = Ad4mDb::with_global_instance(|db| {
-            db.get_pending_diffs_by_size(&uuid, MAX_COMMIT_BYTES, Some(MAX_PENDING_DIFFS_COUNT))
-        })?;
+        // Pending diffs are read while holding the link language lock, so concurrent
+        // callers (the pending diffs loop and `retry_sync`) can't commit the same diffs
+        let mut link_language_lock = self.link_language.lock().await;
+        if let Some(link_language) = link_language_lock.as_mut() {
+            let (pending_diffs, pending_ids) = Ad4mDb::with_global_instance(|db| {
+                db.get_pending_diffs_by_size(&uuid, MAX_COMMIT_BYTES, Some(MAX_PENDING_DIFFS_COUNT))
+            })?;
 
-        if !pending_ids.is_empty() {
-            let mut link_language_lock = self.link_language.lock().await;
-            if let Some(link_language) = link_language_lock.as_mut() {
-                log::info!("Committing {} pending diffs...", pending_ids.len());
-                let commit_result = link_language.commit(pending_diffs).await;
-                match commit_result {
-                    Ok(Some(_)) => {
-                        Ad4mDb::with_global_instance(|db| {
-                            db.clear_pending_diffs(&uuid, pending_ids)
-                        })?;
-                        // Reset immediate commits counter after successful commit
-                        self.set_immediate_commits(IMMEDIATE_COMMITS_COUNT).await;
-                        log::info!("Successfully committed pending diffs");
-                        Ok(())
-                    }
-                    Ok(None) => Err(anyhow!("No diff returned from commit")),
-                    Err(e) => Err(e),
+            if pending_ids.is_empty() {
+                return Ok(());
+            }
+
+            log::info!("Committing {} pending diffs...", pending_ids.len());
+            let commit_result = link_language.commit(pending_diffs).await;
+            let now = chrono::Utc::now().timestamp_millis();
+            match commit_result {
+                Ok(Some(_)) => {
+                    let count = pending_ids.len();
+                    Ad4mDb::with_global_instance(|db| {
+                        db.clear_pending_diffs(&uuid, pending_ids)?;
+                        db.record_diffs_committed(&uuid, count, now)
+                    })?;
+                    // Reset immediate commits counter after successful commit
+                    self.set_immediate_commits(IMMEDIATE_COMMITS_COUNT).await;
+                    log::info!("Successfully committed pending diffs");
+                    Ok(())
+                }
+                Ok(None) => {
+                    let error = anyhow!("No diff returned from commit");
+                    Self::record_commit_failure(&uuid, &pending_ids, &error, now);
+                    Err(error)
+                }
+                Err(e) => {
+                    Self::record_commit_failure(&uuid, &pending_ids, &e, now);
+                    Err(e)
                 }
-            } else {
-                Ok(()) // Keep diffs if no link language
             }
         } else {
-            Ok(())
+            Ok(()) // Keep diffs if no link language
         }
     }
 
+    fn record_commit_failure(uuid: &str, ids: &[u64], error: &AnyError, attempted_at: i64) {
+        if let Err(e) = Ad4mDb::with_global_instance(|db| {
+            db.record_diff_commit_failure(uuid, ids, &error.to_string(), attempted_at)
+        }) {
+            log::error!("Couldn't record failed commit of pending diffs: {:?}", e);
+        }
+    }
+
+    /// Commits all pending diffs now instead of waiting for the next round of
+    /// the pending diffs loop. Failed commits don't return an error, they are
+    /// recorded in the sync status.
+    pub async fn retry_sync(&self) -> Result<(), AnyError> {
+        let uuid = self.persisted.lock().await.uuid.clone();
+        self.set_immediate_commits(IMMEDIATE_COMMITS_COUNT).await;
+        loop {
+            if !self.has_link_language().await {
+                return Err(ExecutorError::LinkLanguageMissing(uuid).into());
+            }
+            let (_, ids) = Ad4mDb::with_global_instance(|db| db.get_pending_diffs(&uuid, Some(1)))?;
+            if ids.is_empty() {
+                return Ok(());
+            }
+            if let Err(e) = self.commit_pending_diffs().await {
+                log::warn!("Retrying sync of perspective {} failed: {:?}", uuid, e);
+                return Ok(());
+            }
+        }
+    }
+
+    pub async fn sync_status(&self) -> Result<PerspectiveSyncStatus, AnyError> {
+        let handle = self.persisted.lock().await.clone();
+        let mut status =
+            Ad4mDb::with_global_instance(|db| db.get_perspective_sync_status(&handle.uuid))?;
+        status.shared_url = handle.shared_url;
+        Ok(status)
+    }
+
     async fn notification_check_loop(&self) {
         let uuid = self.persisted.lock().await.uuid.clone();
         let mut interval = time::interval(Duration::from_secs(5));
@@ -465,66 +561,65 @@ impl PerspectiveInstance {
             return Ok(());
         }
 
-        // Seeing if we already have pending diffs, to not overtake older commits but instead add this one to the queue
-        let (_, pending_ids) =
-            Ad4mDb::with_global_instance(|db| db.get_pending_diffs(&handle.uuid, Some(1)))
-                .unwrap_or((PerspectiveDiff::empty(), Vec::new()));
-
-        let commit_result = if pending_ids.is_empty() {
-            // No pending diffs, let's try
-            if let Some(link_language) = self.link_language.lock().await.as_mut() {
-                // Got lock on Link Language, no other commit running
-                if link_language.current_revision().await?.is_some() {
-                    // Revision set, we are synced
-                    // we are in a healthy Neighbourhood state and should be able to commit
-                    // but let's make sure we're not DoS'ing the link language in bursts
-                    let mut immediate_commits_remaining =
-                        self.immediate_commits_remaining.lock().await;
-                    if *immediate_commits_remaining > 0 {
-                        *immediate_commits_remaining -= 1;
-                        link_language.commit(diff.clone()).await
-                    } else {
-                        Err(anyhow!("Debouncing commit burst"))
-                    }
+        let mut attempted = false;
+        let commit_result = if let Some(link_language) = self.link_language.lock().await.as_mut() {
+            // Got lock on Link Language, no other commit running.
+            // Seeing if we already have pending diffs, to not overtake older commits but instead add this one to the queue
+            let (_, pending_ids) =
+                Ad4mDb::with_global_instance(|db| db.get_pending_diffs(&handle.uuid, Some(1)))
+                    .unwrap_or((PerspectiveDiff::empty(), Vec::new()));
+            if !pending_ids.is_empty() {
+                Err(anyhow!("Other pending diffs already in queue"))
+            } else if link_language.current_revision().await?.is_some() {
+                // Revision set, we are synced
+                // we are in a healthy Neighbourhood state and should be able to commit
+                // but let's make sure we're not DoS'ing the link language in bursts
+                let mut immediate_commits_remaining = self.immediate_commits_remaining.lock().await;
+                if *immediate_commits_remaining > 0 {
+                    *immediate_commits_remaining -= 1;
+                    attempted = true;
+                    link_language.commit(diff.clone()).await
                 } else {
-                    Err(anyhow!("Link Language not synced"))
+                    Err(anyhow!("Debouncing commit burst"))
                 }
             } else {
-                Err(anyhow!("LinkLanguage not available"))
+                Err(anyhow!("Link Language not synced"))
             }
         } else {
-            Err(anyhow!("Other pending diffs already in queue"))
+            Err(anyhow!("LinkLanguage not available"))
         };
 
-        let ok = match commit_result {
-            Ok(Some(rev)) => {
-                if rev.trim().is_empty() {
-                    log::warn!("Committed but got no revision from LinkLanguage!\nStoring in pending diffs for later");
-                    false
-                } else {
-                    log::info!("Committed to revision: {}", rev);
-                    true
-                }
+        let result = match commit_result {
+            Ok(Some(rev)) if !rev.trim().is_empty() => {
+                log::info!("Committed to revision: {}", rev);
+                Ok(())
             }
-            Ok(None) => {
+            Ok(_) => {
                 log::warn!("Committed but got no revision from LinkLanguage!\nStoring in pending diffs for later");
-                false
+                Err(anyhow!("No revision returned from commit"))
             }
             Err(e) => {
                 log::warn!(
                     "Error trying to commit diff: {:?}\nStoring in pending diffs for later",
                     e
                 );
-                false
+                Err(e)
             }
         };
 
-        if !ok {
+        let now = chrono::Utc::now().timestamp_millis();
+        if let Err(error) = result {
             // Store diff in DB
-            Ad4mDb::with_global_instance(|db| db.add_pending_diff(&handle.uuid, diff))?;
+            let id = Ad4mDb::with_global_instance(|db| db.add_pending_diff(&handle.uuid, diff))?;
+            // Only failed commits count as attempts, not waiting for the link language
+            if attempted {
+                Self::record_commit_failure(&handle.uuid, &[id], &error, now);
+            }
             // Update or start timer
             let mut timer = self.commit_debounce_timer.lock().await;
             *timer = Some(tokio::time::Instant::now());
+        } else {
+            Ad4mDb::with_global_instance(|db| db.record_diffs_committed(&handle.uuid, 1, now))?;
         }
 
         Ok(())
@@ -551,6 +646,16 @@ impl PerspectiveInstance {
     }
 
     pub async fn diff_from_link_language(&self, diff: PerspectiveDiff) {
+        // Links of agents that rotated keys or linked devices only verify once
+        // we know the statements they published about it, and revoked devices
+        // are only rejected once we know the revocation
+        agent::signatures::resolve_unknown_keys(
+            diff.additions
+                .iter()
+                .map(|link| (link.author.clone(), link.proof.key.clone())),
+        )
+        .await;
+
         let handle = self.persisted.lock().await.clone();
         if !diff.additions.is_empty() {
             Ad4mDb::with_global_instance(|db| {
@@ -568,17 +673,31 @@ impl PerspectiveInstance {
             });
         }
 
+        Self::record_history(
+            &handle.uuid,
+            PerspectiveDiff::from(
+                with_status(&diff.additions, &LinkStatus::Shared),
+                with_status(&diff.removals, &LinkStatus::Shared),
+            ),
+            false,
+            None,
+        );
+
+        // Prolog and subscribers only get to see the encrypted links we can decrypt
+        let decorate = |links: &[LinkExpression]| {
+            encrypted_links::reveal(
+                links
+                    .iter()
+                    .map(|link| (link.clone(), LinkStatus::Shared))
+                    .collect(),
+            )
+            .into_iter()
+            .map(DecoratedLinkExpression::from)
+            .collect()
+        };
         let decorated_diff = DecoratedPerspectiveDiff {
-            additions: diff
-                .additions
-                .iter()
-                .map(|link| DecoratedLinkExpression::from((link.clone(), LinkStatus::Shared)))
-                .collect(),
-            removals: diff
-                .removals
-                .iter()
-                .map(|link| DecoratedLinkExpression::from((link.clone(), LinkStatus::Shared)))
-                .collect(),
+            additions: decorate(&diff.additions),
+            removals: decorate(&diff.removals),
         };
 
         self.spawn_prolog_facts_update(decorated_diff.clone());
@@ -587,6 +706,7 @@ impl PerspectiveInstance {
     }
 
     pub async fn telepresence_signal_from_link_language(&self, mut signal: PerspectiveExpression) {
+        agent::signatures::resolve_unknown_keys_of_perspective(&signal).await;
         signal.verify_signatures();
         let handle = self.persisted.lock().await.clone();
         get_global_pubsub()
@@ -613,6 +733,89 @@ impl PerspectiveInstance {
             .await
     }
 
+    /// Adds a shared link whose target only `recipients` (and we) can read.
+    /// The link language gets the encrypted link, we keep it encrypted as well
+    /// and decrypt it on reading.
+    pub async fn add_encrypted_link(
+        &mut self,
+        link: Link,
+        recipients: Vec<String>,
+    ) -> Result<DecoratedLinkExpression, AnyError> {
+        let handle = self.persisted.lock().await.clone();
+        if handle.neighbourhood.is_none() {
+            return Err(anyhow!(
+                "Encrypted links can only be added to neighbourhood perspectives"
+            ));
+        }
+        let link_expression: LinkExpression = create_signed_expression(link)?.into();
+        let encrypted = encrypted_links::encrypt(link_expression.clone(), &recipients)?;
+        Ad4mDb::with_global_instance(|db| {
+            db.add_link(&handle.uuid, &encrypted, &LinkStatus::Shared)
+        })?;
+        Self::record_history(
+            &handle.uuid,
+            PerspectiveDiff::from_additions(with_status(&[encrypted.clone()], &LinkStatus::Shared)),
+            true,
+            None,
+        );
+
+        let decorated_link_expression =
+            DecoratedLinkExpression::from((link_expression, LinkStatus::Shared));
+        let decorated_perspective_diff =
+            DecoratedPerspectiveDiff::from_additions(vec![decorated_link_expression.clone()]);
+        self.spawn_prolog_facts_update(decorated_perspective_diff.clone());
+        self.spawn_commit_and_handle_error(&PerspectiveDiff::from_additions(vec![encrypted]));
+        self.pubsub_publish_diff(decorated_perspective_diff).await;
+        *(self.links_have_changed.lock().await) = true;
+        Ok(decorated_link_expression)
+    }
+
+    /// Looks up the stored version of `link`. Encrypted links are handed out
+    /// decrypted, so they are found by their signature instead of their target.
+    fn stored_link(
+        uuid: &str,
+        link: &LinkExpression,
+    ) -> Result<Option<(LinkExpression, LinkStatus)>, AnyError> {
+        if let Some(found) = Ad4mDb::with_global_instance(|db| db.get_link(uuid, link))? {
+            return Ok(Some(found));
+        }
+        Ok(
+            Ad4mDb::with_global_instance(|db| db.get_links_by_source(uuid, &link.data.source))?
+                .into_iter()
+                .find(|(stored, _)| {
+                    encrypted_links::is_encrypted(stored)
+                        && stored.author == link.author
+                        && stored.timestamp == link.timestamp
+                        && stored.proof.signature == link.proof.signature
+                }),
+        )
+    }
+
+    /// Appends an applied diff to the perspective's history and drops the diffs
+    /// that are past the retention period. The change itself already happened,
+    /// so failing to record it only gets logged.
+    fn record_history(
+        uuid: &str,
+        diff: PerspectiveDiff,
+        local: bool,
+        undoes: Option<u64>,
+    ) -> Option<u64> {
+        let applied_at = chrono::Utc::now().timestamp_millis();
+        let retention = HISTORY_RETENTION_MS.load(Ordering::Relaxed);
+        match Ad4mDb::with_global_instance(|db| {
+            if retention > 0 {
+                db.prune_history(uuid, applied_at - retention)?;
+            }
+            db.add_history_diff(uuid, &diff, local, undoes, applied_at)
+        }) {
+            Ok(id) => Some(id),
+            Err(e) => {
+                log::error!("Failed to record history of perspective {}: {}", uuid, e);
+                None
+            }
+        }
+    }
+
     async fn pubsub_publish_diff(&self, decorated_diff: DecoratedPerspectiveDiff) {
         let handle = self.persisted.lock().await.clone();
 
@@ -652,6 +855,12 @@ impl PerspectiveInstance {
     ) -> Result<DecoratedLinkExpression, AnyError> {
         let handle = self.persisted.lock().await.clone();
         Ad4mDb::with_global_instance(|db| db.add_link(&handle.uuid, &link_expression, &status))?;
+        Self::record_history(
+            &handle.uuid,
+            PerspectiveDiff::from_additions(with_status(&[link_expression.clone()], &status)),
+            true,
+            None,
+        );
 
         let diff = PerspectiveDiff::from_additions(vec![link_expression.clone()]);
         let decorated_link_expression =
@@ -696,6 +905,12 @@ impl PerspectiveInstance {
         Ad4mDb::with_global_instance(|db| {
             db.add_many_links(&uuid, link_expressions.clone(), &status)
         })?;
+        Self::record_history(
+            &uuid,
+            PerspectiveDiff::from_additions(with_status(&link_expressions, &status)),
+            true,
+            None,
+        );
 
         self.spawn_prolog_facts_update(decorated_perspective_diff.clone());
         self.pubsub_publish_diff(decorated_perspective_diff).await;
@@ -706,6 +921,44 @@ impl PerspectiveInstance {
         Ok(decorated_link_expressions)
     }
 
+    /// Adds already signed links (e.g. from an imported archive) with their
+    /// original statuses. Unlike `add_links()`, nothing gets committed to the
+    /// link language.
+    pub async fn import_links(
+        &mut self,
+        links: Vec<DecoratedLinkExpression>,
+    ) -> Result<Vec<DecoratedLinkExpression>, AnyError> {
+        let uuid = self.persisted.lock().await.uuid.clone();
+        for status in [LinkStatus::Shared, LinkStatus::Local] {
+            let link_expressions = links
+                .iter()
+                .filter(|l| l.status.clone().unwrap_or_default() == status)
+                .cloned()
+                .map(LinkExpression::from)
+                .collect::<Vec<LinkExpression>>();
+            if !link_expressions.is_empty() {
+                Ad4mDb::with_global_instance(|db| {
+                    db.add_many_links(&uuid, link_expressions, &status)
+                })?;
+            }
+        }
+
+        Self::record_history(
+            &uuid,
+            PerspectiveDiff::from_additions(
+                links.iter().cloned().map(LinkExpression::from).collect(),
+            ),
+            true,
+            None,
+        );
+
+        let decorated_perspective_diff = DecoratedPerspectiveDiff::from_additions(links.clone());
+        self.spawn_prolog_facts_update(decorated_perspective_diff.clone());
+        self.pubsub_publish_diff(decorated_perspective_diff).await;
+        *(self.links_have_changed.lock().await) = true;
+        Ok(links)
+    }
+
     pub async fn link_mutations(
         &mut self,
         mutations: LinkMutations,
@@ -734,6 +987,15 @@ impl PerspectiveInstance {
         }
 
         let diff = PerspectiveDiff::from(additions.clone(), removals.clone());
+        Self::record_history(
+            &handle.uuid,
+            PerspectiveDiff::from(
+                with_status(&additions, &status),
+                with_status(&removals, &status),
+            ),
+            true,
+            None,
+        );
         let decorated_diff = DecoratedPerspectiveDiff {
             additions: additions
                 .into_iter()
@@ -787,6 +1049,15 @@ impl PerspectiveInstance {
         })?;
 
         let diff = PerspectiveDiff::from(vec![new_link_expression.clone()], vec![old_link.clone()]);
+        Self::record_history(
+            &handle.uuid,
+            PerspectiveDiff::from(
+                with_status(&[new_link_expression.clone()], &link_status),
+                with_status(&[link], &link_status),
+            ),
+            true,
+            None,
+        );
         let decorated_new_link_expression =
             DecoratedLinkExpression::from((new_link_expression.clone(), link_status.clone()));
         let decorated_old_link =
@@ -823,12 +1094,24 @@ impl PerspectiveInstance {
         link_expression: LinkExpression,
     ) -> Result<DecoratedLinkExpression, AnyError> {
         let handle = self.persisted.lock().await.clone();
-        if let Some((link_from_db, status)) =
-            Ad4mDb::with_global_instance(|db| db.get_link(&handle.uuid, &link_expression))?
-        {
+        if let Some((link_from_db, status)) = Self::stored_link(&handle.uuid, &link_expression)? {
+            let link_expression = if encrypted_links::is_encrypted(&link_from_db) {
+                link_from_db.clone()
+            } else {
+                link_expression
+            };
             Ad4mDb::with_global_instance(|db| db.remove_link(&handle.uuid, &link_expression))?;
             let diff = PerspectiveDiff::from_removals(vec![link_expression.clone()]);
-            let decorated_link = DecoratedLinkExpression::from((link_from_db, status.clone()));
+            Self::record_history(
+                &handle.uuid,
+                PerspectiveDiff::from_removals(with_status(&[link_from_db.clone()], &status)),
+                true,
+                None,
+            );
+            let decorated_link = DecoratedLinkExpression::from((
+                encrypted_links::revealed(link_from_db),
+                status.clone(),
+            ));
             let decorated_diff =
                 DecoratedPerspectiveDiff::from_removals(vec![decorated_link.clone()]);
 
@@ -855,9 +1138,7 @@ impl PerspectiveInstance {
         // Filter to only existing links and collect their statuses
         let mut existing_links = Vec::new();
         for link in link_expressions {
-            if let Some((link_from_db, status)) =
-                Ad4mDb::with_global_instance(|db| db.get_link(&handle.uuid, &link))?
-            {
+            if let Some((link_from_db, status)) = Self::stored_link(&handle.uuid, &link)? {
                 existing_links.push((link_from_db, status));
             }
         }
@@ -875,9 +1156,14 @@ impl PerspectiveInstance {
 
         // Create decorated versions
         let decorated_links: Vec<DecoratedLinkExpression> = links
-            .into_iter()
+            .iter()
             .zip(statuses.iter())
-            .map(|(link, status)| DecoratedLinkExpression::from((link, status.clone())))
+            .map(|(link, status)| {
+                DecoratedLinkExpression::from((
+                    encrypted_links::revealed(link.clone()),
+                    status.clone(),
+                ))
+            })
             .collect();
 
         let decorated_diff = DecoratedPerspectiveDiff::from_removals(decorated_links.clone());
@@ -886,15 +1172,31 @@ impl PerspectiveInstance {
         for link in diff.removals.iter() {
             Ad4mDb::with_global_instance(|db| db.remove_link(&handle.uuid, link))?;
         }
+        Self::record_history(
+            &handle.uuid,
+            PerspectiveDiff::from_removals(
+                links
+                    .iter()
+                    .zip(statuses.iter())
+                    .map(|(link, status)| LinkExpression {
+                        status: Some(status.clone()),
+                        ..link.clone()
+                    })
+                    .collect(),
+            ),
+            true,
+            None,
+        );
 
         self.spawn_prolog_facts_update(decorated_diff.clone());
         self.pubsub_publish_diff(decorated_diff).await;
 
-        // Only commit shared links by filtering decorated_links
-        let shared_links: Vec<LinkExpression> = decorated_links
-            .iter()
-            .filter(|link| link.status == Some(LinkStatus::Shared))
-            .map(|link| link.clone().into())
+        // Only commit shared links, as stored so encrypted ones stay encrypted
+        let shared_links: Vec<LinkExpression> = links
+            .into_iter()
+            .zip(statuses.iter())
+            .filter(|(_, status)| **status == LinkStatus::Shared)
+            .map(|(link, _)| link)
             .collect();
 
         if !shared_links.is_empty() {
@@ -909,85 +1211,36 @@ impl PerspectiveInstance {
         Ok(decorated_links)
     }
 
-    async fn get_links_local(
-        &self,
-        query: &LinkQuery,
-    ) -> Result<Vec<(LinkExpression, LinkStatus)>, AnyError> {
-        let uuid = self.persisted.lock().await.uuid.clone();
-        let mut result =
-            if query.source.is_none() && query.predicate.is_none() && query.target.is_none() {
-                Ad4mDb::with_global_instance(|db| db.get_all_links(&uuid))?
-            } else if let Some(source) = &query.source {
-                Ad4mDb::with_global_instance(|db| db.get_links_by_source(&uuid, source))?
-            } else if let Some(target) = &query.target {
-                Ad4mDb::with_global_instance(|db| db.get_links_by_target(&uuid, target))?
-            } else if let Some(predicate) = &query.predicate {
-                Ad4mDb::with_global_instance(|db| {
-                    Ok::<Vec<(LinkExpression, LinkStatus)>, AnyError>(
-                        db.get_all_links(&uuid)?
-                            .into_iter()
-                            .filter(|(link, _)| link.data.predicate.as_ref() == Some(predicate))
-                            .collect::<Vec<(LinkExpression, LinkStatus)>>(),
-                    )
-                })?
-            } else {
-                vec![]
-            };
-
-        if let Some(predicate) = &query.predicate {
-            result.retain(|(link, _status)| link.data.predicate.as_ref() == Some(predicate));
-        }
-
-        if let Some(target) = &query.target {
-            result.retain(|(link, _status)| link.data.target == *target);
-        }
-
-        if let Some(source) = &query.source {
-            result.retain(|(link, _status)| link.data.source == *source);
-        }
-
-        let until_date: Option<chrono::DateTime<chrono::Utc>> =
-            query.until_date.clone().map(|d| d.into());
-        let from_date: Option<chrono::DateTime<chrono::Utc>> =
-            query.from_date.clone().map(|d| d.into());
-
-        if let Some(from_date) = &from_date {
-            result.retain(|(link, _)| {
-                let link_date = DateTime::parse_from_rfc3339(&link.timestamp).unwrap();
-                link_date >= *from_date
-            });
-        }
-
-        if let Some(until_date) = &until_date {
-            result.retain(|(link, _)| {
-                let link_date = DateTime::parse_from_rfc3339(&link.timestamp).unwrap();
-                link_date <= *until_date
-            });
-        }
-        /*
-
-        if let Some(limit) = query.limit {
-            let limit = limit as usize;
-            let result_length = result.len();
-            let start_limit = if from_date >= until_date {
-                result_length.saturating_sub(limit)
-            } else {
-                0
-            } as usize;
-
-            let end_limit = if from_date >= until_date {
-                result_length
-            } else {
-                limit.min(result_length)
-            } as usize;
+    pub async fn get_links(&self, q: &LinkQuery) -> Result<Vec<DecoratedLinkExpression>, AnyError> {
+        Ok(self
+            .query_links_page(q)
+            .await?
+            .links
+            .into_iter()
+            .map(DecoratedLinkExpression::from)
+            .collect())
+    }
 
-            result = result[..limit as usize].to_vec();
-        }
-        */
-        Ok(result)
+    /// Like `get_links()`, but with each link's `cursor` set so that the next
+    /// page can be requested with `LinkQuery::after` (or `before`)
+    pub async fn get_links_with_cursors(
+        &self,
+        q: &LinkQuery,
+    ) -> Result<Vec<DecoratedLinkExpression>, AnyError> {
+        let page = self.query_links_page(q).await?;
+        Ok(page
+            .links
+            .into_iter()
+            .zip(page.cursors)
+            .map(|(link, cursor)| {
+                let mut link = DecoratedLinkExpression::from(link);
+                link.cursor = Some(cursor.encode());
+                link
+            })
+            .collect())
     }
 
-    pub async fn get_links(&self, q: &LinkQuery) -> Result<Vec<DecoratedLinkExpression>, AnyError> {
+    async fn query_links_page(&self, q: &LinkQuery) -> Result<LinksPage, AnyError> {
         let mut reverse = false;
         let mut query = q.clone();
 
@@ -1003,29 +1256,208 @@ impl PerspectiveInstance {
             }
         }
 
-        let mut links = self.get_links_local(&query).await?;
+        let descending = match query.direction.as_deref() {
+            None => reverse,
+            Some("asc") => false,
+            Some("desc") => true,
+            Some(other) => {
+                return Err(anyhow!(
+                    "Invalid link query direction: {}. Must be one of 'asc' or 'desc'.",
+                    other
+                ))
+            }
+        };
+
+        let uuid = self.persisted.lock().await.uuid.clone();
+        let backwards = query.before.is_some() && query.after.is_none();
+        let limit = query.limit.map(|l| l.max(0) as usize);
+        let mut page = LinksPage {
+            links: Vec::new(),
+            cursors: Vec::new(),
+            start_cursor: None,
+            end_cursor: None,
+            has_more: false,
+        };
 
-        links.sort_by(|(a, _), (b, _)| {
-            let a_time = DateTime::parse_from_rfc3339(&a.timestamp).unwrap();
-            let b_time = DateTime::parse_from_rfc3339(&b.timestamp).unwrap();
-            if reverse {
-                b_time.cmp(&a_time)
+        // Encrypted links we aren't a recipient of are left out, so we keep
+        // fetching past them until the page is full or there are no more links
+        loop {
+            let fetched =
+                Ad4mDb::with_global_instance(|db| db.get_links_page(&uuid, &query, descending))?;
+            let (mut links, mut cursors): (Vec<_>, Vec<_>) = encrypted_links::reveal_with(
+                fetched
+                    .links
+                    .into_iter()
+                    .zip(fetched.cursors)
+                    .map(|((link, status), cursor)| (link, status, cursor))
+                    .collect(),
+            )
+            .into_iter()
+            .map(|(link, status, cursor)| ((link, status), cursor))
+            .unzip();
+
+            // Paging backwards, every fetch ends where the previous one started
+            if backwards {
+                links.append(&mut page.links);
+                cursors.append(&mut page.cursors);
+                page.links = links;
+                page.cursors = cursors;
+                page.start_cursor = fetched.start_cursor;
+                page.end_cursor = page.end_cursor.take().or(fetched.end_cursor);
             } else {
-                a_time.cmp(&b_time)
+                page.links.append(&mut links);
+                page.cursors.append(&mut cursors);
+                page.start_cursor = page.start_cursor.take().or(fetched.start_cursor);
+                page.end_cursor = fetched.end_cursor;
             }
-        });
+            page.has_more = fetched.has_more;
 
-        if let Some(limit) = query.limit {
-            let limit = links.len().min(limit as usize);
-            links = links[..limit].to_vec();
+            let missing = limit
+                .map(|limit| limit.saturating_sub(page.links.len()))
+                .unwrap_or(0);
+            if missing == 0 || !page.has_more {
+                return Ok(page);
+            }
+            query.limit = Some(missing as i32);
+            if backwards {
+                query.before = page.start_cursor.as_ref().map(|c| c.encode());
+            } else {
+                query.after = page.end_cursor.as_ref().map(|c| c.encode());
+            }
         }
+    }
+
+    /// The links of the perspective as they were at `at`
+    pub async fn get_links_at(
+        &self,
+        at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<DecoratedLinkExpression>, AnyError> {
+        let uuid = self.persisted.lock().await.uuid.clone();
+        let links =
+            Ad4mDb::with_global_instance(|db| db.get_links_at(&uuid, at.timestamp_millis()))?;
+        Ok(encrypted_links::reveal(links)
+            .into_iter()
+            .map(DecoratedLinkExpression::from)
+            .collect())
+    }
 
-        Ok(links
+    /// Diffs applied to the perspective, newest first
+    pub async fn history(
+        &self,
+        since: Option<chrono::DateTime<chrono::Utc>>,
+        limit: Option<usize>,
+    ) -> Result<Vec<PerspectiveHistoryEntry>, AnyError> {
+        let uuid = self.persisted.lock().await.uuid.clone();
+        let diffs = Ad4mDb::with_global_instance(|db| {
+            db.get_history(&uuid, since.map(|s| s.timestamp_millis()), limit)
+        })?;
+        Ok(diffs
             .into_iter()
-            .map(|(link, status)| DecoratedLinkExpression::from((link.clone(), status)))
+            .map(|entry| {
+                let mut authors = Vec::new();
+                for link in entry
+                    .diff
+                    .additions
+                    .iter()
+                    .chain(entry.diff.removals.iter())
+                {
+                    if !authors.contains(&link.author) {
+                        authors.push(link.author.clone());
+                    }
+                }
+                PerspectiveHistoryEntry {
+                    id: entry.id.to_string(),
+                    additions: decorate_history_links(entry.diff.additions),
+                    removals: decorate_history_links(entry.diff.removals),
+                    authors,
+                    local: entry.local,
+                    applied_at: millis_to_timestamp(entry.applied_at),
+                    undoes: entry.undoes.map(|id| id.to_string()),
+                    undone_by: entry.undone_by.map(|id| id.to_string()),
+                }
+            })
             .collect())
     }
 
+    /// Reverts a local diff of the history: removes the links it added that are
+    /// still there and adds back the ones it removed. The inverse is recorded and
+    /// committed like any other change.
+    pub async fn undo(&mut self, diff_id: u64) -> Result<DecoratedPerspectiveDiff, AnyError> {
+        let handle = self.persisted.lock().await.clone();
+        let entry = Ad4mDb::with_global_instance(|db| db.get_history_diff(&handle.uuid, diff_id))?
+            .ok_or_else(|| {
+                ExecutorError::NotFound(format!(
+                    "Diff {} not found in perspective history",
+                    diff_id
+                ))
+            })?;
+        if !entry.local {
+            return Err(ExecutorError::InvalidInput(
+                "Only diffs made by this agent can be undone".to_string(),
+            )
+            .into());
+        }
+        if let Some(undone_by) = entry.undone_by {
+            return Err(ExecutorError::InvalidInput(format!(
+                "Diff {} was already undone by diff {}",
+                diff_id, undone_by
+            ))
+            .into());
+        }
+
+        let mut removals = Vec::new();
+        for link in &entry.diff.additions {
+            if let Some((stored, status)) =
+                Ad4mDb::with_global_instance(|db| db.get_link(&handle.uuid, link))?
+            {
+                removals.push(LinkExpression {
+                    status: Some(status),
+                    ..stored
+                });
+            }
+        }
+        let mut additions = Vec::new();
+        for link in entry.diff.removals {
+            if Ad4mDb::with_global_instance(|db| db.get_link(&handle.uuid, &link))?.is_none() {
+                additions.push(link);
+            }
+        }
+
+        Ad4mDb::with_global_instance(|db| -> Result<(), AnyError> {
+            for link in &removals {
+                db.remove_link(&handle.uuid, link)?;
+            }
+            for link in &additions {
+                db.add_link(&handle.uuid, link, &link.status.clone().unwrap_or_default())?;
+            }
+            Ok(())
+        })?;
+        let inverse = PerspectiveDiff::from(additions, removals);
+        Self::record_history(&handle.uuid, inverse.clone(), true, Some(diff_id));
+
+        let decorated_diff = DecoratedPerspectiveDiff::from(
+            decorate_history_links(inverse.additions.clone()),
+            decorate_history_links(inverse.removals.clone()),
+        );
+        self.spawn_prolog_facts_update(decorated_diff.clone());
+        self.pubsub_publish_diff(decorated_diff.clone()).await;
+
+        let shared = |links: &[LinkExpression]| {
+            links
+                .iter()
+                .filter(|link| link.status == Some(LinkStatus::Shared))
+                .cloned()
+                .collect::<Vec<LinkExpression>>()
+        };
+        let shared_diff =
+            PerspectiveDiff::from(shared(&inverse.additions), shared(&inverse.removals));
+        if !shared_diff.is_empty() {
+            self.spawn_commit_and_handle_error(&shared_diff);
+        }
+        *(self.links_have_changed.lock().await) = true;
+        Ok(decorated_diff)
+    }
+
     /// Adds the given Social DNA code to the perspective's SDNA code
     pub async fn add_sdna(
         &mut self,
@@ -1052,9 +1484,7 @@ impl PerspectiveInstance {
                 source: Some("ad4m://self".to_string()),
                 predicate: Some(predicate.to_string()),
                 target: Some(literal_name.clone()),
-                from_date: None,
-                until_date: None,
-                limit: None,
+                ..Default::default()
             })
             .await?;
 
@@ -1094,24 +1524,21 @@ impl PerspectiveInstance {
     }
 
     async fn ensure_prolog_engine(&self) -> Result<(), AnyError> {
-        let has_prolog_engine = { self.prolog_engine.lock().await.is_some() };
+        let has_prolog_engine = { self.prolog_engine_pool.read().await.is_some() };
 
         let mut rebuild_flag = self.prolog_needs_rebuild.lock().await;
 
         if !has_prolog_engine || *rebuild_flag {
             let _update_lock = self.prolog_update_mutex.write().await;
-            let mut maybe_prolog_engine = self.prolog_engine.lock().await;
-            if *rebuild_flag && maybe_prolog_engine.is_some() {
-                let old_engine = maybe_prolog_engine.as_ref().unwrap();
-                let _ = old_engine.drop();
-                *rebuild_flag = false;
+            let mut maybe_prolog_engine_pool = self.prolog_engine_pool.write().await;
+            if let Some(old_pool) = maybe_prolog_engine_pool.as_ref() {
+                let _ = old_pool.drop();
             }
+            *rebuild_flag = false;
 
-            let mut engine = PrologEngine::new();
-            engine
-                .spawn()
+            let pool = PrologEnginePool::spawn(engine_pool_size())
                 .await
-                .map_err(|e| anyhow!("Failed to spawn Prolog engine: {}", e))?;
+                .map_err(|e| anyhow!("Failed to spawn Prolog engines: {}", e))?;
             let all_links = self.get_links(&LinkQuery::default()).await?;
             let facts = init_engine_facts(
                 all_links,
@@ -1123,10 +1550,8 @@ impl PerspectiveInstance {
                     .map(|n| n.author.clone()),
             )
             .await?;
-            engine
-                .load_module_string("facts".to_string(), facts)
-                .await?;
-            *maybe_prolog_engine = Some(engine);
+            pool.load_module_string("facts".to_string(), facts).await?;
+            *maybe_prolog_engine_pool = Some(pool);
         }
 
         Ok(())
@@ -1134,14 +1559,33 @@ impl PerspectiveInstance {
 
     /// Executes a Prolog query against the engine, spawning and initializing the engine if necessary.
     pub async fn prolog_query(&self, query: String) -> Result<QueryResolution, AnyError> {
+        self.prolog_query_with_options(query, PrologQueryOptions::default(), None)
+            .await
+    }
+
+    /// Like `prolog_query()` but with explicit limits.
+    /// Queries with a `query_id` can be aborted with `cancel_prolog_query()`.
+    /// Fails with a `PrologQueryTimeout` if a limit is hit or the query got cancelled,
+    /// in which case the engine gets restarted if it might still be busy.
+    pub async fn prolog_query_with_options(
+        &self,
+        query: String,
+        options: PrologQueryOptions,
+        query_id: Option<String>,
+    ) -> Result<QueryResolution, AnyError> {
         self.ensure_prolog_engine().await?;
 
-        let _read_lock = self.prolog_update_mutex.read().await;
-        let prolog_engine_mutex = self.prolog_engine.lock().await;
-        let prolog_engine_option_ref = prolog_engine_mutex.as_ref();
-        let prolog_engine = prolog_engine_option_ref
-            .as_ref()
-            .expect("Must be some since we initialized the engine above");
+        let cancel = match query_id.as_ref() {
+            Some(id) => {
+                let (cancel_sender, cancel_receiver) = oneshot::channel();
+                self.running_prolog_queries
+                    .lock()
+                    .await
+                    .insert(id.clone(), cancel_sender);
+                Some(cancel_receiver)
+            }
+            None => None,
+        };
 
         let query = if !query.ends_with('.') {
             query + "."
@@ -1149,15 +1593,51 @@ impl PerspectiveInstance {
             query
         };
 
-        let result = prolog_engine.run_query(query).await?;
+        let result = {
+            let _read_lock = self.prolog_update_mutex.read().await;
+            let prolog_engine_pool = self.prolog_engine_pool.read().await;
+            prolog_engine_pool
+                .as_ref()
+                .expect("Must be some since we initialized the engine above")
+                .run_query_with_options(query, &options, cancel)
+                .await
+        };
+
+        if let Some(id) = query_id {
+            self.running_prolog_queries.lock().await.remove(&id);
+        }
 
         match result {
             Err(e) => {
+                if let Some(timeout) = e.downcast_ref::<PrologQueryTimeout>() {
+                    if timeout.engine_needs_restart() {
+                        log::warn!("{}, restarting Prolog engine", timeout);
+                        *self.prolog_needs_rebuild.lock().await = true;
+                        let self_clone = self.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = self_clone.ensure_prolog_engine().await {
+                                log::error!("Error restarting Prolog engine: {:?}", e);
+                            }
+                        });
+                    }
+                }
+                Err(e)
+            }
+            Ok(Err(e)) => {
                 let mut flag = self.prolog_needs_rebuild.lock().await;
                 *flag = true;
-                Err(anyhow!(e))
+                Err(ExecutorError::PrologError(e.to_string()).into())
             }
-            Ok(resolution) => Ok(resolution),
+            Ok(Ok(resolution)) => Ok(resolution),
+        }
+    }
+
+    /// Cancels the running Prolog query with the given id.
+    /// Returns false if no such query is running.
+    pub async fn cancel_prolog_query(&self, query_id: &str) -> bool {
+        match self.running_prolog_queries.lock().await.remove(query_id) {
+            Some(cancel_sender) => cancel_sender.send(()).is_ok(),
+            None => false,
         }
     }
 
@@ -1169,39 +1649,24 @@ impl PerspectiveInstance {
                 log::error!("Error spawning Prolog engine: {:?}", e)
             };
 
-            let fact_rebuild_needed = !diff.removals.is_empty()
-                || diff.additions.iter().any(|link| is_sdna_link(&link.data));
-
-            let did_update = if !fact_rebuild_needed {
-                let mut assertions: Vec<String> = Vec::new();
-                for addition in &diff.additions {
-                    assertions.push(generic_link_fact("assert_link_and_triple", addition));
-                }
-
-                let query = format!("{}.", assertions.join(","));
-                match self_clone.prolog_query(query).await {
-                    Ok(QueryResolution::True) => true,
+            let did_update = match fact_updates_from_diff(&diff) {
+                Some(goals) => match self_clone.apply_prolog_fact_updates(goals).await {
+                    Ok(()) => true,
                     Err(e) => {
-                        log::error!(
-                            "Error while running assertion query to updating Prolog engine facts: {:?}", e
-                        );
-                        false
-                    }
-                    other => {
-                        log::error!(
-                            "Error getting non-true result from assertion query while updating Prolog engine facts: {:?}", other
+                        log::warn!(
+                            "Incremental Prolog fact update failed, rebuilding all facts: {:?}",
+                            e
                         );
-                        false
+                        self_clone.update_prolog_engine_facts().await.is_ok()
                     }
-                }
-            } else {
-                match self_clone.update_prolog_engine_facts().await {
+                },
+                None => match self_clone.update_prolog_engine_facts().await {
                     Ok(()) => true,
                     Err(e) => {
                         log::error!("Error while updating Prolog engine facts: {:?}", e);
                         false
                     }
-                }
+                },
             };
 
             if did_update {
@@ -1224,8 +1689,18 @@ impl PerspectiveInstance {
         let notifications = Self::all_notifications_for_perspective_id(uuid)?;
         let mut result_map = BTreeMap::new();
         for n in notifications {
-            if let QueryResolution::Matches(matches) = self.prolog_query(n.trigger.clone()).await? {
-                result_map.insert(n.clone(), matches);
+            match self.prolog_query(n.trigger.clone()).await {
+                Ok(QueryResolution::Matches(matches)) => {
+                    result_map.insert(n.clone(), matches);
+                }
+                Ok(_) => {}
+                Err(e) => match e.downcast_ref::<PrologQueryTimeout>() {
+                    // A runaway trigger must not keep the other notifications from firing
+                    Some(timeout) => {
+                        log::warn!("Trigger of notification {} aborted: {}", n.id, timeout)
+                    }
+                    None => return Err(e),
+                },
             }
         }
 
@@ -1285,27 +1760,36 @@ impl PerspectiveInstance {
 
                 if url::Url::parse(&notification.webhook_url).is_ok() {
                     log::info!(
-                        "Notification webhook - posting to {:?}",
+                        "Notification webhook - queueing delivery to {:?}",
                         notification.webhook_url
                     );
-                    let client = reqwest::Client::new();
-                    let res = client
-                        .post(&notification.webhook_url)
-                        .bearer_auth(&notification.webhook_auth)
-                        .header("Content-Type", "application/json")
-                        .body(message.clone())
-                        .send()
-                        .await;
-                    log::info!("Notification webhook response: {:?}", res);
+                    if let Err(e) = notification_webhooks::queue_delivery(&notification, &message) {
+                        log::error!("Error queueing notification webhook delivery: {:?}", e);
+                    }
                 }
             }
         }
     }
 
+    /// Applies `assertz`/`retract` goals to the link facts of all engines.
+    /// Queries wait until every engine got the update so they all see the same facts.
+    async fn apply_prolog_fact_updates(&self, goals: Vec<String>) -> Result<(), AnyError> {
+        if goals.is_empty() {
+            return Ok(());
+        }
+        let _write_lock = self.prolog_update_mutex.write().await;
+        let prolog_engine_pool = self.prolog_engine_pool.read().await;
+        prolog_engine_pool
+            .as_ref()
+            .ok_or(anyhow!("Prolog engine not initialized"))?
+            .update_facts(goals)
+            .await
+    }
+
     async fn update_prolog_engine_facts(&self) -> Result<(), AnyError> {
-        let prolog_engine_mutex = self.prolog_engine.lock().await;
-        let prolog_engine_option_ref = prolog_engine_mutex.as_ref();
-        let prolog_engine = prolog_engine_option_ref
+        let _write_lock = self.prolog_update_mutex.write().await;
+        let prolog_engine_pool_guard = self.prolog_engine_pool.read().await;
+        let prolog_engine_pool = prolog_engine_pool_guard
             .as_ref()
             .expect("Must be some since we initialized the engine above");
         let all_links = self.get_links(&LinkQuery::default()).await?;
@@ -1319,20 +1803,30 @@ impl PerspectiveInstance {
                 .map(|n| n.author.clone()),
         )
         .await?;
-        prolog_engine
+        prolog_engine_pool
             .load_module_string("facts".to_string(), facts)
             .await?;
 
         Ok(())
     }
 
+    /// Queue depths of the Prolog engines, `None` if no engine was spawned yet
+    pub async fn prolog_engine_metrics(&self) -> Option<PrologEnginePoolMetrics> {
+        self.prolog_engine_pool
+            .read()
+            .await
+            .as_ref()
+            .map(|pool| pool.metrics())
+    }
+
     async fn no_link_language_error(&self) -> AnyError {
         let handle = self.persisted.lock().await.clone();
-        anyhow!(
+        log::debug!(
             "Perspective {} has no link language installed. State is: {:?}",
             handle.uuid,
             handle.state
-        )
+        );
+        ExecutorError::LinkLanguageMissing(handle.uuid).into()
     }
 
     pub async fn others(&self) -> Result<Vec<String>, AnyError> {
@@ -1470,9 +1964,7 @@ impl PerspectiveInstance {
                             source: Some(source),
                             predicate,
                             target: Some(target),
-                            from_date: None,
-                            until_date: None,
-                            limit: None,
+                            ..Default::default()
                         })
                         .await?;
                     for link_expression in link_expressions {
@@ -1485,9 +1977,7 @@ impl PerspectiveInstance {
                             source: Some(source.clone()),
                             predicate: predicate.clone(),
                             target: None,
-                            from_date: None,
-                            until_date: None,
-                            limit: None,
+                            ..Default::default()
                         })
                         .await?;
                     for link_expression in link_expressions {
@@ -1509,9 +1999,7 @@ impl PerspectiveInstance {
                             source: Some(source.clone()),
                             predicate: predicate.clone(),
                             target: None,
-                            from_date: None,
-                            until_date: None,
-                            limit: None,
+                            ..Default::default()
                         })
                         .await?;
                     for link_expression in link_expressions {
@@ -1760,6 +2248,32 @@ impl PerspectiveInstance {
     }
 }
 
+/// Copies of `links` carrying `status`, as kept in the perspective history
+fn with_status(links: &[LinkExpression], status: &LinkStatus) -> Vec<LinkExpression> {
+    links
+        .iter()
+        .map(|link| LinkExpression {
+            status: Some(status.clone()),
+            ..link.clone()
+        })
+        .collect()
+}
+
+fn decorate_history_links(links: Vec<LinkExpression>) -> Vec<DecoratedLinkExpression> {
+    encrypted_links::reveal(
+        links
+            .into_iter()
+            .map(|link| {
+                let status = link.status.clone().unwrap_or_default();
+                (link, status)
+            })
+            .collect(),
+    )
+    .into_iter()
+    .map(DecoratedLinkExpression::from)
+    .collect()
+}
+
 pub fn prolog_result(result: String) -> Value {
     let v: Value = serde_json::from_str(&result).unwrap();
     match v {
@@ -1830,6 +2344,63 @@ mod tests {
         assert_eq!(links, all_links);
     }
 
+    #[tokio::test]
+    async fn test_history_and_undo() {
+        let mut perspective = setup();
+        let kept = perspective
+            .add_link(create_link(), LinkStatus::Local)
+            .await
+            .unwrap();
+        let before = chrono::Utc::now();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let removed = perspective
+            .add_link(create_link(), LinkStatus::Local)
+            .await
+            .unwrap();
+        perspective
+            .remove_link(removed.clone().into())
+            .await
+            .unwrap();
+
+        let history = perspective.history(None, None).await.unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].removals, vec![removed.clone()]);
+        assert!(history[0].local);
+        assert_eq!(history[0].authors, vec![removed.author.clone()]);
+
+        let links_at = perspective.get_links_at(before).await.unwrap();
+        assert_eq!(links_at, vec![kept.clone()]);
+
+        let removal_id = history[0].id.clone();
+        let inverse = perspective.undo(removal_id.parse().unwrap()).await.unwrap();
+        assert_eq!(inverse.additions, vec![removed.clone()]);
+        assert_eq!(
+            perspective
+                .get_links(&LinkQuery::default())
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        let error = perspective
+            .undo(removal_id.parse().unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ExecutorError>(),
+            Some(ExecutorError::InvalidInput(_))
+        ));
+        let error = perspective.undo(9999).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ExecutorError>(),
+            Some(ExecutorError::NotFound(_))
+        ));
+
+        let history = perspective.history(None, Some(1)).await.unwrap();
+        assert_eq!(history[0].undoes, Some(removal_id));
+        assert_eq!(history[0].additions, vec![removed]);
+    }
+
     #[tokio::test]
     async fn test_get_links_by_source() {
         let mut perspective = setup();
@@ -2000,6 +2571,168 @@ mod tests {
         assert_eq!(links_date_desc[2].data.target, all_links[2].data.target);
     }
 
+    #[tokio::test]
+    async fn test_get_links_with_cursors() {
+        let mut perspective = setup();
+        let mut all_links = Vec::new();
+        for _ in 0..3 {
+            let expression = perspective
+                .add_link(create_link(), LinkStatus::Local)
+                .await
+                .unwrap();
+            all_links.push(expression);
+        }
+
+        let first = perspective
+            .get_links_with_cursors(&LinkQuery {
+                direction: Some("asc".to_string()),
+                limit: Some(2),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(first.len(), 2);
+        assert!(first.iter().all(|link| link.cursor.is_some()));
+        assert_eq!(first[0].data, all_links[0].data);
+
+        let rest = perspective
+            .get_links_with_cursors(&LinkQuery {
+                direction: Some("asc".to_string()),
+                after: first[1].cursor.clone(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].data, all_links[2].data);
+    }
+
+    #[tokio::test]
+    async fn test_query_links_page_fills_up_past_undecryptable_links() {
+        let mut perspective = setup();
+        let uuid = perspective.persisted.lock().await.uuid.clone();
+        let mut readable = Vec::new();
+        for _ in 0..2 {
+            readable.push(
+                perspective
+                    .add_link(create_link(), LinkStatus::Local)
+                    .await
+                    .unwrap(),
+            );
+        }
+        // Encrypted for other agents only
+        for _ in 0..3 {
+            let sealed = LinkExpression {
+                author: "did:key:someone-else".to_string(),
+                timestamp: chrono::Utc::now().to_rfc3339(),
+                data: Link {
+                    target: format!("{}not-for-us", encrypted_links::ENCRYPTED_TARGET_PREFIX),
+                    ..create_link()
+                },
+                proof: ExpressionProof {
+                    key: String::new(),
+                    signature: String::new(),
+                },
+                status: Some(LinkStatus::Shared),
+            };
+            Ad4mDb::with_global_instance(|db| db.add_link(&uuid, &sealed, &LinkStatus::Shared))
+                .unwrap();
+        }
+        for _ in 0..2 {
+            readable.push(
+                perspective
+                    .add_link(create_link(), LinkStatus::Local)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let first_page = perspective
+            .query_links_page(&LinkQuery {
+                direction: Some("asc".to_string()),
+                limit: Some(3),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(first_page.links.len(), 3);
+        assert_eq!(first_page.links[2].0.data, readable[2].data);
+        assert!(first_page.has_more);
+
+        let previous_page = perspective
+            .query_links_page(&LinkQuery {
+                direction: Some("asc".to_string()),
+                limit: Some(3),
+                before: first_page.end_cursor.as_ref().map(|c| c.encode()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(previous_page.links.len(), 2);
+        assert_eq!(previous_page.links[0].0.data, readable[0].data);
+        assert!(!previous_page.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_query_links_page_direction_and_cursor() {
+        let mut perspective = setup();
+        let mut all_links = Vec::new();
+        for _ in 0..5 {
+            let expression = perspective
+                .add_link(create_link(), LinkStatus::Local)
+                .await
+                .unwrap();
+            all_links.push(expression);
+        }
+
+        let data = |page: &LinksPage| {
+            page.links
+                .iter()
+                .map(|(link, _)| link.data.clone())
+                .collect::<Vec<_>>()
+        };
+        let first_page = perspective
+            .query_links_page(&LinkQuery {
+                direction: Some("desc".to_string()),
+                limit: Some(2),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            data(&first_page),
+            vec![all_links[4].data.clone(), all_links[3].data.clone()]
+        );
+        assert!(first_page.has_more);
+
+        let second_page = perspective
+            .query_links_page(&LinkQuery {
+                direction: Some("desc".to_string()),
+                limit: Some(10),
+                after: first_page.end_cursor.as_ref().map(|c| c.encode()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            data(&second_page),
+            vec![
+                all_links[2].data.clone(),
+                all_links[1].data.clone(),
+                all_links[0].data.clone()
+            ]
+        );
+        assert!(!second_page.has_more);
+
+        let invalid_direction = perspective
+            .get_links(&LinkQuery {
+                direction: Some("sideways".to_string()),
+                ..Default::default()
+            })
+            .await;
+        assert!(invalid_direction.is_err());
+    }
+
     // Additional tests for updateLink, removeLink, syncWithSharingAdapter, etc. would go here
     // following the same pattern as above.
 }
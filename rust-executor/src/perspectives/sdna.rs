@@ -1,4 +1,5 @@
 use crate::agent;
+use crate::graphql::graphql_types::DecoratedPerspectiveDiff;
 use crate::types::{DecoratedLinkExpression, ExpressionRef, LanguageRef, Link};
 use ad4m_client::literal::Literal;
 use chrono::DateTime;
@@ -110,6 +111,38 @@ pub fn is_sdna_link(link: &Link) -> bool {
         .contains(&link.predicate.as_deref().unwrap_or(""))
 }
 
+/// Whether adding or removing this link changes the perspective's Social DNA.
+/// SDNA code is only loaded with a full rebuild of the engine facts.
+pub fn is_sdna_change(link: &Link) -> bool {
+    is_sdna_link(link) || link.predicate.as_deref() == Some("ad4m://sdna")
+}
+
+/// Prolog goals that apply the given diff to the `triple/3` and `link/5` facts of an
+/// engine that was initialized with `init_engine_facts()`, removals first.
+/// Returns `None` if the diff touches SDNA, in which case a full rebuild is needed.
+pub fn fact_updates_from_diff(diff: &DecoratedPerspectiveDiff) -> Option<Vec<String>> {
+    if diff
+        .additions
+        .iter()
+        .chain(diff.removals.iter())
+        .any(|l| is_sdna_change(&l.data))
+    {
+        return None;
+    }
+
+    Some(
+        diff.removals
+            .iter()
+            .map(|l| generic_link_fact("retract_link_and_triple", l))
+            .chain(
+                diff.additions
+                    .iter()
+                    .map(|l| generic_link_fact("assert_link_and_triple", l)),
+            )
+            .collect(),
+    )
+}
+
 pub async fn init_engine_facts(
     all_links: Vec<DecoratedLinkExpression>,
     neighbourhood_author: Option<String>,
@@ -128,8 +161,13 @@ pub async fn init_engine_facts(
         .filter(|l| !is_sdna_link(&l.data))
         .collect();
 
+    // Several links can share a triple, but like assert_triple/3 we only keep one fact
+    let mut seen_triples = HashSet::new();
     for link in &links_without_sdna {
-        lines.push(format!("{}.", triple_fact(link)));
+        let triple = triple_fact(link);
+        if seen_triples.insert(triple.clone()) {
+            lines.push(format!("{}.", triple));
+        }
     }
     for link in &links_without_sdna {
         lines.push(format!("{}.", link_fact(link)));
@@ -372,6 +410,20 @@ url_decode_char(Char) --> [Char], { \+ member(Char, "%") }.
     assert_link_and_triple(Source, Predicate, Target, Timestamp, Author) :-
         (assert_link(Source, Predicate, Target, Timestamp, Author) ; true),
         (assert_triple(Source, Predicate, Target) ; true).
+
+    retract_link(Source, Predicate, Target, Timestamp, Author) :-
+        retract(link(Source, Predicate, Target, Timestamp, Author)), !.
+    retract_link(_, _, _, _, _).
+
+    retract_triple_if_unused(Source, Predicate, Target) :-
+        link(Source, Predicate, Target, _, _), !.
+    retract_triple_if_unused(Source, Predicate, Target) :-
+        retract(triple(Source, Predicate, Target)), !.
+    retract_triple_if_unused(_, _, _).
+
+    retract_link_and_triple(Source, Predicate, Target, Timestamp, Author) :-
+        retract_link(Source, Predicate, Target, Timestamp, Author),
+        retract_triple_if_unused(Source, Predicate, Target).
 "#;
     lines.extend(assert_link.split('\n').map(|s| s.to_string()));
 
@@ -448,3 +500,127 @@ url_decode_char(Char) --> [Char], { \+ member(Char, "%") }.
 
     Ok(lines)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::create_signed_expression;
+    use crate::graphql::graphql_types::LinkStatus;
+    use crate::prolog_service::engine::PrologEngine;
+    use crate::test_utils::setup_wallet;
+    use crate::types::LinkExpression;
+    use scryer_prolog::QueryResolution;
+
+    fn signed_link(source: &str, predicate: &str, target: &str) -> DecoratedLinkExpression {
+        let link = Link {
+            source: source.to_string(),
+            predicate: Some(predicate.to_string()),
+            target: target.to_string(),
+        };
+        let expression: LinkExpression = create_signed_expression(link).unwrap().into();
+        DecoratedLinkExpression::from((expression, LinkStatus::Local))
+    }
+
+    async fn engine_with_links(links: Vec<DecoratedLinkExpression>) -> PrologEngine {
+        let mut engine = PrologEngine::new();
+        engine.spawn().await.expect("Prolog engine should spawn");
+        let facts = init_engine_facts(links, None).await.unwrap();
+        engine
+            .load_module_string("facts".to_string(), facts)
+            .await
+            .expect("Facts should load");
+        engine
+    }
+
+    async fn sorted_results(engine: &PrologEngine, query: &str) -> Vec<String> {
+        match engine.run_query(query.to_string()).await.unwrap() {
+            Ok(QueryResolution::Matches(matches)) => {
+                let mut results = matches
+                    .iter()
+                    .map(|m| format!("{:?}", m))
+                    .collect::<Vec<String>>();
+                results.sort();
+                results
+            }
+            Ok(resolution) => vec![format!("{:?}", resolution)],
+            Err(e) => panic!("Query {} failed: {}", query, e),
+        }
+    }
+
+    #[tokio::test]
+    async fn incremental_fact_updates_match_full_rebuild() {
+        setup_wallet();
+        let a_b = signed_link("test://a", "test://p", "test://b");
+        let b_c = signed_link("test://b", "test://p", "test://c");
+        let c_d = signed_link("test://c", "test://p", "test://d");
+        // Same triple as a_b, so removing one of them has to keep the triple
+        let a_b_again = signed_link("test://a", "test://p", "test://b");
+
+        let incremental = engine_with_links(vec![a_b.clone(), b_c.clone()]).await;
+        let diff = DecoratedPerspectiveDiff {
+            additions: vec![c_d.clone(), a_b_again.clone()],
+            removals: vec![b_c.clone()],
+        };
+        let goals = fact_updates_from_diff(&diff).expect("Diff without SDNA");
+        incremental.update_facts(goals).await.unwrap();
+
+        let rebuilt = engine_with_links(vec![a_b.clone(), c_d.clone(), a_b_again.clone()]).await;
+
+        for query in [
+            "triple(S,P,T).",
+            "link(S,P,T,Timestamp,Author).",
+            "reachable(X,Y).",
+            "triple(\"test://b\",_,_).",
+        ] {
+            assert_eq!(
+                sorted_results(&incremental, query).await,
+                sorted_results(&rebuilt, query).await,
+                "Results differ for {}",
+                query
+            );
+        }
+
+        let removal = DecoratedPerspectiveDiff::from_removals(vec![a_b.clone(), a_b_again]);
+        incremental
+            .update_facts(fact_updates_from_diff(&removal).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            sorted_results(&incremental, "triple(\"test://a\",_,_).").await,
+            vec![format!("{:?}", QueryResolution::False)]
+        );
+    }
+
+    #[test]
+    fn sdna_changes_need_full_rebuild() {
+        setup_wallet();
+        let class = signed_link(
+            "ad4m://self",
+            "ad4m://has_subject_class",
+            "literal://string:Todo",
+        );
+        let code = signed_link(
+            "literal://string:Todo",
+            "ad4m://sdna",
+            "literal://string:code",
+        );
+        let other = signed_link("test://a", "test://p", "test://b");
+
+        assert!(
+            fact_updates_from_diff(&DecoratedPerspectiveDiff::from_additions(vec![
+                other.clone(),
+                class
+            ]))
+            .is_none()
+        );
+        assert!(
+            fact_updates_from_diff(&DecoratedPerspectiveDiff::from_removals(vec![code])).is_none()
+        );
+        assert_eq!(
+            fact_updates_from_diff(&DecoratedPerspectiveDiff::from_additions(vec![other]))
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::ai_service::AIService;
+use crate::db::Ad4mDb;
+use crate::graphql::graphql_types::LinkSearchResult;
+use crate::types::{DecoratedLinkExpression, ModelType};
+use deno_core::anyhow::anyhow;
+use deno_core::error::AnyError;
+
+pub const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+/// Number of links embedded per round of the embedding index loop
+pub const EMBEDDING_BATCH_SIZE: usize = 50;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchMode {
+    /// SQLite FTS5 over the decoded `literal://` link targets
+    FullText,
+    /// Cosine similarity of embeddings from the default embedding model
+    Semantic,
+}
+
+impl SearchMode {
+    pub fn from_string(s: &str) -> Result<Self, AnyError> {
+        match s {
+            "fulltext" => Ok(SearchMode::FullText),
+            "semantic" => Ok(SearchMode::Semantic),
+            _ => Err(anyhow!(
+                "Invalid search mode: {}. Must be one of 'fulltext' or 'semantic'.",
+                s
+            )),
+        }
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f64 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f64 = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| (*x as f64) * (*y as f64))
+        .sum();
+    let norm_a: f64 = a.iter().map(|x| (*x as f64).powi(2)).sum::<f64>().sqrt();
+    let norm_b: f64 = b.iter().map(|x| (*x as f64).powi(2)).sum::<f64>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+/// Embeds the next `EMBEDDING_BATCH_SIZE` link texts of a perspective that have
+/// no embedding of the default embedding model yet and returns how many got embedded.
+/// Does nothing without a default embedding model.
+pub async fn index_embeddings(perspective_uuid: &str) -> Result<usize, AnyError> {
+    let model_id =
+        match Ad4mDb::with_global_instance(|db| db.get_default_model(ModelType::Embedding))? {
+            Some(model_id) => model_id,
+            None => return Ok(0),
+        };
+    let missing = Ad4mDb::with_global_instance(|db| {
+        db.get_link_texts_without_embedding(perspective_uuid, &model_id, EMBEDDING_BATCH_SIZE)
+    })?;
+    if missing.is_empty() {
+        return Ok(0);
+    }
+
+    let model = Ad4mDb::with_global_instance(|db| db.get_model(model_id.clone()))?
+        .ok_or(anyhow!("Default embedding model {} not found", model_id))?;
+    let ai_service = AIService::global_instance().await?;
+    let count = missing.len();
+    for (link_id, link_text) in missing {
+        let embedding = ai_service.embed(model.name.clone(), link_text).await?;
+        Ad4mDb::with_global_instance(|db| db.set_link_embedding(link_id, &model_id, &embedding))?;
+    }
+    Ok(count)
+}
+
+/// Ranks the links that `index_embeddings()` already embedded, links added
+/// since the last round of indexing are not found yet
+async fn semantic_search(
+    perspective_uuid: &str,
+    text: &str,
+    limit: usize,
+) -> Result<Vec<LinkSearchResult>, AnyError> {
+    let model_id = Ad4mDb::with_global_instance(|db| db.get_default_model(ModelType::Embedding))?
+        .ok_or(anyhow!("Semantic search needs a default embedding model"))?;
+    let model = Ad4mDb::with_global_instance(|db| db.get_model(model_id.clone()))?
+        .ok_or(anyhow!("Default embedding model {} not found", model_id))?;
+    let ai_service = AIService::global_instance().await?;
+
+    let query_embedding = ai_service
+        .embed(model.name.clone(), text.to_string())
+        .await?;
+    let candidates =
+        Ad4mDb::with_global_instance(|db| db.get_link_embeddings(perspective_uuid, &model_id))?;
+
+    let mut results = candidates
+        .into_iter()
+        .map(|(link, status, text, embedding)| LinkSearchResult {
+            link: DecoratedLinkExpression::from((link, status)),
+            text,
+            score: cosine_similarity(&query_embedding, &embedding),
+        })
+        .collect::<Vec<LinkSearchResult>>();
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+    results.truncate(limit);
+    Ok(results)
+}
+
+/// Searches the links of a perspective by the text of their literal targets.
+/// `mode` defaults to full-text search.
+pub async fn search_links(
+    perspective_uuid: &str,
+    text: &str,
+    mode: Option<String>,
+    limit: Option<i32>,
+) -> Result<Vec<LinkSearchResult>, AnyError> {
+    let mode = mode
+        .as_deref()
+        .map(SearchMode::from_string)
+        .transpose()?
+        .unwrap_or(SearchMode::FullText);
+    let limit = limit
+        .map(|l| l.max(0) as usize)
+        .unwrap_or(DEFAULT_SEARCH_LIMIT);
+
+    match mode {
+        SearchMode::FullText => {
+            let results =
+                Ad4mDb::with_global_instance(|db| db.search_links(perspective_uuid, text, limit))?;
+            Ok(results
+                .into_iter()
+                .map(|(link, status, text, score)| LinkSearchResult {
+                    link: DecoratedLinkExpression::from((link, status)),
+                    text,
+                    score,
+                })
+                .collect())
+        }
+        SearchMode::Semantic => semantic_search(perspective_uuid, text, limit).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_search_modes() {
+        assert_eq!(
+            SearchMode::from_string("fulltext").unwrap(),
+            SearchMode::FullText
+        );
+        assert_eq!(
+            SearchMode::from_string("semantic").unwrap(),
+            SearchMode::Semantic
+        );
+        assert!(SearchMode::from_string("fuzzy").is_err());
+    }
+
+    #[test]
+    fn cosine_similarity_ranks_similar_vectors_higher() {
+        let query = [1.0, 0.0, 1.0];
+        assert!((cosine_similarity(&query, &[2.0, 0.0, 2.0]) - 1.0).abs() < 1e-9);
+        assert!(cosine_similarity(&query, &[1.0, 1.0, 0.0]) < 1.0);
+        assert_eq!(cosine_similarity(&query, &[0.0, 1.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&query, &[1.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&query, &[0.0, 0.0, 0.0]), 0.0);
+    }
+}
@@ -1,9 +1,113 @@
 use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use deno_core::anyhow::Error;
-use scryer_prolog::{Machine, QueryResult};
+use scryer_prolog::{Machine, QueryResolution, QueryResult, Value};
 use tokio::sync::{mpsc, oneshot};
 
+use super::error::PrologQueryTimeout;
+
+/// Maximum number of goals run in a single query by `PrologEngine::update_facts()`
+pub const FACT_UPDATE_BATCH_SIZE: usize = 500;
+
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_INFERENCE_LIMIT: u64 = 50_000_000;
+
+/// Number of dropped engines whose thread may still run a query that nobody
+/// waits for, above which queries without inference limit get `DEFAULT_INFERENCE_LIMIT`
+pub const MAX_ABANDONED_ENGINES: usize = 4;
+
+static ABANDONED_ENGINES: AtomicUsize = AtomicUsize::new(0);
+
+/// Limits of `PrologQueryOptions::default()`, 0 meaning unlimited
+static DEFAULT_TIMEOUT_MS: AtomicU64 = AtomicU64::new(DEFAULT_QUERY_TIMEOUT.as_millis() as u64);
+static DEFAULT_INFERENCES: AtomicU64 = AtomicU64::new(DEFAULT_INFERENCE_LIMIT);
+
+/// Sets the limits of queries that don't bring their own, like the ones of
+/// subject classes and SDNA (see `Ad4mConfig`). 0 turns a limit off.
+pub fn set_default_query_limits(timeout_ms: Option<u64>, inference_limit: Option<u64>) {
+    if let Some(timeout_ms) = timeout_ms {
+        DEFAULT_TIMEOUT_MS.store(timeout_ms, Ordering::Relaxed);
+    }
+    if let Some(inference_limit) = inference_limit {
+        DEFAULT_INFERENCES.store(inference_limit, Ordering::Relaxed);
+    }
+}
+
+/// Number of dropped engines that are still busy with a query
+pub fn abandoned_engines() -> usize {
+    ABANDONED_ENGINES.load(Ordering::SeqCst)
+}
+
+/// States of an engine thread, shared with its `PrologEngine`
+const ENGINE_IDLE: u8 = 0;
+const ENGINE_RUNNING: u8 = 1;
+const ENGINE_ABANDONED: u8 = 2;
+
+/// Variable that `call_with_inference_limit/3` binds its result to.
+/// It gets removed from the matches before they are handed back.
+const INFERENCE_LIMIT_RESULT_VAR: &str = "AD4MInferenceLimitResult";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrologQueryOptions {
+    /// Time to wait for the result, unlimited if `None`
+    pub timeout: Option<Duration>,
+    /// Maximum number of inferences, unlimited if `None`
+    pub inference_limit: Option<u64>,
+}
+
+impl Default for PrologQueryOptions {
+    /// The limits set with `set_default_query_limits()`
+    fn default() -> Self {
+        let timeout_ms = DEFAULT_TIMEOUT_MS.load(Ordering::Relaxed);
+        let inference_limit = DEFAULT_INFERENCES.load(Ordering::Relaxed);
+        PrologQueryOptions {
+            timeout: (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms)),
+            inference_limit: (inference_limit > 0).then_some(inference_limit),
+        }
+    }
+}
+
+fn with_inference_limit(query: &str, limit: u64) -> String {
+    let goal = query.trim().trim_end_matches('.');
+    format!(
+        "call_with_inference_limit(({}), {}, {}).",
+        goal, limit, INFERENCE_LIMIT_RESULT_VAR
+    )
+}
+
+/// Strips the inference limit result from the matches of a query wrapped by
+/// `with_inference_limit()`, returning an error if the limit was hit.
+fn without_inference_limit_result(
+    result: QueryResult,
+    limit: u64,
+) -> Result<QueryResult, PrologQueryTimeout> {
+    let matches = match result {
+        Ok(QueryResolution::Matches(matches)) => matches,
+        other => return Ok(other),
+    };
+
+    let mut stripped = Vec::with_capacity(matches.len());
+    for mut query_match in matches {
+        if let Some(Value::Atom(limit_result)) =
+            query_match.bindings.remove(INFERENCE_LIMIT_RESULT_VAR)
+        {
+            if limit_result == "inference_limit_exceeded" {
+                return Err(PrologQueryTimeout::InferenceLimitExceeded(limit));
+            }
+        }
+        stripped.push(query_match);
+    }
+
+    if stripped.iter().all(|m| m.bindings.is_empty()) {
+        Ok(Ok(QueryResolution::True))
+    } else {
+        Ok(Ok(QueryResolution::Matches(stripped)))
+    }
+}
+
 #[derive(Debug)]
 pub enum PrologServiceRequest {
     RunQuery(String, oneshot::Sender<PrologServiceResponse>),
@@ -21,6 +125,7 @@ pub enum PrologServiceResponse {
 pub struct PrologEngine {
     request_sender: mpsc::UnboundedSender<PrologServiceRequest>,
     request_receiver: Option<mpsc::UnboundedReceiver<PrologServiceRequest>>,
+    state: Arc<AtomicU8>,
 }
 
 impl PrologEngine {
@@ -30,6 +135,7 @@ impl PrologEngine {
         PrologEngine {
             request_sender,
             request_receiver: Some(request_receiver),
+            state: Arc::new(AtomicU8::new(ENGINE_IDLE)),
         }
     }
 
@@ -39,6 +145,7 @@ impl PrologEngine {
             .take()
             .ok_or_else(|| Error::msg("PrologEngine::spawn called twice"))?;
         let (response_sender, response_receiver) = oneshot::channel();
+        let state = self.state.clone();
 
         std::thread::spawn(move || {
             let rt = tokio::runtime::Builder::new_current_thread()
@@ -51,6 +158,8 @@ impl PrologEngine {
             tokio::task::block_in_place(|| {
                 rt.block_on(async move {
                     let mut machine = Machine::new_lib();
+                    // Provides call_with_inference_limit/3 for limited queries
+                    let _ = machine.run_query("use_module(library(iso_ext)).".to_string());
 
                     response_sender
                         .send(PrologServiceResponse::InitComplete(Ok(())))
@@ -59,9 +168,18 @@ impl PrologEngine {
                     while let Some(message) = receiver.recv().await {
                         match message {
                             PrologServiceRequest::RunQuery(query, response) => {
-                                match std::panic::catch_unwind(AssertUnwindSafe(|| {
+                                // The caller gave up on this query (timeout or cancellation)
+                                if response.is_closed() {
+                                    continue;
+                                }
+                                state.store(ENGINE_RUNNING, Ordering::SeqCst);
+                                let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
                                     machine.run_query(query)
-                                })) {
+                                }));
+                                if state.swap(ENGINE_IDLE, Ordering::SeqCst) == ENGINE_ABANDONED {
+                                    ABANDONED_ENGINES.fetch_sub(1, Ordering::SeqCst);
+                                }
+                                match result {
                                     Ok(result) => {
                                         let _ = response
                                             .send(PrologServiceResponse::QueryResult(result));
@@ -123,6 +241,79 @@ impl PrologEngine {
         }
     }
 
+    /// Runs a query with optional time and inference limits.
+    /// Resolving `cancel` aborts waiting for the query.
+    /// If the time limit is hit or the query got cancelled, the engine thread might still
+    /// be busy and the engine should be replaced (see `PrologQueryTimeout::engine_needs_restart()`).
+    /// While `MAX_ABANDONED_ENGINES` replaced engines are still busy, queries without
+    /// inference limit get `DEFAULT_INFERENCE_LIMIT`, so that their threads end eventually.
+    pub async fn run_query_with_options(
+        &self,
+        query: String,
+        options: &PrologQueryOptions,
+        cancel: Option<oneshot::Receiver<()>>,
+    ) -> Result<QueryResult, Error> {
+        let inference_limit = match options.inference_limit {
+            None if abandoned_engines() >= MAX_ABANDONED_ENGINES => {
+                log::warn!(
+                    "{} Prolog engines are still busy with abandoned queries, limiting query to {} inferences",
+                    abandoned_engines(),
+                    DEFAULT_INFERENCE_LIMIT
+                );
+                Some(DEFAULT_INFERENCE_LIMIT)
+            }
+            limit => limit,
+        };
+        let query = match inference_limit {
+            Some(limit) => with_inference_limit(&query, limit),
+            None => query,
+        };
+
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.request_sender
+            .send(PrologServiceRequest::RunQuery(query, response_sender))?;
+
+        let cancelled = async {
+            match cancel {
+                Some(receiver) => {
+                    // A dropped sender means there is nobody left who could cancel
+                    if receiver.await.is_err() {
+                        std::future::pending::<()>().await
+                    }
+                }
+                None => std::future::pending::<()>().await,
+            }
+        };
+
+        let timed_out = async {
+            match options.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending::<()>().await,
+            }
+        };
+
+        let response = tokio::select! {
+            response = response_receiver => response?,
+            _ = timed_out => {
+                let timeout = options.timeout.unwrap_or_default();
+                return Err(PrologQueryTimeout::TimeLimitExceeded(timeout).into());
+            }
+            _ = cancelled => {
+                return Err(PrologQueryTimeout::Cancelled.into());
+            }
+        };
+
+        let result = match response {
+            PrologServiceResponse::QueryResult(query_result) => query_result,
+            _ => unreachable!(),
+        };
+
+        match inference_limit {
+            Some(limit) => Ok(without_inference_limit_result(result, limit)?),
+            None => Ok(result),
+        }
+    }
+
     pub async fn load_module_string(
         &self,
         module_name: String,
@@ -142,7 +333,40 @@ impl PrologEngine {
         }
     }
 
+    /// Runs fact updating goals like `assertz`/`retract` wrappers in batches, so that
+    /// facts can be changed without re-consulting the module that holds them.
+    pub async fn update_facts(&self, goals: Vec<String>) -> Result<(), Error> {
+        for batch in goals.chunks(FACT_UPDATE_BATCH_SIZE) {
+            let query = format!("{}.", batch.join(","));
+            match self.run_query(query).await? {
+                Ok(QueryResolution::True) | Ok(QueryResolution::Matches(_)) => {}
+                Ok(QueryResolution::False) => {
+                    return Err(Error::msg("Fact update query failed"));
+                }
+                Err(e) => {
+                    return Err(Error::msg(format!("Fact update query failed: {}", e)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops the engine thread once it is done with the query it is running, if any.
+    /// Such a thread counts as abandoned until then (see `abandoned_engines()`).
     pub fn drop(&self) -> Result<(), Error> {
+        ABANDONED_ENGINES.fetch_add(1, Ordering::SeqCst);
+        if self
+            .state
+            .compare_exchange(
+                ENGINE_RUNNING,
+                ENGINE_ABANDONED,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_err()
+        {
+            ABANDONED_ENGINES.fetch_sub(1, Ordering::SeqCst);
+        }
         self.request_sender.send(PrologServiceRequest::Drop)?;
         Ok(())
     }
@@ -186,4 +410,131 @@ mod prolog_test {
         println!("Output: {:?}", output);
         assert!(output.is_ok());
     }
+
+    #[tokio::test]
+    async fn limited_queries_return_plain_results() {
+        let mut engine = PrologEngine::new();
+        engine.spawn().await.unwrap();
+        let facts = String::from(r#"triple("a", "p1", "b")."#);
+        engine
+            .load_module_string("facts".to_string(), vec![facts])
+            .await
+            .unwrap();
+
+        let options = PrologQueryOptions::default();
+        for query in [
+            "triple(\"a\",P,\"b\").",
+            "triple(\"a\",\"p1\",\"b\").",
+            "triple(\"x\",P,T).",
+        ] {
+            let unlimited = engine.run_query(query.to_string()).await.unwrap();
+            let limited = engine
+                .run_query_with_options(query.to_string(), &options, None)
+                .await
+                .unwrap();
+            assert_eq!(limited, unlimited, "Results differ for {}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn query_exceeding_inference_limit_gets_aborted() {
+        let mut engine = PrologEngine::new();
+        engine.spawn().await.unwrap();
+
+        let options = PrologQueryOptions {
+            timeout: Some(Duration::from_secs(30)),
+            inference_limit: Some(10_000),
+        };
+        let error = engine
+            .run_query_with_options(
+                "between(1, 1000000000, X), X < 0.".to_string(),
+                &options,
+                None,
+            )
+            .await
+            .expect_err("Query should hit the inference limit");
+        assert_eq!(
+            error.downcast_ref::<PrologQueryTimeout>(),
+            Some(&PrologQueryTimeout::InferenceLimitExceeded(10_000))
+        );
+
+        // The engine is still usable afterwards
+        let result = engine
+            .run_query_with_options("X = 1.".to_string(), &options, None)
+            .await
+            .unwrap();
+        assert!(matches!(result, Ok(QueryResolution::Matches(_))));
+    }
+
+    #[tokio::test]
+    async fn slow_query_times_out_and_can_be_cancelled() {
+        let mut engine = PrologEngine::new();
+        engine.spawn().await.unwrap();
+        let slow_query = "between(1, 10000000, X), X < 0.".to_string();
+
+        let options = PrologQueryOptions {
+            timeout: Some(Duration::from_millis(50)),
+            inference_limit: None,
+        };
+        let error = engine
+            .run_query_with_options(slow_query.clone(), &options, None)
+            .await
+            .expect_err("Query should time out");
+        assert_eq!(
+            error.downcast_ref::<PrologQueryTimeout>(),
+            Some(&PrologQueryTimeout::TimeLimitExceeded(
+                Duration::from_millis(50)
+            ))
+        );
+
+        let options = PrologQueryOptions {
+            timeout: Some(Duration::from_secs(30)),
+            inference_limit: None,
+        };
+        let (cancel_sender, cancel_receiver) = oneshot::channel();
+        cancel_sender.send(()).unwrap();
+        let error = engine
+            .run_query_with_options(slow_query, &options, Some(cancel_receiver))
+            .await
+            .expect_err("Query should be cancelled");
+        assert_eq!(
+            error.downcast_ref::<PrologQueryTimeout>(),
+            Some(&PrologQueryTimeout::Cancelled)
+        );
+    }
+
+    #[tokio::test]
+    async fn dropped_busy_engine_counts_as_abandoned_until_its_query_ends() {
+        let mut engine = PrologEngine::new();
+        engine.spawn().await.unwrap();
+
+        let options = PrologQueryOptions {
+            timeout: Some(Duration::from_millis(50)),
+            inference_limit: None,
+        };
+        assert!(engine
+            .run_query_with_options("between(1, 3000000, X), X < 0.".to_string(), &options, None)
+            .await
+            .is_err());
+        engine.drop().unwrap();
+        assert_eq!(engine.state.load(Ordering::SeqCst), ENGINE_ABANDONED);
+
+        let mut waited = Duration::ZERO;
+        while engine.state.load(Ordering::SeqCst) != ENGINE_IDLE {
+            assert!(waited < Duration::from_secs(60), "Query never finished");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            waited += Duration::from_millis(50);
+        }
+    }
+
+    #[test]
+    fn default_options_follow_the_configured_limits() {
+        assert_eq!(
+            PrologQueryOptions::default(),
+            PrologQueryOptions {
+                timeout: Some(DEFAULT_QUERY_TIMEOUT),
+                inference_limit: Some(DEFAULT_INFERENCE_LIMIT),
+            }
+        );
+    }
 }
@@ -0,0 +1,46 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// A Prolog query got aborted before it finished.
+/// Returned (wrapped in an `AnyError`) by `PrologEngine::run_query_with_options()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrologQueryTimeout {
+    /// The query did not finish within the given time
+    TimeLimitExceeded(Duration),
+    /// The query needed more inferences than allowed
+    InferenceLimitExceeded(u64),
+    /// The query got cancelled by the caller
+    Cancelled,
+}
+
+impl PrologQueryTimeout {
+    /// Whether the engine might still be busy with the query and needs a restart
+    pub fn engine_needs_restart(&self) -> bool {
+        !matches!(self, PrologQueryTimeout::InferenceLimitExceeded(_))
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            PrologQueryTimeout::TimeLimitExceeded(_) => "TIME_LIMIT",
+            PrologQueryTimeout::InferenceLimitExceeded(_) => "INFERENCE_LIMIT",
+            PrologQueryTimeout::Cancelled => "CANCELLED",
+        }
+    }
+}
+
+impl Error for PrologQueryTimeout {}
+
+impl fmt::Display for PrologQueryTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrologQueryTimeout::TimeLimitExceeded(timeout) => {
+                write!(f, "Prolog query timed out after {}ms", timeout.as_millis())
+            }
+            PrologQueryTimeout::InferenceLimitExceeded(limit) => {
+                write!(f, "Prolog query exceeded inference limit of {}", limit)
+            }
+            PrologQueryTimeout::Cancelled => write!(f, "Prolog query was cancelled"),
+        }
+    }
+}
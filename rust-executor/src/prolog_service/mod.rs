@@ -6,6 +6,8 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 
 pub(crate) mod engine;
+pub(crate) mod error;
+pub(crate) mod pool;
 pub(crate) mod prolog_service_extension;
 
 use self::engine::PrologEngine;
@@ -67,6 +69,14 @@ impl PrologService {
             .ok_or_else(|| Error::msg("Engine not found"))?;
         engine.load_module_string(module_name, program_lines).await
     }
+
+    pub async fn update_facts(&self, engine_name: String, goals: Vec<String>) -> Result<(), Error> {
+        let engines = self.engines.read().await;
+        let engine = engines
+            .get(&engine_name)
+            .ok_or_else(|| Error::msg("Engine not found"))?;
+        engine.update_facts(goals).await
+    }
 }
 
 lazy_static! {
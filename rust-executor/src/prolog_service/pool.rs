@@ -0,0 +1,215 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use deno_core::anyhow::Error;
+use futures::future::try_join_all;
+use scryer_prolog::QueryResult;
+use tokio::sync::oneshot;
+
+use super::engine::{PrologEngine, PrologQueryOptions};
+
+/// One engine per perspective, as before pooling. Larger pools trade memory
+/// (every engine holds all facts) for concurrent queries and are opt-in.
+pub const DEFAULT_ENGINE_POOL_SIZE: usize = 1;
+
+static ENGINE_POOL_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_ENGINE_POOL_SIZE);
+
+/// Sets the number of engines that new pools get spawned with (see `Ad4mConfig`)
+pub fn set_engine_pool_size(size: usize) {
+    ENGINE_POOL_SIZE.store(size.max(1), Ordering::Relaxed);
+}
+
+pub fn engine_pool_size() -> usize {
+    ENGINE_POOL_SIZE.load(Ordering::Relaxed)
+}
+
+/// Snapshot of how busy the engines of a pool are
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrologEnginePoolMetrics {
+    /// Number of queries waiting for or running on each engine
+    pub queue_depths: Vec<usize>,
+    /// Number of queries run on this pool since it was spawned
+    pub queries_total: u64,
+}
+
+struct PooledEngine {
+    engine: PrologEngine,
+    queue_depth: Arc<AtomicUsize>,
+}
+
+/// Decrements the queue depth of an engine when the query finishes or gets dropped
+struct QueueSlot(Arc<AtomicUsize>);
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A set of `PrologEngine`s that hold the same facts.
+/// Queries go to the engine with the shortest queue,
+/// modules and fact updates get applied to all engines.
+pub struct PrologEnginePool {
+    engines: Vec<PooledEngine>,
+    next_engine: AtomicUsize,
+    queries_total: AtomicU64,
+}
+
+impl PrologEnginePool {
+    pub async fn spawn(size: usize) -> Result<PrologEnginePool, Error> {
+        let mut engines = Vec::with_capacity(size.max(1));
+        for _ in 0..size.max(1) {
+            let mut engine = PrologEngine::new();
+            engine.spawn().await?;
+            engines.push(PooledEngine {
+                engine,
+                queue_depth: Arc::new(AtomicUsize::new(0)),
+            });
+        }
+
+        Ok(PrologEnginePool {
+            engines,
+            next_engine: AtomicUsize::new(0),
+            queries_total: AtomicU64::new(0),
+        })
+    }
+
+    /// Picks the engine with the fewest queued queries, starting the search
+    /// at a rotating offset so that idle engines take turns.
+    fn pick_engine(&self) -> (&PrologEngine, QueueSlot) {
+        let offset = self.next_engine.fetch_add(1, Ordering::Relaxed);
+        let count = self.engines.len();
+        let pooled = (0..count)
+            .map(|i| &self.engines[(offset + i) % count])
+            .min_by_key(|pooled| pooled.queue_depth.load(Ordering::SeqCst))
+            .expect("Engine pool is never empty");
+        pooled.queue_depth.fetch_add(1, Ordering::SeqCst);
+        self.queries_total.fetch_add(1, Ordering::Relaxed);
+        (&pooled.engine, QueueSlot(pooled.queue_depth.clone()))
+    }
+
+    pub async fn run_query(&self, query: String) -> Result<QueryResult, Error> {
+        let (engine, _slot) = self.pick_engine();
+        engine.run_query(query).await
+    }
+
+    pub async fn run_query_with_options(
+        &self,
+        query: String,
+        options: &PrologQueryOptions,
+        cancel: Option<oneshot::Receiver<()>>,
+    ) -> Result<QueryResult, Error> {
+        let (engine, _slot) = self.pick_engine();
+        engine.run_query_with_options(query, options, cancel).await
+    }
+
+    pub async fn load_module_string(
+        &self,
+        module_name: String,
+        program_lines: Vec<String>,
+    ) -> Result<(), Error> {
+        try_join_all(self.engines.iter().map(|pooled| {
+            pooled
+                .engine
+                .load_module_string(module_name.clone(), program_lines.clone())
+        }))
+        .await?;
+        Ok(())
+    }
+
+    pub async fn update_facts(&self, goals: Vec<String>) -> Result<(), Error> {
+        try_join_all(
+            self.engines
+                .iter()
+                .map(|pooled| pooled.engine.update_facts(goals.clone())),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub fn metrics(&self) -> PrologEnginePoolMetrics {
+        PrologEnginePoolMetrics {
+            queue_depths: self
+                .engines
+                .iter()
+                .map(|pooled| pooled.queue_depth.load(Ordering::SeqCst))
+                .collect(),
+            queries_total: self.queries_total.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn drop(&self) -> Result<(), Error> {
+        for pooled in &self.engines {
+            pooled.engine.drop()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scryer_prolog::QueryResolution;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn queries_get_spread_over_idle_engines() {
+        let pool = PrologEnginePool::spawn(3).await.unwrap();
+        pool.load_module_string(
+            "facts".to_string(),
+            vec![":- dynamic(triple/3).".to_string()],
+        )
+        .await
+        .unwrap();
+
+        // Keep two engines busy, the third one has to take the next query
+        let slow_query = "between(1, 3000000, X), X < 0.".to_string();
+        let options = PrologQueryOptions {
+            timeout: Some(Duration::from_secs(30)),
+            inference_limit: None,
+        };
+        let (first, second, metrics) = tokio::join!(
+            pool.run_query_with_options(slow_query.clone(), &options, None),
+            pool.run_query_with_options(slow_query.clone(), &options, None),
+            async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                pool.metrics()
+            }
+        );
+        assert_eq!(first.unwrap(), Ok(QueryResolution::False));
+        assert_eq!(second.unwrap(), Ok(QueryResolution::False));
+
+        let mut depths = metrics.queue_depths.clone();
+        depths.sort();
+        assert_eq!(depths, vec![0, 1, 1]);
+        assert_eq!(pool.metrics().queue_depths, vec![0, 0, 0]);
+        assert_eq!(pool.metrics().queries_total, 2);
+    }
+
+    #[tokio::test]
+    async fn fact_updates_reach_every_engine() {
+        let pool = PrologEnginePool::spawn(2).await.unwrap();
+        pool.load_module_string(
+            "facts".to_string(),
+            vec![
+                ":- dynamic(triple/3).".to_string(),
+                "triple(\"a\", \"p\", \"b\").".to_string(),
+                "add_triple(S, P, T) :- assertz(triple(S, P, T)).".to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+        pool.update_facts(vec!["add_triple(\"b\", \"p\", \"c\")".to_string()])
+            .await
+            .unwrap();
+
+        for pooled in &pool.engines {
+            let result = pooled
+                .engine
+                .run_query("triple(\"b\", \"p\", \"c\").".to_string())
+                .await
+                .unwrap();
+            assert_eq!(result, Ok(QueryResolution::True));
+        }
+    }
+}
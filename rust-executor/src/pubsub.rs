@@ -5,67 +5,227 @@ use futures::Stream;
 use futures::StreamExt;
 use log::{debug, error, warn};
 use serde::de::DeserializeOwned;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::watch;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
 use tokio::sync::Mutex;
-use tokio_stream::wrappers::WatchStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
 
 type Topic = String;
 type Message = String;
 
+/// Number of messages a single subscriber can fall behind before it starts
+/// missing messages and gets a lag signal.
+pub const SUBSCRIBER_BUFFER_SIZE: usize = 1024;
+/// Number of recent messages kept per topic so that subscriptions can resume
+/// after a reconnect.
+pub const REPLAY_LOG_SIZE: usize = 256;
+/// How long the replay log of a topic is kept after its last subscriber left.
+/// Long enough for clients to reconnect, after which the log gets dropped and
+/// no messages are logged until somebody subscribes again.
+pub const REPLAY_LOG_RETENTION: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SequencedMessage {
+    pub sequence: u64,
+    pub message: Message,
+}
+
+/// A subscription to a topic: messages from the replay log that the
+/// subscriber asked for, followed by everything published afterwards.
+pub struct Subscription {
+    pub replay: Vec<SequencedMessage>,
+    /// Number of requested messages that already dropped out of the replay log
+    pub missed: u64,
+    pub receiver: broadcast::Receiver<SequencedMessage>,
+}
+
+struct TopicChannel {
+    sender: broadcast::Sender<SequencedMessage>,
+    last_sequence: u64,
+    replay_log: VecDeque<SequencedMessage>,
+    /// Since when the topic has had no subscribers
+    idle_since: Option<Instant>,
+}
+
+impl TopicChannel {
+    fn new() -> Self {
+        let (sender, _) = broadcast::channel(SUBSCRIBER_BUFFER_SIZE);
+        Self {
+            sender,
+            last_sequence: 0,
+            replay_log: VecDeque::new(),
+            idle_since: None,
+        }
+    }
+
+    fn subscribe(&mut self) -> broadcast::Receiver<SequencedMessage> {
+        self.idle_since = None;
+        self.sender.subscribe()
+    }
+
+    /// Drops the replay log if the topic has had no subscribers for longer
+    /// than `retention`. Returns whether the topic should keep logging.
+    fn retain_replay_log(&mut self, now: Instant, retention: Duration) -> bool {
+        if self.sender.receiver_count() > 0 {
+            self.idle_since = None;
+            return true;
+        }
+
+        let idle_since = *self.idle_since.get_or_insert(now);
+        if now.duration_since(idle_since) < retention {
+            return true;
+        }
+
+        if !self.replay_log.is_empty() {
+            debug!(
+                "Dropping replay log of {} messages for topic without subscribers",
+                self.replay_log.len()
+            );
+            self.replay_log = VecDeque::new();
+        }
+        false
+    }
+}
+
 pub struct PubSub {
-    subscribers: Mutex<HashMap<Topic, Vec<watch::Sender<Message>>>>,
+    topics: Mutex<HashMap<Topic, TopicChannel>>,
+    replay_log_retention: Duration,
 }
 
 impl PubSub {
     pub fn new() -> Self {
+        Self::with_replay_log_retention(REPLAY_LOG_RETENTION)
+    }
+
+    pub fn with_replay_log_retention(replay_log_retention: Duration) -> Self {
         Self {
-            subscribers: Mutex::new(HashMap::new()),
+            topics: Mutex::new(HashMap::new()),
+            replay_log_retention,
         }
     }
 
-    pub async fn subscribe(&self, topic: &Topic) -> watch::Receiver<Message> {
-        let (tx, rx) = watch::channel("".to_owned());
-        let mut subscribers = self.subscribers.lock().await;
-        subscribers
+    pub async fn subscribe(&self, topic: &Topic) -> broadcast::Receiver<SequencedMessage> {
+        let mut topics = self.topics.lock().await;
+        topics
             .entry(topic.to_owned())
-            .or_insert_with(Vec::new)
-            .push(tx);
-        rx
+            .or_insert_with(TopicChannel::new)
+            .subscribe()
     }
 
-    pub async fn remove_dead_subscribers(&self) {
-        let mut subscribers = self.subscribers.lock().await;
-        for (_, subscribers_vec) in subscribers.iter_mut() {
-            let mut i = 0;
-            while i < subscribers_vec.len() {
-                if subscribers_vec[i].is_closed() {
-                    warn!("Found closed subscriber, removing...");
-                    subscribers_vec.remove(i);
-                } else {
-                    i += 1;
-                }
+    /// Subscribes to a topic and replays all messages with a sequence number
+    /// greater than `after_sequence` that are still in the topic's replay log.
+    /// Replay and subscription happen under the same lock as `publish()`,
+    /// so no message gets delivered twice or lost in between.
+    pub async fn subscribe_from(&self, topic: &Topic, after_sequence: u64) -> Subscription {
+        let mut topics = self.topics.lock().await;
+        let channel = topics
+            .entry(topic.to_owned())
+            .or_insert_with(TopicChannel::new);
+
+        let replay: Vec<SequencedMessage> = channel
+            .replay_log
+            .iter()
+            .filter(|m| m.sequence > after_sequence)
+            .cloned()
+            .collect();
+
+        let first_available = channel
+            .replay_log
+            .front()
+            .map(|m| m.sequence)
+            .unwrap_or(channel.last_sequence + 1);
+        let missed = first_available
+            .saturating_sub(after_sequence + 1)
+            .min(channel.last_sequence.saturating_sub(after_sequence));
+
+        Subscription {
+            replay,
+            missed,
+            receiver: channel.subscribe(),
+        }
+    }
+
+    /// Returns the sequence number of the last message published on `topic`.
+    pub async fn last_sequence(&self, topic: &Topic) -> u64 {
+        let topics = self.topics.lock().await;
+        topics.get(topic).map(|c| c.last_sequence).unwrap_or(0)
+    }
+
+    pub async fn publish(&self, topic: &Topic, message: &Message) -> u64 {
+        let mut topics = self.topics.lock().await;
+        let now = Instant::now();
+        for (name, channel) in topics.iter_mut() {
+            if name != topic {
+                channel.retain_replay_log(now, self.replay_log_retention);
             }
         }
+
+        let channel = topics
+            .entry(topic.to_owned())
+            .or_insert_with(TopicChannel::new);
+
+        channel.last_sequence += 1;
+        let sequenced = SequencedMessage {
+            sequence: channel.last_sequence,
+            message: message.to_owned(),
+        };
+
+        if channel.retain_replay_log(now, self.replay_log_retention) {
+            if channel.replay_log.len() == REPLAY_LOG_SIZE {
+                channel.replay_log.pop_front();
+            }
+            channel.replay_log.push_back(sequenced.clone());
+        }
+
+        // Sending only fails if there are no subscribers right now,
+        // which is fine since the message is in the replay log
+        // (or nobody subscribed for longer than the retention period).
+        let _ = channel.sender.send(sequenced);
+        channel.last_sequence
     }
+}
 
-    pub async fn publish(&self, topic: &Topic, message: &Message) {
-        let mut subscribers = self.subscribers.lock().await;
-
-        if let Some(subscribers_vec) = subscribers.get_mut(topic) {
-            let mut i = 0;
-            while i < subscribers_vec.len() {
-                let send_res = subscribers_vec[i].send(message.to_owned());
-                if send_res.is_err() {
-                    warn!("Failed to send message to subscriber: {:?} on topic: {:?}, with subscribers, len: {:?}", send_res, topic, subscribers_vec.len());
-                    warn!("Removing subscriber from topic: {:?}", topic);
-                    subscribers_vec.remove(i);
-                } else {
-                    i += 1;
+fn lagged_error(missed: u64) -> FieldError {
+    warn!("Subscriber lagged behind, {} messages were dropped", missed);
+    FieldError::new(
+        format!("Subscriber lagged behind, {} messages were dropped", missed),
+        graphql_value!({ "type": "SUBSCRIPTION_LAGGED", "missed": (missed as f64) }),
+    )
+}
+
+fn process_message<T: DeserializeOwned + std::fmt::Debug + GetValue + GetFilter>(
+    msg: &Message,
+    filter: &Option<String>,
+) -> Option<FieldResult<T::Value>> {
+    match serde_json::from_str::<T>(msg) {
+        Ok(data) => {
+            if let Some(filter) = filter {
+                if &data
+                    .get_filter()
+                    .expect("Could not get filter on T where we expected to filter")
+                    != filter
+                {
+                    return None;
                 }
             }
+            let value = data.get_value(); // Get the underlying value using the GetValue trait
+            Some(Ok(value))
+        }
+        Err(e) => {
+            let type_name = std::any::type_name::<T>();
+            error!("Failed to deserialize pubsub message: {:?}", e);
+            error!("Type: {}", type_name);
+            error!("Message: {:?}", msg);
+
+            let field_error = FieldError::new(
+                e,
+                graphql_value!({ "type": "INTERNAL_ERROR_COULD_NOT_SERIALIZE" }),
+            );
+            Some(Err(field_error))
         }
     }
 }
@@ -77,42 +237,55 @@ pub(crate) async fn subscribe_and_process<
     topic: Topic,
     filter: Option<String>,
 ) -> Pin<Box<dyn Stream<Item = FieldResult<T::Value>> + Send>> {
+    let stream = subscribe_and_process_sequenced::<T>(pubsub, topic, filter, None).await;
+    Box::pin(stream.map(|result| result.map(|(_, value)| value)))
+}
+
+/// Like `subscribe_and_process()` but yields every value together with its
+/// sequence number on the topic, so that clients know where to resume, and
+/// first replays messages published after `from_sequence`. If the subscriber
+/// falls behind, or asks for messages that are not in the replay log anymore,
+/// a `SUBSCRIPTION_LAGGED` error is emitted on the stream before delivery
+/// continues.
+pub(crate) async fn subscribe_and_process_sequenced<
+    T: DeserializeOwned + Send + 'static + std::fmt::Debug + GetValue + GetFilter,
+>(
+    pubsub: Arc<PubSub>,
+    topic: Topic,
+    filter: Option<String>,
+    from_sequence: Option<u64>,
+) -> Pin<Box<dyn Stream<Item = FieldResult<(u64, T::Value)>> + Send>> {
     debug!("Subscribing to topic: {}", topic);
-    pubsub.remove_dead_subscribers().await;
-    let receiver = pubsub.subscribe(&topic).await;
-    let receiver_stream = WatchStream::from_changes(receiver);
-
-    let mapped_stream = receiver_stream.filter_map(move |msg| {
-        match serde_json::from_str::<T>(&msg) {
-            Ok(data) => {
-                if let Some(filter) = &filter {
-                    if &data
-                        .get_filter()
-                        .expect("Could not get filter on T where we expected to filter")
-                        != filter
-                    {
-                        return futures::future::ready(None);
-                    }
-                }
-                let value = data.get_value(); // Get the underlying value using the GetValue trait
-                futures::future::ready(Some(Ok(value)))
-            }
-            Err(e) => {
-                let type_name = std::any::type_name::<T>();
-                error!("Failed to deserialize pubsub message: {:?}", e);
-                error!("Type: {}", type_name);
-                error!("Message: {:?}", msg);
-
-                let field_error = FieldError::new(
-                    e,
-                    graphql_value!({ "type": "INTERNAL_ERROR_COULD_NOT_SERIALIZE" }),
-                );
-                futures::future::ready(Some(Err(field_error)))
-            }
+    let (replay, missed, receiver) = match from_sequence {
+        Some(sequence) => {
+            let subscription = pubsub.subscribe_from(&topic, sequence).await;
+            (
+                subscription.replay,
+                subscription.missed,
+                subscription.receiver,
+            )
         }
+        None => (Vec::new(), 0, pubsub.subscribe(&topic).await),
+    };
+
+    let lagged_stream = futures::stream::iter((missed > 0).then(|| Err(lagged_error(missed))));
+
+    let replay_filter = filter.clone();
+    let replay_stream = futures::stream::iter(replay.into_iter().filter_map(move |msg| {
+        process_message::<T>(&msg.message, &replay_filter)
+            .map(|result| result.map(|value| (msg.sequence, value)))
+    }));
+
+    let live_stream = BroadcastStream::new(receiver).filter_map(move |msg| {
+        let result = match msg {
+            Ok(msg) => process_message::<T>(&msg.message, &filter)
+                .map(|result| result.map(|value| (msg.sequence, value))),
+            Err(BroadcastStreamRecvError::Lagged(missed)) => Some(Err(lagged_error(missed))),
+        };
+        futures::future::ready(result)
     });
 
-    Box::pin(mapped_stream)
+    Box::pin(lagged_stream.chain(replay_stream).chain(live_stream))
 }
 
 lazy_static::lazy_static! {
@@ -139,3 +312,117 @@ lazy_static::lazy_static! {
 pub async fn get_global_pubsub() -> Arc<PubSub> {
     GLOBAL_PUB_SUB.clone()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn slow_subscriber_receives_every_message_in_order() {
+        let pubsub = PubSub::new();
+        let topic = "test-topic".to_string();
+        let mut receiver = pubsub.subscribe(&topic).await;
+
+        for i in 0..100 {
+            pubsub.publish(&topic, &format!("message {}", i)).await;
+        }
+
+        for i in 0..100 {
+            let msg = receiver.recv().await.unwrap();
+            assert_eq!(msg.sequence, i + 1);
+            assert_eq!(msg.message, format!("message {}", i));
+        }
+    }
+
+    #[tokio::test]
+    async fn subscriber_gets_lag_signal_on_overflow() {
+        let pubsub = PubSub::new();
+        let topic = "test-topic".to_string();
+        let mut receiver = pubsub.subscribe(&topic).await;
+
+        for i in 0..SUBSCRIBER_BUFFER_SIZE + 10 {
+            pubsub.publish(&topic, &format!("message {}", i)).await;
+        }
+
+        match receiver.recv().await {
+            Err(broadcast::error::RecvError::Lagged(missed)) => assert_eq!(missed, 10),
+            other => panic!("Expected lag signal, got {:?}", other),
+        }
+        assert_eq!(receiver.recv().await.unwrap().sequence, 11);
+    }
+
+    #[tokio::test]
+    async fn can_resume_from_sequence() {
+        let pubsub = PubSub::new();
+        let topic = "test-topic".to_string();
+
+        for i in 0..10 {
+            pubsub.publish(&topic, &format!("message {}", i)).await;
+        }
+        assert_eq!(pubsub.last_sequence(&topic).await, 10);
+
+        let mut subscription = pubsub.subscribe_from(&topic, 7).await;
+        assert_eq!(subscription.missed, 0);
+        assert_eq!(
+            subscription
+                .replay
+                .iter()
+                .map(|m| m.sequence)
+                .collect::<Vec<_>>(),
+            vec![8, 9, 10]
+        );
+
+        pubsub.publish(&topic, &"message 10".to_string()).await;
+        assert_eq!(subscription.receiver.recv().await.unwrap().sequence, 11);
+    }
+
+    #[tokio::test]
+    async fn resuming_beyond_replay_log_reports_missed_messages() {
+        let pubsub = PubSub::new();
+        let topic = "test-topic".to_string();
+
+        for i in 0..REPLAY_LOG_SIZE + 5 {
+            pubsub.publish(&topic, &format!("message {}", i)).await;
+        }
+
+        let subscription = pubsub.subscribe_from(&topic, 2).await;
+        assert_eq!(subscription.missed, 3);
+        assert_eq!(subscription.replay.len(), REPLAY_LOG_SIZE);
+        assert_eq!(subscription.replay[0].sequence, 6);
+
+        let up_to_date = pubsub.subscribe_from(&topic, 0).await;
+        assert_eq!(up_to_date.missed, 5);
+
+        let current = pubsub
+            .subscribe_from(&topic, (REPLAY_LOG_SIZE + 5) as u64)
+            .await;
+        assert_eq!(current.missed, 0);
+        assert!(current.replay.is_empty());
+    }
+
+    #[tokio::test]
+    async fn replay_log_is_dropped_for_topics_without_subscribers() {
+        let pubsub = PubSub::with_replay_log_retention(Duration::ZERO);
+        let idle = "idle-topic".to_string();
+        let watched = "watched-topic".to_string();
+        let _receiver = pubsub.subscribe(&watched).await;
+
+        pubsub.publish(&idle, &"message 0".to_string()).await;
+        pubsub.publish(&watched, &"message 0".to_string()).await;
+        pubsub.publish(&idle, &"message 1".to_string()).await;
+
+        let idle_subscription = pubsub.subscribe_from(&idle, 0).await;
+        assert!(idle_subscription.replay.is_empty());
+        assert_eq!(idle_subscription.missed, 2);
+
+        let watched_subscription = pubsub.subscribe_from(&watched, 0).await;
+        assert_eq!(watched_subscription.replay.len(), 1);
+
+        // Logging resumes once the topic has a subscriber again
+        pubsub.publish(&idle, &"message 2".to_string()).await;
+        let resumed = pubsub.subscribe_from(&idle, 2).await;
+        assert_eq!(resumed.replay.len(), 1);
+        assert_eq!(resumed.replay[0].sequence, 3);
+        assert_eq!(pubsub.last_sequence(&idle).await, 3);
+    }
+}
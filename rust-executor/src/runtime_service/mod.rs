@@ -1,5 +1,6 @@
 use std::io::Read;
 use std::{fs::File, sync::Mutex};
+pub(crate) mod notification_webhooks;
 pub(crate) mod runtime_service_extension;
 use std::sync::Arc;
 
@@ -0,0 +1,391 @@
+//! At-least-once delivery of notification webhooks.
+//!
+//! Triggered notifications get written to the `notification_delivery` outbox in the
+//! [`Ad4mDb`] first and are then posted by a background loop, which retries failed
+//! calls with exponential backoff. Every request carries an HMAC-SHA256 signature of
+//! timestamp and body, keyed with a random per-notification secret (see [`webhook_secret`]),
+//! so receivers can check that it was sent by this agent.
+
+use std::time::Duration;
+
+use deno_core::anyhow::anyhow;
+use deno_core::error::AnyError;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::Notify;
+
+use crate::agent;
+use crate::db::Ad4mDb;
+use crate::errors::ExecutorError;
+use crate::types::{Notification, NotificationDelivery, NotificationDeliveryStatus};
+
+pub const MAX_DELIVERY_ATTEMPTS: i32 = 10;
+pub const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(5);
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the outbox gets checked if nothing wakes the delivery loop up earlier
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(60);
+const DELIVERY_BATCH_SIZE: usize = 50;
+
+pub const SIGNATURE_HEADER: &str = "X-Ad4m-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Ad4m-Timestamp";
+pub const DELIVERY_ID_HEADER: &str = "X-Ad4m-Delivery";
+pub const AGENT_HEADER: &str = "X-Ad4m-Agent";
+
+lazy_static! {
+    static ref DELIVERIES_QUEUED: Notify = Notify::new();
+}
+
+/// Delay before the next attempt after `attempts` failed ones: 5s, 10s, 20s, .. up to an hour
+pub fn retry_delay(attempts: i32) -> Duration {
+    let exponent = (attempts.max(1) - 1).min(16) as u32;
+    INITIAL_RETRY_DELAY
+        .saturating_mul(2u32.pow(exponent))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Hex encoded secret for signing the webhook calls of the given notification.
+/// It is generated randomly when the notification gets installed and stored with it,
+/// so it survives key rotations and apps can get it via `runtimeNotificationWebhookSecret`.
+pub fn webhook_secret(notification_id: &str) -> Result<String, AnyError> {
+    Ad4mDb::with_global_instance(|db| db.get_notification_webhook_secret(notification_id))?
+        .ok_or_else(|| {
+            ExecutorError::NotFound(format!("Notification not found: {}", notification_id)).into()
+        })
+}
+
+/// `sha256=<hex HMAC>` over `"{timestamp}.{body}"`
+pub fn signature_header_value(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Stores the payload in the outbox and wakes up the delivery loop
+pub fn queue_delivery(notification: &Notification, payload: &str) -> Result<String, AnyError> {
+    let now = chrono::Utc::now().timestamp_millis();
+    let id = Ad4mDb::with_global_instance(|db| {
+        db.add_notification_delivery(&notification.id, &notification.webhook_url, payload, now)
+    })?;
+    DELIVERIES_QUEUED.notify_one();
+    Ok(id)
+}
+
+/// Posts a delivery once, returning the HTTP status if the receiver answered
+async fn attempt_delivery(
+    client: &reqwest::Client,
+    delivery: &NotificationDelivery,
+) -> (Option<u16>, Result<(), AnyError>) {
+    let notification = match Ad4mDb::with_global_instance(|db| {
+        db.get_notification(delivery.notification_id.clone())
+    }) {
+        Ok(Some(notification)) => notification,
+        Ok(None) => return (None, Err(anyhow!("Notification was removed"))),
+        Err(e) => return (None, Err(e.into())),
+    };
+    let secret = match webhook_secret(&notification.id) {
+        Ok(secret) => secret,
+        Err(e) => return (None, Err(e)),
+    };
+
+    let timestamp = chrono::Utc::now().timestamp_millis();
+    let response = client
+        .post(&delivery.webhook_url)
+        .bearer_auth(&notification.webhook_auth)
+        .header("Content-Type", "application/json")
+        .header(TIMESTAMP_HEADER, timestamp.to_string())
+        .header(DELIVERY_ID_HEADER, &delivery.id)
+        .header(AGENT_HEADER, agent::did())
+        .header(
+            SIGNATURE_HEADER,
+            signature_header_value(&secret, timestamp, &delivery.payload),
+        )
+        .body(delivery.payload.clone())
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => {
+            (Some(response.status().as_u16()), Ok(()))
+        }
+        Ok(response) => {
+            let status = response.status();
+            (
+                Some(status.as_u16()),
+                Err(anyhow!("Webhook responded with {}", status)),
+            )
+        }
+        Err(e) => (None, Err(anyhow!("Webhook request failed: {}", e))),
+    }
+}
+
+/// Attempts all deliveries that are due at `now` (in ms) once and records the outcomes.
+/// Returns the number of deliveries that were attempted.
+pub async fn process_due_deliveries(client: &reqwest::Client, now: i64) -> Result<usize, AnyError> {
+    let due = Ad4mDb::with_global_instance(|db| {
+        db.get_due_notification_deliveries(now, DELIVERY_BATCH_SIZE)
+    })?;
+
+    for delivery in due.iter() {
+        let (response_status, result) = attempt_delivery(client, delivery).await;
+        let attempted_at = chrono::Utc::now().timestamp_millis();
+        let attempts = delivery.attempts + 1;
+        let (status, error, next_attempt_at) = match result {
+            Ok(()) => (NotificationDeliveryStatus::Delivered, None, None),
+            Err(e) => {
+                let status = if attempts >= MAX_DELIVERY_ATTEMPTS {
+                    log::error!(
+                        "Giving up on webhook delivery {} for notification {} after {} attempts: {}",
+                        delivery.id,
+                        delivery.notification_id,
+                        attempts,
+                        e
+                    );
+                    NotificationDeliveryStatus::Failed
+                } else {
+                    log::warn!(
+                        "Webhook delivery {} for notification {} failed (attempt {}): {}",
+                        delivery.id,
+                        delivery.notification_id,
+                        attempts,
+                        e
+                    );
+                    NotificationDeliveryStatus::Pending
+                };
+                let next_attempt_at = attempted_at + retry_delay(attempts).as_millis() as i64;
+                (status, Some(e.to_string()), Some(next_attempt_at))
+            }
+        };
+        Ad4mDb::with_global_instance(|db| {
+            db.record_notification_delivery_attempt(
+                &delivery.id,
+                &status,
+                attempted_at,
+                response_status,
+                error,
+                next_attempt_at,
+            )
+        })?;
+    }
+
+    Ok(due.len())
+}
+
+/// Runs forever, delivering queued webhook calls.
+/// Deliveries left over from a previous run get picked up right away.
+pub async fn run_delivery_loop() {
+    let client = reqwest::Client::new();
+    loop {
+        match process_due_deliveries(&client, chrono::Utc::now().timestamp_millis()).await {
+            // There might be more due deliveries than fit in one batch
+            Ok(attempted) if attempted == DELIVERY_BATCH_SIZE => continue,
+            Ok(_) => {}
+            Err(e) => log::error!("Error processing notification webhook outbox: {:?}", e),
+        }
+
+        let now = chrono::Utc::now().timestamp_millis();
+        let wait = match Ad4mDb::with_global_instance(|db| db.next_notification_delivery_due()) {
+            Ok(Some(next_due)) => Duration::from_millis((next_due - now).max(0) as u64),
+            _ => IDLE_POLL_INTERVAL,
+        }
+        .min(IDLE_POLL_INTERVAL);
+
+        tokio::select! {
+            _ = DELIVERIES_QUEUED.notified() => {}
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql::graphql_types::NotificationInput;
+    use crate::test_utils::setup_agent;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use warp::http::{HeaderMap, StatusCode};
+    use warp::Filter;
+
+    #[derive(Clone, Debug)]
+    struct ReceivedRequest {
+        headers: HeaderMap,
+        body: String,
+    }
+
+    /// Local stand-in for a webhook receiver that answers with the given status codes in turn
+    fn start_receiver(statuses: Vec<u16>) -> (SocketAddr, Arc<Mutex<Vec<ReceivedRequest>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+        let route = warp::post()
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(move |headers: HeaderMap, body: warp::hyper::body::Bytes| {
+                let mut received = received_clone.lock().unwrap();
+                received.push(ReceivedRequest {
+                    headers,
+                    body: String::from_utf8_lossy(&body).to_string(),
+                });
+                let status = statuses
+                    .get(received.len() - 1)
+                    .or(statuses.last())
+                    .cloned()
+                    .unwrap_or(200);
+                warp::reply::with_status("", StatusCode::from_u16(status).unwrap())
+            });
+        let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (address, received)
+    }
+
+    fn add_notification(webhook_url: String) -> Notification {
+        let id = Ad4mDb::with_global_instance(|db| {
+            db.add_notification(NotificationInput {
+                description: "Test notification".to_string(),
+                app_name: "Test app".to_string(),
+                app_url: "https://example.com".to_string(),
+                app_icon_path: "".to_string(),
+                trigger: "triple(X, \"p\", Y).".to_string(),
+                perspective_ids: vec!["perspective".to_string()],
+                webhook_url,
+                webhook_auth: "secret-token".to_string(),
+            })
+        })
+        .unwrap();
+        Ad4mDb::with_global_instance(|db| db.get_notification(id))
+            .unwrap()
+            .unwrap()
+    }
+
+    fn now() -> i64 {
+        chrono::Utc::now().timestamp_millis()
+    }
+
+    /// A point in time at which every pending delivery is due, regardless of backoff
+    fn after_any_backoff() -> i64 {
+        now() + 2 * MAX_RETRY_DELAY.as_millis() as i64
+    }
+
+    #[test]
+    fn retry_delay_grows_exponentially_up_to_max() {
+        assert_eq!(retry_delay(1), INITIAL_RETRY_DELAY);
+        assert_eq!(retry_delay(2), INITIAL_RETRY_DELAY * 2);
+        assert_eq!(retry_delay(4), INITIAL_RETRY_DELAY * 8);
+        assert_eq!(retry_delay(30), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn delivers_signed_webhooks_with_retries() {
+        setup_agent();
+        Ad4mDb::init_global_instance(":memory:").unwrap();
+        let client = reqwest::Client::new();
+
+        // Fails once, then succeeds
+        let (address, received) = start_receiver(vec![500, 200]);
+        let notification = add_notification(format!("http://{}/hook", address));
+        let payload = r#"{"triggerMatch":"[]"}"#;
+        let delivery_id = queue_delivery(&notification, payload).unwrap();
+
+        assert_eq!(process_due_deliveries(&client, now()).await.unwrap(), 1);
+
+        let history =
+            Ad4mDb::with_global_instance(|db| db.get_notification_deliveries(&notification.id, 10))
+                .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].id, delivery_id);
+        assert_eq!(history[0].status, NotificationDeliveryStatus::Pending);
+        assert_eq!(history[0].attempts, 1);
+        assert_eq!(history[0].last_response_status, Some(500));
+        assert!(history[0].next_attempt_at.is_some());
+
+        // Not due yet because of the backoff
+        assert_eq!(process_due_deliveries(&client, now()).await.unwrap(), 0);
+        assert_eq!(
+            process_due_deliveries(&client, after_any_backoff())
+                .await
+                .unwrap(),
+            1
+        );
+
+        let history =
+            Ad4mDb::with_global_instance(|db| db.get_notification_deliveries(&notification.id, 10))
+                .unwrap();
+        assert_eq!(history[0].status, NotificationDeliveryStatus::Delivered);
+        assert_eq!(history[0].attempts, 2);
+        assert_eq!(history[0].last_response_status, Some(200));
+        assert!(history[0].delivered_at.is_some());
+
+        let received_requests = received.lock().unwrap().clone();
+        assert_eq!(received_requests.len(), 2);
+        let request = &received_requests[1];
+        assert_eq!(request.body, payload);
+        assert_eq!(
+            request.headers.get("authorization").unwrap(),
+            "Bearer secret-token"
+        );
+        assert_eq!(
+            request.headers.get(DELIVERY_ID_HEADER).unwrap(),
+            delivery_id.as_str()
+        );
+        let timestamp: i64 = request
+            .headers
+            .get(TIMESTAMP_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let secret = webhook_secret(&notification.id).unwrap();
+        assert_eq!(
+            request.headers.get(SIGNATURE_HEADER).unwrap(),
+            signature_header_value(&secret, timestamp, payload).as_str()
+        );
+
+        // Always fails, so the delivery gets given up eventually
+        let (address, received) = start_receiver(vec![503]);
+        let notification = add_notification(format!("http://{}/hook", address));
+        queue_delivery(&notification, "{}").unwrap();
+
+        for _ in 0..MAX_DELIVERY_ATTEMPTS {
+            assert_eq!(
+                process_due_deliveries(&client, after_any_backoff())
+                    .await
+                    .unwrap(),
+                1
+            );
+        }
+        assert_eq!(
+            process_due_deliveries(&client, after_any_backoff())
+                .await
+                .unwrap(),
+            0
+        );
+
+        let history =
+            Ad4mDb::with_global_instance(|db| db.get_notification_deliveries(&notification.id, 10))
+                .unwrap();
+        assert_eq!(history[0].status, NotificationDeliveryStatus::Failed);
+        assert_eq!(history[0].attempts, MAX_DELIVERY_ATTEMPTS);
+        assert_eq!(
+            received.lock().unwrap().len(),
+            MAX_DELIVERY_ATTEMPTS as usize
+        );
+    }
+
+    #[test]
+    fn webhook_secret_is_random_per_notification() {
+        Ad4mDb::init_global_instance(":memory:").unwrap();
+        let first = add_notification("http://localhost/first".to_string());
+        let second = add_notification("http://localhost/second".to_string());
+        let secret = webhook_secret(&first.id).unwrap();
+        assert_eq!(secret, webhook_secret(&first.id).unwrap());
+        assert_ne!(secret, webhook_secret(&second.id).unwrap());
+        assert!(webhook_secret("unknown").is_err());
+        assert_ne!(
+            signature_header_value(&secret, 1, "body"),
+            signature_header_value(&secret, 2, "body")
+        );
+    }
+}
@@ -176,6 +176,10 @@ pub struct DecoratedLinkExpression {
     pub data: Link,
     pub proof: DecoratedExpressionProof,
     pub status: Option<LinkStatus>,
+    /// Opaque position of the link in the results of `perspectiveQueryLinks`,
+    /// pass as `LinkQuery.after` or `LinkQuery.before` to get the neighbouring page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
 }
 
 impl DecoratedLinkExpression {
@@ -206,6 +210,7 @@ impl From<(LinkExpression, LinkStatus)> for DecoratedLinkExpression {
             data: verified_expr.data,
             proof: verified_expr.proof,
             status: Some(status),
+            cursor: None,
         }
     }
 }
@@ -370,6 +375,24 @@ impl PerspectiveDiff {
             removals: vec![],
         }
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.additions.is_empty() && self.removals.is_empty()
+    }
+}
+
+/// A diff as it got applied to a perspective, kept in the perspective's history.
+/// Its links carry the status they had at that time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryDiff {
+    pub id: u64,
+    pub diff: PerspectiveDiff,
+    /// Made by this agent, as opposed to received through the link language
+    pub local: bool,
+    pub applied_at: i64,
+    /// Id of the diff this one reverts
+    pub undoes: Option<u64>,
+    pub undone_by: Option<u64>,
 }
 
 #[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -394,6 +417,20 @@ pub struct AIPromptExamples {
     pub output: String,
 }
 
+/// Format that responses to a task's prompts have to follow. Local models are
+/// constrained while sampling, responses of remote models are validated.
+/// Only one of the two can be set.
+#[derive(
+    GraphQLObject, Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct AIOutputConstraint {
+    /// JSON Schema that responses are JSON instances of
+    pub json_schema: Option<String>,
+    /// Regular expression that whole responses match
+    pub regex: Option<String>,
+}
+
 #[derive(
     GraphQLObject, Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord,
 )]
@@ -405,6 +442,51 @@ pub struct AITask {
     pub system_prompt: String,
     pub prompt_examples: Vec<AIPromptExamples>,
     pub meta_data: Option<String>,
+    pub output_constraint: Option<AIOutputConstraint>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// How much of a session's history gets sent along with a prompt
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, GraphQLEnum, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum AIContextTruncation {
+    /// The whole history
+    #[default]
+    KeepAll,
+    /// The last `maxMessages` messages
+    LastMessages,
+    /// As many of the latest messages as fit into `maxTokens`, estimated from their length
+    TokenBudget,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, GraphQLEnum, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AIMessageRole {
+    User,
+    Assistant,
+}
+
+#[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AIMessage {
+    pub role: AIMessageRole,
+    pub content: String,
+    pub created_at: String,
+}
+
+/// A conversation with the model of a task that keeps its message history
+#[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AISession {
+    pub session_id: String,
+    pub task_id: String,
+    pub name: Option<String>,
+    pub truncation: AIContextTruncation,
+    pub max_messages: Option<i32>,
+    pub max_tokens: Option<i32>,
+    /// Perspective that the transcript gets stored in as local (unshared) links
+    pub perspective_uuid: Option<String>,
     pub created_at: String,
     pub updated_at: String,
 }
@@ -434,10 +516,120 @@ pub struct TriggeredNotification {
     pub trigger_match: String,
 }
 
+#[derive(GraphQLEnum, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum NotificationDeliveryStatus {
+    /// Not delivered yet, will be (re)tried
+    Pending,
+    Delivered,
+    /// Gave up after too many failed attempts
+    Failed,
+}
+
+impl FromStr for NotificationDeliveryStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(NotificationDeliveryStatus::Pending),
+            "delivered" => Ok(NotificationDeliveryStatus::Delivered),
+            "failed" => Ok(NotificationDeliveryStatus::Failed),
+            _ => Err(format!("Unknown NotificationDeliveryStatus: {}", s)),
+        }
+    }
+}
+
+impl Display for NotificationDeliveryStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotificationDeliveryStatus::Pending => write!(f, "pending"),
+            NotificationDeliveryStatus::Delivered => write!(f, "delivered"),
+            NotificationDeliveryStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Statement that an agent replaced the key it signs with, signed by the old key.
+/// Keys are did:key DIDs, the timestamp is RFC 3339.
+#[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyRotation {
+    pub agent_did: String,
+    pub old_key: String,
+    pub new_key: String,
+    pub timestamp: String,
+    pub signature: String,
+}
+
+/// Statement that a device key entangled with an agent DID must not sign for it anymore.
+/// Signed by `signing_key`, a key of the agent valid at `revoked_at`.
+#[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceRevocation {
+    pub did: String,
+    pub device_key: String,
+    pub revoked_at: String,
+    pub signing_key: String,
+    pub signature: String,
+}
+
+/// A diff of a neighbourhood perspective that is not committed to its link language yet
+#[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingDiff {
+    pub id: String,
+    pub additions: i32,
+    pub removals: i32,
+    pub created_at: Option<String>,
+    /// Failed commit attempts. Diffs that waited for the link language to sync
+    /// haven't been attempted.
+    pub attempts: i32,
+    pub last_attempt_at: Option<String>,
+    pub last_error: Option<String>,
+}
+
+/// How far a neighbourhood perspective is from its link language
+#[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PerspectiveSyncStatus {
+    pub uuid: String,
+    pub shared_url: Option<String>,
+    pub pending_diffs: i32,
+    pub committed_diffs: i32,
+    pub last_commit_at: Option<String>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<String>,
+    /// Pending diffs with at least one failed commit attempt, oldest first
+    pub failed_diffs: Vec<PendingDiff>,
+}
+
+/// A webhook call for a triggered notification, kept in the outbox until it succeeds
+#[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationDelivery {
+    pub id: String,
+    pub notification_id: String,
+    pub webhook_url: String,
+    /// JSON encoded `TriggeredNotification` that gets posted
+    pub payload: String,
+    pub status: NotificationDeliveryStatus,
+    pub attempts: i32,
+    pub created_at: String,
+    pub next_attempt_at: Option<String>,
+    pub last_attempt_at: Option<String>,
+    /// HTTP status of the last response, if there was one
+    pub last_response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<String>,
+}
+
 #[derive(GraphQLEnum, Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub enum ModelApiType {
+    /// OpenAI or any server with an OpenAI-compatible `/v1` API (llama.cpp server, vLLM, LM Studio, ...)
     OpenAi,
+    /// Ollama's native `/api` endpoints
+    Ollama,
 }
 
 impl FromStr for ModelApiType {
@@ -450,6 +642,7 @@ impl FromStr for ModelApiType {
             "openAi" => Ok(ModelApiType::OpenAi),
             "OpenAi" => Ok(ModelApiType::OpenAi),
             "OPEN_AI" => Ok(ModelApiType::OpenAi),
+            "ollama" | "Ollama" | "OLLAMA" => Ok(ModelApiType::Ollama),
             _ => Err(format!("Unknown ModelApiType: {}", s)),
         }
     }
@@ -460,10 +653,19 @@ impl ToString for ModelApiType {
     fn to_string(&self) -> String {
         match self {
             ModelApiType::OpenAi => "openAi".to_string(),
+            ModelApiType::Ollama => "ollama".to_string(),
         }
     }
 }
 
+/// HTTP header sent along with every request to a model API
+#[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelApiHeader {
+    pub name: String,
+    pub value: String,
+}
+
 #[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct ModelApi {
@@ -471,6 +673,8 @@ pub struct ModelApi {
     pub api_key: String,
     pub model: String,
     pub api_type: ModelApiType,
+    #[serde(default)]
+    pub headers: Vec<ModelApiHeader>,
 }
 
 #[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -480,6 +684,8 @@ pub struct LocalModel {
     pub tokenizer_source: Option<TokenizerSource>,
     pub huggingface_repo: Option<String>,
     pub revision: Option<String>,
+    /// Spoken language for transcription models (e.g. "en"), detected if not set
+    pub language: Option<String>,
 }
 
 #[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone, PartialEq)]
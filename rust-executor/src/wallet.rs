@@ -1,12 +1,15 @@
 use argon2::password_hash::Salt;
 use argon2::{self, Argon2, PasswordHasher};
 use base64::Engine;
+use chrono::{DateTime, Utc};
 use crypto_box::aead::Aead;
 use crypto_box::{Nonce, PublicKey as cPublicKey, SalsaBox, SecretKey as cSecretKey};
 use deno_core::anyhow::anyhow;
 use deno_core::error::AnyError;
-use did_key::{CoreSign, DIDCore, Ed25519KeyPair, KeyMaterial, PatchedKeyPair};
+use did_key::{CoreSign, DIDCore, Ed25519KeyPair, Generate, KeyMaterial, PatchedKeyPair};
 use lazy_static::lazy_static;
+use rand::rngs::OsRng;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::convert::TryInto;
@@ -39,7 +42,10 @@ fn padded(passphrase: String) -> String {
     passphrase
 }
 
-fn encrypt(payload: String, passphrase: String) -> String {
+// Keystores written before format version 2 derive the Argon2 salt from the
+// passphrase and use an all-zero nonce. They are only read to migrate them.
+#[cfg(test)]
+fn encrypt_legacy(payload: String, passphrase: String) -> String {
     let passphrase = padded(passphrase);
     let b64_passphrase =
         base64::engine::general_purpose::STANDARD_NO_PAD.encode(passphrase.as_bytes());
@@ -74,7 +80,7 @@ fn encrypt(payload: String, passphrase: String) -> String {
     base64::engine::general_purpose::STANDARD_NO_PAD.encode(encrypted_data)
 }
 
-fn decrypt(payload: String, passphrase: String) -> Result<String, crypto_box::aead::Error> {
+fn decrypt_legacy(payload: String, passphrase: String) -> Result<String, crypto_box::aead::Error> {
     let passphrase = padded(passphrase);
     let b64_passphrase =
         base64::engine::general_purpose::STANDARD_NO_PAD.encode(passphrase.as_bytes());
@@ -112,10 +118,185 @@ fn decrypt(payload: String, passphrase: String) -> Result<String, crypto_box::ae
     decrypted_data
 }
 
+pub const KEYSTORE_VERSION: u32 = 2;
+pub const MIN_PASSPHRASE_LENGTH: usize = 8;
+const KEYSTORE_KDF: &str = "argon2id";
+const KEYSTORE_SALT_LENGTH: usize = 16;
+
+/// Argon2id cost parameters used when writing a keystore.
+/// Reading uses the parameters stored in the keystore.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct KeystoreKdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KeystoreKdfParams {
+    fn default() -> Self {
+        KeystoreKdfParams {
+            memory_kib: argon2::Params::DEFAULT_M_COST,
+            iterations: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KeystoreKdfParams {
+    fn argon2(&self) -> Result<Argon2<'static>, AnyError> {
+        let params =
+            argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+                .map_err(|e| anyhow!("Invalid keystore KDF parameters: {}", e))?;
+        Ok(Argon2::new(
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            params,
+        ))
+    }
+}
+
+lazy_static! {
+    static ref KDF_PARAMS: Mutex<KeystoreKdfParams> = Mutex::new(KeystoreKdfParams::default());
+}
+
+/// Sets the KDF parameters that new keystores get written with (see `Ad4mConfig`)
+pub fn set_keystore_kdf_params(params: KeystoreKdfParams) -> Result<(), AnyError> {
+    params.argon2()?;
+    *KDF_PARAMS.lock().unwrap() = params;
+    Ok(())
+}
+
+pub fn check_passphrase_policy(passphrase: &str) -> Result<(), AnyError> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LENGTH {
+        return Err(anyhow!(
+            "Passphrase must be at least {} characters long",
+            MIN_PASSPHRASE_LENGTH
+        ));
+    }
+    if passphrase.trim().is_empty() {
+        return Err(anyhow!("Passphrase must not consist of whitespace only"));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Keystore {
+    version: u32,
+    kdf: String,
+    kdf_params: KeystoreKdfParams,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+fn keystore_box(
+    passphrase: &str,
+    salt: &[u8],
+    params: &KeystoreKdfParams,
+) -> Result<SalsaBox, AnyError> {
+    let mut derived_secret_key = [0u8; 32];
+    params
+        .argon2()?
+        .hash_password_into(passphrase.as_bytes(), salt, &mut derived_secret_key)
+        .map_err(|e| anyhow!("Could not derive keystore key: {}", e))?;
+    let secret_key = cSecretKey::from(derived_secret_key);
+    let public_key = cPublicKey::from(&secret_key);
+    Ok(SalsaBox::new(&public_key, &secret_key))
+}
+
+/// Encrypts `payload` into a keystore document. Also used for agent backups.
+pub(crate) fn encrypt(payload: String, passphrase: String) -> String {
+    let params = *KDF_PARAMS.lock().unwrap();
+    let mut salt = [0u8; KEYSTORE_SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = Nonce::default();
+    OsRng.fill_bytes(&mut nonce);
+
+    let crypto_box = keystore_box(&passphrase, &salt, &params)
+        .expect("keystore KDF parameters are validated when set");
+    let encrypted_data = crypto_box.encrypt(&nonce, payload.as_bytes()).unwrap();
+
+    let b64 = base64::engine::general_purpose::STANDARD_NO_PAD;
+    serde_json::to_string(&Keystore {
+        version: KEYSTORE_VERSION,
+        kdf: KEYSTORE_KDF.to_string(),
+        kdf_params: params,
+        salt: b64.encode(salt),
+        nonce: b64.encode(nonce),
+        ciphertext: b64.encode(encrypted_data),
+    })
+    .expect("keystore to serialize")
+}
+
+/// Keystores of format version 2 and later are JSON documents,
+/// older ones are a bare base64 string.
+fn is_legacy_keystore(payload: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(payload)
+        .map(|value| !value.is_object())
+        .unwrap_or(true)
+}
+
+pub(crate) fn decrypt(payload: String, passphrase: String) -> Result<String, AnyError> {
+    if is_legacy_keystore(&payload) {
+        return decrypt_legacy(payload, passphrase).map_err(|err| anyhow!(err));
+    }
+
+    let keystore: Keystore = serde_json::from_str(&payload)?;
+    if keystore.version > KEYSTORE_VERSION {
+        return Err(anyhow!(
+            "Keystore format version {} is not supported by this version of AD4M",
+            keystore.version
+        ));
+    }
+    if keystore.kdf != KEYSTORE_KDF {
+        return Err(anyhow!("Unsupported keystore KDF: {}", keystore.kdf));
+    }
+
+    let b64 = base64::engine::general_purpose::STANDARD_NO_PAD;
+    let salt = b64.decode(keystore.salt)?;
+    let nonce = b64.decode(keystore.nonce)?;
+    if nonce.len() != Nonce::default().len() {
+        return Err(anyhow!("Keystore nonce has an invalid length"));
+    }
+    let ciphertext = b64.decode(keystore.ciphertext)?;
+
+    let crypto_box = keystore_box(&passphrase, &salt, &keystore.kdf_params)?;
+    let decrypted_data = crypto_box
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|err| anyhow!(err))?;
+    Ok(String::from_utf8(decrypted_data)?)
+}
+
+pub const MAIN_KEY: &str = "main";
+
+/// Multibase prefix of Ed25519 did:keys, the only kind the wallet creates
+const ED25519_DID_PREFIX: &str = "did:key:z6Mk";
+
+/// X25519 public key to encrypt to the holder of the Ed25519 key `did`
+pub fn key_agreement_public_key(did: &str) -> Result<cPublicKey, AnyError> {
+    if !did.starts_with(ED25519_DID_PREFIX) {
+        return Err(anyhow!("Not an Ed25519 did:key: {}", did));
+    }
+    let key = did_key::resolve(did).map_err(|_| anyhow!("Invalid did:key: {}", did))?;
+    let x25519 = Ed25519KeyPair::from_public_key(&key.public_key_bytes()).get_x25519();
+    let bytes: [u8; 32] = x25519
+        .public_key_bytes()
+        .try_into()
+        .map_err(|_| anyhow!("Invalid X25519 key derived from {}", did))?;
+    Ok(cPublicKey::from(bytes))
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Key {
     pub secret: Vec<u8>,
     pub public: Vec<u8>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    /// Retired keys can't sign anymore but their past signatures stay valid
+    #[serde(default)]
+    pub retired_at: Option<DateTime<Utc>>,
 }
 
 impl Key {
@@ -123,6 +304,33 @@ impl Key {
         Key {
             secret: did.private_key_bytes(),
             public: did.public_key_bytes(),
+            created_at: Some(Utc::now()),
+            retired_at: None,
+        }
+    }
+
+    fn did(&self) -> String {
+        did_key::from_existing_key::<Ed25519KeyPair>(&self.public, None)
+            .get_did_document(did_key::Config::default())
+            .id
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyInfo {
+    pub name: String,
+    pub did: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+impl KeyInfo {
+    fn from_key(name: &str, key: &Key) -> KeyInfo {
+        KeyInfo {
+            name: name.to_string(),
+            did: key.did(),
+            created_at: key.created_at,
+            retired_at: key.retired_at,
         }
     }
 }
@@ -226,15 +434,134 @@ impl Wallet {
     }
 
     pub fn sign(&self, name: &String, message: &[u8]) -> Option<Vec<u8>> {
-        self.keys.as_ref()?.by_name.get(name).map(|key| {
-            let key = did_key::from_existing_key::<Ed25519KeyPair>(
-                &key.public.clone(),
-                Some(&key.secret.clone()),
-            );
-            key.sign(message)
+        self.keys
+            .as_ref()?
+            .by_name
+            .get(name)
+            .filter(|key| key.retired_at.is_none())
+            .map(|key| {
+                let key = did_key::from_existing_key::<Ed25519KeyPair>(
+                    &key.public.clone(),
+                    Some(&key.secret.clone()),
+                );
+                key.sign(message)
+            })
+    }
+
+    pub fn list_keys(&self) -> Vec<KeyInfo> {
+        self.keys
+            .as_ref()
+            .map(|keys| {
+                keys.by_name
+                    .iter()
+                    .map(|(name, key)| KeyInfo::from_key(name, key))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn get_key_info(&self, name: &str) -> Option<KeyInfo> {
+        self.keys
+            .as_ref()?
+            .by_name
+            .get(name)
+            .map(|key| KeyInfo::from_key(name, key))
+    }
+
+    /// Looks up a (possibly retired) key by its did:key DID
+    pub fn find_key_by_did(&self, did: &str) -> Option<(KeyInfo, Vec<u8>)> {
+        self.keys.as_ref()?.by_name.iter().find_map(|(name, key)| {
+            let info = KeyInfo::from_key(name, key);
+            (info.did == did).then(|| (info, key.public.clone()))
         })
     }
 
+    /// Decrypts a box that `sender` sealed to the public key returned by
+    /// `key_agreement_public_key()` for our (possibly retired) key `name`
+    pub fn open_box(
+        &self,
+        name: &str,
+        sender: &cPublicKey,
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Option<Vec<u8>> {
+        if nonce.len() != Nonce::default().len() {
+            return None;
+        }
+        let key = self.keys.as_ref()?.by_name.get(name)?;
+        let x25519 = Ed25519KeyPair::from_secret_key(&key.secret).get_x25519();
+        let secret: [u8; 32] = x25519.private_key_bytes().try_into().ok()?;
+        SalsaBox::new(sender, &cSecretKey::from(secret))
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+
+    pub fn create_key(&mut self, name: String) -> Result<KeyInfo, AnyError> {
+        if !self.is_unlocked() {
+            return Err(anyhow!("Wallet is locked"));
+        }
+        if self.get_key_info(&name).is_some() {
+            return Err(anyhow!("Key {} already exists", name));
+        }
+        self.generate_keypair(name.clone());
+        self.get_key_info(&name)
+            .ok_or(anyhow!("Key {} was not created", name))
+    }
+
+    pub fn retire_key(&mut self, name: &str) -> Result<KeyInfo, AnyError> {
+        if name == MAIN_KEY {
+            return Err(anyhow!("The main key can't be retired, rotate it instead"));
+        }
+        let key = self
+            .keys
+            .as_mut()
+            .ok_or(anyhow!("Wallet is locked"))?
+            .by_name
+            .get_mut(name)
+            .ok_or(anyhow!("Key {} not found", name))?;
+        if key.retired_at.is_some() {
+            return Err(anyhow!("Key {} is already retired", name));
+        }
+        key.retired_at = Some(Utc::now());
+        Ok(KeyInfo::from_key(name, key))
+    }
+
+    /// Replaces the main key with a freshly generated one.
+    /// The old main key is kept as a retired key. Before it is retired it signs
+    /// the statement built by `statement` from the retired key's info and the
+    /// new key's DID, so the rotation can be verified by others.
+    pub fn rotate_main_key<F>(&mut self, statement: F) -> Result<(KeyInfo, Vec<u8>), AnyError>
+    where
+        F: FnOnce(&KeyInfo, &str) -> Vec<u8>,
+    {
+        let keys = self.keys.as_mut().ok_or(anyhow!("Wallet is locked"))?;
+        let mut old_key = keys
+            .by_name
+            .remove(MAIN_KEY)
+            .ok_or(anyhow!("main key not found. call createMainKey() first"))?;
+        let now = Utc::now();
+        old_key.retired_at = Some(now);
+        let retired_name = format!("{}-retired-{}", MAIN_KEY, now.timestamp_millis());
+        let info = KeyInfo::from_key(&retired_name, &old_key);
+
+        let new_key = Key::from(did_key::generate::<Ed25519KeyPair>(None));
+        let signature =
+            did_key::from_existing_key::<Ed25519KeyPair>(&old_key.public, Some(&old_key.secret))
+                .sign(&statement(&info, &new_key.did()));
+
+        keys.by_name.insert(retired_name, old_key);
+        keys.by_name.insert(MAIN_KEY.to_string(), new_key);
+        Ok((info, signature))
+    }
+
+    /// Checks a passphrase against the currently loaded keystore
+    pub fn verify_passphrase(&self, passphrase: String) -> bool {
+        match &self.cipher {
+            Some(cipher) => decrypt(cipher.clone(), passphrase).is_ok(),
+            None => false,
+        }
+    }
+
     pub fn lock(&mut self, passphrase: String) {
         if let Some(keys) = &self.keys {
             let string = serde_json::to_string(&keys).unwrap();
@@ -245,17 +572,31 @@ impl Wallet {
     }
 
     pub fn unlock(&mut self, passphrase: String) -> Result<(), AnyError> {
-        let string = decrypt(self.cipher.clone().expect("No cypher selected"), passphrase)
-            .map_err(|err| anyhow!(err))?;
+        let string = decrypt(self.cipher.clone().expect("No cypher selected"), passphrase)?;
         let keys: Keys = serde_json::from_str(&string)?;
         self.keys = Some(keys);
         Ok(())
     }
 
+    /// True if the loaded keystore predates `KEYSTORE_VERSION` and should be
+    /// re-exported after the next successful unlock
+    pub fn keystore_needs_migration(&self) -> bool {
+        self.cipher
+            .as_ref()
+            .map(|cipher| is_legacy_keystore(cipher))
+            .unwrap_or(false)
+    }
+
     pub fn is_unlocked(&self) -> bool {
         self.keys.is_some()
     }
 
+    /// Drops the unlocked keys without writing them back to the keystore,
+    /// e.g. because a different keystore was loaded
+    pub fn discard_keys(&mut self) {
+        self.keys = None;
+    }
+
     pub fn export(&mut self, passphrase: String) -> String {
         if let Some(keys) = &self.keys {
             let string = serde_json::to_string(keys).unwrap();
@@ -439,4 +780,156 @@ mod tests {
             assert!(result.is_err());
         }
     }
+
+    #[test]
+    fn keystores_use_random_salt_and_nonce() {
+        let passphrase = "test_passphrase".to_string();
+        let first = encrypt("payload".to_string(), passphrase.clone());
+        let second = encrypt("payload".to_string(), passphrase.clone());
+
+        let first_keystore: Keystore = serde_json::from_str(&first).unwrap();
+        let second_keystore: Keystore = serde_json::from_str(&second).unwrap();
+        assert_eq!(first_keystore.version, KEYSTORE_VERSION);
+        assert_eq!(first_keystore.kdf, "argon2id");
+        assert_ne!(first_keystore.salt, second_keystore.salt);
+        assert_ne!(first_keystore.nonce, second_keystore.nonce);
+        assert_ne!(first_keystore.ciphertext, second_keystore.ciphertext);
+
+        assert_eq!(decrypt(first, passphrase.clone()).unwrap(), "payload");
+        assert_eq!(decrypt(second, passphrase).unwrap(), "payload");
+    }
+
+    #[test]
+    fn keystores_are_read_with_their_own_kdf_params() {
+        let mut keystore: Keystore =
+            serde_json::from_str(&encrypt("payload".to_string(), "passphrase".to_string()))
+                .unwrap();
+        let cheap = KeystoreKdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let salt = [7u8; KEYSTORE_SALT_LENGTH];
+        let nonce = Nonce::default();
+        let b64 = base64::engine::general_purpose::STANDARD_NO_PAD;
+        keystore.kdf_params = cheap;
+        keystore.salt = b64.encode(salt);
+        keystore.nonce = b64.encode(nonce);
+        keystore.ciphertext = b64.encode(
+            keystore_box("passphrase", &salt, &cheap)
+                .unwrap()
+                .encrypt(&nonce, "payload".as_bytes())
+                .unwrap(),
+        );
+
+        let payload = serde_json::to_string(&keystore).unwrap();
+        assert_eq!(
+            decrypt(payload, "passphrase".to_string()).unwrap(),
+            "payload"
+        );
+
+        keystore.version = KEYSTORE_VERSION + 1;
+        let payload = serde_json::to_string(&keystore).unwrap();
+        assert!(decrypt(payload, "passphrase".to_string()).is_err());
+    }
+
+    #[test]
+    fn invalid_kdf_params_are_rejected() {
+        assert!(set_keystore_kdf_params(KeystoreKdfParams {
+            memory_kib: 0,
+            iterations: 0,
+            parallelism: 0,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn legacy_keystores_get_migrated_on_export() {
+        let passphrase = "test_passphrase".to_string();
+        let mut wallet = Wallet::new();
+        wallet.generate_keypair("main".to_string());
+        let keys = serde_json::to_string(wallet.keys.as_ref().unwrap()).unwrap();
+        let public_key = wallet.get_public_key(&"main".to_string());
+
+        let mut legacy_wallet = Wallet::new();
+        legacy_wallet.load(encrypt_legacy(keys, passphrase.clone()));
+        assert!(legacy_wallet.keystore_needs_migration());
+        assert!(legacy_wallet
+            .unlock("wrong_passphrase".to_string())
+            .is_err());
+        legacy_wallet.unlock(passphrase.clone()).unwrap();
+        assert_eq!(
+            legacy_wallet.get_public_key(&"main".to_string()),
+            public_key
+        );
+
+        let exported = legacy_wallet.export(passphrase.clone());
+        assert!(!legacy_wallet.keystore_needs_migration());
+
+        let mut migrated_wallet = Wallet::new();
+        migrated_wallet.load(exported);
+        assert!(!migrated_wallet.keystore_needs_migration());
+        migrated_wallet.unlock(passphrase).unwrap();
+        assert_eq!(
+            migrated_wallet.get_public_key(&"main".to_string()),
+            public_key
+        );
+    }
+
+    #[test]
+    fn passphrase_policy_requires_a_minimum_length() {
+        assert!(check_passphrase_policy("secret").is_err());
+        assert!(check_passphrase_policy("          ").is_err());
+        assert!(check_passphrase_policy("passphrase").is_ok());
+    }
+
+    #[test]
+    fn keys_can_be_created_and_retired() {
+        let mut wallet = Wallet::new();
+        wallet.generate_keypair(MAIN_KEY.to_string());
+
+        let device_key = wallet.create_key("device".to_string()).unwrap();
+        assert!(device_key.did.starts_with("did:key:"));
+        assert!(device_key.retired_at.is_none());
+        assert!(wallet.create_key("device".to_string()).is_err());
+        assert_eq!(wallet.list_keys().len(), 2);
+
+        assert!(wallet.sign(&"device".to_string(), b"message").is_some());
+        let retired = wallet.retire_key("device").unwrap();
+        assert!(retired.retired_at.is_some());
+        assert!(wallet.sign(&"device".to_string(), b"message").is_none());
+        assert!(wallet.retire_key("device").is_err());
+        assert!(wallet.retire_key(MAIN_KEY).is_err());
+
+        let (found, public) = wallet.find_key_by_did(&device_key.did).unwrap();
+        assert_eq!(found.name, "device");
+        assert_eq!(Some(public), wallet.get_public_key(&"device".to_string()));
+    }
+
+    #[test]
+    fn rotating_the_main_key_keeps_the_old_one_retired() {
+        let mut wallet = Wallet::new();
+        wallet.generate_keypair(MAIN_KEY.to_string());
+        let old_main = wallet.get_key_info(MAIN_KEY).unwrap();
+
+        let (retired, signature) = wallet
+            .rotate_main_key(|_, new_did| new_did.as_bytes().to_vec())
+            .unwrap();
+        assert_eq!(retired.did, old_main.did);
+        assert!(retired.retired_at.is_some());
+
+        let new_main = wallet.get_key_info(MAIN_KEY).unwrap();
+        assert_ne!(new_main.did, old_main.did);
+        let old_key = did_key::resolve(&old_main.did).unwrap();
+        assert!(old_key.verify(new_main.did.as_bytes(), &signature).is_ok());
+        assert!(wallet.sign(&MAIN_KEY.to_string(), b"message").is_some());
+        assert!(wallet.sign(&retired.name, b"message").is_none());
+
+        // Survives a keystore roundtrip
+        let exported = wallet.export("test_passphrase".to_string());
+        let mut loaded = Wallet::new();
+        loaded.load(exported);
+        loaded.unlock("test_passphrase".to_string()).unwrap();
+        assert_eq!(loaded.get_key_info(&retired.name), Some(retired));
+    }
 }
@@ -38,7 +38,7 @@ describe("Prolog + Literals", () => {
         console.log("Creating ad4m client")
         ad4m = new Ad4mClient(apolloClient(gqlPort))
         console.log("Generating agent")
-        await ad4m.agent.generate("secret")
+        await ad4m.agent.generate("passphrase")
         console.log("Done")
     })
 